@@ -0,0 +1,144 @@
+//! Adaptive sampling, which spends more of each pass on the samples whose orbits feed the
+//! noisiest parts of the image so far, such as sparse filaments, and less on those that only add
+//! to parts that have already converged, such as the bright core
+//!
+//! The image is split into coarse tiles, and the noise of each tile is estimated from how many
+//! points it has taken so far: counts of points are about Poisson distributed, so their relative
+//! variance is about `1 / n` for `n` points per pixel. Every sample traced during a pass is then
+//! scored with the sum of the noise of the tiles that its points land in, and scores are gathered
+//! over the cells of a grid over the unit square of samples that they come from, across every
+//! pass.
+//!
+//! After a few uniform passes, each pass draws its samples from an [`ImportanceMap`] of the mean
+//! score of the samples of each cell, and weighs their points like guided samples do, which
+//! keeps the image unbiased. Unlike the guided sampler, whose map is fixed, the map changes from
+//! pass to pass, so every weight is taken on the same fixed scale [`SCALE`] instead of one
+//! measured from the map.
+
+use crate::guided::{self, ImportanceMap};
+use crate::raw_image::RawImage;
+
+/// Number of cells along each axis of the grid over the unit square of samples
+pub const GRID: u32 = 64;
+
+/// Number of tiles along each axis of the image that noise is estimated over
+const TILES: u32 = 16;
+
+/// Whole weight of every point of a uniform sample, like the scale `S` of guided samples
+pub const SCALE: f64 = 16.0;
+
+/// Number of uniform passes that sample statistics are gathered over before they are used
+pub const WARMUP_PASSES: u64 = 2;
+
+/// Number of samples that a cell needs to have taken for its mean score to be trusted, below
+/// which the mean score of every sample is used instead
+const MIN_SAMPLES: u64 = 16;
+
+/// Estimated relative variance of the pixels of each tile of an image
+pub struct Noise {
+    /// Column of tiles of each column of pixels, and the first tile of the row of tiles of each
+    /// row of pixels, which spare a division for every point
+    columns: Vec<u32>,
+    rows: Vec<u32>,
+    tiles: Vec<f64>,
+}
+
+impl Noise {
+    /// Estimate the noise of the tiles of an `image` of `width` × `height` pixels of some
+    /// `channels`, whose points were plotted with weights of [`SCALE`], each shared between
+    /// pixels on a `splat` scale, a row at a time
+    pub fn new(image: &RawImage, width: u32, height: u32, channels: u32, splat: u32) -> Noise {
+        let columns: Vec<u32> = (0..width)
+            .map(|x| (x as u64 * TILES as u64 / width as u64) as u32)
+            .collect();
+        let rows: Vec<u32> = (0..height)
+            .map(|y| (y as u64 * TILES as u64 / height as u64) as u32 * TILES)
+            .collect();
+        let mut totals = vec![0.0; (TILES * TILES) as usize];
+        let mut pixels = vec![0u64; (TILES * TILES) as usize];
+        let mut values = Vec::with_capacity(width as usize * channels as usize);
+        for (y, row) in image.rows().enumerate() {
+            values.clear();
+            values.extend(row);
+            for (x, pixel) in values.chunks_exact(channels as usize).enumerate() {
+                let tile = (rows[y] + columns[x]) as usize;
+                totals[tile] += pixel.iter().map(|&v| v as f64).sum::<f64>();
+                pixels[tile] += 1;
+            }
+        }
+        let tiles = totals
+            .iter()
+            .zip(&pixels)
+            .map(|(&total, &pixels)| {
+                let points = total / (SCALE * splat as f64) / pixels.max(1) as f64;
+                1.0 / (1.0 + points)
+            })
+            .collect();
+        Noise {
+            columns,
+            rows,
+            tiles,
+        }
+    }
+
+    /// Noise of the tile of the pixel at `x` - `y`
+    pub fn at(&self, x: u32, y: u32) -> f64 {
+        self.tiles[(self.rows[y as usize] + self.columns[x as usize]) as usize]
+    }
+}
+
+/// Scores of the samples taken so far from each cell of the grid, and how many there were
+#[derive(Clone)]
+pub struct Sources {
+    samples: Vec<u64>,
+    scores: Vec<f64>,
+}
+
+impl Default for Sources {
+    fn default() -> Sources {
+        Sources {
+            samples: vec![0; (GRID * GRID) as usize],
+            scores: vec![0.0; (GRID * GRID) as usize],
+        }
+    }
+}
+
+impl Sources {
+    /// Count a sample at `(u, v)` of the unit square, scored with the sum of the noise of the
+    /// tiles of the points it plotted
+    pub fn add(&mut self, point: (f64, f64), score: f64) {
+        let cell = guided::cell(GRID, point);
+        self.samples[cell] += 1;
+        self.scores[cell] += score;
+    }
+
+    /// Combine the scores of two sets of samples
+    pub fn merge(mut self, other: Sources) -> Sources {
+        for (a, b) in self.samples.iter_mut().zip(other.samples) {
+            *a += b;
+        }
+        for (a, b) in self.scores.iter_mut().zip(other.scores) {
+            *a += b;
+        }
+        self
+    }
+
+    /// Map of where to take samples from, with a `strength` between 0 and 1
+    pub fn map(&self, strength: f64) -> ImportanceMap {
+        let samples: u64 = self.samples.iter().sum();
+        let mean = self.scores.iter().sum::<f64>() / samples.max(1) as f64;
+        let importance: Vec<f64> = self
+            .samples
+            .iter()
+            .zip(&self.scores)
+            .map(|(&samples, &score)| {
+                if samples < MIN_SAMPLES {
+                    mean
+                } else {
+                    score / samples as f64
+                }
+            })
+            .collect();
+        ImportanceMap::new(GRID, &importance, strength)
+    }
+}
@@ -0,0 +1,876 @@
+//! Loading of TOML and JSON configuration files, including migration from older versions of the
+//! format
+
+use crate::error::NebulaeError;
+use crate::render_settings::{Location, Palette, PaletteRegistry, RenderSettings};
+use console::style;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+use toml::value::Table;
+
+/// Version of the configuration format written by this version of the program
+pub const CONFIG_VERSION: u32 = 1;
+
+/// A migration of a configuration table from one version of the format to the next
+/// Returns notes about any change in meaning that the user should know about, or why the table
+/// can't be migrated
+type Migration = fn(&mut Table) -> Result<Vec<String>, String>;
+
+/// Migrations from each version of the format to the next, starting at version 0
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [migrate_v0];
+
+/// Keys that are accepted in configuration files, but aren't fields of [`RenderSettings`]
+const SHORTHAND_KEYS: [&str; 8] = [
+    "size",
+    "location",
+    "palette",
+    "palettes",
+    "limit_scale",
+    "extends",
+    "jobs",
+    "output",
+];
+
+/// Longest chain of configuration files that can extend one another
+const MAX_EXTENDS_DEPTH: usize = 16;
+
+/// Settings of a render described by a configuration file, and the output path it gives, if any
+pub type Job = (RenderSettings, Option<String>);
+
+/// Formats that configuration files can be written in
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    /// TOML, like the default configuration
+    Toml,
+    /// JSON, as other programs tend to write it
+    Json,
+}
+
+impl ConfigFormat {
+    /// Gets the format matching the extension of a path, if it has a known one
+    pub fn from_path(path: &str) -> Option<ConfigFormat> {
+        match Path::new(path).extension()?.to_str()? {
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of some configuration data, from the fact that only JSON starts with `{`
+    fn sniff(data: &str) -> ConfigFormat {
+        if data.trim_start().starts_with('{') {
+            ConfigFormat::Json
+        } else {
+            ConfigFormat::Toml
+        }
+    }
+
+    /// Parses configuration data of the file at `path` in this format to a TOML value
+    /// Errors tell which format the data was parsed as
+    fn parse(self, data: &str, path: &str) -> Result<toml::Value, NebulaeError> {
+        // The messages of parse errors end with their location, which is kept apart
+        let error = |message: String, location: Option<(usize, usize)>| {
+            let message = match location {
+                Some((line, column)) => message
+                    .strip_suffix(&format!(" at line {line} column {column}"))
+                    .map(str::to_string)
+                    .unwrap_or(message),
+                None => message,
+            };
+            NebulaeError::Config {
+                path: path.to_string(),
+                location,
+                message: format!("not valid {self}: {message}"),
+            }
+        };
+        match self {
+            ConfigFormat::Toml => toml::from_str(data).map_err(|parse_error| {
+                let location = parse_error
+                    .line_col()
+                    .map(|(line, column)| (line + 1, column + 1));
+                error(parse_error.to_string(), location)
+            }),
+            ConfigFormat::Json => {
+                let mut json: serde_json::Value =
+                    serde_json::from_str(data).map_err(|parse_error| {
+                        let location = Some((parse_error.line(), parse_error.column()))
+                            .filter(|&(line, _)| line > 0);
+                        error(parse_error.to_string(), location)
+                    })?;
+                // TOML has no null, and an unset key means the same thing
+                strip_nulls(&mut json);
+                toml::Value::try_from(json)
+                    .map_err(|parse_error| error(parse_error.to_string(), None))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Toml => write!(f, "TOML"),
+            ConfigFormat::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+/// Opens a TOML or JSON file (or `-` for standard input) to the list of renders it describes
+/// The format is picked from the file's extension, or guessed from its contents.
+///
+/// A file holds a single render, unless it has a `[[jobs]]` array: each job is then a render with
+/// the file's top-level settings, overridden by the job's own.
+/// `output` paths are relative to the file that gives them.
+/// Unknown keys are reported as warnings, or as an error if `strict` is set
+pub fn load_jobs(path: &str, strict: bool) -> Result<Vec<Job>, NebulaeError> {
+    let mut table = load_table(path, &mut Vec::new())?;
+    let palettes = match table.remove("palettes") {
+        Some(toml::Value::Table(palettes)) => registry(&palettes, path)?,
+        _ => PaletteRegistry::default(),
+    };
+    let job_tables = match table.remove("jobs") {
+        None => vec![Table::new()],
+        Some(toml::Value::Array(jobs)) => jobs
+            .into_iter()
+            .map(|job| match job {
+                toml::Value::Table(job) => Ok(job),
+                _ => Err(invalid(path, "every entry of `jobs` must be a table")),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err(invalid(path, "`jobs` must be an array of tables")),
+    };
+    let multiple = job_tables.len() > 1;
+    let default_output = match table.remove("output") {
+        None => None,
+        Some(output) => Some(
+            output
+                .as_str()
+                .ok_or_else(|| invalid(path, "`output` must be a path"))?
+                .to_string(),
+        ),
+    };
+
+    let mut unknown_keys = BTreeSet::new();
+    let mut jobs = Vec::new();
+    for (index, mut job_table) in job_tables.into_iter().enumerate() {
+        let source = if multiple {
+            format!("{path} (job {})", index + 1)
+        } else {
+            String::from(path)
+        };
+        resolve_shorthands(&mut job_table, &palettes).map_err(|error| error.in_file(&source))?;
+        let output = match job_table.remove("output") {
+            None => default_output.clone(),
+            Some(output) => Some(
+                output
+                    .as_str()
+                    .ok_or_else(|| invalid(&source, "`output` must be a path"))?
+                    .to_string(),
+            ),
+        };
+        // Only report a job's own unknown keys as such, the top-level ones are shared by every job
+        let job_keys: Vec<String> = job_table.keys().cloned().collect();
+        let mut merged = table.clone();
+        merged.extend(job_table);
+        // The scale applies to the limits once every other key has been resolved
+        let limit_scale = match merged.remove("limit_scale") {
+            None => None,
+            Some(scale) => Some(
+                scale
+                    .as_float()
+                    .or_else(|| scale.as_integer().map(|scale| scale as f64))
+                    .ok_or_else(|| invalid(&source, "`limit_scale` must be a number"))?,
+            ),
+        };
+        // Limits that are given, even by a palette, are used as they are rather than picked
+        if merged.contains_key("auto_limits") {
+            if merged.contains_key("limits") {
+                warn(format!(
+                    "{source}: `limits` are given, `auto_limits` is ignored"
+                ));
+                merged.remove("auto_limits");
+            } else if limit_scale.is_some() {
+                warn(format!(
+                    "{source}: `limit_scale` only scales given `limits`, not `auto_limits`"
+                ));
+            }
+        }
+        let mut settings: RenderSettings =
+            serde_ignored::deserialize(toml::Value::Table(merged), |key| {
+                let key = key.to_string();
+                let top_level = key.split('.').next().unwrap_or_default();
+                if multiple && job_keys.iter().any(|job_key| job_key == top_level) {
+                    unknown_keys.insert(format!("jobs[{index}].{key}"));
+                } else {
+                    unknown_keys.insert(key);
+                }
+            })
+            .map_err(|error| invalid(&source, error.to_string()))?;
+        if let Some(scale) = limit_scale {
+            settings
+                .scale_limits(scale)
+                .map_err(|error| error.in_file(&source))?;
+        }
+        jobs.push((settings, output));
+    }
+
+    if !unknown_keys.is_empty() {
+        let known_keys = [field_names::<RenderSettings>(), &SHORTHAND_KEYS].concat();
+        let report: Vec<String> = unknown_keys
+            .iter()
+            .map(|key| match closest_match(key, &known_keys) {
+                Some(known) => format!("`{key}` (did you mean `{known}`?)"),
+                None => format!("`{key}`"),
+            })
+            .collect();
+        if strict {
+            return Err(invalid(path, format!("unknown keys {}", report.join(", "))));
+        }
+        warn(format!("Unknown keys in {path}: {}", report.join(", ")));
+    }
+    Ok(jobs)
+}
+
+/// Reads the user-defined [`Palette`]s of a palettes file, in addition to the built-in ones
+pub fn load_palettes(path: &str) -> Result<PaletteRegistry, NebulaeError> {
+    registry(&read_table(path)?, path)
+}
+
+/// Reads a configuration file to an up to date table, on top of the file it `extends`, if any
+/// The `palettes` of the file and its bases are gathered into a single table, and `palette` keys
+/// are expanded with them
+/// `chain` holds the files that are being read, to detect circular inheritance
+fn load_table(path: &str, chain: &mut Vec<PathBuf>) -> Result<Table, NebulaeError> {
+    let mut table = read_table(path)?;
+    for note in migrate(&mut table).map_err(|message| invalid(path, message))? {
+        warn(format!("{path}: {note}"));
+    }
+    // Paths in the file are relative to it
+    let directory = match Path::new(path).parent() {
+        Some(directory) if path != "-" => Some(directory),
+        _ => None,
+    };
+    if let Some(directory) = directory {
+        resolve_paths(&mut table, directory);
+    }
+    let own_palettes = match table.remove("palettes") {
+        None => Table::new(),
+        Some(toml::Value::Table(palettes)) => palettes,
+        Some(toml::Value::String(file)) => read_table(
+            &directory
+                .map_or_else(|| PathBuf::from(&file), |directory| directory.join(&file))
+                .to_string_lossy(),
+        )?,
+        Some(_) => {
+            return Err(invalid(path, "`palettes` must be a table or a path"));
+        }
+    };
+
+    let mut merged = match table.remove("extends") {
+        Some(base) => {
+            let base = base
+                .as_str()
+                .ok_or_else(|| invalid(path, "`extends` must be a path"))?;
+            let base =
+                directory.map_or_else(|| PathBuf::from(base), |directory| directory.join(base));
+            let identity =
+                |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            chain.push(identity(Path::new(path)));
+            if chain.contains(&identity(&base)) {
+                let cycle: Vec<String> = chain
+                    .iter()
+                    .chain([&identity(&base)])
+                    .map(|path| path.display().to_string())
+                    .collect();
+                return Err(invalid(
+                    path,
+                    format!("circular inheritance: {}", cycle.join(" -> ")),
+                ));
+            }
+            if chain.len() > MAX_EXTENDS_DEPTH {
+                return Err(invalid(
+                    path,
+                    format!(
+                        "it extends a chain of more than {MAX_EXTENDS_DEPTH} configuration files"
+                    ),
+                ));
+            }
+            let merged = load_table(&base.to_string_lossy(), chain)?;
+            chain.pop();
+            merged
+        }
+        None => Table::new(),
+    };
+    // Palettes are added to the base's one by one, rather than replacing them all
+    let mut palettes = match merged.remove("palettes") {
+        Some(toml::Value::Table(palettes)) => palettes,
+        _ => Table::new(),
+    };
+    palettes.extend(own_palettes);
+    resolve_shorthands(&mut table, &registry(&palettes, path)?)
+        .map_err(|error| error.in_file(path))?;
+    // Each other key that is set replaces the base's value as a whole
+    merged.extend(table);
+    merged.insert(String::from("palettes"), toml::Value::Table(palettes));
+    Ok(merged)
+}
+
+/// Reads and parses a file to a table, in the format picked from its extension or contents
+fn read_table(path: &str) -> Result<Table, NebulaeError> {
+    let data = if path == "-" {
+        let mut data = String::new();
+        io::stdin()
+            .read_to_string(&mut data)
+            .map_err(|source| NebulaeError::Read {
+                path: String::from("standard input"),
+                source,
+            })?;
+        data
+    } else {
+        fs::read_to_string(path).map_err(|source| NebulaeError::Read {
+            path: path.to_string(),
+            source,
+        })?
+    };
+    let format = ConfigFormat::from_path(path).unwrap_or_else(|| ConfigFormat::sniff(&data));
+    match format.parse(&data, path)? {
+        toml::Value::Table(table) => Ok(table),
+        _ => Err(invalid(path, format!("its {format} doesn't hold a table"))),
+    }
+}
+
+/// Builds a registry of the built-in [`Palette`]s and those defined in a table of the file at
+/// `path`, by name
+fn registry(palettes: &Table, path: &str) -> Result<PaletteRegistry, NebulaeError> {
+    let mut registry = PaletteRegistry::default();
+    for (name, palette) in palettes {
+        let palette = Palette::deserialize(palette.clone())
+            .map_err(|error| invalid(path, format!("palette `{name}`: {error}")))?;
+        registry
+            .add(name, palette)
+            .map_err(|error| error.in_file(path))?;
+    }
+    Ok(registry)
+}
+
+/// Gives the error of a configuration file at `path` that doesn't describe valid settings
+fn invalid(path: &str, message: impl Into<String>) -> NebulaeError {
+    NebulaeError::Config {
+        path: path.to_string(),
+        location: None,
+        message: message.into(),
+    }
+}
+
+/// Removes null values from JSON objects, recursively
+fn strip_nulls(json: &mut serde_json::Value) {
+    match json {
+        serde_json::Value::Object(object) => {
+            object.retain(|_, value| !value.is_null());
+            object.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(array) => array.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Brings a configuration table written in any older version of the format up to date
+fn migrate(table: &mut Table) -> Result<Vec<String>, String> {
+    // Files written before the format was versioned have no `version` key
+    let version = match table.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or("`version` must be a positive integer")?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "its version ({version}) is newer than the latest supported ({CONFIG_VERSION}), please update nebulae"
+        ));
+    }
+    let mut notes = Vec::new();
+    for migration in &MIGRATIONS[version as usize..] {
+        notes.extend(migration(table)?);
+    }
+    table.insert(String::from("version"), i64::from(CONFIG_VERSION).into());
+    Ok(notes)
+}
+
+/// Version 0 had a single `size` for square images, replaced by `width` and `height`
+fn migrate_v0(table: &mut Table) -> Result<Vec<String>, String> {
+    split_size(table)?;
+    Ok(vec![])
+}
+
+/// Expands keys that stand for other keys, which take precedence when set explicitly
+fn resolve_shorthands(table: &mut Table, palettes: &PaletteRegistry) -> Result<(), NebulaeError> {
+    if split_size(table).map_err(NebulaeError::Invalid)? {
+        warn("`size` is deprecated, use `width` and `height` instead");
+    }
+    if let Some(name) = table.remove("location") {
+        let name = name
+            .as_str()
+            .ok_or_else(|| NebulaeError::Invalid(String::from("`location` must be a string")))?;
+        let location = Location::find(name)?;
+        table
+            .entry("center_re")
+            .or_insert(location.center_re.into());
+        table
+            .entry("center_im")
+            .or_insert(location.center_im.into());
+        table.entry("zoom").or_insert(location.zoom.into());
+    }
+    if let Some(name) = table.remove("palette") {
+        let name = name
+            .as_str()
+            .ok_or_else(|| NebulaeError::Invalid(String::from("`palette` must be a string")))?;
+        let limits = palettes.find(name)?.limits();
+        table
+            .entry("limits")
+            .or_insert_with(|| limits.into_iter().map(i64::from).collect::<Vec<_>>().into());
+    }
+    Ok(())
+}
+
+/// Makes the `output`, `mask`, `background` and `noise_map` paths of a configuration table and its
+/// jobs relative to a `directory`
+fn resolve_paths(table: &mut Table, directory: &Path) {
+    let resolve = |table: &mut Table| {
+        for key in ["output", "mask", "background", "noise_map"] {
+            if let Some(toml::Value::String(path)) = table.get_mut(key) {
+                *path = directory.join(&*path).to_string_lossy().into_owned();
+            }
+        }
+    };
+    resolve(table);
+    if let Some(toml::Value::Array(jobs)) = table.get_mut("jobs") {
+        jobs.iter_mut()
+            .filter_map(toml::Value::as_table_mut)
+            .for_each(resolve);
+    }
+}
+
+/// Replaces `size` with `width` and `height`, returning whether there was one
+/// The size is checked before it is split, so that a bad one is reported as the key it was given as
+fn split_size(table: &mut Table) -> Result<bool, String> {
+    let Some(size) = table.remove("size") else {
+        return Ok(false);
+    };
+    if size
+        .as_integer()
+        .is_none_or(|size| u32::try_from(size).is_err())
+    {
+        return Err(format!(
+            "`size` ({size}) must be a number of pixels, such as 512"
+        ));
+    }
+    table.entry("width").or_insert_with(|| size.clone());
+    table.entry("height").or_insert(size);
+    Ok(true)
+}
+
+/// Prints a warning to standard error
+pub fn warn(message: impl fmt::Display) {
+    eprintln!("{} {message}", style("Warning:").yellow().bold());
+}
+
+/// Gets the names of the fields of a struct, as declared to serde
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    /// A deserializer that only records the fields it is asked for
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de, 'a> serde::Deserializer<'de> for FieldNames<'a> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: serde::de::Visitor<'de>>(
+            self,
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: serde::de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(serde::de::Error::custom("only looking for field names"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+            ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Finds the candidate closest to a misspelt `key`, if any is close enough to be a likely typo
+fn closest_match<'a>(key: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let leaf = key.rsplit('.').next().unwrap_or(key);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(leaf, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= (leaf.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Edit distance between two strings, counting insertions, deletions, substitutions, and
+/// transpositions of adjacent characters (optimal string alignment distance)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// Path of a file of some `contents` in a directory of this test run, named `name`
+    fn file(name: &str, contents: &str) -> String {
+        let directory = std::env::temp_dir().join(format!("nebulae-config-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn missing_files_fail_to_read() {
+        let error = load_jobs("no/such/config.toml", false).err().unwrap();
+        assert!(
+            matches!(error, NebulaeError::Read { ref path, .. } if path == "no/such/config.toml")
+        );
+    }
+
+    #[test]
+    fn malformed_files_tell_where() {
+        let path = file("malformed.toml", "width = 512\nheight = = 3\n");
+        match load_jobs(&path, false).err().unwrap() {
+            NebulaeError::Config {
+                path: at,
+                location: Some((line, _)),
+                ..
+            } => assert_eq!((at, line), (path, 2)),
+            error => panic!("{error}"),
+        }
+        let path = file("mistyped.toml", "width = \"wide\"\n");
+        assert!(matches!(
+            load_jobs(&path, false),
+            Err(NebulaeError::Config { .. })
+        ));
+    }
+
+    #[test]
+    fn sizes_split_into_width_and_height() {
+        let mut old = table("size = 300\nheight = 200");
+        migrate(&mut old).unwrap();
+        assert_eq!(
+            old.get("width").and_then(toml::Value::as_integer),
+            Some(300)
+        );
+        assert_eq!(
+            old.get("height").and_then(toml::Value::as_integer),
+            Some(200)
+        );
+        assert!(old.get("size").is_none());
+
+        let mut current = table("version = 1\nsize = 64");
+        resolve_shorthands(&mut current, &PaletteRegistry::default()).unwrap();
+        assert_eq!(
+            current.get("height").and_then(toml::Value::as_integer),
+            Some(64)
+        );
+    }
+
+    #[test]
+    fn bad_sizes_are_reported_as_size() {
+        for size in ["\"big\"", "-1", "1.5", "4294967296"] {
+            let error = migrate(&mut table(&format!("size = {size}"))).unwrap_err();
+            assert!(error.starts_with("`size`"), "{error}");
+
+            let mut current = table(&format!("version = 1\nsize = {size}"));
+            let error = resolve_shorthands(&mut current, &PaletteRegistry::default())
+                .unwrap_err()
+                .to_string();
+            assert!(error.contains("`size`"), "{error}");
+            assert!(
+                !error.contains("width") && !error.contains("height"),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_the_closest_name() {
+        let path = file("misspelt.toml", "cruve = 2.0\nwidht = 64\n");
+        let message = load_jobs(&path, true).err().unwrap().to_string();
+        assert!(
+            message.contains("`cruve` (did you mean `curve`?)"),
+            "{message}"
+        );
+        assert!(
+            message.contains("`widht` (did you mean `width`?)"),
+            "{message}"
+        );
+        // Without `strict` they are only warned about
+        assert_eq!(load_jobs(&path, false).unwrap().len(), 1);
+
+        let path = file(
+            "misspelt-job.toml",
+            "cruve = 2.0\n[[jobs]]\nzom = 2.0\n[[jobs]]\nwidth = 64\n",
+        );
+        let message = load_jobs(&path, true).err().unwrap().to_string();
+        assert!(message.contains("`cruve`"), "{message}");
+        assert!(
+            message.contains("`jobs[0].zom` (did you mean `zoom`?)"),
+            "{message}"
+        );
+
+        assert_eq!(
+            closest_match("nothing_like_a_key", &["curve", "zoom"]),
+            None
+        );
+    }
+
+    #[test]
+    fn unversioned_files_are_migrated() {
+        let path = file("unversioned.toml", "size = 96\nzoom = 2.0\n");
+        let (settings, _) = load_jobs(&path, true).unwrap().remove(0);
+        assert_eq!((settings.width, settings.height), (96, 96));
+        assert_eq!(settings.version, CONFIG_VERSION);
+        assert_eq!(settings.zoom, 2.0);
+
+        let path = file(
+            "future.toml",
+            &format!("version = {}\n", CONFIG_VERSION + 1),
+        );
+        let message = load_jobs(&path, false).err().unwrap().to_string();
+        assert!(message.contains("newer"), "{message}");
+
+        let written = RenderSettings::default()
+            .serialize(ConfigFormat::Toml)
+            .unwrap();
+        assert_eq!(
+            table(&written)
+                .get("version")
+                .and_then(toml::Value::as_integer),
+            Some(i64::from(CONFIG_VERSION))
+        );
+    }
+
+    #[test]
+    fn json_files_load_like_toml_ones() {
+        let settings = RenderSettings {
+            width: 80,
+            zoom: 3.0,
+            seed: Some(5),
+            ..RenderSettings::default()
+        };
+        let load = |name: &str, format: ConfigFormat| {
+            let path = file(name, &settings.serialize(format).unwrap());
+            let (loaded, _) = load_jobs(&path, true).unwrap().remove(0);
+            loaded.serialize(ConfigFormat::Toml).unwrap()
+        };
+        let toml = load("settings.toml", ConfigFormat::Toml);
+        assert_eq!(toml, settings.serialize(ConfigFormat::Toml).unwrap());
+        assert_eq!(load("settings.json", ConfigFormat::Json), toml);
+        // Without a known extension, the format is told from the contents
+        assert_eq!(load("settings-json.conf", ConfigFormat::Json), toml);
+        assert_eq!(load("settings-toml.conf", ConfigFormat::Toml), toml);
+
+        let path = file("malformed.json", "{\n  \"width\": ,\n}\n");
+        match load_jobs(&path, false).err().unwrap() {
+            NebulaeError::Config {
+                location: Some((line, _)),
+                message,
+                ..
+            } => {
+                assert_eq!(line, 2);
+                assert!(message.starts_with("not valid JSON"), "{message}");
+            }
+            error => panic!("{error}"),
+        }
+        let path = file("malformed-toml.conf", "width = = 3\n");
+        let message = load_jobs(&path, false).err().unwrap().to_string();
+        assert!(message.contains("not valid TOML"), "{message}");
+    }
+
+    #[test]
+    fn children_override_only_what_they_set() {
+        let base = file(
+            "base.toml",
+            "width = 64\nheight = 48\ncurve = 2.0\npalette = \"cyber-pink\"\n",
+        );
+        let child = file("child.toml", "extends = \"base.toml\"\nsize = 8192\n");
+        let (base, _) = load_jobs(&base, true).unwrap().remove(0);
+        let (child, _) = load_jobs(&child, true).unwrap().remove(0);
+        assert_eq!((child.width, child.height), (8192, 8192));
+        let expected = RenderSettings {
+            width: 8192,
+            height: 8192,
+            ..base
+        };
+        assert_eq!(
+            child.serialize(ConfigFormat::Toml).unwrap(),
+            expected.serialize(ConfigFormat::Toml).unwrap()
+        );
+    }
+
+    #[test]
+    fn circular_inheritance_fails() {
+        file("loop-b.toml", "extends = \"loop-a.toml\"\n");
+        let path = file("loop-a.toml", "extends = \"loop-b.toml\"\n");
+        let message = load_jobs(&path, false).err().unwrap().to_string();
+        assert!(message.contains("circular inheritance"), "{message}");
+
+        let path = file("self.toml", "extends = \"self.toml\"\n");
+        assert!(load_jobs(&path, false).is_err());
+
+        file("chain-0.toml", "width = 64\n");
+        for depth in 1..=MAX_EXTENDS_DEPTH + 1 {
+            let base = format!("extends = \"chain-{}.toml\"\n", depth - 1);
+            let path = file(&format!("chain-{depth}.toml"), &base);
+            let loaded = load_jobs(&path, false);
+            assert_eq!(loaded.is_ok(), depth <= MAX_EXTENDS_DEPTH, "{depth}");
+        }
+    }
+
+    #[test]
+    fn jobs_override_the_top_level_settings() {
+        let path = file(
+            "jobs.toml",
+            "width = 64\noutput = \"shared.png\"\n\
+             [[jobs]]\nzoom = 2.0\n\
+             [[jobs]]\nwidth = 32\noutput = \"own.png\"\n",
+        );
+        let jobs = load_jobs(&path, true).unwrap();
+        assert_eq!(jobs.len(), 2);
+        let directory = Path::new(&path).parent().unwrap();
+        let (first, output) = &jobs[0];
+        assert_eq!((first.width, first.zoom), (64, 2.0));
+        assert_eq!(
+            output.as_deref().map(Path::new),
+            Some(&*directory.join("shared.png"))
+        );
+        let (second, output) = &jobs[1];
+        assert_eq!(
+            (second.width, second.zoom),
+            (32, RenderSettings::default().zoom)
+        );
+        assert_eq!(
+            output.as_deref().map(Path::new),
+            Some(&*directory.join("own.png"))
+        );
+
+        let path = file("single.toml", "width = 64\n");
+        let jobs = load_jobs(&path, true).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert!(jobs[0].1.is_none());
+
+        let path = file("bad-jobs.toml", "jobs = [1, 2]\n");
+        assert!(load_jobs(&path, false).is_err());
+    }
+
+    #[test]
+    fn palettes_expand_into_limits() {
+        let path = file("palette.toml", "palette = \"cyber-pink\"\n");
+        let (settings, _) = load_jobs(&path, true).unwrap().remove(0);
+        let builtin = PaletteRegistry::default();
+        assert_eq!(
+            settings.limits,
+            builtin.find("cyber-pink").unwrap().limits()
+        );
+
+        let path = file(
+            "own-palette.toml",
+            "palette = \"mine\"\n\
+             [palettes.mine]\nchannels = [0, 0, 1]\nintensity = [10, 20, 30]\ndefinition = 2\n",
+        );
+        let (settings, _) = load_jobs(&path, true).unwrap().remove(0);
+        assert_eq!(settings.limits, [20, 20, 40]);
+
+        // Explicit limits win over the palette's
+        let path = file(
+            "palette-limits.toml",
+            "palette = \"nebulous\"\nlimits = [1, 2, 3]\n",
+        );
+        let (settings, _) = load_jobs(&path, true).unwrap().remove(0);
+        assert_eq!(settings.limits, [1, 2, 3]);
+
+        let path = file("unknown-palette.toml", "palette = \"beige\"\n");
+        let message = load_jobs(&path, false).err().unwrap().to_string();
+        assert!(message.contains("`beige`"), "{message}");
+        assert!(message.contains("nebulous, blue-ish"), "{message}");
+
+        let path = file(
+            "bad-palette.toml",
+            "[palettes.bad]\nchannels = [0, 1, 3]\nintensity = [1, 1, 1]\ndefinition = 1\n",
+        );
+        assert!(load_jobs(&path, false).is_err());
+    }
+
+    #[test]
+    fn locations_set_the_view_that_is_not_given() {
+        let location = Location::find("north-bulb").unwrap();
+        let path = file("location.toml", "location = \"north-bulb\"\nzoom = 3.0\n");
+        let (settings, _) = load_jobs(&path, true).unwrap().remove(0);
+        assert_eq!(
+            (settings.center_re, settings.center_im, settings.zoom),
+            (location.center_re, location.center_im, 3.0)
+        );
+
+        let path = file("unknown-location.toml", "location = \"atlantis\"\n");
+        let message = load_jobs(&path, false).err().unwrap().to_string();
+        assert!(message.contains("`atlantis`"), "{message}");
+        assert!(message.contains("north-bulb"), "{message}");
+    }
+
+    #[test]
+    fn files_leave_the_defaults_of_what_they_dont_set() {
+        let shown = |settings: &RenderSettings| settings.serialize(ConfigFormat::Toml).unwrap();
+        let path = file("empty.toml", "");
+        let (settings, output) = load_jobs(&path, true).unwrap().remove(0);
+        assert_eq!(shown(&settings), shown(&RenderSettings::default()));
+        assert!(output.is_none());
+
+        let path = file("zoomed.toml", "zoom = 3.0\n");
+        let (settings, _) = load_jobs(&path, true).unwrap().remove(0);
+        let zoomed = RenderSettings {
+            zoom: 3.0,
+            ..RenderSettings::default()
+        };
+        assert_eq!(shown(&settings), shown(&zoomed));
+    }
+}
@@ -0,0 +1,169 @@
+//! Denoising of the counts of an image before they are tonemapped, which smooths the speckles that
+//! too few points leave in sparse parts of the image
+//!
+//! Each channel is filtered on its own, by either a Gaussian blur or a 3×3 median, and each pixel
+//! is then blended with its filtered value in proportion to how noisy it is. Counts of points are
+//! about Poisson distributed, so the relative variance of a pixel that took `n` points is about
+//! `1 / n`, and a pixel takes `s / (s + n)` of its filtered value for a `strength` `s`: sparse
+//! pixels are mostly smoothed, while bright ones, which have converged, keep their own value. `n`
+//! is measured on the filtered image, across every channel, as the pixel's own count is as noisy
+//! as what it is meant to tell about.
+//!
+//! Blended values aren't whole counts, so they are returned as multiples of `1 / SCALE`, which the
+//! tonemapping doesn't tell apart from counts as it divides them by their maximum.
+
+use crate::render_settings::Denoise;
+use rayon::prelude::*;
+
+/// Number of steps that each count is split into, which keeps the fractions of blended values
+const SCALE: f64 = 256.0;
+
+/// Standard deviation of the Gaussian blur, in pixels
+const SIGMA: f64 = 1.0;
+
+/// Number of pixels on each side of the center of the Gaussian blur's kernel
+const RADIUS: usize = 3;
+
+/// Denoise the `data` of an image of `width` × `height` pixels of some `channels` with a
+/// `filter`, and a `strength`
+/// that is the number of points at which a pixel takes half of its filtered value
+/// Returns the denoised data, in multiples of `1 / SCALE` of a count (the data as it is without a
+/// filter)
+pub fn denoise(
+    data: &[u64],
+    width: u32,
+    height: u32,
+    channels: u32,
+    filter: Denoise,
+    strength: f64,
+) -> Vec<u64> {
+    let filter = match filter {
+        Denoise::None => return data.to_vec(),
+        Denoise::Gaussian => blur,
+        Denoise::Median => median,
+    };
+    let values: Vec<f64> = data.par_iter().map(|&value| value as f64).collect();
+    let (width, height, channels) = (width as usize, height as usize, channels as usize);
+    let filtered = filter(&values, width, height, channels);
+    values
+        .par_chunks_exact(channels)
+        .zip(filtered.par_chunks_exact(channels))
+        .flat_map_iter(|(pixel, filtered)| {
+            let points: f64 = filtered.iter().sum();
+            let share = strength / (strength + points);
+            pixel.iter().zip(filtered).map(move |(&value, &filtered)| {
+                ((share * filtered + (1.0 - share) * value) * SCALE).round() as u64
+            })
+        })
+        .collect()
+}
+
+/// Blur each channel of the `values` of an image with a Gaussian kernel, along its rows and then
+/// along its columns, repeating the pixels at its edges
+fn blur(values: &[f64], width: usize, height: usize, channels: usize) -> Vec<f64> {
+    let weights: Vec<f64> = (0..=2 * RADIUS)
+        .map(|k| {
+            let offset = k as f64 - RADIUS as f64;
+            (-offset * offset / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect();
+    let total: f64 = weights.iter().sum();
+    let weights: Vec<f64> = weights.iter().map(|weight| weight / total).collect();
+    let row_length = width * channels;
+    let neighbour =
+        |at: usize, k: usize, length: usize| (at + k).saturating_sub(RADIUS).min(length - 1);
+    let mut across = vec![0.0; values.len()];
+    across
+        .par_chunks_exact_mut(row_length)
+        .zip(values.par_chunks_exact(row_length))
+        .for_each(|(target, source)| {
+            for x in 0..width {
+                for channel in 0..channels {
+                    target[x * channels + channel] = weights
+                        .iter()
+                        .enumerate()
+                        .map(|(k, weight)| {
+                            weight * source[neighbour(x, k, width) * channels + channel]
+                        })
+                        .sum();
+                }
+            }
+        });
+    let mut blurred = vec![0.0; values.len()];
+    blurred
+        .par_chunks_exact_mut(row_length)
+        .enumerate()
+        .for_each(|(y, target)| {
+            for (k, weight) in weights.iter().enumerate() {
+                let source = &across[neighbour(y, k, height) * row_length..][..row_length];
+                for (sum, value) in target.iter_mut().zip(source) {
+                    *sum += weight * value;
+                }
+            }
+        });
+    blurred
+}
+
+/// Replace each channel of each pixel of the `values` of an image with the median of those of
+/// the 3×3 pixels around it, repeating the pixels at its edges
+fn median(values: &[f64], width: usize, height: usize, channels: usize) -> Vec<f64> {
+    let row_length = width * channels;
+    let mut filtered = vec![0.0; values.len()];
+    filtered
+        .par_chunks_exact_mut(row_length)
+        .enumerate()
+        .for_each(|(y, target)| {
+            let rows = [y.saturating_sub(1), y, (y + 1).min(height - 1)];
+            for x in 0..width {
+                let columns = [x.saturating_sub(1), x, (x + 1).min(width - 1)];
+                for channel in 0..channels {
+                    let mut window = [0.0; 9];
+                    for (i, &row) in rows.iter().enumerate() {
+                        for (j, &column) in columns.iter().enumerate() {
+                            window[i * 3 + j] =
+                                values[row * row_length + column * channels + channel];
+                        }
+                    }
+                    let (_, middle, _) = window.select_nth_unstable_by(4, f64::total_cmp);
+                    target[x * channels + channel] = *middle;
+                }
+            }
+        });
+    filtered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let data = vec![40; 5 * 4 * 2];
+        assert_eq!(denoise(&data, 5, 4, 2, Denoise::None, 10.0), data);
+        for filter in [Denoise::Gaussian, Denoise::Median] {
+            let denoised = denoise(&data, 5, 4, 2, filter, 10.0);
+            assert!(denoised.iter().all(|&value| value == 40 * SCALE as u64));
+        }
+    }
+
+    #[test]
+    fn sparse_pixels_take_their_filtered_value() {
+        // A single point amid nothing: the median removes it, and the blur spreads it around
+        let mut data = vec![0; 9];
+        data[4] = 1;
+        assert_eq!(denoise(&data, 3, 3, 1, Denoise::Median, 1e9), [0; 9]);
+        let blurred = denoise(&data, 3, 3, 1, Denoise::Gaussian, 1e9);
+        assert!(blurred[4] < SCALE as u64);
+        assert!(blurred.iter().all(|&value| value > 0));
+    }
+
+    #[test]
+    fn bright_pixels_keep_their_own_value() {
+        // A single point on one channel, in pixels that are bright on the other
+        let mut data = [0, 1_000_000].repeat(9);
+        data[8] = 1;
+        let denoised = denoise(&data, 3, 3, 2, Denoise::Median, 1.0);
+        assert_eq!(denoised[8], SCALE as u64);
+        assert_eq!(denoised[9], 1_000_000 * SCALE as u64);
+    }
+}
@@ -0,0 +1,108 @@
+//! Double-double arithmetic, where a number is the unevaluated sum of two `f64`s, which gives
+//! about 32 significant digits instead of 16
+//!
+//! Based on the error-free transformations of Dekker and Knuth, as described by Hida, Li and Bailey
+//! in "Library for Double-Double and Quad-Double Arithmetic"
+
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// A number with about twice the precision of an `f64`
+#[derive(Clone, Copy)]
+pub struct DoubleDouble {
+    /// The `f64` nearest to the number
+    hi: f64,
+    /// What is left over, much smaller than `hi`
+    lo: f64,
+}
+
+impl DoubleDouble {
+    /// Gives the `f64` nearest to the number
+    pub fn to_f64(self) -> f64 {
+        self.hi
+    }
+}
+
+impl From<f64> for DoubleDouble {
+    fn from(value: f64) -> Self {
+        DoubleDouble { hi: value, lo: 0.0 }
+    }
+}
+
+/// Sum of two `f64`s, and the rounding error of that sum
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    let b_virtual = sum - a;
+    (sum, (a - (sum - b_virtual)) + (b - b_virtual))
+}
+
+/// Like [`two_sum`], only valid when `|a| >= |b|`
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    (sum, b - (sum - a))
+}
+
+/// Product of two `f64`s, and the rounding error of that product
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let product = a * b;
+    (product, a.mul_add(b, -product))
+}
+
+impl Add for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn add(self, other: DoubleDouble) -> DoubleDouble {
+        let (sum, error) = two_sum(self.hi, other.hi);
+        let (low_sum, low_error) = two_sum(self.lo, other.lo);
+        let (sum, error) = quick_two_sum(sum, error + low_sum);
+        let (hi, lo) = quick_two_sum(sum, error + low_error);
+        DoubleDouble { hi, lo }
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn neg(self) -> DoubleDouble {
+        DoubleDouble {
+            hi: -self.hi,
+            lo: -self.lo,
+        }
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn sub(self, other: DoubleDouble) -> DoubleDouble {
+        self + -other
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn mul(self, other: DoubleDouble) -> DoubleDouble {
+        let (product, error) = two_product(self.hi, other.hi);
+        let (hi, lo) = quick_two_sum(product, error + (self.hi * other.lo + self.lo * other.hi));
+        DoubleDouble { hi, lo }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_parts_are_kept_apart() {
+        let one = DoubleDouble::from(1.0);
+        let tiny = DoubleDouble::from(1e-20);
+        assert_eq!(((one + tiny) - one).to_f64(), 1e-20);
+        assert_eq!((one + tiny).to_f64(), 1.0);
+        // (1 + 2^-40)² keeps its last term, which falls below the precision of an f64
+        let near_one = one + DoubleDouble::from(2f64.powi(-40));
+        let square = near_one * near_one;
+        let rest = square - one - DoubleDouble::from(2f64.powi(-39));
+        assert_eq!(rest.to_f64(), 2f64.powi(-80));
+        assert_eq!((-square + square).to_f64(), 0.0);
+    }
+}
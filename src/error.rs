@@ -0,0 +1,116 @@
+//! Errors of loading settings, rendering, and writing images, which tell what went wrong and with
+//! which file in a single line
+
+use std::io;
+
+/// Anything that stops `nebulae` from loading its settings, rendering, or writing an image
+#[derive(Debug, thiserror::Error)]
+pub enum NebulaeError {
+    /// A configuration file can't be parsed, or doesn't describe valid settings
+    #[error("Invalid configuration in {path}{}: {message}", at(.location))]
+    Config {
+        /// Path of the file, `-` for standard input, along with the job it describes, if any
+        path: String,
+        /// Line and column of the error in the file, from 1, when the parser knows them
+        location: Option<(usize, usize)>,
+        /// What is wrong with the file
+        message: String,
+    },
+    /// A file can't be read
+    #[error("Could not read {path}: {source}")]
+    Read {
+        /// Path of the file
+        path: String,
+        /// Error from the system
+        #[source]
+        source: io::Error,
+    },
+    /// A file can't be written
+    #[error("Could not write {path}: {source}")]
+    Write {
+        /// Path of the file
+        path: String,
+        /// Error from the system
+        #[source]
+        source: io::Error,
+    },
+    /// An image can't be encoded into its file
+    #[error("Could not encode {path}: {source}")]
+    Encoding {
+        /// Path of the image
+        path: String,
+        /// Error from the encoder
+        #[source]
+        source: png::EncodingError,
+    },
+    /// A checkpoint file can't be resumed from
+    #[error("Could not resume from {path}: {message}")]
+    Checkpoint {
+        /// Path of the checkpoint
+        path: String,
+        /// What is wrong with the checkpoint
+        message: String,
+    },
+    /// The terminal can't be used to ask the user for settings
+    #[error("Could not use the terminal: {0}")]
+    Terminal(#[source] io::Error),
+    /// Settings, arguments, or images that can't be used together
+    #[error("{0}")]
+    Invalid(String),
+    /// The user canceled
+    #[error("Canceled by the user")]
+    Cancelled,
+}
+
+impl NebulaeError {
+    /// Attributes an error about some settings to the configuration file at `path` they come from
+    pub(crate) fn in_file(self, path: &str) -> NebulaeError {
+        match self {
+            NebulaeError::Invalid(message) => NebulaeError::Config {
+                path: path.to_string(),
+                location: None,
+                message,
+            },
+            error => error,
+        }
+    }
+}
+
+/// Where an error is in a configuration file, when it is known
+fn at(location: &Option<(usize, usize)>) -> String {
+    match location {
+        Some((line, column)) => format!(" at line {line}, column {column}"),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn errors_tell_what_went_wrong_in_a_line() {
+        let config = |location| NebulaeError::Config {
+            path: "jobs.toml".to_string(),
+            location,
+            message: "unknown key".to_string(),
+        };
+        assert_eq!(
+            config(Some((3, 7))).to_string(),
+            "Invalid configuration in jobs.toml at line 3, column 7: unknown key"
+        );
+        assert_eq!(
+            config(None).to_string(),
+            "Invalid configuration in jobs.toml: unknown key"
+        );
+        let read = NebulaeError::Read {
+            path: "in.png".to_string(),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        };
+        assert!(read.to_string().starts_with("Could not read in.png: "));
+        assert!(read.source().is_some());
+        assert!(NebulaeError::Invalid("no".to_string()).source().is_none());
+        assert_eq!(NebulaeError::Cancelled.to_string(), "Canceled by the user");
+    }
+}
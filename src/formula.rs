@@ -0,0 +1,451 @@
+//! A small expression language for custom iterated functions of `z` and `c`, such as
+//! `z^2 + c/z` or `sin(z) + c`
+
+use crate::mandelbrot::Complex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An iterated function, parsed from its source text
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct Formula {
+    source: String,
+    expression: Expression,
+}
+
+impl Formula {
+    /// Computes the next value of `z`
+    pub fn evaluate(&self, z: Complex, c: Complex) -> Complex {
+        self.expression.evaluate(z, c)
+    }
+}
+
+impl std::str::FromStr for Formula {
+    type Err = FormulaError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            next: 0,
+            end: source.chars().count(),
+        };
+        let expression = parser.sum()?;
+        match parser.peek() {
+            None => Ok(Formula {
+                source: source.to_string(),
+                expression,
+            }),
+            Some((position, token)) => Err(FormulaError {
+                position,
+                message: format!("unexpected {token}"),
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for Formula {
+    type Error = FormulaError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+impl From<Formula> for String {
+    fn from(formula: Formula) -> Self {
+        formula.source
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// A mistake in the source text of a [`Formula`]
+#[derive(Debug)]
+pub struct FormulaError {
+    /// Character at which the mistake was found, starting at 1
+    position: usize,
+    message: String,
+}
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid formula at character {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl std::error::Error for FormulaError {}
+
+/// Functions that can be called in a [`Formula`]
+#[derive(Clone, Copy)]
+enum Function {
+    /// Absolute values of both parts
+    Abs,
+    Conj,
+    Re,
+    Im,
+    Exp,
+    Log,
+    Sqrt,
+    Sin,
+    Cos,
+}
+
+impl Function {
+    const NAMES: [(&'static str, Function); 9] = [
+        ("abs", Function::Abs),
+        ("conj", Function::Conj),
+        ("re", Function::Re),
+        ("im", Function::Im),
+        ("exp", Function::Exp),
+        ("log", Function::Log),
+        ("sqrt", Function::Sqrt),
+        ("sin", Function::Sin),
+        ("cos", Function::Cos),
+    ];
+
+    fn apply(self, w: Complex) -> Complex {
+        match self {
+            Function::Abs => Complex {
+                re: w.re.abs(),
+                im: w.im.abs(),
+            },
+            Function::Conj => w.conj(),
+            Function::Re => Complex { re: w.re, im: 0.0 },
+            Function::Im => Complex { re: w.im, im: 0.0 },
+            Function::Exp => w.exp(),
+            Function::Log => w.log(),
+            Function::Sqrt => w.pow(0.5),
+            Function::Sin => Complex {
+                re: w.re.sin() * w.im.cosh(),
+                im: w.re.cos() * w.im.sinh(),
+            },
+            Function::Cos => Complex {
+                re: w.re.cos() * w.im.cosh(),
+                im: -w.re.sin() * w.im.sinh(),
+            },
+        }
+    }
+}
+
+/// Syntax tree of a [`Formula`]
+#[derive(Clone)]
+enum Expression {
+    Z,
+    C,
+    Constant(Complex),
+    Negate(Box<Expression>),
+    Add(Box<Expression>, Box<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+    Multiply(Box<Expression>, Box<Expression>),
+    Divide(Box<Expression>, Box<Expression>),
+    Power(Box<Expression>, Box<Expression>),
+    Call(Function, Box<Expression>),
+}
+
+impl Expression {
+    fn evaluate(&self, z: Complex, c: Complex) -> Complex {
+        match self {
+            Expression::Z => z,
+            Expression::C => c,
+            Expression::Constant(value) => *value,
+            Expression::Negate(a) => -a.evaluate(z, c),
+            Expression::Add(a, b) => a.evaluate(z, c) + b.evaluate(z, c),
+            Expression::Subtract(a, b) => a.evaluate(z, c) - b.evaluate(z, c),
+            Expression::Multiply(a, b) => a.evaluate(z, c) * b.evaluate(z, c),
+            Expression::Divide(a, b) => a.evaluate(z, c) / b.evaluate(z, c),
+            Expression::Power(a, b) => {
+                let (a, b) = (a.evaluate(z, c), b.evaluate(z, c));
+                if b.im == 0.0 {
+                    a.pow(b.re)
+                } else {
+                    (b * a.log()).exp()
+                }
+            }
+            Expression::Call(function, a) => function.apply(a.evaluate(z, c)),
+        }
+    }
+}
+
+/// Lexical elements of a [`Formula`]
+#[derive(Clone)]
+enum Token {
+    Number(f64),
+    Imaginary(f64),
+    Name(String),
+    Symbol(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number `{value}`"),
+            Token::Imaginary(value) => write!(f, "number `{value}i`"),
+            Token::Name(name) => write!(f, "`{name}`"),
+            Token::Symbol(symbol) => write!(f, "`{symbol}`"),
+        }
+    }
+}
+
+/// Splits the source text of a [`Formula`] into tokens, with the position of each
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, FormulaError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let start = index;
+        let character = chars[index];
+        if character.is_whitespace() {
+            index += 1;
+            continue;
+        }
+        if character.is_ascii_digit() || character == '.' {
+            while index < chars.len()
+                && (chars[index].is_ascii_digit()
+                    || chars[index] == '.'
+                    || (matches!(chars[index], 'e' | 'E')
+                        && chars.get(index + 1).is_some_and(|next| {
+                            next.is_ascii_digit() || *next == '-' || *next == '+'
+                        }))
+                    || (matches!(chars[index], '-' | '+') && matches!(chars[index - 1], 'e' | 'E')))
+            {
+                index += 1;
+            }
+            let text: String = chars[start..index].iter().collect();
+            let value = text.parse().map_err(|_| FormulaError {
+                position: start + 1,
+                message: format!("invalid number `{text}`"),
+            })?;
+            // A number directly followed by `i` is imaginary
+            if chars.get(index) == Some(&'i')
+                && !chars
+                    .get(index + 1)
+                    .is_some_and(|next| next.is_alphanumeric())
+            {
+                index += 1;
+                tokens.push((start + 1, Token::Imaginary(value)));
+            } else {
+                tokens.push((start + 1, Token::Number(value)));
+            }
+        } else if character.is_alphabetic() {
+            while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '_') {
+                index += 1;
+            }
+            tokens.push((start + 1, Token::Name(chars[start..index].iter().collect())));
+        } else if "+-*/^()".contains(character) {
+            index += 1;
+            tokens.push((start + 1, Token::Symbol(character)));
+        } else {
+            return Err(FormulaError {
+                position: start + 1,
+                message: format!("unexpected character `{character}`"),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser of [`Formula`]s, with the usual precedence of operators
+/// `^` binds tightest and is right-associative, so `-z^2` is `-(z^2)`
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Length of the source text, for errors at its end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(usize, Token)> {
+        self.tokens.get(self.next).cloned()
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if let Some((_, Token::Symbol(next))) = self.peek() {
+            if next == symbol {
+                self.next += 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), FormulaError> {
+        if self.eat(symbol) {
+            return Ok(());
+        }
+        Err(match self.peek() {
+            Some((position, token)) => FormulaError {
+                position,
+                message: format!("expected `{symbol}`, found {token}"),
+            },
+            None => FormulaError {
+                position: self.end + 1,
+                message: format!("expected `{symbol}` at the end"),
+            },
+        })
+    }
+
+    /// Terms separated by `+` and `-`
+    fn sum(&mut self) -> Result<Expression, FormulaError> {
+        let mut expression = self.product()?;
+        loop {
+            if self.eat('+') {
+                expression = Expression::Add(Box::new(expression), Box::new(self.product()?));
+            } else if self.eat('-') {
+                expression = Expression::Subtract(Box::new(expression), Box::new(self.product()?));
+            } else {
+                return Ok(expression);
+            }
+        }
+    }
+
+    /// Factors separated by `*` and `/`
+    fn product(&mut self) -> Result<Expression, FormulaError> {
+        let mut expression = self.unary()?;
+        loop {
+            if self.eat('*') {
+                expression = Expression::Multiply(Box::new(expression), Box::new(self.unary()?));
+            } else if self.eat('/') {
+                expression = Expression::Divide(Box::new(expression), Box::new(self.unary()?));
+            } else {
+                return Ok(expression);
+            }
+        }
+    }
+
+    /// A factor, possibly negated
+    fn unary(&mut self) -> Result<Expression, FormulaError> {
+        if self.eat('-') {
+            Ok(Expression::Negate(Box::new(self.unary()?)))
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    /// An atom, possibly raised to a power
+    fn power(&mut self) -> Result<Expression, FormulaError> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            Ok(Expression::Power(Box::new(base), Box::new(self.unary()?)))
+        } else {
+            Ok(base)
+        }
+    }
+
+    /// A number, variable, function call, or parenthesized expression
+    fn atom(&mut self) -> Result<Expression, FormulaError> {
+        let (position, token) = self.peek().ok_or(FormulaError {
+            position: self.end + 1,
+            message: String::from("unexpected end of the formula"),
+        })?;
+        self.next += 1;
+        match token {
+            Token::Number(value) => Ok(Expression::Constant(Complex { re: value, im: 0.0 })),
+            Token::Imaginary(value) => Ok(Expression::Constant(Complex { re: 0.0, im: value })),
+            Token::Symbol('(') => {
+                let expression = self.sum()?;
+                self.expect(')')?;
+                Ok(expression)
+            }
+            Token::Name(name) => match name.as_str() {
+                "z" => Ok(Expression::Z),
+                "c" => Ok(Expression::C),
+                "i" => Ok(Expression::Constant(Complex { re: 0.0, im: 1.0 })),
+                _ => {
+                    let function = Function::NAMES
+                        .iter()
+                        .find(|(known, _)| *known == name)
+                        .map(|(_, function)| *function)
+                        .ok_or_else(|| {
+                            let names: Vec<&str> =
+                                Function::NAMES.iter().map(|(name, _)| *name).collect();
+                            FormulaError {
+                                position,
+                                message: format!(
+                                    "unknown name `{name}`, expected `z`, `c`, `i`, or a function: {}",
+                                    names.join(", ")
+                                ),
+                            }
+                        })?;
+                    self.expect('(')?;
+                    let argument = self.sum()?;
+                    self.expect(')')?;
+                    Ok(Expression::Call(function, Box::new(argument)))
+                }
+            },
+            token => Err(FormulaError {
+                position,
+                message: format!("unexpected {token}"),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Value of a formula for some `z` and `c`
+    fn evaluate(source: &str, z: (f64, f64), c: (f64, f64)) -> (f64, f64) {
+        let formula: Formula = source.parse().unwrap();
+        let value = formula.evaluate(Complex { re: z.0, im: z.1 }, Complex { re: c.0, im: c.1 });
+        (value.re, value.im)
+    }
+
+    #[test]
+    fn operators_have_the_usual_precedence() {
+        assert_eq!(evaluate("z^2 + c", (1.0, 1.0), (0.5, 0.0)), (0.5, 2.0));
+        assert_eq!(evaluate("-z^2", (3.0, 0.0), (0.0, 0.0)), (-9.0, 0.0));
+        assert_eq!(evaluate("2^3^2", (0.0, 0.0), (0.0, 0.0)), (512.0, 0.0));
+        assert_eq!(
+            evaluate("1 - 2 - 3 * c / 2", (0.0, 0.0), (2.0, 0.0)),
+            (-4.0, 0.0)
+        );
+        assert_eq!(
+            evaluate("(1 + 2i) * i", (0.0, 0.0), (0.0, 0.0)),
+            (-2.0, 1.0)
+        );
+        assert_eq!(
+            evaluate("1.5e1 + abs(z)", (-1.0, -2.0), (0.0, 0.0)),
+            (16.0, 2.0)
+        );
+        assert_eq!(
+            evaluate("re(c) + im(c)*i + conj(z)", (0.0, 1.0), (3.0, 4.0)),
+            (3.0, 3.0)
+        );
+        let (re, im) = evaluate("exp(log(z)) - sqrt(z^2)", (0.6, 0.8), (0.0, 0.0));
+        assert!(re.abs() < 1e-15 && im.abs() < 1e-15);
+    }
+
+    #[test]
+    fn mistakes_tell_where_they_are() {
+        for (source, position, message) in [
+            ("z^2 + ", 7, "unexpected end"),
+            ("z^2 + $", 7, "unexpected character `$`"),
+            ("sin z", 5, "expected `(`"),
+            ("tan(z)", 1, "unknown name `tan`"),
+            ("(z + c", 7, "expected `)` at the end"),
+            ("z c", 3, "unexpected `c`"),
+        ] {
+            let error = source.parse::<Formula>().err().unwrap();
+            assert_eq!(error.position, position, "{source}: {error}");
+            assert!(error.message.contains(message), "{source}: {error}");
+        }
+    }
+
+    #[test]
+    fn formulas_are_written_as_they_were_given() {
+        let formula: Formula = "z^3 +  c".parse().unwrap();
+        assert_eq!(String::from(formula.clone()), "z^3 +  c");
+        assert_eq!(formula.to_string(), "z^3 +  c");
+    }
+}
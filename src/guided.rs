@@ -0,0 +1,106 @@
+//! Guided sampling, which takes samples in proportion to an importance map of how much of their
+//! orbits landed in view during a quick pre-pass, so that zoomed in renders spend fewer samples
+//! on orbits that are never seen
+//!
+//! The unit square of samples is split into a grid of cells, and the pre-pass counts, for each
+//! cell, the points that its uniform samples plotted in view, across every channel. Each cell is
+//! then picked with a probability `p` in proportion to its count, mixed with a share of uniform
+//! probability so that no cell is starved (its count is only an estimate, and cells that plotted
+//! nothing during the pre-pass may still plot something later), and samples are spread uniformly
+//! within their cell. A sample is then `q = p · cells` times as likely as in a uniform render,
+//! so its points are plotted with a weight of `1 / q` to estimate the same image.
+//!
+//! As the image only holds whole counts, every weight is multiplied by a scale `S`, the largest
+//! `q`, which brings the weight of the samples in the most visited cells to 1. Each orbit is then
+//! plotted `⌊w⌋` times, and once more with probability `w - ⌊w⌋`, for a weight `w`.
+
+use rand::Rng;
+
+/// Share of samples spread uniformly over the whole square, whatever the map
+const FLOOR: f64 = 0.1;
+
+/// Largest scale of the weights, which keeps the counts of the image from overflowing
+const MAX_SCALE: f64 = 65_536.0;
+
+/// Probabilities of picking each cell of a grid over the unit square of samples
+pub struct ImportanceMap {
+    /// Cells along each axis
+    grid: u32,
+    /// Running totals of the probabilities of the cells, row by row
+    cumulative: Vec<f64>,
+    /// Largest `q` of any cell, which guided samples take as their scale `S`
+    largest: f64,
+}
+
+/// Cell of a `grid` that a sample at `(u, v)` of the unit square falls in
+pub fn cell(grid: u32, (u, v): (f64, f64)) -> usize {
+    let column = ((u * grid as f64) as u32).min(grid - 1);
+    let row = ((v * grid as f64) as u32).min(grid - 1);
+    (row * grid + column) as usize
+}
+
+impl ImportanceMap {
+    /// Build the map of a `grid` from the importance of the samples of each cell, such as the
+    /// number of points they plotted, with a `strength` between 0 (uniform samples) and 1 (all
+    /// but a floor of samples taken by importance)
+    pub fn new(grid: u32, importance: &[f64], strength: f64) -> ImportanceMap {
+        let cells = importance.len() as f64;
+        let total: f64 = importance.iter().sum();
+        // Without any point in view, there is nothing to guide samples by
+        let share = if total > 0.0 {
+            strength * (1.0 - FLOOR)
+        } else {
+            0.0
+        };
+        let probability = |importance: f64| {
+            let guided = if total > 0.0 { importance / total } else { 0.0 };
+            share * guided + (1.0 - share) / cells
+        };
+        let largest = importance.iter().copied().fold(0.0, f64::max);
+        let mut sum = 0.0;
+        let cumulative = importance
+            .iter()
+            .map(|&importance| {
+                sum += probability(importance);
+                sum
+            })
+            .collect();
+        ImportanceMap {
+            grid,
+            cumulative,
+            largest: probability(largest) * cells,
+        }
+    }
+
+    /// Scale `S` of the weights of guided samples, which brings those of the samples in the most
+    /// visited cells to 1
+    pub fn scale(&self) -> f64 {
+        self.largest.ceil().clamp(1.0, MAX_SCALE)
+    }
+
+    /// Draw a sample of the unit square, and how many times as likely it was to be drawn as a
+    /// uniform sample, `q`, whose inverse is the weight to plot each of its points with
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> ((f64, f64), f64) {
+        let last = self.cumulative.len() - 1;
+        let target = rng.gen::<f64>() * self.cumulative[last];
+        let index = self
+            .cumulative
+            .partition_point(|&sum| sum <= target)
+            .min(last);
+        let probability = match index {
+            0 => self.cumulative[0],
+            _ => self.cumulative[index] - self.cumulative[index - 1],
+        } / self.cumulative[last];
+        let (column, row) = (index as u32 % self.grid, index as u32 / self.grid);
+        let point = (
+            (column as f64 + rng.gen::<f64>()) / self.grid as f64,
+            (row as f64 + rng.gen::<f64>()) / self.grid as f64,
+        );
+        // Sums of a cell's edge and a fraction below 1 can still round up to 1
+        let point = (
+            point.0.min(1.0 - f64::EPSILON),
+            point.1.min(1.0 - f64::EPSILON),
+        );
+        (point, probability * self.cumulative.len() as f64)
+    }
+}
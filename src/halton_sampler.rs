@@ -0,0 +1,111 @@
+//! Quasi-random sampling along the Halton sequence, whose points fill the unit square far more
+//! evenly than random ones, so that a render converges with fewer samples
+
+use crate::sampler::{self, Sampler, Strata};
+use rand::{Rng, RngCore};
+
+/// Samples along the 2D Halton sequence, in bases 2 and 3
+///
+/// Every pass would otherwise take the same points, so each one shifts the whole sequence by its
+/// own random offset, wrapping around the edges of the square (a Cranley-Patterson rotation).
+/// When passes are stratified together, they instead carry on along one sequence, shifted by one
+/// offset for the whole render, so that the first `k` passes take its first `k` runs of points.
+/// The sequence isn't shifted until the sampler is first reset.
+pub struct HaltonSampler {
+    /// Number of points along the sequence that each pass takes
+    samples: u64,
+    /// First point of the pass along the sequence
+    start: u64,
+    offset: (f64, f64),
+}
+
+impl HaltonSampler {
+    /// Construct a [`HaltonSampler`] for passes of `samples` samples
+    pub fn new(samples: u64) -> HaltonSampler {
+        HaltonSampler {
+            samples,
+            start: 0,
+            offset: (0.0, 0.0),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn reset(&mut self, rng: &mut dyn RngCore, strata: Option<Strata>) {
+        (self.start, self.offset) = match strata {
+            Some(strata) => (strata.number().wrapping_mul(self.samples), strata.origin()),
+            None => (0, (rng.gen(), rng.gen())),
+        };
+    }
+
+    fn point(&self, index: u64, _rng: &mut dyn RngCore) -> Option<(f64, f64)> {
+        let index = self.start.wrapping_add(index);
+        Some(sampler::rotate(
+            (radical_inverse(index, 2), radical_inverse(index, 3)),
+            self.offset,
+        ))
+    }
+}
+
+/// Mirrors the digits of `index` in a given `base` around the radix point, such that 6 (`110` in
+/// base 2) gives 0.011 in base 2
+fn radical_inverse(index: u64, base: u64) -> f64 {
+    let mut index = index;
+    let mut inverse = 0.0;
+    let mut scale = 1.0 / base as f64;
+    while index > 0 {
+        inverse += (index % base) as f64 * scale;
+        index /= base;
+        scale /= base as f64;
+    }
+    inverse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn radical_inverses_mirror_the_digits() {
+        assert_eq!(radical_inverse(0, 2), 0.0);
+        assert_eq!(radical_inverse(1, 2), 0.5);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        assert!((radical_inverse(5, 3) - 7.0 / 9.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn runs_of_points_take_a_cell_each() {
+        // Any 2^a × 3^b consecutive points of the sequence fall one in each cell of such a grid, though
+        // sums of thirds can fall just short of the edges they lie on
+        let mut rng = StdRng::seed_from_u64(1);
+        let sampler = HaltonSampler::new(36);
+        for start in [0, 36, 1000] {
+            let mut cells = [0; 36];
+            for index in start..start + 36 {
+                let (u, v) = sampler.point(index, &mut rng).unwrap();
+                cells[(v * 9.0 + 1e-9) as usize * 4 + (u * 4.0) as usize] += 1;
+            }
+            assert_eq!(cells, [1; 36], "{start}");
+        }
+    }
+
+    #[test]
+    fn stratified_passes_carry_on_along_the_sequence() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let strata = Strata::new(&mut rng);
+        let mut sampler = HaltonSampler::new(10);
+        sampler.reset(&mut rng, Some(strata.pass(3)));
+        let mut unshifted = HaltonSampler::new(10);
+        for index in 0..10 {
+            let point = unshifted.point(30 + index, &mut rng).unwrap();
+            let expected = sampler::rotate(point, strata.origin());
+            assert_eq!(sampler.point(index, &mut rng), Some(expected));
+        }
+        // Unstratified passes are shifted some other way each, but stay in the square
+        unshifted.reset(&mut rng, None);
+        let point = unshifted.point(0, &mut rng).unwrap();
+        assert!(point != (0.0, 0.0) && point.0 < 1.0 && point.1 < 1.0);
+    }
+}
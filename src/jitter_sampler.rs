@@ -0,0 +1,253 @@
+//! Stratified sampling, where a rectangle is divided into a grid of cells that each take one
+//! randomly placed sample in turn, which spreads samples more evenly than independent ones
+
+use crate::sampler::{self, Sampler, Strata};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use std::ops::Range;
+
+/// Largest number of cells in a grid, which keeps the order that they are visited in to a few
+/// dozen megabytes
+const MAX_CELLS: u64 = 1 << 24;
+
+/// Samples that each fall in their own cell of a grid, jittered within it
+///
+/// The grid has about as many cells as there are samples in a pass, as many along each axis as
+/// keep the cells about square over the domain, without more cells than samples. The cells are
+/// visited in bit-reversed Morton order over the smallest power-of-two grid that holds them,
+/// skipping those past its edges: every run of `2^k` consecutive samples then takes about one
+/// sample in each of `2^k` equal blocks of cells, so that even channels that only take the first
+/// few samples of a pass are spread evenly. The samples left over once every cell has one, fewer
+/// than a row, go over the grid again.
+///
+/// The position of each sample within its cell is hashed from a key that each pass draws from the
+/// sampler's own generator, rather than drawn from the task that takes the sample, so that a
+/// seeded generator gives the same samples however the pass is split between threads. When passes
+/// are stratified together, each cell instead takes the same random position in every pass,
+/// shifted across the cell by the step of the pass, so that the samples of the first few passes
+/// also spread out within each cell.
+pub struct JitterSampler<R: Rng = StdRng> {
+    /// Number of samples in a pass
+    samples: u64,
+    columns: u32,
+    rows: u32,
+    /// Index of each cell in the order that they are visited in, counted along the rows
+    order: Vec<u32>,
+    /// Rectangle that the samples are spread over
+    x: Range<f64>,
+    y: Range<f64>,
+    strata: Option<Strata>,
+    /// Generator of the key of each pass, and the key of the current one
+    rng: R,
+    key: u64,
+}
+
+impl JitterSampler {
+    /// Construct a [`JitterSampler`] over the unit square, with a grid for `samples` samples per
+    /// pass, and cells shaped for a domain that is `aspect` times as wide as it is high, whose
+    /// passes are seeded from the system's source of randomness
+    pub fn new(samples: u64, aspect: f64) -> JitterSampler {
+        JitterSampler::with_rng(samples, aspect, StdRng::from_entropy())
+    }
+
+    /// Construct a [`JitterSampler`] that spreads `samples` samples per pass over the rectangle
+    /// `x` × `y` instead of the unit square
+    /// The render loop maps samples onto the sampling region itself, as deep zooms need them
+    /// relative to it, so this is only for samplers used on their own
+    #[cfg(test)]
+    pub fn over(samples: u64, x: Range<f64>, y: Range<f64>) -> JitterSampler {
+        let aspect = (x.end - x.start) / (y.end - y.start);
+        JitterSampler {
+            x,
+            y,
+            ..JitterSampler::new(samples, aspect)
+        }
+    }
+}
+
+impl<R: Rng> JitterSampler<R> {
+    /// Like [`JitterSampler::new`], drawing the random positions of the samples of each pass from
+    /// `rng`
+    pub fn with_rng(samples: u64, aspect: f64, mut rng: R) -> JitterSampler<R> {
+        let key = rng.gen();
+        let cells = samples.clamp(1, MAX_CELLS);
+        let aspect = if aspect.is_finite() && aspect > 0.0 {
+            aspect
+        } else {
+            1.0
+        };
+        let columns = ((cells as f64 * aspect).sqrt().ceil() as u64).clamp(1, cells);
+        let rows = (cells / columns).max(1);
+        let (columns, rows) = (columns as u32, rows as u32);
+
+        // Bits of the index of a cell along each axis, in the power-of-two grid that holds them
+        let x_bits = columns.next_power_of_two().ilog2();
+        let y_bits = rows.next_power_of_two().ilog2();
+        let bits = x_bits + y_bits;
+        // Even bits of a cell's Morton index give its column, odd bits its row, up to the bits of
+        // the shorter axis, above which the rest go to the longer one
+        let shared = x_bits.min(y_bits);
+        let order = (0..1u64 << bits)
+            .filter_map(|index| {
+                let cell = match bits {
+                    0 => 0,
+                    bits => index.reverse_bits() >> (64 - bits),
+                };
+                let (mut x, mut y) = (0u32, 0u32);
+                for bit in 0..shared {
+                    x |= ((cell >> (2 * bit)) as u32 & 1) << bit;
+                    y |= ((cell >> (2 * bit + 1)) as u32 & 1) << bit;
+                }
+                let rest = ((cell >> (2 * shared)) as u32) << shared;
+                if x_bits > y_bits {
+                    x |= rest;
+                } else {
+                    y |= rest;
+                }
+                (x < columns && y < rows).then_some(y * columns + x)
+            })
+            .collect();
+        JitterSampler {
+            samples,
+            columns,
+            rows,
+            order,
+            x: 0.0..1.0,
+            y: 0.0..1.0,
+            strata: None,
+            rng,
+            key,
+        }
+    }
+}
+
+impl<R: Rng + Sync> Sampler for JitterSampler<R> {
+    fn reset(&mut self, _rng: &mut dyn RngCore, strata: Option<Strata>) {
+        self.strata = strata;
+        self.key = self.rng.gen();
+    }
+
+    fn point(&self, index: u64, _rng: &mut dyn RngCore) -> Option<(f64, f64)> {
+        if index >= self.samples {
+            return None;
+        }
+        let cell = self.order[(index % self.order.len() as u64) as usize];
+        let (x, y) = (cell % self.columns, cell / self.columns);
+        let (u, v) = match &self.strata {
+            Some(strata) => sampler::rotate(strata.hash(cell as u64), strata.step()),
+            None => sampler::hash(self.key, index),
+        };
+        let u = jitter(x, self.columns, u);
+        let v = jitter(y, self.rows, v);
+        Some((
+            self.x.start + u * (self.x.end - self.x.start),
+            self.y.start + v * (self.y.end - self.y.start),
+        ))
+    }
+}
+
+/// Position at a `fraction` of the way across the cell at `index` out of `cells` along an axis of
+/// the unit square, which rounding can't push onto the far edge
+fn jitter(index: u32, cells: u32, fraction: f64) -> f64 {
+    ((index as f64 + fraction) / cells as f64).min(1.0 - f64::EPSILON / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points of the next pass of a `sampler`, taking `samples` samples with a task generator
+    /// seeded from `task`
+    fn pass<R: Rng + Sync>(
+        sampler: &mut JitterSampler<R>,
+        samples: u64,
+        task: u64,
+    ) -> Vec<(f64, f64)> {
+        let mut rng = StdRng::seed_from_u64(task);
+        sampler.reset(&mut rng, None);
+        (0..samples)
+            .map(|index| sampler.point(index, &mut rng).unwrap())
+            .collect()
+    }
+
+    /// Number of the points of a pass of a `sampler` over `x` × `y` that fall in each of its
+    /// cells
+    fn cell_counts<R: Rng + Sync>(
+        sampler: &JitterSampler<R>,
+        points: &[(f64, f64)],
+        x: Range<f64>,
+        y: Range<f64>,
+    ) -> Vec<u32> {
+        let (columns, rows) = (sampler.columns as usize, sampler.rows as usize);
+        let mut counts = vec![0; columns * rows];
+        for &(u, v) in points {
+            assert!(x.contains(&u) && y.contains(&v), "({u}, {v})");
+            let column = ((u - x.start) / (x.end - x.start) * columns as f64) as usize;
+            let row = ((v - y.start) / (y.end - y.start) * rows as f64) as usize;
+            counts[row * columns + column] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn samples_take_a_cell_each_of_any_rectangle() {
+        let (x, y) = (-2.0..1.0, -1.0..1.0);
+        let mut sampler = JitterSampler::over(600, x.clone(), y.clone());
+        // Cells are as square as the rectangle allows, and there are as many as samples
+        assert_eq!((sampler.columns, sampler.rows), (30, 20));
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut strata = Strata::new(&mut rng);
+        for pass in 0..3 {
+            let points = (0..600)
+                .map(|index| sampler.point(index, &mut rng).unwrap())
+                .collect::<Vec<_>>();
+            let counts = cell_counts(&sampler, &points, x.clone(), y.clone());
+            assert!(counts.iter().all(|&count| count == 1));
+            strata = strata.pass(pass + 1);
+            sampler.reset(&mut rng, (pass > 0).then_some(strata));
+        }
+    }
+
+    #[test]
+    fn awkward_counts_are_stratified_but_for_less_than_a_row() {
+        for samples in [7, 10, 999_983] {
+            let mut sampler = JitterSampler::with_rng(samples, 1.0, StdRng::seed_from_u64(0));
+            let points = pass(&mut sampler, samples, 0);
+            let counts = cell_counts(&sampler, &points, 0.0..1.0, 0.0..1.0);
+            let left_over = samples - counts.len() as u64;
+            assert!(left_over < sampler.columns as u64, "{samples} samples");
+            assert!(counts.iter().all(|&count| count == 1 || count == 2));
+            let twice = counts.iter().filter(|&&count| count == 2).count();
+            assert_eq!(twice as u64, left_over);
+        }
+    }
+
+    #[test]
+    fn seeded_samplers_draw_the_same_samples() {
+        let seeded = |seed| JitterSampler::with_rng(1_000, 1.5, StdRng::seed_from_u64(seed));
+        let (mut first, mut second) = (seeded(7), seeded(7));
+        for _ in 0..3 {
+            assert_eq!(pass(&mut first, 1_000, 1), pass(&mut second, 1_000, 2));
+        }
+        assert_ne!(pass(&mut first, 1_000, 1), pass(&mut seeded(8), 1_000, 1));
+        // Every pass draws samples of its own
+        let mut sampler = seeded(7);
+        assert_ne!(pass(&mut sampler, 1_000, 1), pass(&mut sampler, 1_000, 1));
+    }
+
+    #[test]
+    fn passes_end_after_their_samples_however_often_reset() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sampler = JitterSampler::with_rng(1_001, 1.0, StdRng::seed_from_u64(0));
+        let capacity = sampler.order.capacity();
+        for _ in 0..2 {
+            sampler.reset(&mut rng, None);
+            let taken = (0..)
+                .map_while(|index| sampler.point(index, &mut rng))
+                .count();
+            assert_eq!(taken, 1_001);
+            assert_eq!(sampler.point(u64::MAX, &mut rng), None);
+            assert_eq!(sampler.order.capacity(), capacity);
+        }
+    }
+}
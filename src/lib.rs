@@ -0,0 +1,172 @@
+//! Render a beautiful Nebulabrot.
+//!
+//! The Nebulabrot is an alternate way to render the Mandelbrot set
+//!
+//! Output of calling `nebulae` without arguments:
+//! <div style="margin:auto;">
+//!     <img style="margin:auto;"
+//!         src="https://raw.githubusercontent.com/Bradshaw/nebulae/main/example.png"
+//!         alt="A render of a Nebulabrot. A nebulous-looking version of a Mandelbrot fractal."
+//!     >
+//! </div>
+//!
+//! # Usage:
+//!
+//! When installed via `cargo install` as `nebulae`:
+//! ```text
+//! USAGE:
+//!     nebulae [OPTIONS] [SUBCOMMAND]
+//!
+//! OPTIONS:
+//!     -c, --config <CONFIG>               Configuration file (TOML or JSON, `-` for standard input)
+//!         --fail-fast                     Stop at the first job that fails, instead of moving on to
+//!                                         the next
+//!     -h, --help                          Print help information
+//!         --intermediate-every <EVERY>    Intermediate interval: N passes, Ns seconds, or none
+//!         --limit-scale <SCALE>           Multiply the escape limit of every channel
+//!         --mkdirs                        Create the missing directories of output files
+//!     -n, --no-intermediates              Do not write intermediate files
+//!         --noise-map <PATH>              Write a map of how noisy each pixel is to this PNG,
+//!                                         alongside intermediates
+//!     -o, --output <OUTPUT>               File to write to [default: image.png]
+//!         --save-settings <PATH>          Save the settings that each job renders with, its limits as
+//!                                         picked and scaled, to this file
+//!         --seed <SEED>                   Seed for reproducible renders (overrides the configuration)
+//!         --strict                        Treat warnings about the render settings as errors
+//!         --strict-config                 Treat unknown keys in the configuration file as errors
+//!         --threads <THREADS>             Number of threads to render with (overrides the
+//!                                         configuration)
+//!     -V, --version                       Print version information
+//!
+//! SUBCOMMANDS:
+//!     help             Print this message or the help of the given subcommand(s)
+//!     locations        List the named locations that can be used with the `location` key
+//!     mandelbrot       Render a quick escape-time Mandelbrot of the view, to frame it
+//!     wizard           Display configuration wizard
+//!     write-default    Write the default configuration to TOML
+//! ```
+//!
+//! ## Subcommands:
+//!
+//! ### `nebulae wizard`
+//! ```text
+//! Display configuration wizard
+//!
+//! USAGE:
+//!     nebulae wizard [OPTIONS]
+//!
+//! OPTIONS:
+//!     -h, --help                         Print help information
+//! ```
+//!
+//! ### `nebulae locations`
+//! ```text
+//! List the named locations that can be used with the `location` key
+//!
+//! USAGE:
+//!     nebulae locations
+//!
+//! OPTIONS:
+//!     -h, --help                         Print help information
+//! ```
+//!
+//! ### `nebulae mandelbrot`
+//! ```text
+//! Render a quick escape-time Mandelbrot of the view, to frame it
+//!
+//! USAGE:
+//!     nebulae mandelbrot
+//!
+//! OPTIONS:
+//!     -h, --help                         Print help information
+//! ```
+//!
+//! ### `nebulae write-default`
+//! ```text
+//! Write the default configuration to TOML
+//!
+//! USAGE:
+//!     nebulae write-default [OPTIONS]
+//!
+//! OPTIONS:
+//!     -f, --format <FORMAT>              Format to write the configuration in (defaults to the file's
+//!                                        extension, or TOML) [possible values: toml, json]
+//!     -h, --help                         Print help information
+//!     -s, --save-config <SAVE_CONFIG>    Path to write the default configuration to (writes to stdout
+//! ```
+//!
+//! ## Recipes:
+//!
+//! * Just render a Nebulabrot with default settings:
+//!     * `nebulae`
+//! * Render a Nebulabrot using a configuration file (TOML or JSON):
+//!     * `nebulae -c my_config.toml`
+//! * Use the wizard to render a custom Nebulabrot, and save the configuration for future use:
+//!     * `nebulae wizard -c my_config.toml`
+//! * Render a default Nebulabrot with a custom filename:
+//!     * `nebulae -o my_render.png`
+//! * Frame the view of a configuration file in an escape-time Mandelbrot before rendering it:
+//!     * `nebulae -c my_config.toml -o preview.png mandelbrot`
+//! * See where a render is still noisy, as it goes:
+//!     * `nebulae -c my_config.toml --noise-map noise.png`
+//!
+//! # Library:
+//!
+//! The renderer is also a library, for programs of their own: [`render_nebulabrot`] counts the
+//! points of a Nebulabrot into a [`RawImage`] from its [`RenderSettings`], given back in a
+//! [`RenderResult`] with statistics of the run, reporting its progress to a [`ProgressSink`]
+//! (such as [`NoProgress`], which reports nothing) until it is done or a [`CancelToken`] is
+//! cancelled, and [`output::write_image`] tonemaps the image into a PNG file.
+//! [`render_escape_times`] and [`output::write_escape_times`] do the same for a conventional
+//! escape-time image of the view. Renders with a `noise_map` also give back a [`NoiseMap`] of how
+//! noisy each pixel is, which [`output::write_noise_map`] writes from its errors. Whatever fails
+//! along the way is a [`NebulaeError`], which tells which file is at fault.
+
+#![warn(missing_docs)]
+
+pub use crate::error::NebulaeError;
+pub use crate::noise_map::NoiseMap;
+pub use crate::raw_image::{RawImage, RawImageF32};
+pub use crate::render::{
+    calibrate_limits, render_escape_times, render_nebulabrot, CancelToken, NoProgress,
+    ProgressSink, RenderResult,
+};
+pub use crate::render_settings::RenderSettings;
+
+mod adaptive;
+pub mod config_file;
+mod denoise;
+mod double_double;
+mod error;
+mod formula;
+mod guided;
+mod halton_sampler;
+mod jitter_sampler;
+pub mod mandelbrot;
+mod mapping;
+mod mask;
+mod metropolis;
+mod noise_map;
+pub mod output;
+mod poisson_sampler;
+pub mod raw_image;
+mod render;
+pub mod render_settings;
+mod sampler;
+mod splat;
+mod viewport;
+
+/// Derive a seed for an independent random stream from a base seed and the stream's indices
+fn derive_seed(seed: u64, indices: &[u64]) -> u64 {
+    indices
+        .iter()
+        .fold(splitmix64(seed), |state, index| splitmix64(state ^ index))
+}
+
+/// SplitMix64 finalizer, which scrambles similar inputs into very different outputs
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
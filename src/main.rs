@@ -1,236 +1,435 @@
-//! Render a beautiful Nebulabrot.
-//!
-//! The Nebulabrot is an alternate way to render the Mandelbrot set
-//!
-//! Output of calling `nebulae` without arguments:
-//! <div style="margin:auto;">
-//!     <img style="margin:auto;"
-//!         src="https://raw.githubusercontent.com/Bradshaw/nebulae/main/example.png"
-//!         alt="A render of a Nebulabrot. A nebulous-looking version of a Mandelbrot fractal."
-//!     >
-//! </div>
-//!
-//! # Usage:
-//!
-//! When installed via `cargo install` as `nebulae`:
-//! ```text
-//! USAGE:
-//!     nebulae [OPTIONS] [SUBCOMMAND]
-//!
-//! OPTIONS:
-//!     -c, --config <CONFIG>     Configuration file
-//!     -h, --help                Print help information
-//!     -n, --no-intermediates    Do not write intermediate files
-//!     -o, --output <OUTPUT>     File to write to [default: image.png]
-//!     -V, --version             Print version information
-//!
-//! SUBCOMMANDS:
-//!     help             Print this message or the help of the given subcommand(s)
-//!     wizard           Display configuration wizard
-//!     write-default    Write the default configuration to TOML
-//! ```
-//!
-//! ## Subcommands:
-//!
-//! ### `nebulae wizard`
-//! ```text
-//! Display configuration wizard
-//!
-//! USAGE:
-//!     nebulae wizard [OPTIONS]
-//!
-//! OPTIONS:
-//!     -h, --help                         Print help information
-//! ```
-//!
-//! ### `nebulae write-default`
-//! ```text
-//! Write the default configuration to TOML
-//!
-//! USAGE:
-//!     nebulae write-default [OPTIONS]
-//!
-//! OPTIONS:
-//!     -h, --help                         Print help information
-//!     -s, --save-config <SAVE_CONFIG>    Path to write the default configuration to (writes to stdout
-//! ```
-//!
-//! ## Recipes:
-//!
-//! * Just render a Nebulabrot with default settings:
-//!     * `nebulae`
-//! * Render a Nebulabrot using a configuration file:
-//!     * `nebulae my_config.toml`
-//! * Use the wizard to render a custom Nebulabrot, and save the configuration for future use:
-//!     * `nebulae wizard -c my_config.toml`
-//! * Render a default Nebulabrot with a custom filename:
-//!     * `nebulae -o my_render.png`
-
-use crate::mandelbrot::Complex;
-use crate::program_options::ProgramOptions;
-use crate::raw_image::RawImage;
-use crate::render_settings::*;
+//! Command line of `nebulae`, which renders the jobs of its options one after the other, see the
+//! library for its usage
+
 use console::style;
-use dialoguer::console::Term;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rand::Rng;
-use rayon::prelude::*;
-use std::cmp::min;
-use std::error::Error;
-use std::fs::File;
-use std::io::BufWriter;
-use std::path::Path;
-use std::sync::Arc;
-use std::thread;
-use std::thread::JoinHandle;
+use nebulae::output::{write_escape_times, write_image, write_noise_map, Background, Source};
+use nebulae::{
+    render_escape_times, render_nebulabrot, CancelToken, NebulaeError, NoiseMap, ProgressSink,
+    RawImage, RawImageF32,
+};
+use program_options::{ProgramOptions, RenderJob};
+use std::fmt::Display;
+use std::process::ExitCode;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
-mod mandelbrot;
 mod program_options;
-mod raw_image;
-mod render_settings;
-
-/// This program is hard-coded to output an RGB-encoded PNG file, so 3 channels are used throughout.
-const CHANNELS: u32 = 3;
 
 /// Main function that will hopefully give you a nice picture by the end
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> ExitCode {
     let ProgramOptions {
+        jobs,
+        fail_fast,
+        escape_time,
+    } = match program_options::get_options() {
+        Ok(options) => options,
+        Err(error) => return fail(error),
+    };
+
+    // The first Ctrl-C stops the current render and writes its image, the second one exits
+    let cancel = CancelToken::new();
+    let interrupt = cancel.clone();
+    let handler = ctrlc::set_handler(move || {
+        if interrupt.is_cancelled() {
+            std::process::exit(130);
+        }
+        interrupt.cancel();
+        eprintln!(
+            "{} Stopping the render, press Ctrl-C again to quit without writing",
+            style("Interrupted:").yellow().bold()
+        );
+    });
+    if let Err(error) = handler {
+        return fail(format!("Could not handle Ctrl-C: {error}"));
+    }
+
+    let mut failures = 0;
+    for (index, job) in jobs.iter().enumerate() {
+        if cancel.is_cancelled() {
+            eprintln!("Skipping the remaining {} jobs", jobs.len() - index);
+            break;
+        }
+        if jobs.len() > 1 {
+            eprintln!(
+                "{} {}",
+                style(format!("Job {}/{}", index + 1, jobs.len())).bold(),
+                job.output_path
+            );
+        }
+        let result = if escape_time {
+            run_escape_time_job(job, &cancel)
+        } else {
+            run_job(job, &cancel)
+        };
+        if let Err(error) = result {
+            if fail_fast {
+                return fail(error);
+            }
+            eprintln!("{} {error}", style("Job failed:").red().bold());
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        return fail(format!("{failures} of {} jobs failed", jobs.len()));
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print an error on a single line, giving the exit code of a run that failed
+fn fail(error: impl Display) -> ExitCode {
+    eprintln!("{} {error}", style("Error:").red().bold());
+    ExitCode::FAILURE
+}
+
+/// Render a job's Nebulabrot until it is done or `cancel` is cancelled, and write it to its output
+/// path
+fn run_job(job: &RenderJob, cancel: &CancelToken) -> Result<(), NebulaeError> {
+    let RenderJob {
         render_settings,
         output_path,
-        render_intermediates,
-    } = program_options::get_options()?;
-
-    let intermediate_function = if render_intermediates {
-        Some(|data: &Vec<u32>, maximum: u32| {
-            write_image(render_settings, &output_path, &data, maximum);
-        })
-    } else {
-        None
-    };
+        ..
+    } = job;
 
-    let (data, maximum) = render_nebulabrot(render_settings, &intermediate_function)?;
+    let (name, limits) = match &render_settings.bands {
+        Some(bands) => ("Bands:", bands),
+        None => ("Escape limits:", &render_settings.limits),
+    };
+    let limits: Vec<String> = limits.iter().map(u32::to_string).collect();
+    eprintln!("{} {}", style(name).bold(), limits.join(", "));
+    let threads = match render_settings.threads {
+        Some(threads) => threads as usize,
+        None => rayon::current_num_threads(),
+    };
+    eprintln!("{} {threads}", style("Threads:").bold());
+    // The background is read before the render, so that a bad one fails the job at once
+    let background = Background::load(render_settings)?.map(Arc::new);
+    let sink = JobSink::new(job, background.clone());
+    let result = render_nebulabrot(render_settings, &sink, cancel);
+    // The last intermediate is written to the same file, so it must be done before the image is
+    sink.finish_intermediate();
+    drop(sink);
+    let result = result?;
+    eprintln!("{} {result}", style("Rendered:").bold());
+    if result.maximum() == 0 {
+        eprintln!("{}", black_image_warning());
+    }
+    if result.partial {
+        eprintln!("Writing the points counted until the render was stopped");
+    }
 
-    write_image(render_settings, &output_path, &data, maximum)
+    // Weighted points are written from their sums, which their counts only tell the number of
+    let source = match result.weights {
+        Some(weights) => Source::Weights(weights),
+        None => Source::Image(result.image),
+    };
+    write_image(render_settings.clone(), output_path, source, background)
         .join()
-        .unwrap();
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+    if let (Some(noise), Some(path)) = (result.noise, &render_settings.noise_map) {
+        match noise.errors() {
+            Some(errors) => write_noise_map(render_settings, &errors, path)?,
+            None => eprintln!(
+                "Not writing the noise map, which takes 2 passes to measure, but the render did {}",
+                noise.passes()
+            ),
+        }
+    }
     Ok(())
 }
 
-/// Render a Nebulabrot
-/// Returns a vector of values that represent an RGB-encoded grid
-fn render_nebulabrot<F>(
-    settings: RenderSettings,
-    intermediates: &Option<F>,
-) -> Result<(Vec<u32>, u32), Box<dyn Error>>
-where
-    F: Fn(&Vec<u32>, u32),
-{
-    let template = format!(
-        "{{spinner:.reverse}}{{wide_bar}}{}",
-        style(" {elapsed:<4} {percent:>4}% ").reverse()
+/// Render a job's view as a conventional escape-time Mandelbrot, and write it to its output path
+fn run_escape_time_job(job: &RenderJob, cancel: &CancelToken) -> Result<(), NebulaeError> {
+    let RenderJob {
+        render_settings,
+        output_path,
+        ..
+    } = job;
+
+    let limit = render_settings.limit(0);
+    eprintln!("{} {limit}", style("Iterations:").bold());
+    let start = Instant::now();
+    let times = render_escape_times(render_settings, cancel)?;
+    let escaped = times.iter().filter(|&&time| time > 0.0).count();
+    eprintln!(
+        "{} escape times of {} pixels in {:.1?}, escaped {:.1}%",
+        style("Rendered:").bold(),
+        times.len(),
+        start.elapsed(),
+        100.0 * escaped as f64 / times.len().max(1) as f64
     );
-    let m = MultiProgress::new();
-    let sty = ProgressStyle::with_template(template.as_str())
-        .unwrap()
-        .progress_chars("██▉▊▋▌▍▎▏ ");
-
-    let pb = m.add(ProgressBar::new(settings.passes as u64));
-    pb.set_style(sty.clone());
-    pb.enable_steady_tick(Duration::from_millis(100));
-
-    let raw_image = Arc::new(RawImage::new(settings.size, settings.size));
-
-    let mut last_render = Instant::now();
-
-    for _pass in 0..settings.passes {
-        let pb2 = m.insert_after(&pb, ProgressBar::new((CHANNELS * settings.samples) as u64));
-        pb2.set_style(sty.clone());
-        pb2.enable_steady_tick(Duration::from_millis(100));
-        (0..CHANNELS).into_par_iter().for_each(|channel| {
-            (0..settings.samples).into_par_iter().for_each(|_| {
-                pb2.inc(1);
-                let mut rng = rand::thread_rng();
-                let limit = settings.limits[channel as usize];
-                let z = Complex { re: 0.0, im: 0.0 };
-                let c = Complex {
-                    re: rng.gen::<f64>() * 5.0 - 2.5,
-                    im: rng.gen::<f64>() * 5.0 - 2.5,
-                };
-                let (zs, bailed) = mandelbrot::iterate(z, c, limit, 2.0, 3.0);
-                if bailed {
-                    for z in zs {
-                        let x = f64_to_index(z.re, -2.0, 2.0, settings.size);
-                        let y = f64_to_index(z.im, -2.0, 2.0, settings.size);
-                        match x.zip(y) {
-                            None => {}
-                            Some((x, y)) => {
-                                raw_image.bump(x as u32, y as u32, channel);
-                            }
-                        }
-                    }
-                }
-            });
-        });
+    write_escape_times(render_settings, &times, output_path)
+}
 
-        pb.inc(1);
-        if last_render.elapsed() >= Duration::from_secs(60) {
-            if let Some(intermediates) = intermediates {
-                intermediates(&raw_image.get_data(), raw_image.get_maximum());
-                last_render = Instant::now();
+/// Warning for an image that nothing was plotted in, which is written all black
+fn black_image_warning() -> String {
+    format!(
+        "{} Nothing was plotted, so the image is black: either no sample escaped within the \
+        escape limits, or no orbit passed through the view. Check the limits and the sampling \
+        region",
+        style("Warning:").yellow().bold()
+    )
+}
+
+/// Shows the progress of a job's render in progress bars, and writes its intermediates
+struct JobSink<'a> {
+    job: &'a RenderJob,
+    /// Background that intermediates are composited over, read once for all of them
+    background: Option<Arc<Background>>,
+    bars: MultiProgress,
+    style: ProgressStyle,
+    /// Bar of the passes, or a spinner that counts them when there is no number of passes
+    passes: ProgressBar,
+    /// Bar of the samples of the current pass, from the first pass on
+    samples: OnceLock<ProgressBar>,
+    /// Samples traced that the bar doesn't show yet, as it is only moved a step at a time
+    pending: AtomicU64,
+    /// Number of samples that the bar is moved by at once, a thousandth of the pass
+    step: AtomicU64,
+    /// Writer of the intermediates, which doesn't start one before the last one is done
+    intermediates: Intermediates,
+    /// Whether an intermediate was skipped yet, which is only reported once
+    skipped_intermediate: AtomicBool,
+    /// Whether an intermediate was black yet, which is only reported once
+    black_intermediate: AtomicBool,
+}
+
+impl<'a> JobSink<'a> {
+    fn new(job: &'a RenderJob, background: Option<Arc<Background>>) -> JobSink<'a> {
+        let template = format!(
+            "{{spinner:.reverse}}{{wide_bar}}{}",
+            style(" {elapsed:<4} {percent:>4}% ").reverse()
+        );
+        let bars = MultiProgress::new();
+        let sty = ProgressStyle::with_template(template.as_str())
+            .unwrap()
+            .progress_chars("██▉▊▋▌▍▎▏ ");
+
+        // Without a number of passes, the pass counter is all there is to show
+        let passes = match job.render_settings.passes {
+            0 => bars.add(ProgressBar::new_spinner()).with_style(
+                ProgressStyle::with_template(
+                    format!(
+                        "{{spinner:.reverse}}{}",
+                        style(" pass {pos} {elapsed:<4} ").reverse()
+                    )
+                    .as_str(),
+                )
+                .unwrap(),
+            ),
+            passes => bars
+                .add(ProgressBar::new(passes as u64))
+                .with_style(sty.clone()),
+        };
+        passes.enable_steady_tick(Duration::from_millis(100));
+        JobSink {
+            job,
+            background,
+            bars,
+            style: sty,
+            passes,
+            samples: OnceLock::new(),
+            pending: AtomicU64::new(0),
+            step: AtomicU64::new(1),
+            intermediates: Intermediates::default(),
+            skipped_intermediate: AtomicBool::new(false),
+            black_intermediate: AtomicBool::new(false),
+        }
+    }
+
+    /// Start writing an intermediate with `write`, unless the last one is still being written, in
+    /// which case this one is skipped rather than written to the same file at the same time
+    fn start_intermediate(&self, write: impl FnOnce() -> JoinHandle<Result<(), NebulaeError>>) {
+        match self.intermediates.start(write) {
+            Some(last) => self.report_intermediate(last),
+            None => {
+                if !self.skipped_intermediate.swap(true, Relaxed) {
+                    self.message(
+                        "Skipping intermediates while the last one is still being written",
+                    );
+                }
             }
         }
     }
-    Ok((raw_image.get_data(), raw_image.get_maximum()))
+
+    /// Wait for the last intermediate to be written
+    fn finish_intermediate(&self) {
+        self.report_intermediate(self.intermediates.finish());
+    }
+
+    /// Report whether an intermediate couldn't be written, from the `result` of its writer
+    fn report_intermediate(&self, result: Result<(), NebulaeError>) {
+        if let Err(error) = result {
+            self.message(&format!("{} {error}", style("Warning:").yellow().bold()));
+        }
+    }
 }
 
-fn write_image(
-    settings: RenderSettings,
-    output_path: &str,
-    data: &Vec<u32>,
-    maximum: u32,
-) -> JoinHandle<()> {
-    let data = data.clone();
-    let output_path = String::from(output_path);
-    thread::spawn(move || {
-        let path = Path::new(output_path.as_str());
-        let prep = map_to_color(data, maximum, settings.curve);
-        data_to_png(prep, settings.size as u32, settings.size as u32, path)
-            .expect("data to be saved as png");
-    })
+/// Writer of the intermediates of a render, one at a time
+#[derive(Default)]
+struct Intermediates {
+    /// Writer of the last intermediate, which the next one can't start before it is done
+    writer: Mutex<Option<JoinHandle<Result<(), NebulaeError>>>>,
 }
 
-fn map_to_color(data: Vec<u32>, maximum: u32, curve: f64) -> Vec<u8> {
-    let multiplier = 1.0 / maximum as f64;
-    data.into_iter()
-        .map(|p| min(255, ((p as f64 * multiplier).powf(curve) * 256.0) as u8))
-        .collect()
+impl Intermediates {
+    /// Start writing an intermediate with `write`, and give the result of the last one, unless it
+    /// is still being written, in which case this one is skipped and `None` is given
+    fn start(
+        &self,
+        write: impl FnOnce() -> JoinHandle<Result<(), NebulaeError>>,
+    ) -> Option<Result<(), NebulaeError>> {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        if writer.as_ref().is_some_and(|writer| !writer.is_finished()) {
+            return None;
+        }
+        let last = writer.take().map_or(Ok(()), join);
+        writer.replace(write());
+        Some(last)
+    }
+
+    /// Wait for the last intermediate to be written, and give its result
+    fn finish(&self) -> Result<(), NebulaeError> {
+        let writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        writer.map_or(Ok(()), join)
+    }
 }
 
-fn data_to_png(
-    data: Vec<u8>,
-    width: u32,
-    height: u32,
-    path: &Path,
-) -> Result<(), png::EncodingError> {
-    let file = File::create(path).unwrap();
-    let ref mut w = BufWriter::new(file);
-    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
-    encoder.set_color(png::ColorType::Rgb);
-    encoder.set_depth(png::BitDepth::Eight);
-    let mut writer = encoder.write_header().unwrap();
-    writer.write_image_data(&data)
+/// Result of the thread of a `writer`, passing on its panic if it had one
+fn join(writer: JoinHandle<Result<(), NebulaeError>>) -> Result<(), NebulaeError> {
+    writer
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
 }
 
-fn f64_to_index(point: f64, min: f64, max: f64, size: u32) -> Option<usize> {
-    if min == max {
-        return None;
-    };
-    let interp = (point - min) / (max - min);
-    let pixel = (size as f64 * interp) as usize;
-    if interp >= 0.0 && pixel < (size as usize) {
-        Some(pixel)
-    } else {
-        None
+impl ProgressSink for JobSink<'_> {
+    fn pass_started(&self, _pass: u64, samples: u64) {
+        let mut new = false;
+        let bar = self.samples.get_or_init(|| {
+            new = true;
+            let bar = self
+                .bars
+                .insert_after(&self.passes, ProgressBar::new(samples));
+            bar.set_style(self.style.clone());
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        });
+        if !new {
+            bar.reset();
+            bar.set_length(samples);
+        }
+        self.pending.store(0, Relaxed);
+        self.step.store((samples / 1000).max(1), Relaxed);
+    }
+
+    fn samples_completed(&self, samples: u64) {
+        // Samples come in from every worker a few at a time, far more often than the bar can
+        // take them without slowing the render down
+        let pending = self.pending.fetch_add(samples, Relaxed) + samples;
+        if pending >= self.step.load(Relaxed) {
+            if let Some(bar) = self.samples.get() {
+                bar.inc(self.pending.swap(0, Relaxed));
+            }
+        }
+    }
+
+    fn pass_finished(&self, _pass: u64) {
+        if let Some(bar) = self.samples.get() {
+            bar.inc(self.pending.swap(0, Relaxed));
+        }
+        self.passes.inc(1);
+    }
+
+    fn intermediate(
+        &self,
+        image: &RawImage,
+        weights: Option<&RawImageF32>,
+        noise: Option<&NoiseMap>,
+    ) {
+        if !self.job.render_intermediates {
+            return;
+        }
+        // Intermediates are written from snapshots, as the image keeps changing while they are
+        self.start_intermediate(|| {
+            let snapshot = image.snapshot();
+            if snapshot.get_maximum() == 0 && !self.black_intermediate.swap(true, Relaxed) {
+                self.message(&black_image_warning());
+            }
+            let source = match weights {
+                Some(weights) => Source::Weights(Arc::new(weights.snapshot())),
+                None => Source::Image(Arc::new(snapshot)),
+            };
+            let writer = write_image(
+                self.job.render_settings.clone(),
+                &self.job.output_path,
+                source,
+                self.background.clone(),
+            );
+            // The noise map is written once the image is, by the same writer, so that the next
+            // intermediate waits for both
+            let settings = &self.job.render_settings;
+            match (noise.and_then(NoiseMap::errors), settings.noise_map.clone()) {
+                (Some(errors), Some(path)) => {
+                    let settings = settings.clone();
+                    thread::spawn(move || {
+                        writer
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+                        write_noise_map(&settings, &errors, &path)
+                    })
+                }
+                _ => writer,
+            }
+        });
+    }
+
+    fn message(&self, message: &str) {
+        self.bars.suspend(|| eprintln!("{message}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intermediates_are_skipped_while_the_last_one_is_written() {
+        let intermediates = Intermediates::default();
+        // Writers that are running, the most of them that ever ran at once, and those started
+        let running = Arc::new(AtomicU64::new(0));
+        let most = Arc::new(AtomicU64::new(0));
+        let mut started = 0;
+        for _ in 0..40 {
+            let last = intermediates.start(|| {
+                started += 1;
+                let (running, most) = (running.clone(), most.clone());
+                thread::spawn(move || {
+                    most.fetch_max(running.fetch_add(1, Relaxed) + 1, Relaxed);
+                    thread::sleep(Duration::from_millis(100));
+                    running.fetch_sub(1, Relaxed);
+                    Ok(())
+                })
+            });
+            assert!(last.is_none_or(|last| last.is_ok()));
+            thread::sleep(Duration::from_millis(10));
+        }
+        intermediates.finish().unwrap();
+        assert_eq!(most.load(Relaxed), 1);
+        assert_eq!(running.load(Relaxed), 0);
+        // Intermediates come in ten times as fast as they are written, so most are skipped, but
+        // not those that come in once the last one is done
+        assert!(started > 1 && started < 20, "{started}");
+
+        // Errors of a writer are given once it is done
+        intermediates.start(|| thread::spawn(|| Err(NebulaeError::Cancelled)));
+        assert!(intermediates.finish().is_err());
+        assert!(intermediates.finish().is_ok());
     }
 }
@@ -2,20 +2,383 @@
 //! returning whether or not the input value "escapes" within the iteration limit, also returns the
 //! list of values from each iteration, necessary for rendering a Nebulabrot
 
-/// Real and imaginary parts of a complex number
+use crate::double_double::DoubleDouble;
+use crate::formula::Formula;
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// Recurrence that is iterated, as a variation on `z^d + c`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Variant {
+    /// The standard `z^d + c`
+    Mandelbrot,
+    /// Absolute values of both parts of `z` are taken before raising it to the power
+    BurningShip,
+    /// The conjugate of `z` is raised to the power
+    Tricorn,
+    /// The absolute value of the real part of `z^d` is taken
+    Celtic,
+}
+
+impl Variant {
+    /// Raises `z` to the power, as modified by this variant
+    fn power(self, z: Complex, power: f64) -> Complex {
+        match self {
+            Variant::Mandelbrot => z.pow(power),
+            Variant::BurningShip => Complex {
+                re: z.re.abs(),
+                im: z.im.abs(),
+            }
+            .pow(power),
+            Variant::Tricorn => z.conj().pow(power),
+            Variant::Celtic => {
+                let z = z.pow(power);
+                Complex {
+                    re: z.re.abs(),
+                    im: z.im,
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Variant::Mandelbrot => write!(f, "Mandelbrot"),
+            Variant::BurningShip => write!(f, "Burning Ship"),
+            Variant::Tricorn => write!(f, "Tricorn"),
+            Variant::Celtic => write!(f, "Celtic"),
+        }
+    }
+}
+
+/// Floating-point types that a [`Complex`] can be made of
+pub trait Float:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    /// The closest value of this type to a double precision `value`
+    fn from_f64(value: f64) -> Self;
+    /// This value in double precision
+    fn to_f64(self) -> f64;
+}
+
+impl Float for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Float for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+/// Real and imaginary parts of a complex number, in double precision unless stated otherwise
 #[derive(Clone, Copy)]
-pub struct Complex {
-    pub re: f64,
-    pub im: f64,
+pub struct Complex<T = f64> {
+    /// Real part
+    pub re: T,
+    /// Imaginary part
+    pub im: T,
+}
+
+impl<T: Float> Complex<T> {
+    /// Square of the distance to the origin
+    pub fn norm_sqr(self) -> T {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Complex conjugate
+    pub fn conj(self) -> Complex<T> {
+        Complex {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    /// Raises to a whole power, by exponentiation by squaring
+    pub fn powi(self, exponent: u32) -> Complex<T> {
+        let mut exponent = exponent;
+        let mut base = self;
+        let mut result = Complex {
+            re: T::from_f64(1.0),
+            im: T::from_f64(0.0),
+        };
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            exponent >>= 1;
+        }
+        result
+    }
+
+    /// Converts to another precision
+    pub fn cast<U: Float>(self) -> Complex<U> {
+        Complex {
+            re: U::from_f64(self.re.to_f64()),
+            im: U::from_f64(self.im.to_f64()),
+        }
+    }
+}
+
+impl Complex {
+    /// Exponential of a complex number
+    pub fn exp(self) -> Complex {
+        let modulus = self.re.exp();
+        Complex {
+            re: modulus * self.im.cos(),
+            im: modulus * self.im.sin(),
+        }
+    }
+
+    /// Principal natural logarithm of a complex number
+    pub fn log(self) -> Complex {
+        Complex {
+            re: self.norm_sqr().ln() / 2.0,
+            im: self.im.atan2(self.re),
+        }
+    }
+
+    /// Raises to a real power, by repeated multiplication for small whole powers, or in polar form
+    pub fn pow(self, power: f64) -> Complex {
+        if power.fract() == 0.0 && (1.0..=MAX_MULTIPLIED_POWER).contains(&power) {
+            self.powi(power as u32)
+        } else {
+            let modulus = self.norm_sqr().powf(power / 2.0);
+            let argument = self.im.atan2(self.re) * power;
+            Complex {
+                re: modulus * argument.cos(),
+                im: modulus * argument.sin(),
+            }
+        }
+    }
+}
+
+impl<T: Float> Add for Complex<T> {
+    type Output = Complex<T>;
+
+    fn add(self, other: Complex<T>) -> Complex<T> {
+        Complex {
+            re: self.re + other.re,
+            im: self.im + other.im,
+        }
+    }
+}
+
+impl<T: Float> Sub for Complex<T> {
+    type Output = Complex<T>;
+
+    fn sub(self, other: Complex<T>) -> Complex<T> {
+        Complex {
+            re: self.re - other.re,
+            im: self.im - other.im,
+        }
+    }
+}
+
+impl<T: Float> Mul for Complex<T> {
+    type Output = Complex<T>;
+
+    fn mul(self, other: Complex<T>) -> Complex<T> {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
 }
 
-/// Iterated Mandelbrot function that also returns the points that were traversed during iteration
-pub fn iterate(z: Complex, c: Complex, limit: u32, escape: f64, stop: f64) -> (Vec<Complex>, bool) {
+/// Scaling by a real number
+impl<T: Float> Mul<T> for Complex<T> {
+    type Output = Complex<T>;
+
+    fn mul(self, factor: T) -> Complex<T> {
+        Complex {
+            re: self.re * factor,
+            im: self.im * factor,
+        }
+    }
+}
+
+impl<T: Float> Div for Complex<T> {
+    type Output = Complex<T>;
+
+    fn div(self, other: Complex<T>) -> Complex<T> {
+        let norm_sqr = other.norm_sqr();
+        Complex {
+            re: (self.re * other.re + self.im * other.im) / norm_sqr,
+            im: (self.im * other.re - self.re * other.im) / norm_sqr,
+        }
+    }
+}
+
+impl<T: Float> Neg for Complex<T> {
+    type Output = Complex<T>;
+
+    fn neg(self) -> Complex<T> {
+        Complex {
+            re: -self.re,
+            im: -self.im,
+        }
+    }
+}
+
+/// Whether `c` is inside the main cardioid of the Mandelbrot set, so that its orbit never escapes
+pub fn in_main_cardioid(c: Complex) -> bool {
+    let re = c.re - 0.25;
+    let q = re * re + c.im * c.im;
+    q * (q + re) <= 0.25 * c.im * c.im
+}
+
+/// Whether `c` is inside the period-2 bulb of the Mandelbrot set, so that its orbit never escapes
+pub fn in_period2_bulb(c: Complex) -> bool {
+    let re = c.re + 1.0;
+    re * re + c.im * c.im <= 0.0625
+}
+
+/// Largest whole power that is computed by repeated multiplication rather than in polar form
+const MAX_MULTIPLIED_POWER: f64 = 64.0;
+
+/// Distance along each axis under which an orbit is considered to have come back to an earlier
+/// point
+const PERIODICITY_EPSILON: f64 = 1e-13;
+
+/// Brent-style detection of orbits that have fallen into a cycle, by comparing each point with a
+/// reference point that is moved forward at every power of two iterations
+struct CycleDetector {
+    reference: Complex,
+    next_update: u32,
+}
+
+impl CycleDetector {
+    fn new(z: Complex) -> CycleDetector {
+        CycleDetector {
+            reference: z,
+            next_update: 1,
+        }
+    }
+
+    /// Whether the point `z`, reached after `iter` iterations, closes a cycle
+    fn cycled(&mut self, z: Complex, iter: u32) -> bool {
+        if (z.re - self.reference.re).abs() < PERIODICITY_EPSILON
+            && (z.im - self.reference.im).abs() < PERIODICITY_EPSILON
+        {
+            return true;
+        }
+        if iter == self.next_update {
+            self.reference = z;
+            self.next_update = self.next_update.saturating_mul(2);
+        }
+        false
+    }
+}
+
+/// Iterated Multibrot function `z^power + c`, or a `variant` of it, that also records the points
+/// that were traversed during iteration
+/// The points are written to `zs`, a buffer owned by the caller that is cleared first, so that its
+/// allocation can be reused from one orbit to the next; returns whether the orbit escaped
+/// With `periodicity` checking, orbits that fall into a cycle are given up on early, and recorded
+/// as an empty orbit that didn't escape
+#[allow(clippy::too_many_arguments)]
+pub fn iterate_into(
+    z: Complex,
+    c: Complex,
+    variant: Variant,
+    power: f64,
+    limit: u32,
+    escape: f64,
+    stop: f64,
+    periodicity: bool,
+    zs: &mut Vec<Complex>,
+) -> bool {
+    zs.clear();
+    if variant == Variant::Mandelbrot && power == 2.0 {
+        return iterate_squared(z, c, limit, escape, stop, periodicity, zs);
+    }
     let mut z = z;
-    let mut zs: Vec<Complex> = Vec::new();
-    let mut escaped = false;
-    let escape_squared = escape * escape;
     let stop_squared = stop * stop;
+    let mut cycle = CycleDetector::new(z);
+
+    let mut iter = 0;
+
+    while (iter < limit) && (z.norm_sqr() < stop_squared) {
+        z = variant.power(z, power) + c;
+        zs.push(z);
+
+        iter += 1;
+        if periodicity && cycle.cycled(z, iter) {
+            zs.clear();
+            return false;
+        }
+    }
+
+    z.norm_sqr() > escape * escape
+}
+
+/// Iterated custom function that also records the points that were traversed during iteration,
+/// into a buffer like [`iterate_into`]
+pub fn iterate_formula_into(
+    z: Complex,
+    c: Complex,
+    formula: &Formula,
+    limit: u32,
+    escape: f64,
+    stop: f64,
+    zs: &mut Vec<Complex>,
+) -> bool {
+    let mut z = z;
+    zs.clear();
+    let stop_squared = stop * stop;
+
+    let mut iter = 0;
+
+    // Comparisons are false for NaN, which ends orbits that leave the function's domain
+    while (iter < limit) && (z.norm_sqr() < stop_squared) {
+        z = formula.evaluate(z, c);
+        zs.push(z);
+
+        iter += 1;
+    }
+
+    z.norm_sqr() > escape * escape
+}
+
+/// Iterated Mandelbrot function that also records the points that were traversed during iteration,
+/// in any precision
+/// The traversed points are recorded in double precision, to be plotted like any other orbit
+fn iterate_squared<T: Float>(
+    z: Complex<T>,
+    c: Complex<T>,
+    limit: u32,
+    escape: f64,
+    stop: f64,
+    periodicity: bool,
+    zs: &mut Vec<Complex>,
+) -> bool {
+    let mut z = z;
+    let mut escaped = false;
+    let mut cycle = CycleDetector::new(z.cast());
+    let two = T::from_f64(2.0);
+    let escape_squared = T::from_f64(escape * escape);
+    let stop_squared = T::from_f64(stop * stop);
 
     let mut z2 = Complex {
         re: z.re * z.re,
@@ -26,7 +389,7 @@ pub fn iterate(z: Complex, c: Complex, limit: u32, escape: f64, stop: f64) -> (V
 
     while (iter < limit) && (z2.re + z2.im < stop_squared) {
         // update z
-        z.im = 2.0 * z.re * z.im + c.im;
+        z.im = two * z.re * z.im + c.im;
         z.re = z2.re - z2.im + c.re;
 
         // update z^2
@@ -34,13 +397,406 @@ pub fn iterate(z: Complex, c: Complex, limit: u32, escape: f64, stop: f64) -> (V
         z2.im = z.im * z.im;
 
         // record path
-        zs.push(z);
+        let point = z.cast();
+        zs.push(point);
 
-        iter = iter + 1;
+        iter += 1;
+        if periodicity && cycle.cycled(point, iter) {
+            zs.clear();
+            return false;
+        }
     }
     if z2.re + z2.im > escape_squared {
         escaped = true;
     }
 
-    (zs, escaped)
+    escaped
+}
+
+/// Number of orbits that [`iterate_batch`] advances together
+pub const LANES: usize = 4;
+
+/// Iterated Mandelbrot function for a batch of orbits that are advanced in lockstep, so that the
+/// arithmetic can be vectorized across lanes
+/// Only as many lanes as there are buffers in `zs` are used, and each records its points into its
+/// own buffer like [`iterate_into`], retiring once it reaches the stop radius, the limit, or (with
+/// `periodicity` checking) a cycle; returns whether each orbit escaped
+pub fn iterate_batch(
+    z: [Complex; LANES],
+    c: [Complex; LANES],
+    limit: u32,
+    escape: f64,
+    stop: f64,
+    periodicity: bool,
+    zs: &mut [Vec<Complex>],
+) -> [bool; LANES] {
+    let escape_squared = escape * escape;
+    let stop_squared = stop * stop;
+    let (mut re, mut im) = (z.map(|z| z.re), z.map(|z| z.im));
+    let (c_re, c_im) = (c.map(|c| c.re), c.map(|c| c.im));
+    let mut re2 = re.map(|re| re * re);
+    let mut im2 = im.map(|im| im * im);
+    let mut cycles = z.map(CycleDetector::new);
+    let mut cycled = [false; LANES];
+    let mut active = [false; LANES];
+    for (lane, zs) in zs.iter_mut().enumerate() {
+        zs.clear();
+        active[lane] = limit > 0 && re2[lane] + im2[lane] < stop_squared;
+    }
+
+    let mut iter = 0;
+
+    while active.contains(&true) {
+        // Every lane is computed, but retired ones keep their last values
+        for lane in 0..LANES {
+            let next_im = 2.0 * re[lane] * im[lane] + c_im[lane];
+            let next_re = re2[lane] - im2[lane] + c_re[lane];
+            if active[lane] {
+                re[lane] = next_re;
+                im[lane] = next_im;
+                re2[lane] = next_re * next_re;
+                im2[lane] = next_im * next_im;
+            }
+        }
+
+        iter += 1;
+        for (lane, zs) in zs.iter_mut().enumerate() {
+            if active[lane] {
+                let z = Complex {
+                    re: re[lane],
+                    im: im[lane],
+                };
+                zs.push(z);
+                if periodicity && cycles[lane].cycled(z, iter) {
+                    zs.clear();
+                    cycled[lane] = true;
+                    active[lane] = false;
+                } else {
+                    active[lane] = iter < limit && re2[lane] + im2[lane] < stop_squared;
+                }
+            }
+        }
+    }
+
+    let mut escaped = [false; LANES];
+    for lane in 0..zs.len() {
+        escaped[lane] = !cycled[lane] && re2[lane] + im2[lane] > escape_squared;
+    }
+    escaped
+}
+
+/// Iterated Mandelbrot function in single precision, which is faster but only accurate enough for
+/// shallow views
+/// The traversed points are recorded in double precision, to be plotted like any other orbit
+pub fn iterate_f32_into(
+    z: Complex,
+    c: Complex,
+    limit: u32,
+    escape: f64,
+    stop: f64,
+    zs: &mut Vec<Complex>,
+) -> bool {
+    zs.clear();
+    iterate_squared::<f32>(z.cast(), c.cast(), limit, escape, stop, false, zs)
+}
+
+/// Orbit of 0 for a reference point `c`, computed in double-double precision, against which nearby
+/// orbits can be iterated as small differences
+pub struct ReferenceOrbit {
+    /// Points of the orbit, rounded to `f64`
+    points: Vec<Complex>,
+    /// Differences between the points of the orbit and `c`, rounded to `f64` after being computed
+    /// in double-double precision
+    offsets: Vec<Complex>,
+}
+
+impl ReferenceOrbit {
+    /// Computes the orbit of 0 for `c`, for up to `limit` iterations or until it reaches `stop`
+    pub fn new(c: Complex, limit: u32, stop: f64) -> ReferenceOrbit {
+        let c_re = DoubleDouble::from(c.re);
+        let c_im = DoubleDouble::from(c.im);
+        let (mut z_re, mut z_im) = (DoubleDouble::from(0.0), DoubleDouble::from(0.0));
+        let mut points = vec![Complex { re: 0.0, im: 0.0 }];
+        let mut offsets = vec![-c];
+        // The first iteration is kept even if it stops, so that there is always a step to take
+        while (points.len() as u32) <= limit.max(1)
+            && (points.len() < 2 || points[points.len() - 1].norm_sqr() < stop * stop)
+        {
+            let re = z_re * z_re - z_im * z_im + c_re;
+            z_im = DoubleDouble::from(2.0) * z_re * z_im + c_im;
+            z_re = re;
+            points.push(Complex {
+                re: z_re.to_f64(),
+                im: z_im.to_f64(),
+            });
+            offsets.push(Complex {
+                re: (z_re - c_re).to_f64(),
+                im: (z_im - c_im).to_f64(),
+            });
+        }
+        ReferenceOrbit { points, offsets }
+    }
+}
+
+/// Iterated Mandelbrot function for a `c` given by its offset `delta_c` from that of a
+/// [`ReferenceOrbit`], computed as the difference from the reference orbit so that it stays
+/// accurate at deep zooms
+/// Records the traversed points into a buffer like [`iterate_into`], as offsets from the
+/// reference's `c` rather than as points
+pub fn iterate_perturbed_into(
+    reference: &ReferenceOrbit,
+    z: Complex,
+    delta_c: Complex,
+    limit: u32,
+    escape: f64,
+    stop: f64,
+    offsets: &mut Vec<Complex>,
+) -> bool {
+    // The reference orbit starts at 0
+    let mut delta = z;
+    let mut index = 0;
+    offsets.clear();
+    let stop_squared = stop * stop;
+    let mut z_squared = z.norm_sqr();
+
+    let mut iter = 0;
+
+    while (iter < limit) && (z_squared < stop_squared) {
+        // (Z + δ)² + c - (Z² + C) = (2Z + δ)δ + δc
+        let point = reference.points[index];
+        delta = (point * 2.0 + delta) * delta + delta_c;
+        index += 1;
+
+        let point = reference.points[index];
+        let z = point + delta;
+        z_squared = z.norm_sqr();
+        offsets.push(reference.offsets[index] + delta);
+
+        // Rebase onto the start of the reference orbit when the difference would lose precision,
+        // or when the reference orbit runs out
+        if z_squared < delta.norm_sqr() || index + 1 == reference.points.len() {
+            delta = z;
+            index = 0;
+        }
+
+        iter += 1;
+    }
+
+    z_squared > escape * escape
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that two complex numbers are within a `tolerance` of each other
+    fn assert_near(a: Complex, b: Complex, tolerance: f64) {
+        assert!(
+            (a.re - b.re).abs() <= tolerance && (a.im - b.im).abs() <= tolerance,
+            "{} + {}i isn't {} + {}i",
+            a.re,
+            a.im,
+            b.re,
+            b.im
+        );
+    }
+
+    #[test]
+    fn complex_numbers_do_arithmetic() {
+        let (a, b) = (Complex { re: 1.0, im: 2.0 }, Complex { re: -3.0, im: 0.5 });
+        assert_near(a + b, Complex { re: -2.0, im: 2.5 }, 0.0);
+        assert_near(a - b, Complex { re: 4.0, im: 1.5 }, 0.0);
+        assert_near(a * b, Complex { re: -4.0, im: -5.5 }, 0.0);
+        assert_near(a * b / b, a, 1e-15);
+        assert_near(-a, Complex { re: -1.0, im: -2.0 }, 0.0);
+        assert_eq!(a.norm_sqr(), 5.0);
+        assert_near(a.powi(3), a * a * a, 0.0);
+        assert_near(a.powi(0), Complex { re: 1.0, im: 0.0 }, 0.0);
+        // Whole powers are multiplied out, and agree with the polar form of others nearby
+        assert_near(a.pow(2.0), a * a, 0.0);
+        assert_near(a.pow(2.0 + 1e-12), a * a, 1e-10);
+        assert_near(a.log().exp(), a, 1e-15);
+    }
+
+    /// Whether the orbit of 0 for `c` escapes within `limit` iterations, and its points
+    fn orbit(c: Complex, limit: u32, periodicity: bool) -> (bool, Vec<Complex>) {
+        let mut zs = vec![Complex { re: 9.0, im: 9.0 }];
+        let zero = Complex { re: 0.0, im: 0.0 };
+        let escaped = iterate_into(
+            zero,
+            c,
+            Variant::Mandelbrot,
+            2.0,
+            limit,
+            2.0,
+            2.0,
+            periodicity,
+            &mut zs,
+        );
+        (escaped, zs)
+    }
+
+    #[test]
+    fn cardioid_and_bulb_points_never_escape() {
+        let inside = [
+            (0.0, 0.0),
+            (-0.5, 0.5),
+            (0.2, 0.0),
+            (-1.0, 0.0),
+            (-1.2, 0.1),
+        ];
+        for (re, im) in inside {
+            let c = Complex { re, im };
+            assert!(in_main_cardioid(c) || in_period2_bulb(c), "{re} + {im}i");
+            assert!(!orbit(c, 10_000, false).0, "{re} + {im}i");
+        }
+        // Points of the set beyond both, and points outside of it
+        for (re, im) in [
+            (-0.1, 0.9),
+            (-1.755, 0.0),
+            (0.3, 0.0),
+            (-2.0, 0.5),
+            (0.26, 0.0),
+        ] {
+            let c = Complex { re, im };
+            assert!(!in_main_cardioid(c) && !in_period2_bulb(c), "{re} + {im}i");
+        }
+    }
+
+    #[test]
+    fn cycling_orbits_are_given_up_on() {
+        // 0 is a fixed point for c = 0, and -1 is a cycle of period 2 for c = -1
+        for re in [0.0, -1.0] {
+            let (escaped, zs) = orbit(Complex { re, im: 0.0 }, 1_000_000, true);
+            assert!(!escaped && zs.is_empty(), "{re}");
+        }
+        // Escaping orbits are followed all the way either way
+        let c = Complex { re: 0.4, im: 0.3 };
+        let (escaped, zs) = orbit(c, 1_000, true);
+        let (unchecked, all) = orbit(c, 1_000, false);
+        assert!(escaped && unchecked);
+        assert_eq!(zs.len(), all.len());
+    }
+
+    #[test]
+    fn buffers_hold_only_the_last_orbit() {
+        let mut zs = Vec::new();
+        let zero = Complex { re: 0.0, im: 0.0 };
+        let c = Complex { re: -0.1, im: 0.1 };
+        iterate_into(
+            zero,
+            c,
+            Variant::Mandelbrot,
+            2.0,
+            50,
+            2.0,
+            2.0,
+            false,
+            &mut zs,
+        );
+        assert_eq!(zs.len(), 50);
+        let c = Complex { re: 1.0, im: 1.0 };
+        assert!(iterate_into(
+            zero,
+            c,
+            Variant::Mandelbrot,
+            2.0,
+            50,
+            2.0,
+            2.0,
+            false,
+            &mut zs
+        ));
+        assert_eq!(zs.len(), 2);
+        assert_near(zs[0], c, 0.0);
+        assert!(zs.capacity() >= 50);
+    }
+
+    #[test]
+    fn batched_orbits_are_those_iterated_one_by_one() {
+        let zero = Complex { re: 0.0, im: 0.0 };
+        let cs =
+            [(0.4, 0.3), (-1.0, 0.0), (-0.1, 0.1), (1.0, 1.0)].map(|(re, im)| Complex { re, im });
+        for lanes in [LANES, 3] {
+            let mut batch = vec![Vec::new(); lanes];
+            let escaped = iterate_batch([zero; LANES], cs, 200, 2.0, 2.0, true, &mut batch);
+            for lane in 0..lanes {
+                let (alone, zs) = orbit(cs[lane], 200, true);
+                assert_eq!(escaped[lane], alone, "{lane}");
+                assert_eq!(batch[lane].len(), zs.len(), "{lane}");
+                for (a, b) in batch[lane].iter().zip(&zs) {
+                    assert_near(*a, *b, 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_precision_orbits_stay_close_for_a_while() {
+        let zero = Complex { re: 0.0, im: 0.0 };
+        let c = Complex { re: -0.7, im: 0.35 };
+        let mut zs = Vec::new();
+        let escaped = iterate_f32_into(zero, c, 20, 2.0, 2.0, &mut zs);
+        let (double, points) = orbit(c, 20, false);
+        assert_eq!((escaped, zs.len()), (double, points.len()));
+        for (a, b) in zs.iter().zip(&points) {
+            assert_near(*a, *b, 1e-4);
+        }
+    }
+
+    #[test]
+    fn multibrots_raise_to_their_power() {
+        let z = Complex { re: 0.3, im: -0.2 };
+        let c = Complex { re: 0.1, im: 0.4 };
+        let mut zs = Vec::new();
+        iterate_into(z, c, Variant::Mandelbrot, 3.0, 2, 2.0, 2.0, false, &mut zs);
+        let first = z * z * z + c;
+        assert_near(zs[0], first, 1e-15);
+        assert_near(zs[1], first * first * first + c, 1e-15);
+        // ... even for powers that aren't whole
+        iterate_into(z, c, Variant::Mandelbrot, 2.5, 1, 2.0, 2.0, false, &mut zs);
+        assert_near(
+            zs[0],
+            (z.log() * Complex { re: 2.5, im: 0.0 }).exp() + c,
+            1e-12,
+        );
+    }
+
+    #[test]
+    fn variants_modify_z_before_or_after_its_power() {
+        let z = Complex { re: -0.3, im: -0.2 };
+        let c = Complex { re: 0.1, im: 0.4 };
+        let step = |variant| {
+            let mut zs = Vec::new();
+            iterate_into(z, c, variant, 2.0, 1, 2.0, 2.0, false, &mut zs);
+            zs[0]
+        };
+        let absolute = Complex { re: 0.3, im: 0.2 };
+        assert_near(step(Variant::BurningShip), absolute * absolute + c, 1e-15);
+        assert_near(step(Variant::Tricorn), z.conj() * z.conj() + c, 1e-15);
+        let squared = z * z;
+        let celtic = Complex {
+            re: squared.re.abs(),
+            im: squared.im,
+        };
+        assert_near(step(Variant::Celtic), celtic + c, 1e-15);
+        assert_near(step(Variant::Mandelbrot), squared + c, 1e-15);
+    }
+
+    #[test]
+    fn perturbed_orbits_follow_their_own_c() {
+        let center = Complex { re: -0.75, im: 0.1 };
+        let reference = ReferenceOrbit::new(center, 100, 2.0);
+        let zero = Complex { re: 0.0, im: 0.0 };
+        for delta_c in [(1e-6, -2e-6), (0.05, 0.02)].map(|(re, im)| Complex { re, im }) {
+            let mut offsets = Vec::new();
+            let escaped =
+                iterate_perturbed_into(&reference, zero, delta_c, 100, 2.0, 2.0, &mut offsets);
+            let (direct, zs) = orbit(center + delta_c, 100, false);
+            assert_eq!((escaped, offsets.len()), (direct, zs.len()));
+            for (offset, z) in offsets.iter().zip(&zs) {
+                assert_near(center + *offset, *z, 1e-9);
+            }
+        }
+    }
 }
@@ -0,0 +1,168 @@
+//! Memory maps of files that hold the counters of images too large to comfortably keep in memory
+//!
+//! The file is created in a given directory, sized to hold every counter, mapped into memory,
+//! and at once removed from the directory, so that nothing is left behind however the program
+//! exits. Its pages only take memory while they are in use: the system writes cold ones back to
+//! the file to make room for others, rather than running out of memory, and pages that were never
+//! touched read as zero without taking any memory or disk at all.
+//!
+//! Only the program maps the file, so counters in it are atomics like any others, with the same
+//! semantics. A few caveats depend on the platform:
+//!
+//! - maps are only made on 64-bit Unix, and images stay in memory elsewhere
+//! - a directory on a tmpfs, as `/tmp` often is on Linux, keeps the file in memory anyway
+//! - the disk must have room for the whole image: the file is sparse, so running out of space
+//!   only shows once pages are written back, which kills the program (with `SIGBUS`)
+//! - counting into pages that were written back reads them from disk first, which is much slower
+//!   than memory when the points of a pass land all over an image larger than it
+
+use memmap2::{MmapMut, MmapOptions};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::ops::Deref;
+use std::path::Path;
+use std::process;
+use std::ptr::NonNull;
+use std::slice;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicUsize};
+
+/// Whether memory maps can be made on this platform
+pub const SUPPORTED: bool = cfg!(all(unix, target_pointer_width = "64"));
+
+/// Number of maps made so far, which tells their files apart
+static MAPS: AtomicUsize = AtomicUsize::new(0);
+
+/// Values that are valid when all of their bits are 0, and then mean 0
+///
+/// # Safety
+///
+/// Only types whose bit pattern of zero is a valid value, the one that [`Zeroed::zero`] returns,
+/// may implement this
+pub unsafe trait Zeroed {
+    /// The value whose bits are all 0
+    fn zero() -> Self;
+}
+
+unsafe impl Zeroed for AtomicU16 {
+    fn zero() -> Self {
+        AtomicU16::new(0)
+    }
+}
+
+unsafe impl Zeroed for AtomicU32 {
+    fn zero() -> Self {
+        AtomicU32::new(0)
+    }
+}
+
+unsafe impl Zeroed for AtomicU64 {
+    fn zero() -> Self {
+        AtomicU64::new(0)
+    }
+}
+
+/// Values held in a memory map of a file, which start at 0
+pub struct Mapped<T> {
+    values: NonNull<T>,
+    len: usize,
+    /// Map that the values are in, which is unmapped when it is dropped, or none when there are no
+    /// values to map
+    _map: Option<MmapMut>,
+}
+
+// The values are only reached through shared references, like those of a `Vec<T>`
+unsafe impl<T: Sync> Send for Mapped<T> {}
+unsafe impl<T: Sync> Sync for Mapped<T> {}
+
+impl<T: Zeroed> Mapped<T> {
+    /// Map `len` values in a file created in a `directory`
+    pub fn new(len: usize, directory: &Path) -> io::Result<Mapped<T>> {
+        let bytes = len
+            .checked_mul(size_of::<T>())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The map is too large"))?;
+        if !SUPPORTED {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Memory maps are only made on 64-bit Unix",
+            ));
+        }
+        if bytes == 0 {
+            return Ok(Mapped {
+                values: NonNull::dangling(),
+                len,
+                _map: None,
+            });
+        }
+        let path = directory.join(format!(
+            "nebulae-{}-{}.counts",
+            process::id(),
+            MAPS.fetch_add(1, Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // The file is only reached through the map from now on, which outlives its name, so that
+        // nothing else can change it under the map
+        let map = file
+            .set_len(bytes as u64)
+            .and_then(|_| unsafe { MmapOptions::new().len(bytes).map_mut(&file) });
+        let removed = fs::remove_file(&path);
+        let mut map = map?;
+        removed?;
+        Ok(Mapped {
+            // Maps start at the start of a page, which is aligned for any value
+            values: NonNull::new(map.as_mut_ptr().cast()).expect("maps are never at address 0"),
+            len,
+            _map: Some(map),
+        })
+    }
+}
+
+impl<T> Deref for Mapped<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // The map holds `len` values, which are valid as they start at 0, until it is dropped
+        unsafe { slice::from_raw_parts(self.values.as_ptr(), self.len) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw_image::RawImage;
+    use crate::render_settings::Layout;
+
+    #[test]
+    fn maps_start_at_0_and_leave_no_file_behind() {
+        let directory = std::env::temp_dir().join(format!("nebulae-maps-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        if !SUPPORTED {
+            assert!(Mapped::<AtomicU32>::new(4, &directory).is_err());
+            return;
+        }
+        let values = Mapped::<AtomicU64>::new(1 << 20, &directory).unwrap();
+        assert!(values.iter().all(|value| value.load(Relaxed) == 0));
+        values[12_345].fetch_add(7, Relaxed);
+        assert_eq!(values[12_345].load(Relaxed), 7);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        assert!(Mapped::<AtomicU16>::new(0, &directory).unwrap().is_empty());
+
+        let (mapped, memory) = (
+            RawImage::mapped(70, 20, 3, 32, Layout::Tiled, &directory).unwrap(),
+            RawImage::new(70, 20, 3, 32, Layout::Tiled),
+        );
+        assert!(mapped.is_mapped() && !memory.is_mapped());
+        for image in [&mapped, &memory] {
+            image.add(69, 19, 2, 5);
+            image.add(0, 3, 1, 2);
+        }
+        assert_eq!(mapped.get_data(), memory.get_data());
+        assert!(!mapped.snapshot().is_mapped());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+//! Masks that restrict where the points of orbits are counted, read from the gray levels of an
+//! image
+//!
+//! A mask is stretched over the image that points are counted in, at its full resolution with
+//! supersampling. A point that lands on a black pixel of the mask isn't counted, one that lands on
+//! a white pixel is, and one that lands on a gray pixel is counted with a probability of its level,
+//! so that counts stay whole while soft edges keep their shades. Whether a point is counted is
+//! decided by a hash of the point rather than by a random stream, so that masked renders stay
+//! reproducible under a seed.
+
+use crate::error::NebulaeError;
+use crate::output::read_png;
+
+/// Gray levels of a mask, resampled to the image that points are counted in
+pub struct Mask {
+    width: u32,
+    /// Level of each pixel of the mask, row by row, from 0 (black) to 255 (white)
+    levels: Vec<u8>,
+    /// Width and height of the image the mask was read from, in pixels
+    source: (u32, u32),
+}
+
+impl Mask {
+    /// Read the mask at `path`, a PNG image of any color type, stretched to `width` × `height`
+    /// pixels
+    /// Colors are taken by their luma, and transparent pixels are black
+    pub fn load(path: &str, width: u32, height: u32) -> Result<Mask, NebulaeError> {
+        let (info, data) = read_png(path, png::Transformations::normalize_to_color8())?;
+        let level = |pixel: &[u8]| -> u8 {
+            let luma = |r: u8, g: u8, b: u8| {
+                ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114 + 500) / 1000) as u8
+            };
+            let opacity = |level: u8, alpha: u8| ((level as u32 * alpha as u32 + 127) / 255) as u8;
+            match *pixel {
+                [level] => level,
+                [level, alpha] => opacity(level, alpha),
+                [r, g, b] => luma(r, g, b),
+                [r, g, b, alpha] => opacity(luma(r, g, b), alpha),
+                _ => 0,
+            }
+        };
+        let channels = info.color_type.samples();
+        let (source_width, source_height) = (info.width, info.height);
+        let rows: Vec<&[u8]> = data
+            .chunks_exact(info.line_size)
+            .take(source_height as usize)
+            .collect();
+        // Each pixel takes the level of the source pixel that its center falls in
+        let source_of = |at: u32, length: u32, source_length: u32| {
+            ((2 * at as u64 + 1) * source_length as u64 / (2 * length as u64)) as usize
+        };
+        let column = |x: u32| source_of(x, width, source_width);
+        let levels = (0..height)
+            .flat_map(|y| {
+                let row = rows[source_of(y, height, source_height)];
+                (0..width).map(move |x| level(&row[column(x) * channels..][..channels]))
+            })
+            .collect();
+        Ok(Mask {
+            width,
+            levels,
+            source: (source_width, source_height),
+        })
+    }
+
+    /// Width and height of the image the mask was read from, in pixels
+    pub fn source_size(&self) -> (u32, u32) {
+        self.source
+    }
+
+    /// Whether a point that lands on the pixel at `(x, y)` is counted, given a `hash` of the point
+    /// that is spread evenly, and tells it apart from the other points that land there
+    pub fn keeps(&self, x: u32, y: u32, hash: u64) -> bool {
+        match self.levels[y as usize * self.width as usize + x as usize] {
+            0 => false,
+            u8::MAX => true,
+            level => hash % 255 < u64::from(level),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+
+    /// Path of a PNG mask of some gray `levels` with their `alpha`, `width` pixels wide, written
+    /// in a directory of this test run
+    fn mask_file(name: &str, width: u32, levels: &[[u8; 2]]) -> String {
+        let directory = std::env::temp_dir().join(format!("nebulae-mask-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(name);
+        let height = levels.len() as u32 / width;
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), width, height);
+        encoder.set_color(png::ColorType::GrayscaleAlpha);
+        let data: Vec<u8> = levels.concat();
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&data)
+            .unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn masks_are_stretched_over_the_image() {
+        let path = mask_file("halves.png", 2, &[[0, 255], [255, 255]]);
+        let mask = Mask::load(&path, 4, 2).unwrap();
+        assert_eq!(mask.source_size(), (2, 1));
+        for y in 0..2 {
+            for (x, kept) in [false, false, true, true].into_iter().enumerate() {
+                assert!((0..100).all(|hash| mask.keeps(x as u32, y, hash) == kept));
+            }
+        }
+    }
+
+    #[test]
+    fn gray_and_transparent_pixels_keep_some_points() {
+        let path = mask_file("shades.png", 3, &[[255, 0], [51, 255], [255, 51]]);
+        let mask = Mask::load(&path, 3, 1).unwrap();
+        assert!((0..255).all(|hash| !mask.keeps(0, 0, hash)));
+        for x in [1, 2] {
+            let kept = (0..255 * 4).filter(|&hash| mask.keeps(x, 0, hash)).count();
+            assert_eq!(kept, 51 * 4, "{x}");
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Metropolis-Hastings sampling, which wanders from sample to sample in proportion to how much of
+//! their orbits lands in view, so that zoomed in renders don't spend almost all of their time on
+//! orbits that are never seen
+//!
+//! Samples are points `x` of the unit square (mapped onto the sampling region as usual), and a
+//! uniform render estimates the image `I(p) = ∫ h_p(x) dx`, where `h_p(x)` is the number of times
+//! that the orbit of `x` is plotted at pixel `p`, by averaging `h_p` over independent samples.
+//!
+//! A chain instead visits samples distributed as `π(x) = f(x) / B`, where `f(x) = Σ_p h_p(x)` is
+//! the number of points that the orbit plots in view, across every channel, and `B = ∫ f(x) dx`.
+//! Each step proposes a new sample `y`, either anywhere in the square (a large step), or near the
+//! current sample (a small step), both of which are symmetric, and moves to it with probability
+//! `min(1, f(y) / f(x))`. Then, since `E_π[h_p / f] = I(p) / B`, plotting the current sample of
+//! every step with a weight of `B / f(x)` per point estimates the same image as a uniform render
+//! does, sample for sample: the chain just spends its samples where the image is.
+//!
+//! `B` is the mean of `f` over uniform samples, which is measured once for the whole render, so
+//! that every chain plots on the same scale. As the image only holds whole counts, every weight is
+//! also multiplied by a scale `S`, about the mean of `f` over the samples that chains visit
+//! (`E_π[f] / B = E[f²] / E[f]²` over uniform samples), which brings the weight of a typical point
+//! to about 1; each point is then plotted `⌊w⌋` times, and once more with probability `w - ⌊w⌋`,
+//! for a weight `w`, which keeps its expected value. Each chain starts with a warm-up of uniform
+//! samples, plotted with a weight of `S` as they would be in a uniform render, and starts from one
+//! of them picked in proportion to `f`, which already follows `π`, so no steps need to be thrown
+//! away.
+
+use crate::render::CancelToken;
+use rand::Rng;
+use std::f64::consts::TAU;
+
+/// A point plotted by an orbit: its pixel, the channel it is plotted in, and its share of the
+/// point, which is shared between several pixels by bilinear splats
+pub type Plot = (u32, u32, u32, u32);
+
+/// Probability that a step proposes a sample anywhere in the square, which lets chains reach
+/// every part of the image instead of getting stuck around one orbit
+const LARGE_STEP: f64 = 0.1;
+
+/// Ratio between the largest and smallest small steps, whose lengths are spread evenly on a
+/// logarithmic scale between them
+const STEP_RANGE: f64 = 1024.0;
+
+/// Number of uniform samples that [`Calibration`]s are measured over
+pub const CALIBRATION_SAMPLES: u64 = 100_000;
+
+/// Largest scale of the weights, which keeps the counts of the image from overflowing
+const MAX_SCALE: f64 = 65_536.0;
+
+/// Sums over uniform samples of the number of points `f` that each one plots, and of its square
+#[derive(Clone, Copy, Default)]
+pub struct Tally {
+    samples: u64,
+    total: u64,
+    squares: f64,
+}
+
+impl Tally {
+    /// Count a sample whose orbit plots `points` points
+    pub fn add(&mut self, points: u64) {
+        self.samples += 1;
+        self.total += points;
+        self.squares += (points as f64).powi(2);
+    }
+
+    /// Combine two tallies of different samples
+    pub fn merge(self, other: Tally) -> Tally {
+        Tally {
+            samples: self.samples + other.samples,
+            total: self.total + other.total,
+            squares: self.squares + other.squares,
+        }
+    }
+}
+
+/// Weights that chains plot their points with, measured over uniform samples
+pub struct Calibration {
+    /// Mean number of points plotted by a uniform sample, `B`
+    mean: f64,
+    /// Whole weight of every point of a uniform sample, `S`
+    scale: u32,
+}
+
+impl Calibration {
+    /// Calibrate the weights from a [`Tally`] of uniform samples
+    pub fn new(tally: Tally) -> Calibration {
+        let mean = tally.total as f64 / tally.samples.max(1) as f64;
+        let scale = if tally.total == 0 {
+            1.0
+        } else {
+            (tally.squares * tally.samples as f64 / (tally.total as f64).powi(2))
+                .round()
+                .clamp(1.0, MAX_SCALE)
+        };
+        Calibration {
+            mean,
+            scale: scale as u32,
+        }
+    }
+}
+
+/// Run a chain of `samples` samples, the first `warmup` of which are uniform, or more when none
+/// of those plotted any point in view
+///
+/// `step` is the largest small step along each axis of the unit square, and `trace` iterates
+/// the orbit of a sample, adding the points it plots to a buffer, and returns whether the sample
+/// was skipped without iterating. Every point is passed to `plot` with the number of times to
+/// plot it. The chain stops early once `cancel` is cancelled. Returns how many samples were
+/// skipped.
+#[allow(clippy::too_many_arguments)]
+pub fn run_chain<R: Rng>(
+    rng: &mut R,
+    samples: u64,
+    warmup: u64,
+    step: (f64, f64),
+    calibration: &Calibration,
+    cancel: &CancelToken,
+    mut trace: impl FnMut(&mut R, (f64, f64), &mut Vec<Plot>) -> bool,
+    mut plot: impl FnMut(Plot, u32),
+) -> u64 {
+    let mut skipped = 0;
+    let (mut current, mut proposed) = (Vec::new(), Vec::new());
+    let mut sample = (0.0, 0.0);
+    // Uniform samples so far, and how many points they plotted in total
+    let (mut uniform, mut total) = (0, 0);
+    for _ in 0..samples {
+        if cancel.is_cancelled() {
+            break;
+        }
+        proposed.clear();
+        // Nothing was ever seen in view, so that there is nothing to weigh chains against
+        if uniform < warmup || current.is_empty() || calibration.mean == 0.0 {
+            let point = (rng.gen(), rng.gen());
+            if trace(rng, point, &mut proposed) {
+                skipped += 1;
+            }
+            for &point in &proposed {
+                plot(point, calibration.scale);
+            }
+            uniform += 1;
+            total += proposed.len() as u64;
+            // Keeps each sample with a probability of its share of the points seen so far, so
+            // that the last one kept was picked in proportion to `f`
+            if !proposed.is_empty() && rng.gen_range(0..total) < proposed.len() as u64 {
+                std::mem::swap(&mut current, &mut proposed);
+                sample = point;
+            }
+            continue;
+        }
+
+        let point = if rng.gen::<f64>() < LARGE_STEP {
+            (rng.gen(), rng.gen())
+        } else {
+            let length = STEP_RANGE.powf(-rng.gen::<f64>());
+            let (sin, cos) = (TAU * rng.gen::<f64>()).sin_cos();
+            (
+                sample.0 + step.0 * length * cos,
+                sample.1 + step.1 * length * sin,
+            )
+        };
+        // Samples outside of the square have no orbit, and are never moved to
+        if (0.0..1.0).contains(&point.0)
+            && (0.0..1.0).contains(&point.1)
+            && trace(rng, point, &mut proposed)
+        {
+            skipped += 1;
+        }
+        if rng.gen::<f64>() * (current.len() as f64) < proposed.len() as f64 {
+            std::mem::swap(&mut current, &mut proposed);
+            sample = point;
+        }
+        let weight = calibration.scale as f64 * calibration.mean / current.len() as f64;
+        for &point in &current {
+            let copies = copies(rng, weight);
+            if copies > 0 {
+                plot(point, copies);
+            }
+        }
+    }
+    skipped
+}
+
+/// Number of times to plot a point of a given `weight`: its whole part, and once more with a
+/// probability of its fractional part, which keeps the expected value of the image
+pub fn copies<R: Rng>(rng: &mut R, weight: f64) -> u32 {
+    weight as u32 + u32::from(rng.gen::<f64>() < weight.fract())
+}
@@ -0,0 +1,154 @@
+//! Estimates of how noisy each pixel of a render still is, from how much the points that each
+//! pass adds to it vary from one pass to the next
+//!
+//! Passes are independent draws of the same sum, so the counts that they add to a pixel spread
+//! around its mean like any samples do: their sample variance is that of a pass, of which the
+//! image is the sum over every pass. The relative standard error of each pixel is then the
+//! square root of that sum of variances over its count, which falls off as the square root of the
+//! number of passes, and shows where more of them would still change the image.
+//!
+//! Unlike the estimate of [`crate::adaptive`], which takes counts to be Poisson distributed, this
+//! one is measured, so it holds for points that are weighted or shared between pixels, and for
+//! orbits whose points land in the same pixel over and over. Counts are taken over every channel
+//! of each pixel of the output image, summed over the accumulated pixels that are averaged into
+//! it.
+//!
+//! Passes stratified together aren't independent: each one fills in where the others left gaps,
+//! so they vary more from one to the next than their sum does, and the map overstates the noise
+//! of their image.
+
+use crate::raw_image::RawImage;
+use rayon::prelude::*;
+
+/// Counts of each pixel of an image as of its last pass, and the sums of the squares of the
+/// counts that every pass added to them
+pub struct NoiseMap {
+    width: u32,
+    height: u32,
+    /// Number of accumulated pixels along each axis of a pixel of the image
+    factor: u32,
+    channels: u32,
+    /// Number of passes added so far
+    passes: u64,
+    /// Count of each pixel as of the last pass added
+    totals: Vec<u64>,
+    /// Sum of the squares of the counts of each pass
+    squares: Vec<f64>,
+}
+
+impl NoiseMap {
+    /// Noise map of an image of `width` × `height` pixels, accumulated in some `channels` at
+    /// `factor` times its resolution, before any pass
+    pub fn new(width: u32, height: u32, factor: u32, channels: u32) -> NoiseMap {
+        let pixels = width as usize * height as usize;
+        NoiseMap {
+            width,
+            height,
+            factor,
+            channels,
+            passes: 0,
+            totals: vec![0; pixels],
+            squares: vec![0.0; pixels],
+        }
+    }
+
+    /// Number of bytes of memory that the noise map of an image of `width` × `height` pixels
+    /// holds
+    pub fn bytes(width: u32, height: u32) -> u128 {
+        u128::from(width) * u128::from(height) * 16
+    }
+
+    /// Width and height of the map
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Number of passes added to the map
+    pub fn passes(&self) -> u64 {
+        self.passes
+    }
+
+    /// Add the counts of the pass that was just counted into the accumulated `image`
+    pub fn add_pass(&mut self, image: &RawImage) {
+        let (width, factor) = (self.width as usize, self.factor);
+        let pixel_values = (self.channels * factor) as usize;
+        self.totals
+            .par_chunks_exact_mut(width)
+            .zip(self.squares.par_chunks_exact_mut(width))
+            .enumerate()
+            .for_each(|(y, (totals, squares))| {
+                let mut counts = vec![0u64; width];
+                for row in y as u32 * factor..(y as u32 + 1) * factor {
+                    for (index, value) in image.row(row).enumerate() {
+                        counts[index / pixel_values] += value;
+                    }
+                }
+                for ((total, square), count) in totals.iter_mut().zip(squares).zip(counts) {
+                    let added = count.saturating_sub(*total) as f64;
+                    *square += added * added;
+                    *total = count;
+                }
+            });
+        self.passes += 1;
+    }
+
+    /// Relative standard error of the count of each pixel, row by row, from 0 for a pixel that
+    /// took the same count from every pass, up to 1 for one as uncertain as its count or more,
+    /// and for those that took none yet
+    /// Returns `None` before the second pass, as a single pass has nothing to vary against
+    pub fn errors(&self) -> Option<Vec<f32>> {
+        if self.passes < 2 {
+            return None;
+        }
+        let passes = self.passes as f64;
+        let errors = self
+            .totals
+            .par_iter()
+            .zip(&self.squares)
+            .map(|(&total, &squares)| {
+                if total == 0 {
+                    return 1.0;
+                }
+                let total = total as f64;
+                let variance = ((squares - total * total / passes) / (passes - 1.0)).max(0.0);
+                ((passes * variance).sqrt() / total).min(1.0) as f32
+            })
+            .collect();
+        Some(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_settings::Layout;
+
+    #[test]
+    fn steady_pixels_have_no_noise() {
+        // Two pixels of the map, each of 2 × 2 accumulated pixels of 2 channels
+        let image = RawImage::new(4, 2, 2, 32, Layout::Linear);
+        let mut map = NoiseMap::new(2, 1, 2, 2);
+        for pass in 0..3 {
+            assert_eq!(map.errors().is_none(), pass < 2);
+            for (x, y, channel) in [(0, 0, 0), (1, 1, 1), (1, 0, 0)] {
+                image.add(x, y, channel, 5);
+            }
+            map.add_pass(&image);
+        }
+        assert_eq!(map.passes(), 3);
+        assert_eq!(map.errors(), Some(vec![0.0, 1.0]));
+    }
+
+    #[test]
+    fn errors_are_those_of_the_passes_counts() {
+        let image = RawImage::new(1, 1, 1, 32, Layout::Linear);
+        let mut map = NoiseMap::new(1, 1, 1, 1);
+        for count in [2, 4] {
+            image.add(0, 0, 0, count);
+            map.add_pass(&image);
+        }
+        // Passes of 2 and 4 points vary by 2, of a sum of 6 points of two passes
+        let error = map.errors().unwrap()[0];
+        assert!((error - 1.0 / 3.0).abs() < 1e-6, "{error}");
+    }
+}
@@ -0,0 +1,1062 @@
+//! Writing of rendered images into PNG files: their counts are downsampled, mixed, denoised,
+//! tonemapped and composited over a background into samples a few rows at a time, as they are
+//! encoded
+
+use crate::denoise;
+use crate::error::NebulaeError;
+use crate::raw_image::{RawImage, RawImageF32};
+use crate::render_settings::{Blend, Denoise, Normalize, PerChannel, RenderSettings, Splat};
+use rayon::prelude::*;
+use std::cmp::min;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Number of steps that each count is split into once channels are mixed, which keeps the
+/// fractions of mixed values
+const MIX_SCALE: f64 = 256.0;
+
+/// Number of steps that a weight of 1 is split into when sums of weighted points are written,
+/// which keeps their fractions as they are tonemapped like counts
+const WEIGHT_SCALE: f64 = 65_536.0;
+
+/// Number of steps that the logarithm of a smooth escape time is split into when it is written,
+/// which keeps its fractions as it is tonemapped like counts
+const ESCAPE_TIME_STEPS: f64 = 65_536.0;
+
+/// Number of the smallest values of each channel whose tonemapped values are looked up in a table
+/// rather than computed, which covers most pixels of most images
+const TONEMAP_TABLE_SIZE: u64 = 65_536;
+
+/// Number of rows of the output image that are tonemapped together before they are written
+const WRITE_ROWS: u32 = 64;
+
+/// Size of the chunks of compressed image data in a PNG that is written a few rows at a time, in
+/// bytes, which is large enough that their headers don't add to the size of the file
+const PNG_CHUNK_BYTES: usize = 1 << 20;
+
+/// Counts of an image to write
+pub enum Source {
+    /// An image that nothing adds to anymore, such as a snapshot of one that is still being
+    /// rendered, which is read a few rows at a time
+    Image(Arc<RawImage>),
+    /// An image of weighted points, once nothing adds to it anymore, whose sums are read in
+    /// multiples of `1 / WEIGHT_SCALE`
+    Weights(Arc<RawImageF32>),
+}
+
+impl Source {
+    /// Values of `count` rows from row `first`, side by side
+    fn rows(&self, first: u32, count: u32) -> Vec<u64> {
+        match self {
+            Source::Image(image) => (first..first + count).flat_map(|y| image.row(y)).collect(),
+            Source::Weights(image) => (first..first + count)
+                .flat_map(|y| image.row(y))
+                .map(scale_weight)
+                .collect(),
+        }
+    }
+
+    /// Number of steps that each point is split into in the values of the rows, by the `splat`
+    /// it was counted with
+    fn scale(&self, splat: Splat) -> f64 {
+        match self {
+            Source::Weights(_) => WEIGHT_SCALE,
+            _ => splat.scale() as f64,
+        }
+    }
+
+    /// Largest count of each channel
+    fn maxima(&self) -> Vec<u64> {
+        match self {
+            Source::Image(image) => image.get_channel_maxima(),
+            Source::Weights(image) => image
+                .get_channel_maxima()
+                .into_iter()
+                .map(scale_weight)
+                .collect(),
+        }
+    }
+}
+
+/// A sum of weights in multiples of `1 / WEIGHT_SCALE`
+fn scale_weight(sum: f32) -> u64 {
+    (sum as f64 * WEIGHT_SCALE).round() as u64
+}
+
+/// Write the image of a `source` to an output path, a few rows at a time, so that the output
+/// image is never held whole unless it is denoised, composited over a `background` loaded from
+/// the settings if there is one
+pub fn write_image(
+    settings: RenderSettings,
+    output_path: &str,
+    source: Source,
+    background: Option<Arc<Background>>,
+) -> JoinHandle<Result<(), NebulaeError>> {
+    let output_path = String::from(output_path);
+    thread::spawn(move || {
+        let path = Path::new(output_path.as_str());
+        let (width, height) = (settings.width, settings.height);
+        let (channels, outputs) = (settings.image_channels(), settings.output_channels());
+        let factor = settings.supersample;
+        // Separate bands are written as they are
+        let mixes = !settings.channel_mixing.is_empty() && !settings.separates_bands();
+        // Counts of each row of the output image, before they are denoised
+        let counts = |y: u32| {
+            let rows = source.rows(y * factor, factor);
+            let row = if factor > 1 {
+                downsample(&rows, width, 1, channels, factor)
+            } else {
+                rows
+            };
+            if mixes {
+                mix_channels(&row, channels, &settings.channel_mixing)
+            } else {
+                row
+            }
+        };
+        // Denoising takes the neighbours of each pixel, so it takes the whole image
+        let denoised = (settings.denoise != Denoise::None).then(|| {
+            let data: Vec<u64> = (0..height).into_par_iter().flat_map_iter(counts).collect();
+            denoise::denoise(
+                &data,
+                width,
+                height,
+                outputs,
+                settings.denoise,
+                // The strength is a number of points
+                settings.denoise_strength * source.scale(settings.splat),
+            )
+        });
+        // Without denoising, the maxima are those of the source's own counts, or they are found in
+        // a first reading of every row
+        let row_length = width as usize * outputs as usize;
+        let larger =
+            |a: Vec<u64>, b: Vec<u64>| a.into_iter().zip(b).map(|(a, b)| a.max(b)).collect();
+        let mut maxima = match &denoised {
+            Some(data) => data
+                .par_chunks_exact(row_length)
+                .map(|row| channel_maxima(row, outputs))
+                .reduce(|| vec![0; outputs as usize], larger),
+            None if factor == 1 && !mixes => source.maxima(),
+            None => (0..height)
+                .into_par_iter()
+                .map(|y| channel_maxima(&counts(y), outputs))
+                .reduce(|| vec![0; outputs as usize], larger),
+        };
+        if settings.normalize == Normalize::Global {
+            let maximum = maxima.iter().copied().max().unwrap_or(0);
+            maxima.fill(maximum);
+        }
+        let tonemap = Tonemap::new(&maxima, &settings.curve);
+        let colors = |y: u32| {
+            let row = match &denoised {
+                Some(data) => data[y as usize * row_length..][..row_length].to_vec(),
+                None => counts(y),
+            };
+            let background = background
+                .as_deref()
+                .map(|background| (background.row(y), settings.blend));
+            map_to_color(row, &tonemap, settings.bit_depth, background)
+        };
+        let Some(bands) = settings
+            .bands
+            .as_ref()
+            .filter(|_| settings.separates_bands())
+        else {
+            return data_to_png(colors, width, height, outputs, settings.bit_depth, path);
+        };
+        // Bands are tonemapped together, so that they share their scale under global
+        // normalization, and each is written on its own
+        let bytes = settings.bit_depth as usize / 8;
+        bands
+            .iter()
+            .enumerate()
+            .try_for_each(|(band, &escape_time)| {
+                let colors = |y: u32| -> Vec<u8> {
+                    let row = colors(y);
+                    let samples = row.chunks_exact(bytes).skip(band).step_by(outputs as usize);
+                    samples.flatten().copied().collect()
+                };
+                let path = band_path(path, escape_time);
+                data_to_png(colors, width, height, 1, settings.bit_depth, &path)
+            })
+    })
+}
+
+/// Tonemap the smooth escape times of an image given by
+/// [`render_escape_times`](crate::render_escape_times) into a grayscale PNG file at `path`, scaled
+/// by the longest of them and raised to the curve of the first channel, after they are averaged
+/// down from the supersampled image
+/// Times run from a few iterations far from the set to thousands close to it, so they are
+/// tonemapped by their logarithm, which shows both, from the shortest of the image on, which
+/// keeps the contrast of deep zooms whose every orbit takes thousands of iterations
+pub fn write_escape_times(
+    settings: &RenderSettings,
+    times: &[f32],
+    path: &str,
+) -> Result<(), NebulaeError> {
+    let (width, height) = (settings.width, settings.height);
+    let steps = |time: f32| ((time as f64).ln_1p() * ESCAPE_TIME_STEPS).round() as u64;
+    let shortest = times
+        .iter()
+        .filter(|&&time| time > 0.0)
+        .map(|&time| steps(time))
+        .min()
+        .unwrap_or(0);
+    // Orbits that escape stay brighter than those that don't, even the shortest of them
+    let steps: Vec<u64> = times
+        .iter()
+        .map(|&time| match time {
+            0.0 => 0,
+            time => steps(time) - shortest + 1,
+        })
+        .collect();
+    let data = match settings.supersample {
+        1 => steps,
+        factor => downsample(&steps, width, height, 1, factor),
+    };
+    let maximum = data.iter().copied().max().unwrap_or(0);
+    let tonemap = Tonemap::new(&[maximum], &settings.curve);
+    let colors = |y: u32| {
+        let row = data[y as usize * width as usize..][..width as usize].to_vec();
+        map_to_color(row, &tonemap, settings.bit_depth, None)
+    };
+    data_to_png(
+        colors,
+        width,
+        height,
+        1,
+        settings.bit_depth,
+        Path::new(path),
+    )
+}
+
+/// Write the relative standard errors of the pixels of an image given by a
+/// [`NoiseMap`](crate::NoiseMap) into a grayscale PNG file at `path`, in the bit depth of the
+/// image, on a linear scale from black for no error to white for an error of 100%
+/// Errors aren't tonemapped, so that maps of different renders, or of one render as it goes, can
+/// be compared
+pub fn write_noise_map(
+    settings: &RenderSettings,
+    errors: &[f32],
+    path: &str,
+) -> Result<(), NebulaeError> {
+    let (width, height) = (settings.width, settings.height);
+    let colors = |y: u32| {
+        let row = &errors[y as usize * width as usize..][..width as usize];
+        to_samples(row.iter().map(|&error| error as f64), settings.bit_depth)
+    };
+    data_to_png(
+        colors,
+        width,
+        height,
+        1,
+        settings.bit_depth,
+        Path::new(path),
+    )
+}
+
+/// Path of the image of the band of orbits that escape within `escape_time` iterations, which is
+/// named after the output image at `path`
+fn band_path(path: &Path, escape_time: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-band-{escape_time}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-band-{escape_time}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Box-filter data of some `channels` accumulated at `factor` times the resolution down to
+/// `width` × `height` pixels
+pub fn downsample(data: &[u64], width: u32, height: u32, channels: u32, factor: u32) -> Vec<u64> {
+    let (width, height, factor) = (width as usize, height as usize, factor as usize);
+    let channels = channels as usize;
+    let source_width = width * factor;
+    let mut filtered = vec![0; width * height * channels];
+    for (row, source_row) in data.chunks_exact(source_width * channels).enumerate() {
+        let target_row = &mut filtered[(row / factor) * width * channels..][..width * channels];
+        for (column, pixel) in source_row.chunks_exact(channels).enumerate() {
+            let target = &mut target_row[(column / factor) * channels..][..channels];
+            for (sum, value) in target.iter_mut().zip(pixel) {
+                *sum += value;
+            }
+        }
+    }
+    filtered
+}
+
+/// Mix the data of some `channels` into the channels of the output image, with the `weights` of
+/// every channel in each of them
+/// Returns the mixed data, in multiples of `1 / MIX_SCALE` of a count
+pub fn mix_channels(data: &[u64], channels: u32, weights: &[Vec<f64>]) -> Vec<u64> {
+    data.par_chunks_exact(channels as usize)
+        .flat_map_iter(|pixel| {
+            weights.iter().map(move |weights| {
+                let value: f64 = weights
+                    .iter()
+                    .zip(pixel)
+                    .map(|(weight, &count)| weight * count as f64)
+                    .sum();
+                (value * MIX_SCALE).round() as u64
+            })
+        })
+        .collect()
+}
+
+/// Largest value of each of the `channels` of some `data`
+fn channel_maxima(data: &[u64], channels: u32) -> Vec<u64> {
+    let mut maxima = vec![0; channels as usize];
+    for pixel in data.chunks_exact(channels as usize) {
+        for (maximum, &value) in maxima.iter_mut().zip(pixel) {
+            *maximum = value.max(*maximum);
+        }
+    }
+    maxima
+}
+
+/// Scaling and curve that each channel of an image is tonemapped by, worked out once for all of
+/// its rows
+pub struct Tonemap {
+    /// Multiplier of each channel, which brings its maximum to 1, and the power it is raised to
+    channels: Vec<(f64, f64)>,
+    /// Tonemapped values of the smallest values of each channel, which most pixels take, so that
+    /// they are looked up rather than raised to their power one by one
+    /// Channels that are tonemapped alike share their table
+    tables: Vec<Arc<Vec<f64>>>,
+}
+
+impl Tonemap {
+    /// Tonemap channels by dividing each by its value in `maxima`, and raising it to its `curve`
+    /// A channel whose maximum is 0 has nothing to show, and stays black rather than being divided
+    /// by 0
+    pub fn new(maxima: &[u64], curve: &PerChannel<f64>) -> Tonemap {
+        let channels: Vec<(f64, f64)> = maxima
+            .iter()
+            .enumerate()
+            .map(|(channel, &maximum)| match maximum {
+                0 => (0.0, curve.get(channel)),
+                maximum => (1.0 / maximum as f64, curve.get(channel)),
+            })
+            .collect();
+        let mut tables: Vec<Arc<Vec<f64>>> = Vec::with_capacity(channels.len());
+        for (channel, &(multiplier, power)) in channels.iter().enumerate() {
+            let like = (0..channel).find(|&other| channels[other] == (multiplier, power));
+            let table = match like {
+                Some(other) => tables[other].clone(),
+                // The table stops at the channel's maximum, which none of its values are past
+                None => Arc::new(
+                    (0..min(maxima[channel] + 1, TONEMAP_TABLE_SIZE))
+                        .map(|value| tonemap(value, multiplier, power))
+                        .collect(),
+                ),
+            };
+            tables.push(table);
+        }
+        Tonemap { channels, tables }
+    }
+
+    /// Number of channels that are tonemapped
+    pub fn channels(&self) -> u32 {
+        self.channels.len() as u32
+    }
+
+    /// Tonemapped `value` of a `channel`, from 0 at 0 to 1 at its maximum
+    fn value(&self, channel: usize, value: u64) -> f64 {
+        match self.tables[channel].get(value as usize) {
+            Some(&tonemapped) => tonemapped,
+            None => {
+                let (multiplier, power) = self.channels[channel];
+                tonemap(value, multiplier, power)
+            }
+        }
+    }
+}
+
+/// A `value` tonemapped by a `multiplier` and a `power`, as the tables of [`Tonemap`] hold it
+fn tonemap(value: u64, multiplier: f64, power: f64) -> f64 {
+    (value as f64 * multiplier).powf(power)
+}
+
+/// Tonemap the data of each channel of a `tonemap` into PNG sample bytes of the given
+/// `bit_depth` (8 or 16), blending them over the samples of a row of a `background` if there is
+/// one
+/// 16-bit samples are big-endian, as the PNG format expects
+pub fn map_to_color(
+    data: Vec<u64>,
+    tonemap: &Tonemap,
+    bit_depth: u8,
+    background: Option<(&[u16], Blend)>,
+) -> Vec<u8> {
+    let channels = tonemap.channels() as usize;
+    let values = data
+        .iter()
+        .zip((0..channels).cycle())
+        .map(|(&value, channel)| tonemap.value(channel, value));
+    // Without a background, values go straight into samples
+    let Some((samples, blend)) = background else {
+        return to_samples(values, bit_depth);
+    };
+    let mut values: Vec<f64> = values.collect();
+    let pixels = values.chunks_exact_mut(channels);
+    for (pixel, samples) in pixels.zip(samples.chunks_exact(channels)) {
+        // The image covers its background as much as it is bright
+        let opacity = match (blend, &*pixel) {
+            (Blend::Additive, _) => 0.0,
+            (Blend::Normal, &[r, g, b]) => 0.299 * r + 0.587 * g + 0.114 * b,
+            (Blend::Normal, pixel) => pixel[0],
+        };
+        for (value, &sample) in pixel.iter_mut().zip(samples) {
+            *value += (1.0 - opacity.min(1.0)) * sample as f64 / u16::MAX as f64;
+        }
+    }
+    to_samples(values.into_iter(), bit_depth)
+}
+
+/// PNG sample bytes of the given `bit_depth` of some `values` from 0 to 1
+fn to_samples(values: impl Iterator<Item = f64>, bit_depth: u8) -> Vec<u8> {
+    match bit_depth {
+        16 => values
+            .flat_map(|v| min(u16::MAX, (v * 65536.0) as u16).to_be_bytes())
+            .collect(),
+        _ => values.map(|v| min(255, (v * 256.0) as u8)).collect(),
+    }
+}
+
+/// An image that a render is composited over, cropped and scaled down to the output image
+pub struct Background {
+    /// Samples of every pixel, row by row, in the channels of the output image, from 0 (black) to
+    /// `u16::MAX` (white)
+    samples: Vec<u16>,
+    /// Number of samples in a row
+    row_length: usize,
+}
+
+impl Background {
+    /// Read the `background` image of some settings, if they have one, cropped around its center
+    /// to the aspect ratio of the output image, and scaled down to its size
+    /// Each pixel is the average of the pixels it covers, colors are taken by their luma in a
+    /// grayscale image, and transparent pixels are black. An image smaller than the output image
+    /// along either axis is an error, as it would have to be blown up
+    pub fn load(settings: &RenderSettings) -> Result<Option<Background>, NebulaeError> {
+        // Separate bands are written as they are
+        let Some(path) = settings
+            .background
+            .as_ref()
+            .filter(|_| !settings.separates_bands())
+        else {
+            return Ok(None);
+        };
+        let (width, height) = (settings.width, settings.height);
+        let channels = settings.output_channels() as usize;
+        let (info, data) = read_png(path, png::Transformations::EXPAND)?;
+        let (source_width, source_height) = (info.width, info.height);
+        if source_width < width || source_height < height {
+            return Err(NebulaeError::Invalid(format!(
+                "The background {path} of {source_width}x{source_height} pixels is smaller than \
+                the image of {width}x{height}"
+            )));
+        }
+        // The largest region around the center that has the aspect ratio of the image
+        let (crop_width, crop_height) =
+            if source_width as u64 * height as u64 > source_height as u64 * width as u64 {
+                let crop_width = source_height as u64 * width as u64 / height as u64;
+                (crop_width as u32, source_height)
+            } else {
+                let crop_height = source_width as u64 * height as u64 / width as u64;
+                (source_width, crop_height as u32)
+            };
+        let (left, top) = (
+            (source_width - crop_width) / 2,
+            (source_height - crop_height) / 2,
+        );
+        let sixteen = info.bit_depth == png::BitDepth::Sixteen;
+        let sample = |pixel: &[u8], index: usize| -> u64 {
+            match sixteen {
+                true => u16::from_be_bytes([pixel[2 * index], pixel[2 * index + 1]]) as u64,
+                false => pixel[index] as u64 * 257,
+            }
+        };
+        let color_type = info.color_type;
+        let pixel_bytes = color_type.samples() * if sixteen { 2 } else { 1 };
+        let max = u16::MAX as u64;
+        let rgb = |pixel: &[u8]| -> [u64; 3] {
+            let opaque = |value: u64, alpha: u64| value * alpha / max;
+            match color_type {
+                png::ColorType::Grayscale => [sample(pixel, 0); 3],
+                png::ColorType::GrayscaleAlpha => [opaque(sample(pixel, 0), sample(pixel, 1)); 3],
+                png::ColorType::Rgba => {
+                    let alpha = sample(pixel, 3);
+                    [0, 1, 2].map(|index| opaque(sample(pixel, index), alpha))
+                }
+                _ => [0, 1, 2].map(|index| sample(pixel, index)),
+            }
+        };
+        let rows: Vec<&[u8]> = data.chunks_exact(info.line_size).collect();
+        // Pixels of the cropped region covered by the pixel at `at` along an axis of `length`
+        let covered = |at: u32, length: u32, start: u32, crop_length: u32| {
+            let bound = |at: u32| start + (at as u64 * crop_length as u64 / length as u64) as u32;
+            bound(at) as usize..bound(at + 1) as usize
+        };
+        let samples = (0..height)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                let rows = &rows[covered(y, height, top, crop_height)];
+                (0..width).flat_map(move |x| {
+                    let columns = covered(x, width, left, crop_width);
+                    let mut sums = [0; 3];
+                    for row in rows {
+                        for pixel in row[columns.start * pixel_bytes..columns.end * pixel_bytes]
+                            .chunks_exact(pixel_bytes)
+                        {
+                            for (sum, value) in sums.iter_mut().zip(rgb(pixel)) {
+                                *sum += value;
+                            }
+                        }
+                    }
+                    let count = (rows.len() * columns.len()) as u64;
+                    let [r, g, b] = sums.map(|sum| sum / count);
+                    let luma = (r * 299 + g * 587 + b * 114 + 500) / 1000;
+                    match channels {
+                        1 => vec![luma as u16],
+                        _ => vec![r as u16, g as u16, b as u16],
+                    }
+                })
+            })
+            .collect();
+        Ok(Some(Background {
+            samples,
+            row_length: width as usize * channels,
+        }))
+    }
+
+    /// Samples of the row at `y`
+    fn row(&self, y: u32) -> &[u16] {
+        &self.samples[y as usize * self.row_length..][..self.row_length]
+    }
+}
+
+/// Read the image of the PNG file at `path`, decoded with some `transformations`
+/// Returns its format along with its data, row by row
+pub(crate) fn read_png(
+    path: &str,
+    transformations: png::Transformations,
+) -> Result<(png::OutputInfo, Vec<u8>), NebulaeError> {
+    let read_error = |source| NebulaeError::Read {
+        path: path.to_string(),
+        source,
+    };
+    let decoding_error = |error| match error {
+        png::DecodingError::IoError(source) => read_error(source),
+        error => read_error(io::Error::new(io::ErrorKind::InvalidData, error)),
+    };
+    let file = File::open(path).map_err(read_error)?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(transformations);
+    let mut reader = decoder.read_info().map_err(decoding_error)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(decoding_error)?;
+    data.truncate(info.buffer_size());
+    Ok((info, data))
+}
+
+/// Write the PNG sample bytes of every row of an image, from the `colors` of each, which are
+/// found a few rows at a time in parallel
+pub fn data_to_png<F>(
+    colors: F,
+    width: u32,
+    height: u32,
+    channels: u32,
+    bit_depth: u8,
+    path: &Path,
+) -> Result<(), NebulaeError>
+where
+    F: Fn(u32) -> Vec<u8> + Sync,
+{
+    let write_error = |source| NebulaeError::Write {
+        path: path.display().to_string(),
+        source,
+    };
+    let encoding_error = |error| match error {
+        png::EncodingError::IoError(source) => write_error(source),
+        source => NebulaeError::Encoding {
+            path: path.display().to_string(),
+            source,
+        },
+    };
+    let (temporary, file) = TemporaryFile::create(path).map_err(write_error)?;
+    let mut file = BufWriter::new(file);
+    let mut encoder = png::Encoder::new(&mut file, width, height);
+    encoder.set_color(match channels {
+        1 => png::ColorType::Grayscale,
+        _ => png::ColorType::Rgb,
+    });
+    encoder.set_depth(match bit_depth {
+        16 => png::BitDepth::Sixteen,
+        _ => png::BitDepth::Eight,
+    });
+    let mut writer = encoder.write_header().map_err(encoding_error)?;
+    let mut stream = writer
+        .stream_writer_with_size(PNG_CHUNK_BYTES)
+        .map_err(encoding_error)?;
+    for first in (0..height).step_by(WRITE_ROWS as usize) {
+        let rows: Vec<Vec<u8>> = (first..min(height, first + WRITE_ROWS))
+            .into_par_iter()
+            .map(&colors)
+            .collect();
+        for row in rows {
+            stream.write_all(&row).map_err(write_error)?;
+        }
+    }
+    // Errors of the last writes would go unnoticed if they were left for when they are dropped
+    stream.finish().map_err(encoding_error)?;
+    writer.finish().map_err(encoding_error)?;
+    let file = file
+        .into_inner()
+        .map_err(|error| write_error(error.into_error()))?;
+    temporary.persist(file).map_err(write_error)
+}
+
+/// A file that is written next to the one at its path, and only moved over it once it is
+/// complete, so that a crash or an error can't leave a half-written file in its place, nor let
+/// anything that reads it see one
+/// It is removed if it is dropped before it is complete, even by a panic
+pub(crate) struct TemporaryFile {
+    /// Path of the file that is replaced
+    path: PathBuf,
+    /// Path of the file that is written, until it is moved, or none when the file at `path` is
+    /// written in place, as it isn't a regular file (like `/dev/stdout`) that could be replaced
+    temporary: Option<PathBuf>,
+}
+
+impl TemporaryFile {
+    /// Create a file to replace the one at `path` with, at the same path followed by a number and
+    /// `.tmp`, which is new, so that it is never a file that was already there, such as the
+    /// temporary file of another write to the same path
+    /// Returns the temporary file, and the file to write to
+    pub(crate) fn create(path: &Path) -> io::Result<(TemporaryFile, File)> {
+        /// Number of the next temporary file of this process
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        if fs::metadata(path).is_ok_and(|metadata| !metadata.is_file()) {
+            let temporary = TemporaryFile {
+                path: path.to_path_buf(),
+                temporary: None,
+            };
+            return Ok((temporary, File::create(path)?));
+        }
+        loop {
+            let mut temporary = path.as_os_str().to_owned();
+            temporary.push(format!(
+                ".{}-{}.tmp",
+                std::process::id(),
+                NEXT.fetch_add(1, Relaxed)
+            ));
+            let temporary = PathBuf::from(temporary);
+            match File::options()
+                .write(true)
+                .create_new(true)
+                .open(&temporary)
+            {
+                Ok(file) => {
+                    let temporary = TemporaryFile {
+                        path: path.to_path_buf(),
+                        temporary: Some(temporary),
+                    };
+                    return Ok((temporary, file));
+                }
+                // Left behind by an earlier process of the same id that crashed
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Move the temporary file over the one it replaces, once all of it is stored in the `file`
+    pub(crate) fn persist(mut self, file: File) -> io::Result<()> {
+        if let Some(temporary) = &self.temporary {
+            file.sync_all()?;
+            fs::rename(temporary, &self.path)?;
+            self.temporary = None;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        if let Some(temporary) = &self.temporary {
+            // There is nothing left to do if it can't be removed, the error that stopped the
+            // write is the one that matters
+            let _ = fs::remove_file(temporary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_settings::Layout;
+
+    /// Empty directory of this test run for the test of a given `name`
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("nebulae-output-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// Names of the files in a `directory`
+    fn files(directory: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn images_are_replaced_whole_or_not_at_all() {
+        let directory = directory("replace");
+        let path = directory.join("image.png");
+        let gray = |level: u8| move |_: u32| vec![level; 16];
+        data_to_png(gray(10), 16, 16, 1, 8, &path).unwrap();
+        let complete = fs::read(&path).unwrap();
+
+        // An encode that dies partway leaves the last complete image, and nothing else
+        let died = std::panic::catch_unwind(|| {
+            data_to_png(
+                |y| match y {
+                    0..12 => vec![200; 16],
+                    _ => panic!("stopped partway through the image"),
+                },
+                16,
+                16,
+                1,
+                8,
+                &path,
+            )
+        });
+        assert!(died.is_err());
+        assert_eq!(fs::read(&path).unwrap(), complete);
+        assert_eq!(files(&directory), ["image.png"]);
+
+        // Failed writes, such as those of rows of the wrong size, leave it too
+        assert!(data_to_png(|_| vec![0; 3], 16, 16, 1, 8, &path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), complete);
+        assert_eq!(files(&directory), ["image.png"]);
+
+        data_to_png(gray(20), 16, 16, 1, 8, &path).unwrap();
+        assert_ne!(fs::read(&path).unwrap(), complete);
+        assert_eq!(files(&directory), ["image.png"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn temporary_files_are_never_files_already_there() {
+        let directory = directory("temporary");
+        let path = directory.join("image.png");
+        fs::write(directory.join("image.png.tmp"), "someone else's").unwrap();
+        let (first, first_file) = TemporaryFile::create(&path).unwrap();
+        let (second, second_file) = TemporaryFile::create(&path).unwrap();
+        assert_ne!(first.temporary, second.temporary);
+        drop((second, second_file));
+        first.persist(first_file).unwrap();
+        assert_eq!(
+            fs::read_to_string(directory.join("image.png.tmp")).unwrap(),
+            "someone else's"
+        );
+        assert_eq!(files(&directory), ["image.png", "image.png.tmp"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Samples of the PNG that `settings` write a `source` into, in a directory of a given `name`
+    fn written(name: &str, settings: &RenderSettings, source: Source) -> Vec<u8> {
+        let directory = directory(name);
+        let path = directory.join("image.png");
+        write_image(settings.clone(), &path.to_string_lossy(), source, None)
+            .join()
+            .unwrap()
+            .unwrap();
+        let (_, data) = read_png(&path.to_string_lossy(), png::Transformations::IDENTITY).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        data
+    }
+
+    #[test]
+    fn channels_without_counts_are_black() {
+        let tonemap = Tonemap::new(&[0, 4, 0], &PerChannel::Uniform(1.0));
+        let samples = map_to_color(vec![0, 4, 0, 0, 2, 0], &tonemap, 8, None);
+        assert_eq!(samples, [0, 255, 0, 0, 128, 0]);
+        let settings = RenderSettings {
+            width: 5,
+            height: 3,
+            ..RenderSettings::default()
+        };
+        for normalize in [Normalize::Global, Normalize::PerChannel] {
+            let settings = RenderSettings {
+                normalize,
+                ..settings.clone()
+            };
+            let blank = RawImage::new(5, 3, 3, 32, Layout::Linear);
+            let samples = written("blank", &settings, Source::Image(Arc::new(blank)));
+            assert_eq!(samples, [0; 5 * 3 * 3]);
+            let weights = RawImageF32::new(5, 3, 3);
+            let samples = written(
+                "blank-weights",
+                &settings,
+                Source::Weights(Arc::new(weights)),
+            );
+            assert_eq!(samples, [0; 5 * 3 * 3]);
+        }
+    }
+
+    #[test]
+    fn channels_are_normalized_together_or_apart() {
+        let settings = RenderSettings {
+            width: 2,
+            height: 1,
+            curve: PerChannel::Uniform(1.0),
+            ..RenderSettings::default()
+        };
+        // Channels whose maxima are 10, 100 and 1000, each at half of it in the second pixel
+        let image = || RawImage::from_counts(2, 1, 3, vec![10, 100, 1000, 5, 50, 500]).unwrap();
+        // The brightest channel sets the scale of them all, which dims the other two
+        let global = written("global", &settings, Source::Image(Arc::new(image())));
+        assert_eq!(global, [2, 25, 255, 1, 12, 128]);
+        let settings = RenderSettings {
+            normalize: Normalize::PerChannel,
+            ..settings
+        };
+        let apart = written("per-channel", &settings, Source::Image(Arc::new(image())));
+        assert_eq!(apart, [255, 255, 255, 128, 128, 128]);
+    }
+
+    #[test]
+    fn sixteen_bit_samples_are_big_endian() {
+        let tonemap = Tonemap::new(&[4], &PerChannel::Uniform(1.0));
+        let samples = map_to_color(vec![0, 1, 4], &tonemap, 16, None);
+        assert_eq!(samples, [0, 0, 0x40, 0, 0xff, 0xff]);
+        let settings = RenderSettings {
+            width: 3,
+            height: 1,
+            bit_depth: 16,
+            curve: PerChannel::Uniform(1.0),
+            ..RenderSettings::default()
+        };
+        let image = RawImage::from_counts(3, 1, 3, vec![4, 0, 0, 1, 0, 0, 0, 0, 0]).unwrap();
+        let samples = written("sixteen", &settings, Source::Image(Arc::new(image)));
+        assert_eq!(samples.len(), 3 * 3 * 2);
+        assert_eq!(
+            (&samples[..2], &samples[6..8]),
+            (&[0xff, 0xff][..], &[0x40, 0][..])
+        );
+    }
+
+    #[test]
+    fn each_channel_takes_its_own_curve() {
+        let curve = PerChannel::Channels(vec![1.0, 0.5, 2.0]);
+        let tonemap = Tonemap::new(&[100, 100, 100], &curve);
+        let samples = map_to_color(vec![25, 25, 25, 100, 100, 100], &tonemap, 8, None);
+        assert_eq!(samples, [64, 128, 16, 255, 255, 255]);
+        // Values past the table are tonemapped alike
+        let tonemap = Tonemap::new(&[1 << 20], &PerChannel::Uniform(0.5));
+        let samples = map_to_color(vec![1 << 18, 1 << 20], &tonemap, 8, None);
+        assert_eq!(samples, [128, 255]);
+    }
+
+    #[test]
+    fn supersampled_pixels_are_summed_down() {
+        // 4 × 2 pixels of 2 channels, down to 2 × 1
+        let data = [1, 0, 2, 0, 3, 1, 4, 1, 5, 0, 6, 0, 7, 1, 8, 2];
+        assert_eq!(downsample(&data, 2, 1, 2, 2), [14, 0, 22, 5]);
+        let settings = RenderSettings {
+            width: 2,
+            height: 1,
+            supersample: 2,
+            curve: PerChannel::Uniform(1.0),
+            ..RenderSettings::default()
+        };
+        let image = RawImage::from_counts(4, 2, 3, vec![1; 24]).unwrap();
+        image.add(3, 1, 0, 4);
+        let samples = written("supersampled", &settings, Source::Image(Arc::new(image)));
+        assert_eq!(samples, [128, 128, 128, 255, 128, 128]);
+    }
+
+    #[test]
+    fn backgrounds_are_cropped_scaled_and_blended() {
+        let directory = directory("background");
+        let path = directory.join("background.png");
+        // 4 × 2 pixels, whose middle 2 × 2 are kept for a square image, and averaged into 1 × 1
+        let row = |_| [[0, 0, 0], [255, 0, 0], [0, 0, 255], [9, 9, 9]].concat();
+        data_to_png(row, 4, 2, 3, 8, &path).unwrap();
+        let settings = RenderSettings {
+            width: 1,
+            height: 1,
+            background: Some(path.to_string_lossy().into_owned()),
+            curve: PerChannel::Uniform(1.0),
+            ..RenderSettings::default()
+        };
+        let background = Background::load(&settings).unwrap().unwrap();
+        let half = u16::MAX / 2;
+        assert_eq!(background.row(0), [half, 0, half]);
+
+        let tonemap = Tonemap::new(&[4, 4, 4], &settings.curve);
+        let additive = map_to_color(
+            vec![2, 0, 0],
+            &tonemap,
+            8,
+            Some((background.row(0), Blend::Additive)),
+        );
+        assert_eq!(additive, [255, 0, 127]);
+        // A pixel as bright as white covers the background, and a black one lets it through
+        let normal =
+            |pixel| map_to_color(pixel, &tonemap, 8, Some((background.row(0), Blend::Normal)));
+        assert_eq!(normal(vec![4, 4, 4]), [255, 255, 255]);
+        assert_eq!(normal(vec![0, 0, 0]), [127, 0, 127]);
+
+        let larger = RenderSettings {
+            width: 8,
+            ..settings
+        };
+        assert!(Background::load(&larger).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rows_are_written_in_order_a_few_at_a_time() {
+        // More rows than are tonemapped together, each of its own gray
+        let (width, height) = (3, WRITE_ROWS * 2 + 5);
+        let settings = RenderSettings {
+            width,
+            height,
+            curve: PerChannel::Uniform(1.0),
+            channel_mixing: vec![vec![1.0, 0.0, 0.0]],
+            ..RenderSettings::default()
+        };
+        let counts = (0..height)
+            .flat_map(|y| [y, 0, 7].repeat(width as usize))
+            .collect();
+        let image = RawImage::from_counts(width, height, 3, counts).unwrap();
+        let samples = written("rows", &settings, Source::Image(Arc::new(image)));
+        for (y, row) in samples.chunks_exact(width as usize).enumerate() {
+            let level = (y as f64 / (height - 1) as f64 * 256.0).min(255.0) as u8;
+            assert_eq!(row, [level; 3], "row {y}");
+        }
+    }
+
+    #[test]
+    fn separate_bands_are_written_to_images_of_their_own() {
+        let directory = directory("bands");
+        let settings = RenderSettings {
+            width: 2,
+            height: 1,
+            bands: Some(vec![10, 100]),
+            separate_bands: true,
+            curve: PerChannel::Uniform(1.0),
+            ..RenderSettings::default()
+        };
+        let image = RawImage::new(2, 1, 2, 32, Layout::Linear);
+        image.add(0, 0, 0, 4);
+        image.add(1, 0, 0, 2);
+        image.add(1, 0, 1, 8);
+        let path = directory.join("image.png");
+        write_image(
+            settings,
+            &path.to_string_lossy(),
+            Source::Image(Arc::new(image)),
+            None,
+        )
+        .join()
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            files(&directory),
+            ["image-band-10.png", "image-band-100.png"]
+        );
+        let band = |name: &str| {
+            let path = directory.join(name);
+            let (info, data) =
+                read_png(&path.to_string_lossy(), png::Transformations::IDENTITY).unwrap();
+            assert_eq!(info.color_type, png::ColorType::Grayscale);
+            data
+        };
+        // Bands share the scale of the brightest of them under global normalization
+        assert_eq!(band("image-band-10.png"), [128, 64]);
+        assert_eq!(band("image-band-100.png"), [0, 255]);
+        assert_eq!(
+            band_path(Path::new("renders/image"), 7),
+            Path::new("renders/image-band-7")
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn streamed_images_are_those_encoded_whole() {
+        /// Writer of a PNG of 8-bit RGB samples into `whole`
+        fn encoder(whole: &mut Vec<u8>, width: u32, height: u32) -> png::Writer<&mut Vec<u8>> {
+            let mut encoder = png::Encoder::new(whole, width, height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header().unwrap()
+        }
+
+        // Counts that reach over several runs of rows, to be tonemapped and encoded at once
+        let (width, height) = (37, WRITE_ROWS * 2 + 9);
+        let counts: Vec<u32> = (0..width as u64 * height as u64 * 3)
+            .map(|index| (crate::splitmix64(index) % 1_000) as u32)
+            .collect();
+        let settings = RenderSettings {
+            width,
+            height,
+            ..RenderSettings::default()
+        };
+        let image = RawImage::from_counts(width, height, 3, counts.clone()).unwrap();
+        let directory = directory("whole");
+        let path = directory.join("image.png");
+        write_image(
+            settings.clone(),
+            &path.to_string_lossy(),
+            Source::Image(Arc::new(image)),
+            None,
+        )
+        .join()
+        .unwrap()
+        .unwrap();
+        let streamed = fs::read(&path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        // The same image, tonemapped whole and encoded in one write
+        let maximum = counts.iter().copied().max().unwrap() as u64;
+        let tonemap = Tonemap::new(&[maximum; 3], &settings.curve);
+        let samples = map_to_color(
+            counts.into_iter().map(u64::from).collect(),
+            &tonemap,
+            8,
+            None,
+        );
+        let mut whole = Vec::new();
+        let mut writer = encoder(&mut whole, width, height);
+        let mut stream = writer.stream_writer_with_size(PNG_CHUNK_BYTES).unwrap();
+        stream.write_all(&samples).unwrap();
+        stream.finish().unwrap();
+        writer.finish().unwrap();
+        assert!(streamed == whole);
+        // Image data written in one go is compressed in chunks of its own, but to the same samples
+        let mut whole = Vec::new();
+        let mut writer = encoder(&mut whole, width, height);
+        writer.write_image_data(&samples).unwrap();
+        writer.finish().unwrap();
+        let mut reader = png::Decoder::new(&whole[..]).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, samples);
+    }
+}
@@ -0,0 +1,249 @@
+//! Blue noise sampling, where no two samples of a pass are closer than a minimum distance, which
+//! spreads them out without the regular structure of a grid or sequence, at the cost of
+//! generating every sample of a pass up front
+
+use crate::sampler::{self, Sampler, Strata};
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+use std::f64::consts::{SQRT_2, TAU};
+
+/// Number of candidates tried around a point before giving up on placing more next to it
+const CANDIDATES: usize = 8;
+
+/// Number of points that fill the unit square for a minimum distance of 1, measured for this
+/// generator, from which the distance that gives a number of samples is worked out
+const DENSITY: f64 = 0.783;
+
+/// Samples with a minimum distance between any two of them, generated with Bridson's algorithm
+///
+/// Candidates are tried evenly spaced around a circle just past the minimum distance, starting
+/// from a random angle, which packs points more tightly than candidates spread over an annulus,
+/// for fewer tries. The unit square wraps around, so that its edges get as many samples as
+/// everywhere else.
+///
+/// The number of samples can't be chosen exactly, only the minimum distance that gives about that
+/// many, so passes take slightly more or fewer samples than requested. The samples are shuffled,
+/// so that channels taking only some of them still get them from all over the square.
+///
+/// When passes are stratified together, the points are only laid out for the first pass, and
+/// every pass shifts them by its own step across the typical space around a point, which keeps
+/// them apart as the square wraps around.
+pub struct PoissonSampler {
+    points: Vec<(f64, f64)>,
+    /// Offset of the points in this pass
+    offset: (f64, f64),
+    /// Number of samples that the points are generated for
+    requested: u64,
+    /// Buffers for laying out the points, kept from one pass to the next: the point in each cell
+    /// of a grid over the square, if any, and the points that more may be placed around
+    grid: Vec<(f64, f64)>,
+    active: Vec<(f64, f64)>,
+}
+
+impl PoissonSampler {
+    /// Construct a [`PoissonSampler`] for about `samples` points, which are laid out when it is
+    /// reset for each pass
+    pub fn new(samples: u64) -> PoissonSampler {
+        PoissonSampler {
+            points: Vec::new(),
+            offset: (0.0, 0.0),
+            requested: samples,
+            grid: Vec::new(),
+            active: Vec::new(),
+        }
+    }
+}
+
+impl Sampler for PoissonSampler {
+    fn reset(&mut self, rng: &mut dyn RngCore, strata: Option<Strata>) {
+        let PoissonSampler {
+            points,
+            offset,
+            requested: samples,
+            grid,
+            active,
+        } = self;
+        if strata.is_some() && !points.is_empty() {
+            *offset = shift(strata, points.len());
+            return;
+        }
+        points.clear();
+        if *samples > 0 {
+            let radius = (DENSITY / *samples as f64).sqrt();
+            // Cells small enough to hold at most one point, that evenly divide the square
+            let cells = (SQRT_2 / radius).ceil() as usize;
+            let cell_size = 1.0 / cells as f64;
+            grid.clear();
+            grid.resize(cells * cells, (f64::NAN, f64::NAN));
+            let cell_of = |(x, y): (f64, f64)| {
+                (
+                    ((x / cell_size) as usize).min(cells - 1),
+                    ((y / cell_size) as usize).min(cells - 1),
+                )
+            };
+            // Whether two points are at least the radius apart, the short way around the square
+            let far_enough = |a: (f64, f64), b: (f64, f64)| {
+                let dx = (a.0 - b.0).abs();
+                let dy = (a.1 - b.1).abs();
+                let (dx, dy) = (dx.min(1.0 - dx), dy.min(1.0 - dy));
+                dx * dx + dy * dy >= radius * radius
+            };
+            let wrap = |x: f64| {
+                if x < 0.0 {
+                    x + 1.0
+                } else if x >= 1.0 {
+                    x - 1.0
+                } else {
+                    x
+                }
+            };
+            // Slightly past the radius, so that rounding doesn't bring candidates too close
+            let distance = radius * (1.0 + 1e-9);
+            let directions: [(f64, f64); CANDIDATES] = std::array::from_fn(|candidate| {
+                let (sin, cos) = (TAU * candidate as f64 / CANDIDATES as f64).sin_cos();
+                (distance * cos, distance * sin)
+            });
+
+            let first = (rng.gen::<f64>(), rng.gen::<f64>());
+            let (x, y) = cell_of(first);
+            grid[y * cells + x] = first;
+            points.push(first);
+            active.push(first);
+            while !active.is_empty() {
+                let slot = rng.gen_range(0..active.len());
+                let center = active[slot];
+                let (sin, cos) = (TAU * rng.gen::<f64>()).sin_cos();
+                let placed = directions.iter().find_map(|&(dx, dy)| {
+                    let candidate = (
+                        wrap(center.0 + dx * cos - dy * sin),
+                        wrap(center.1 + dx * sin + dy * cos),
+                    );
+                    let (x, y) = cell_of(candidate);
+                    if !grid[y * cells + x].0.is_nan() {
+                        return None;
+                    }
+                    // Any point closer than the radius is within two cells, outside of the corners
+                    let around = |i: usize| -> [usize; 5] {
+                        std::array::from_fn(|d| (i + cells + d - 2) % cells)
+                    };
+                    let (columns, rows) = (around(x), around(y));
+                    let clear = rows.iter().enumerate().all(|(dy, &row)| {
+                        columns.iter().enumerate().all(|(dx, &column)| {
+                            let neighbour = grid[row * cells + column];
+                            (dx % 4 == 0 && dy % 4 == 0)
+                                || neighbour.0.is_nan()
+                                || far_enough(candidate, neighbour)
+                        })
+                    });
+                    clear.then(|| {
+                        grid[y * cells + x] = candidate;
+                        candidate
+                    })
+                });
+                match placed {
+                    Some(point) => {
+                        points.push(point);
+                        active.push(point);
+                    }
+                    None => {
+                        active.swap_remove(slot);
+                    }
+                }
+            }
+            points.shuffle(rng);
+        }
+        *offset = shift(strata, points.len());
+    }
+
+    fn point(&self, index: u64, _rng: &mut dyn RngCore) -> Option<(f64, f64)> {
+        let point = *self.points.get(usize::try_from(index).ok()?)?;
+        Some(sampler::rotate(point, self.offset))
+    }
+
+    fn samples(&self, requested: u64) -> u64 {
+        match self.requested {
+            0 => 0,
+            generated => requested * self.points.len() as u64 / generated,
+        }
+    }
+}
+
+/// Offset of the points of a pass, out of `points`, shifted by the step of the pass across the
+/// side of the square that each point has to itself on average when stratified
+fn shift(strata: Option<Strata>, points: usize) -> (f64, f64) {
+    match strata {
+        Some(strata) => {
+            let spacing = (1.0 / points.max(1) as f64).sqrt();
+            let (u, v) = strata.step();
+            sampler::rotate(strata.origin(), (u * spacing, v * spacing))
+        }
+        None => (0.0, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Points of the next pass of a `sampler`
+    fn pass(
+        sampler: &mut PoissonSampler,
+        rng: &mut StdRng,
+        strata: Option<Strata>,
+    ) -> Vec<(f64, f64)> {
+        sampler.reset(rng, strata);
+        (0..).map_while(|index| sampler.point(index, rng)).collect()
+    }
+
+    /// Shortest distance between two of some `points`, the short way around the unit square
+    fn closest(points: &[(f64, f64)]) -> f64 {
+        let mut closest = f64::INFINITY;
+        for (index, a) in points.iter().enumerate() {
+            for b in &points[index + 1..] {
+                let dx = (a.0 - b.0).abs();
+                let dy = (a.1 - b.1).abs();
+                let (dx, dy) = (dx.min(1.0 - dx), dy.min(1.0 - dy));
+                closest = closest.min((dx * dx + dy * dy).sqrt());
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn samples_are_kept_apart() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for samples in [10, 500, 3_000] {
+            let mut sampler = PoissonSampler::new(samples);
+            let radius = (DENSITY / samples as f64).sqrt();
+            let points = pass(&mut sampler, &mut rng, None);
+            assert!(closest(&points) >= radius, "{samples} samples");
+            assert!(points
+                .iter()
+                .all(|&(x, y)| (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y)));
+            // Passes shifted across the square keep their distances
+            let strata = Strata::new(&mut rng);
+            pass(&mut sampler, &mut rng, Some(strata));
+            let shifted = pass(&mut sampler, &mut rng, Some(strata.pass(3)));
+            assert!(closest(&shifted) >= radius * (1.0 - 1e-9));
+        }
+    }
+
+    #[test]
+    fn passes_take_about_as_many_samples_as_requested() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for samples in [1_000, 20_000, 100_000] {
+            let mut sampler = PoissonSampler::new(samples);
+            for _ in 0..3 {
+                let taken = pass(&mut sampler, &mut rng, None).len() as u64;
+                assert!(
+                    taken.abs_diff(samples) * 20 <= samples,
+                    "{taken} of {samples}"
+                );
+                assert_eq!(sampler.samples(samples), taken);
+            }
+        }
+        assert!(pass(&mut PoissonSampler::new(0), &mut rng, None).is_empty());
+    }
+}
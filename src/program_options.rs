@@ -1,24 +1,75 @@
 //! Utility for program configuration arguments
 
-use crate::{RenderSettings, DEFAULT_RENDER_SETTINGS};
 use clap::{Parser, Subcommand};
+use console::style;
+use nebulae::config_file::{self, ConfigFormat};
+use nebulae::render_settings::{IntermediateInterval, PaletteRegistry, Problem, LOCATIONS};
+use nebulae::{calibrate_limits, NebulaeError, RenderSettings};
+use std::fs::{self, File};
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::Path;
 use std::process::exit;
+use std::thread;
+
+/// File to write to when neither the arguments nor the configuration give one
+const DEFAULT_OUTPUT_PATH: &str = "image.png";
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// File to write to
-    #[clap(short, long, value_parser, default_value = "image.png")]
-    output: String,
+    /// File to write to [default: image.png]
+    #[clap(short, long, value_parser)]
+    output: Option<String>,
 
     /// Do not write intermediate files
     #[clap(short, long, value_parser)]
     no_intermediates: bool,
 
-    /// Configuration file
+    /// Configuration file (TOML or JSON, `-` for standard input)
     #[clap(short, long, value_parser)]
     config: Option<String>,
 
+    /// Intermediate interval: N passes, Ns seconds, or none
+    #[clap(long, value_parser, value_name = "EVERY")]
+    intermediate_every: Option<IntermediateInterval>,
+
+    /// Stop at the first job that fails, instead of moving on to the next
+    #[clap(long, value_parser)]
+    fail_fast: bool,
+
+    /// Create the missing directories of output files
+    #[clap(long, value_parser)]
+    mkdirs: bool,
+
+    /// Treat unknown keys in the configuration file as errors
+    #[clap(long, value_parser)]
+    strict_config: bool,
+
+    /// Treat warnings about the render settings as errors
+    #[clap(long, value_parser)]
+    strict: bool,
+
+    /// Multiply the escape limit of every channel
+    #[clap(long, value_parser, value_name = "SCALE")]
+    limit_scale: Option<f64>,
+
+    /// Seed for reproducible renders (overrides the configuration)
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+
+    /// Number of threads to render with (overrides the configuration)
+    #[clap(long, value_parser)]
+    threads: Option<u32>,
+
+    /// Save the settings that each job renders with, its limits as picked and scaled, to this file
+    #[clap(long, value_parser, value_name = "PATH")]
+    save_settings: Option<String>,
+
+    /// Write a map of how noisy each pixel is to this PNG, alongside intermediates
+    #[clap(long, value_parser, value_name = "PATH")]
+    noise_map: Option<String>,
+
     /// Alternate behaviours for the program
     #[clap(subcommand)]
     command: Option<Commands>,
@@ -31,17 +82,29 @@ enum Commands {
         /// Path to write the selected configuration to
         #[clap(short, long, value_parser)]
         save_config: Option<String>,
+
+        /// File of palettes to offer along with the built-in ones
+        #[clap(short, long, value_parser)]
+        palettes: Option<String>,
     },
     /// Write the default configuration to TOML
     WriteDefault {
         /// Path to write the default configuration to (writes to stdout if unset)
         #[clap(short, long, value_parser)]
         save_config: Option<String>,
+
+        /// Format to write the configuration in (defaults to the file's extension, or TOML)
+        #[clap(short, long, value_enum)]
+        format: Option<ConfigFormat>,
     },
+    /// List the named locations that can be used with the `location` key
+    Locations,
+    /// Render a quick escape-time Mandelbrot of the view, to frame it
+    Mandelbrot,
 }
 
-/// How to run the program
-pub struct ProgramOptions {
+/// A single render to run
+pub struct RenderJob {
     /// Rendering settings
     pub render_settings: RenderSettings,
 
@@ -52,48 +115,414 @@ pub struct ProgramOptions {
     pub render_intermediates: bool,
 }
 
+/// How to run the program
+pub struct ProgramOptions {
+    /// Renders to run, one after the other
+    pub jobs: Vec<RenderJob>,
+
+    /// Stop at the first job that fails?
+    pub fail_fast: bool,
+
+    /// Render escape-time images of the jobs' views, instead of their Nebulabrots?
+    pub escape_time: bool,
+}
+
 /// Get options from program arguments
-pub fn get_options() -> Result<ProgramOptions, Box<dyn std::error::Error>> {
+pub fn get_options() -> Result<ProgramOptions, NebulaeError> {
     let args: Args = Args::parse();
-    let render_settings = match &args.command {
+    let jobs = match &args.command {
         Some(Commands::WriteDefault {
             save_config: config,
+            format,
         }) => {
+            let format = format
+                .or_else(|| config.as_deref().and_then(ConfigFormat::from_path))
+                .unwrap_or(ConfigFormat::Toml);
+            let serialized = RenderSettings::default().serialize(format)?;
             match config {
                 Some(path) => {
-                    DEFAULT_RENDER_SETTINGS.to_file(path)?;
+                    std::fs::write(path, serialized).map_err(|source| NebulaeError::Write {
+                        path: path.clone(),
+                        source,
+                    })?;
                 }
                 None => {
-                    println!("{}", DEFAULT_RENDER_SETTINGS.serialize()?);
+                    println!("{serialized}");
                 }
             };
             exit(0);
         }
+        Some(Commands::Locations) => {
+            for location in LOCATIONS.iter() {
+                println!("{location}");
+            }
+            exit(0);
+        }
         Some(Commands::Wizard {
             save_config: config,
-        }) => match RenderSettings::from_wizard()? {
-            Some(settings) => {
+            palettes,
+        }) => match RenderSettings::from_wizard(&match palettes {
+            Some(path) => config_file::load_palettes(path)?,
+            None => PaletteRegistry::default(),
+        })? {
+            Some(mut settings) => {
+                // The saved configuration has the limits that will actually be used
+                pick_limits(&mut settings, "")?;
+                if let Some(scale) = args.limit_scale {
+                    settings.scale_limits(scale)?;
+                }
                 if let Some(config) = config {
-                    settings.to_file(config)?;
+                    let output = args
+                        .output
+                        .as_deref()
+                        .map(|output| saved_output_path(output, config));
+                    settings.to_file(config, output.as_deref())?;
                 }
-                Ok(settings)
+                Ok(vec![(settings, None)])
             }
-            None => Err("User canceled..."),
+            None => Err(NebulaeError::Cancelled),
         },
-        None => {
+        Some(Commands::Mandelbrot) | None => {
             if let Some(config_path) = args.config.as_deref() {
-                Ok(RenderSettings::from_file(config_path)?)
+                Ok(config_file::load_jobs(config_path, args.strict_config)?)
             } else {
-                Ok(DEFAULT_RENDER_SETTINGS)
+                Ok(vec![(RenderSettings::default(), None)])
             }
         }
     }?;
+    // The wizard's settings already have their limits scaled
+    let wizard = matches!(args.command, Some(Commands::Wizard { .. }));
+    let escape_time = matches!(args.command, Some(Commands::Mandelbrot));
+    let job_count = jobs.len();
+    let jobs = jobs
+        .into_iter()
+        .enumerate()
+        .map(|(index, (mut render_settings, output))| {
+            if let Some(interval) = args.intermediate_every {
+                render_settings.intermediate_every = interval;
+            }
+            if let Some(seed) = args.seed {
+                render_settings.seed = Some(seed);
+            }
+            if let Some(threads) = args.threads {
+                render_settings.threads = Some(threads);
+            }
+            // Like `--output`, an explicit `--noise-map` gets a file for each job
+            if let Some(noise_map) = &args.noise_map {
+                render_settings.noise_map = Some(match job_count {
+                    1 => noise_map.clone(),
+                    _ => numbered_path(noise_map, index + 1),
+                });
+            }
+            // Limits are picked once, before anything else reads them, and then given as if they
+            // had been all along
+            let job = if job_count > 1 {
+                format!(" of job {}", index + 1)
+            } else {
+                String::new()
+            };
+            pick_limits(&mut render_settings, &job)?;
+            if let Some(scale) = args.limit_scale.filter(|_| !wizard) {
+                render_settings.scale_limits(scale)?;
+            }
+            check(&render_settings, index, job_count, args.strict)?;
 
-    let render_intermediates = !args.no_intermediates;
-    let output_path = args.output.clone();
+            // Escape-time images are written all at once
+            let render_intermediates = !escape_time
+                && !args.no_intermediates
+                && render_settings.intermediate_every != IntermediateInterval::Never;
+            // Intermediates are written on a thread of their own, which is left a core of its own
+            // rather than taking one from the render
+            let parallelism = thread::available_parallelism().map_or(1, NonZeroUsize::get) as u32;
+            if render_intermediates
+                && parallelism > 1
+                && render_settings.threads == Some(parallelism)
+            {
+                render_settings.threads = Some(parallelism - 1);
+            }
+            // An explicit `--output` takes precedence over the configuration
+            let output_path = match (&args.output, output) {
+                (None, Some(output)) => output,
+                (output, _) => {
+                    let output = output.as_deref().unwrap_or(DEFAULT_OUTPUT_PATH);
+                    if job_count > 1 {
+                        numbered_path(output, index + 1)
+                    } else {
+                        output.to_string()
+                    }
+                }
+            };
+            check_output(&output_path, args.mkdirs)?;
+            match &render_settings.noise_map {
+                Some(noise_map) if noise_map == &output_path => {
+                    return Err(NebulaeError::Invalid(format!(
+                        "The noise map would be written over the image at {output_path}"
+                    )));
+                }
+                Some(noise_map) if !escape_time => check_output(noise_map, args.mkdirs)?,
+                _ => {}
+            }
+            // The saved settings are those that the job renders with, rather than those that it
+            // was given, so that it can be rendered again as it is
+            if let Some(path) = &args.save_settings {
+                let path = match job_count {
+                    1 => path.clone(),
+                    _ => numbered_path(path, index + 1),
+                };
+                save_settings(&render_settings, &output_path, &path)?;
+            }
+            Ok(RenderJob {
+                render_settings,
+                output_path,
+                render_intermediates,
+            })
+        })
+        .collect::<Result<_, NebulaeError>>()?;
     Ok(ProgramOptions {
-        render_settings,
-        output_path,
-        render_intermediates,
+        jobs,
+        fail_fast: args.fail_fast,
+        escape_time,
     })
 }
+
+/// Reports every problem with the settings of a job, failing if any is an error, or if any is a
+/// warning and `strict` is set
+fn check(
+    settings: &RenderSettings,
+    index: usize,
+    job_count: usize,
+    strict: bool,
+) -> Result<(), NebulaeError> {
+    let job = if job_count > 1 {
+        format!(" of job {}", index + 1)
+    } else {
+        String::new()
+    };
+    // Every problem is printed, rather than stopping at the first one
+    let mut errors = 0;
+    for problem in settings.validate() {
+        match problem {
+            Problem::Warning(_) if !strict => {
+                config_file::warn(format!("Settings{job}: {problem}"))
+            }
+            _ => {
+                eprintln!("{} Settings{job}: {problem}", style("Error:").red().bold());
+                errors += 1;
+            }
+        }
+    }
+    match errors {
+        0 => Ok(()),
+        1 => Err(NebulaeError::Invalid(format!("Invalid settings{job}"))),
+        _ => Err(NebulaeError::Invalid(format!(
+            "Invalid settings{job} ({errors} problems)"
+        ))),
+    }
+}
+
+/// Checks that an image can be written to `output_path` before a job is rendered, rather than
+/// finding out once it is done, creating its directory first if `mkdirs` is set
+fn check_output(output_path: &str, mkdirs: bool) -> Result<(), NebulaeError> {
+    let write_error = |path: &Path| {
+        let path = path.display().to_string();
+        move |source| NebulaeError::Write { path, source }
+    };
+    let path = Path::new(output_path);
+    if output_path.is_empty() {
+        return Err(NebulaeError::Invalid(String::from(
+            "The output path is empty",
+        )));
+    }
+    // `Path` drops the separator at the end of a path, which would then name the directory's file
+    if output_path.ends_with(std::path::is_separator) || path.is_dir() {
+        return Err(NebulaeError::Invalid(format!(
+            "Output path {output_path} is a directory, not an image file"
+        )));
+    }
+    if path.exists() && !path.is_file() {
+        // Files that aren't regular, like `/dev/stdout`, are written in place
+        File::options()
+            .write(true)
+            .open(path)
+            .map_err(write_error(path))?;
+        return Ok(());
+    }
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    if !directory.is_dir() {
+        if !mkdirs {
+            return Err(NebulaeError::Invalid(format!(
+                "Output directory {} does not exist (pass --mkdirs to create it)",
+                directory.display()
+            )));
+        }
+        fs::create_dir_all(directory).map_err(write_error(directory))?;
+    }
+    // Images are written next to their path before they are moved over it, so that is what must
+    // be possible
+    // The probe is a new file of its own, which can't be any that is already there, such as the
+    // temporary file of another write
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut attempt = 0;
+    loop {
+        let probe = path.with_file_name(format!(".{name}.{}-{attempt}.probe", std::process::id()));
+        match File::options().write(true).create_new(true).open(&probe) {
+            Ok(_) => return fs::remove_file(&probe).map_err(write_error(path)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(error) => return Err(write_error(path)(error)),
+        }
+    }
+}
+
+/// Picks the limits of some `settings` that ask for `auto_limits`, and prints them for a `job`
+/// Bands replace the channels of the limits, which aren't picked for them
+fn pick_limits(settings: &mut RenderSettings, job: &str) -> Result<(), NebulaeError> {
+    if let (Some(percentiles), None) = (settings.auto_limits.clone(), &settings.bands) {
+        settings.limits = calibrate_limits(settings)?;
+        settings.auto_limits = None;
+        eprintln!(
+            "{} {} (at percentiles {} of the escape times)",
+            style(format!("Picked limits{job}:")).bold(),
+            join(&settings.limits),
+            join(&percentiles)
+        );
+    }
+    Ok(())
+}
+
+/// Saves some `settings` of a job that writes to `output` to a configuration file at `path`, with
+/// their paths made relative to it
+fn save_settings(settings: &RenderSettings, output: &str, path: &str) -> Result<(), NebulaeError> {
+    let mut settings = settings.clone();
+    for file in [
+        &mut settings.mask,
+        &mut settings.background,
+        &mut settings.noise_map,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        *file = saved_output_path(file, path);
+    }
+    settings.to_file(path, Some(&saved_output_path(output, path)))
+}
+
+/// Joins some values into a list, separated by commas
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Gives the path to save in a configuration file, to write to `output` when relative to `config`
+fn saved_output_path(output: &str, config: &str) -> String {
+    let output = Path::new(output);
+    let same_directory = Path::new(config)
+        .parent()
+        .is_none_or(|directory| directory.as_os_str().is_empty());
+    if output.is_absolute() || same_directory {
+        output.to_string_lossy().into_owned()
+    } else {
+        // Rather than working out a path relative to the configuration file, save where it is now
+        std::env::current_dir()
+            .map(|directory| directory.join(output))
+            .unwrap_or_else(|_| output.to_path_buf())
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Inserts a number before the extension of a path, so that each job gets its own file
+fn numbered_path(path: &str, number: usize) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-{number}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{number}"),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn probes_leave_other_files_alone() {
+        let directory = std::env::temp_dir().join(format!("nebulae-probe-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let output = directory.join("image.png");
+        let temporary = directory.join("image.png.tmp");
+        fs::write(&temporary, "not ours").unwrap();
+
+        check_output(&output.to_string_lossy(), false).unwrap();
+        assert_eq!(fs::read_to_string(&temporary).unwrap(), "not ours");
+        let mut left: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["image.png.tmp"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn saved_settings_render_the_same_image_from_anywhere() {
+        let directory = std::env::temp_dir().join(format!("nebulae-saved-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("saved.toml");
+        let path = path.to_string_lossy();
+        let settings = RenderSettings {
+            limits: vec![15_480, 5_160, 1_720],
+            mask: Some("mask.png".to_string()),
+            ..RenderSettings::default()
+        };
+
+        save_settings(&settings, "image.png", &path).unwrap();
+        let (saved, output) = config_file::load_jobs(&path, true).unwrap().remove(0);
+        let here = std::env::current_dir().unwrap();
+        assert_eq!(saved.limits, settings.limits);
+        assert_eq!(saved.mask.map(PathBuf::from), Some(here.join("mask.png")));
+        assert_eq!(output.map(PathBuf::from), Some(here.join("image.png")));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn missing_directories_are_created_only_when_asked() {
+        let directory = std::env::temp_dir().join(format!("nebulae-mkdirs-{}", std::process::id()));
+        let output = directory.join("renders").join("image.png");
+        let output = output.to_string_lossy();
+        assert!(check_output(&output, false).is_err());
+        check_output(&output, true).unwrap();
+        assert!(directory.join("renders").is_dir());
+        assert!(check_output(&directory.to_string_lossy(), true).is_err());
+        assert!(check_output("", true).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn saved_outputs_lead_back_to_the_same_file() {
+        let here = std::env::current_dir().unwrap();
+        assert_eq!(saved_output_path("image.png", "saved.toml"), "image.png");
+        assert_eq!(
+            PathBuf::from(saved_output_path("renders/image.png", "configs/saved.toml")),
+            here.join("renders/image.png")
+        );
+        let absolute = here.join("image.png");
+        let absolute = absolute.to_string_lossy();
+        assert_eq!(saved_output_path(&absolute, "configs/saved.toml"), absolute);
+    }
+
+    #[test]
+    fn jobs_get_numbered_files() {
+        assert_eq!(numbered_path("renders/image.png", 2), "renders/image-2.png");
+        assert_eq!(numbered_path("image", 10), "image-10");
+    }
+}
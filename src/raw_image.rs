@@ -1,38 +1,1950 @@
-use crate::CHANNELS;
-use std::sync::atomic::AtomicU32;
-use std::sync::atomic::Ordering::SeqCst;
+//! Images of counts of points, which every worker of a render adds to at once
+//!
+//! Each value is an independent counter, which nothing else is ever read in step with, so counts
+//! are added with `Relaxed` ordering: every addition still happens exactly once, only their order
+//! across values is left open. The image is only read once the workers that add to it are done,
+//! as rayon waits for them before a pass ends, and that wait orders every addition before the
+//! reads, which can then be `Relaxed` too.
+//!
+//! Counters are 32 bits wide unless the render asks for 64, which take twice the memory but
+//! don't saturate on the brightest pixels of extreme renders, or for 16, which take half of it
+//! for drafts. Either way, images are read as 64-bit values. Counters of very large images can
+//! also be held in a memory map of a file rather than in memory, see [`crate::mapping`].
+//!
+//! Points that don't count as whole ones, such as those spread over several pixels, are added
+//! into a [`RawImageF32`] instead, whose values are sums of `f32` weights. There are no atomic
+//! floats, so each sum is added by swapping its bits in with a compare-exchange, which is tried
+//! again whenever another thread changed them in between.
+//!
+//! Compact 16-bit counters are promoted to 32 bits a band of rows at a time, once any of the
+//! band's counters overflows: the thread whose addition wraps a counter around adds the carry to
+//! the upper halves of the band's counters, which are only allocated then. Every addition is still
+//! a single atomic one on the lower half, and no count is ever lost to the promotion.
+//!
+//! 32-bit counters, compact ones included, saturate at `u32::MAX` rather than wrap around, which
+//! would turn the brightest pixels black. An addition that wraps a counter around sets it back to
+//! the cap, and the image counts the counters that reach it, so that renders can tell that they
+//! lost points to it. 64-bit counters would take centuries of points to fill, and just add.
+//!
+//! Images can be saved to checkpoint files and loaded back, so that long renders aren't lost to
+//! a crash. A checkpoint holds, in little-endian order:
+//!
+//! - the magic bytes `NEBULAE` and `0x1a`, then the version of the format (a `u32`, currently 1)
+//! - the width, height and number of channels of the image (`u32`s), the bits of its counters (a
+//!   `u8`, then 7 zero bytes), and the number of passes that it took (a `u64`)
+//! - every count, pixels row by row from the top left and channels side by side, as `u32`s, or
+//!   `u64`s for 64-bit counters
+//! - a checksum of everything before it (a `u64`), which catches files that were damaged
+//!
+//! Counts aren't compressed, to keep the format simple to read from anywhere.
+
+use crate::error::NebulaeError;
+use crate::mapping::{Mapped, Zeroed};
+use crate::output::TemporaryFile;
+use crate::render_settings::{Layout, RenderSettings};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicUsize};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+/// Most memory that the local images of every worker may take together, past which workers
+/// count their points straight into the shared image
+pub(crate) const MAX_LOCAL_BYTES: u64 = 1 << 30;
+
+/// First bytes of every checkpoint file
+const CHECKPOINT_MAGIC: [u8; 8] = *b"NEBULAE\x1a";
+
+/// Version of the checkpoint format written by this version of the program
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// Size of the header of a checkpoint file, magic bytes included, in bytes
+const CHECKPOINT_HEADER_BYTES: u64 = 40;
+
+/// Largest value whose bin in a histogram is looked up in a table
+const HISTOGRAM_LOOKUP: u64 = 1 << 16;
+
+/// Number of rows of pixels in each band of compact counters that are promoted together
+const BAND_ROWS: usize = 16;
+
+/// Number of pixels along each side of the tiles of a tiled image
+const TILE: usize = 64;
+
+/// Counters held in memory, or in a memory map of a file
+enum Store<T> {
+    Memory(Vec<T>),
+    Mapped(Mapped<T>),
+}
+
+impl<T: Zeroed> Store<T> {
+    /// `len` counters at 0, in a memory map of a file in a `directory` if there is one
+    fn zeroed(len: usize, directory: Option<&Path>) -> io::Result<Store<T>> {
+        Ok(match directory {
+            Some(directory) => Store::Mapped(Mapped::new(len, directory)?),
+            None => Store::Memory((0..len).map(|_| T::zero()).collect()),
+        })
+    }
+}
+
+impl<T> Deref for Store<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Store::Memory(values) => values,
+            Store::Mapped(values) => values,
+        }
+    }
+}
+
+/// Values of an image, in counters of any width
+enum Values {
+    /// Lower halves of 32-bit counters, and the upper halves of each band of them that overflowed
+    Compact {
+        low: Store<AtomicU16>,
+        high: Vec<OnceLock<Box<[AtomicU16]>>>,
+        /// Number of values in each band
+        band: usize,
+    },
+    Narrow(Store<AtomicU32>),
+    Wide(Store<AtomicU64>),
+    /// 32-bit counters of the values that aren't 0, by their index in each band of values
+    Sparse {
+        bands: Vec<Mutex<HashMap<u32, u32>>>,
+        /// Number of values in each band
+        band: usize,
+    },
+}
+
+impl Values {
+    /// `len` counters at 0 of 16, 32 or 64 `bits`, compact ones being promoted in bands of `band`
+    /// values, in a memory map of a file in a `directory` if there is one (the upper halves of
+    /// compact counters are always held in memory)
+    fn zeroed(len: usize, band: usize, bits: u8, directory: Option<&Path>) -> io::Result<Values> {
+        Ok(match bits {
+            16 => Values::Compact {
+                low: Store::zeroed(len, directory)?,
+                high: (0..len.div_ceil(band)).map(|_| OnceLock::new()).collect(),
+                band,
+            },
+            64 => Values::Wide(Store::zeroed(len, directory)?),
+            _ => Values::Narrow(Store::zeroed(len, directory)?),
+        })
+    }
+
+    /// Add `count` to the lower half of the compact counter at `index`, and any carry to its upper
+    /// half, promoting its band first if needed, and saturating at `u32::MAX`
+    /// Returns whether the band was promoted, and whether the counter saturated
+    fn add_compact(
+        low: &[AtomicU16],
+        high: &[OnceLock<Box<[AtomicU16]>>],
+        band: usize,
+        index: usize,
+        count: u32,
+    ) -> (bool, bool) {
+        let low_count = count as u16;
+        let old = low[index].fetch_add(low_count, Relaxed);
+        let new = old.wrapping_add(low_count);
+        let carry = (count >> 16) + ((old as u32 + low_count as u32) >> 16);
+        if carry == 0 {
+            // Only a counter whose upper half is full saturates without a carry
+            let full = new == u16::MAX
+                && high[index / band]
+                    .get()
+                    .is_some_and(|upper| upper[index % band].load(Relaxed) == u16::MAX);
+            return (false, full);
+        }
+        let mut promoted = false;
+        let upper = high[index / band].get_or_init(|| {
+            promoted = true;
+            (0..band).map(|_| AtomicU16::new(0)).collect()
+        });
+        let old_upper = upper[index % band].fetch_add(carry as u16, Relaxed);
+        if old_upper as u32 + carry < u16::MAX as u32 {
+            return (promoted, false);
+        }
+        if old_upper as u32 + carry > u16::MAX as u32 {
+            // The upper half wrapped around, so both halves are set back to the cap
+            upper[index % band].store(u16::MAX, Relaxed);
+            low[index].store(u16::MAX, Relaxed);
+            return (promoted, !(old == u16::MAX && old_upper == u16::MAX));
+        }
+        (promoted, new == u16::MAX)
+    }
+
+    /// Add `count` to a 32-bit `counter`, saturating at `u32::MAX`
+    /// Returns the new value, and whether the counter saturated
+    fn add_narrow(counter: &AtomicU32, count: u32) -> (u32, bool) {
+        let old = counter.fetch_add(count, Relaxed);
+        match old.checked_add(count) {
+            Some(new) if new < u32::MAX => (new, false),
+            new => {
+                // Additions that race with the wrapped value being set back are past the cap too
+                if new.is_none() {
+                    counter.store(u32::MAX, Relaxed);
+                }
+                (u32::MAX, old != u32::MAX)
+            }
+        }
+    }
+
+    /// Add `count` to a 32-bit `value` of a sparse image, saturating at `u32::MAX`
+    /// Returns whether the value saturated
+    fn add_sparse(value: &mut u32, count: u32) -> bool {
+        let old = *value;
+        *value = old.saturating_add(count);
+        *value == u32::MAX && old != u32::MAX
+    }
+}
 
 /// A structure to hold unscaled, integer "photo-counting" style images.
+///
+/// Pixels are read row by row from the top left, with the values of every channel of a pixel
+/// side by side, like the rows of a PNG, and held that way unless the image is tiled.
+///
+/// Tiled images hold their pixels in tiles of `TILE` × `TILE`, row by row of tiles from the top
+/// left, and the values of one channel of a tile after those of another, each row by row. Tiles
+/// at the right and bottom edges are cut to the image, so that it takes no more counters. Orbits
+/// plot their points a channel at a time, so points that land near each other in a tile land on
+/// the same pages and cache lines, where rows would spread them over many. Yet the orbits of a full
+/// view hop across the image more than they walk through it, and finding the position of each
+/// point takes longer: passes of a default render of 8192 × 8192 pixels took about 40% longer
+/// tiled, so images are only tiled when asked to be.
 pub struct RawImage {
+    width: u32,
     height: u32,
-    data: Vec<AtomicU32>,
-    maximum: AtomicU32,
+    channels: u32,
+    values: Values,
+    /// Largest value of each channel, which compact counters don't track, as they only know the
+    /// upper halves of their values once the image is read
+    maxima: Box<[AtomicU64]>,
+    /// Number of bands of compact counters that were promoted to 32 bits
+    promoted: AtomicUsize,
+    /// Number of 32-bit counters that saturated at `u32::MAX`
+    saturated: AtomicUsize,
+    /// Order that the counters are held in
+    layout: Layout,
 }
 
 impl RawImage {
-    /// Construct a new [`RawImage`] with a given width and height, initialized to 0
-    pub fn new(width: u32, height: u32) -> RawImage {
+    /// Construct a new [`RawImage`] with a given width, height and number of channels,
+    /// initialized to 0, with counters of 16 (promoted to 32 as needed), 32 or 64 `bits`, held
+    /// in a given `layout`
+    pub fn new(width: u32, height: u32, channels: u32, bits: u8, layout: Layout) -> RawImage {
+        RawImage::zeroed(width, height, channels, bits, layout, None)
+            .expect("counters in memory are made without any file")
+    }
+
+    /// Construct a new [`RawImage`] like [`RawImage::new`], whose counters are held in a memory
+    /// map of a file created in a `directory`
+    pub fn mapped(
+        width: u32,
+        height: u32,
+        channels: u32,
+        bits: u8,
+        layout: Layout,
+        directory: &Path,
+    ) -> io::Result<RawImage> {
+        RawImage::zeroed(width, height, channels, bits, layout, Some(directory))
+    }
+
+    /// Construct a new [`RawImage`] like [`RawImage::new`] with 32-bit counters, of which only
+    /// those that aren't 0 take memory, a band of rows at a time behind a lock
+    pub fn sparse(width: u32, height: u32, channels: u32) -> RawImage {
+        let values = width as usize * height as usize * channels as usize;
+        let band = width as usize * BAND_ROWS * channels as usize;
         RawImage {
+            width,
+            height,
+            channels,
+            values: Values::Sparse {
+                bands: (0..values.div_ceil(band))
+                    .map(|_| Mutex::new(HashMap::new()))
+                    .collect(),
+                band,
+            },
+            maxima: (0..channels).map(|_| AtomicU64::new(0)).collect(),
+            promoted: AtomicUsize::new(0),
+            saturated: AtomicUsize::new(0),
+            layout: Layout::Linear,
+        }
+    }
+
+    fn zeroed(
+        width: u32,
+        height: u32,
+        channels: u32,
+        bits: u8,
+        layout: Layout,
+        directory: Option<&Path>,
+    ) -> io::Result<RawImage> {
+        let values = width as usize * height as usize * channels as usize;
+        let band = width as usize * BAND_ROWS * channels as usize;
+        Ok(RawImage {
+            width,
+            height,
+            channels,
+            values: Values::zeroed(values, band, bits, directory)?,
+            maxima: (0..channels).map(|_| AtomicU64::new(0)).collect(),
+            promoted: AtomicUsize::new(0),
+            saturated: AtomicUsize::new(0),
+            layout,
+        })
+    }
+
+    /// Memory that the counters of an image of `width` × `height` pixels of some `channels` take,
+    /// with counters of `bits` (before any compact ones are promoted)
+    pub fn bytes(width: u32, height: u32, channels: u32, bits: u8) -> u64 {
+        width as u64 * height as u64 * channels as u64 * (bits as u64 / 8)
+    }
+
+    /// Rebuild a [`RawImage`] of `width` × `height` pixels and some `channels` with 32-bit
+    /// counters from its `counts`, such as those of [`RawImage::get_data`] once deserialized
+    pub fn from_counts(
+        width: u32,
+        height: u32,
+        channels: u32,
+        counts: Vec<u32>,
+    ) -> Result<RawImage, NebulaeError> {
+        let values = width as usize * height as usize * channels as usize;
+        if counts.len() != values {
+            return Err(NebulaeError::Invalid(format!(
+                "{} counts can't fill a {width}x{height} image of {channels} channels, which takes {values}",
+                counts.len()
+            )));
+        }
+        let saturated = counts.iter().filter(|&&count| count == u32::MAX).count();
+        let mut maxima = vec![0; channels as usize];
+        for pixel in counts.chunks_exact(channels as usize) {
+            for (maximum, &count) in maxima.iter_mut().zip(pixel) {
+                *maximum = count.max(*maximum);
+            }
+        }
+        Ok(RawImage {
+            width,
             height,
-            data: vec![(); (width * height * CHANNELS) as usize].iter().map(|_| AtomicU32::new(0)).collect(),
-            maximum: AtomicU32::new(0),
+            channels,
+            values: Values::Narrow(Store::Memory(
+                counts.into_iter().map(AtomicU32::new).collect(),
+            )),
+            maxima: maxima
+                .into_iter()
+                .map(|maximum| AtomicU64::new(maximum as u64))
+                .collect(),
+            promoted: AtomicUsize::new(0),
+            saturated: AtomicUsize::new(saturated),
+            layout: Layout::Linear,
+        })
+    }
+
+    /// Add every count of an `other` image of the same size into this one, such as one rendered
+    /// by another process
+    /// Counts saturate past 32 bits in this image's 32-bit counters, as they do when rendering
+    pub fn merge(&self, other: &RawImage) -> Result<(), NebulaeError> {
+        if (other.width, other.height, other.channels) != (self.width, self.height, self.channels) {
+            return Err(NebulaeError::Invalid(format!(
+                "Can't merge a {}x{} image of {} channels into a {}x{} one of {}",
+                other.width, other.height, other.channels, self.width, self.height, self.channels
+            )));
+        }
+        // A row at a time, which spares a copy of a sparse image from taking as much memory as a
+        // dense one
+        let row_length = self.width as usize * self.channels as usize;
+        let mut row = Vec::with_capacity(row_length);
+        for y in 0..self.height {
+            row.clear();
+            row.extend(other.row(y));
+            self.add_counts_at(y as usize * row_length, &row);
+        }
+        Ok(())
+    }
+
+    /// Add `count` to the value of a given `channel` at `x` - `y` coordinates
+    pub fn add(&self, x: u32, y: u32, channel: u32, count: u32) {
+        debug_assert!(
+            x < self.width && y < self.height && channel < self.channels,
+            "({x}, {y}) in channel {channel} is outside of a {}x{} image of {} channels",
+            self.width,
+            self.height,
+            self.channels
+        );
+        if let Some(new_value) = self.add_at(self.position(x, y, channel), count as u64) {
+            self.maxima[channel as usize].fetch_max(new_value, Relaxed);
+        }
+    }
+
+    /// Add `count` to the counter at a `position`, saturating 32-bit counters at `u32::MAX`
+    /// Returns the new value, unless the counter is compact
+    fn add_at(&self, position: usize, count: u64) -> Option<u64> {
+        match &self.values {
+            Values::Compact { low, high, band } => {
+                let count = count.min(u32::MAX as u64) as u32;
+                let (promoted, saturated) = Values::add_compact(low, high, *band, position, count);
+                if promoted {
+                    self.promoted.fetch_add(1, Relaxed);
+                }
+                if saturated {
+                    self.saturated.fetch_add(1, Relaxed);
+                }
+                None
+            }
+            Values::Narrow(values) => {
+                let count = count.min(u32::MAX as u64) as u32;
+                let (new_value, saturated) = Values::add_narrow(&values[position], count);
+                if saturated {
+                    self.saturated.fetch_add(1, Relaxed);
+                }
+                Some(new_value as u64)
+            }
+            Values::Wide(values) => Some(values[position].fetch_add(count, Relaxed) + count),
+            Values::Sparse { bands, band } => {
+                let mut values = bands[position / band]
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                let value = values.entry((position % band) as u32).or_insert(0);
+                if Values::add_sparse(value, count.min(u32::MAX as u64) as u32) {
+                    self.saturated.fetch_add(1, Relaxed);
+                }
+                Some(*value as u64)
+            }
+        }
+    }
+
+    /// Position among the counters of the value of a given `channel` at `x` - `y` coordinates
+    fn position(&self, x: u32, y: u32, channel: u32) -> usize {
+        let (first, plane) = self.pixel_position(x, y);
+        first + channel as usize * plane
+    }
+
+    /// Position among the counters of the first channel of the pixel at `x` - `y` coordinates,
+    /// and the distance between the positions of its channels
+    fn pixel_position(&self, x: u32, y: u32) -> (usize, usize) {
+        let (x, y) = (x as usize, y as usize);
+        let (width, height) = (self.width as usize, self.height as usize);
+        let channels = self.channels as usize;
+        match self.layout {
+            Layout::Linear => ((y * width + x) * channels, 1),
+            Layout::Tiled => {
+                let (left, top) = (x / TILE * TILE, y / TILE * TILE);
+                let (tile_width, tile_height) = (TILE.min(width - left), TILE.min(height - top));
+                // Past the rows of tiles above and the tiles to the left
+                let tile = top * width * channels + left * tile_height * channels;
+                (
+                    tile + (y - top) * tile_width + x - left,
+                    tile_width * tile_height,
+                )
+            }
+        }
+    }
+
+    /// Positions among the counters of the values in a `range` of indices, in the order of
+    /// [`RawImage::get_data`], which are only found anew for the first pixel in each tile
+    fn positions(&self, range: Range<usize>) -> impl Iterator<Item = usize> + '_ {
+        let (width, channels) = (self.width.max(1), self.channels.max(1));
+        let pixel = range.start / channels as usize;
+        let mut channel = (range.start % channels as usize) as u32;
+        let (mut x, mut y) = (
+            (pixel % width as usize) as u32,
+            (pixel / width as usize) as u32,
+        );
+        let (mut first, mut plane) = self.pixel_position(x, y);
+        // Pixels side by side in a row of a tile are next to each other
+        let step = match self.layout {
+            Layout::Linear => channels as usize,
+            Layout::Tiled => 1,
+        };
+        range.map(move |_| {
+            let position = first + channel as usize * plane;
+            channel += 1;
+            if channel == channels {
+                channel = 0;
+                x += 1;
+                if x == width {
+                    x = 0;
+                    y += 1;
+                }
+                if (x as usize).is_multiple_of(TILE) {
+                    (first, plane) = self.pixel_position(x, y);
+                } else {
+                    first += step;
+                }
+            }
+            position
+        })
+    }
+
+    /// Ranges of the positions of the counters of a tiled image that each hold the values of a
+    /// channel of a tile, in the order of the counters, and their channels
+    fn planes(&self) -> impl Iterator<Item = (Range<usize>, usize)> + '_ {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let channels = self.channels as usize;
+        (0..height).step_by(TILE).flat_map(move |top| {
+            let tile_height = TILE.min(height - top);
+            (0..width).step_by(TILE).flat_map(move |left| {
+                let plane = TILE.min(width - left) * tile_height;
+                let tile = top * width * channels + left * tile_height * channels;
+                (0..channels).map(move |channel| {
+                    let first = tile + channel * plane;
+                    (first..first + plane, channel)
+                })
+            })
+        })
+    }
+
+    /// Add counts to the values in a `range` of indices of a tiled image, a value at a time, taking
+    /// the count of each from its position among the counters and its index
+    fn add_tiled(&self, range: Range<usize>, count: impl Fn(usize, usize) -> u64) {
+        let channels = self.channels as usize;
+        let mut maxima = vec![0; channels];
+        for (position, index) in self.positions(range.clone()).zip(range) {
+            let count = count(position, index);
+            if count > 0 {
+                if let Some(new_value) = self.add_at(position, count) {
+                    let maximum = &mut maxima[index % channels];
+                    *maximum = new_value.max(*maximum);
+                }
+            }
+        }
+        for (maximum, value) in self.maxima.iter().zip(maxima) {
+            maximum.fetch_max(value, Relaxed);
+        }
+    }
+
+    /// Value of the counter at a `position`
+    fn stored(&self, position: usize) -> u64 {
+        match &self.values {
+            Values::Compact { low, high, band } => {
+                let upper = high[position / band]
+                    .get()
+                    .map_or(0, |high| high[position % band].load(Relaxed) as u64);
+                upper << 16 | low[position].load(Relaxed) as u64
+            }
+            Values::Narrow(values) => values[position].load(Relaxed) as u64,
+            Values::Wide(values) => values[position].load(Relaxed),
+            Values::Sparse { bands, band } => bands[position / band]
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(&((position % band) as u32))
+                .map_or(0, |&value| value as u64),
+        }
+    }
+
+    /// Add the `counts` of every value of another image of the same size, in the order of
+    /// [`RawImage::get_data`]
+    pub fn add_counts<T: Copy + Into<u64>>(&self, counts: &[T]) {
+        debug_assert_eq!(
+            counts.len(),
+            self.width as usize * self.height as usize * self.channels as usize
+        );
+        self.add_counts_at(0, counts);
+    }
+
+    /// Add the `counts` of a local image, which are held in the order of this image's counters
+    fn add_local_counts<T: Copy + Into<u64>>(&self, counts: &[T]) {
+        match self.layout {
+            Layout::Linear => self.add_counts(counts),
+            Layout::Tiled => {
+                // Each plane of a tile holds the counts of one channel, side by side
+                let mut maxima = vec![0; self.channels as usize];
+                for (plane, channel) in self.planes() {
+                    let maximum = &mut maxima[channel];
+                    for (position, &count) in plane.clone().zip(&counts[plane]) {
+                        let count = count.into();
+                        if count > 0 {
+                            if let Some(new_value) = self.add_at(position, count) {
+                                *maximum = new_value.max(*maximum);
+                            }
+                        }
+                    }
+                }
+                for (maximum, value) in self.maxima.iter().zip(maxima) {
+                    maximum.fetch_max(value, Relaxed);
+                }
+            }
+        }
+    }
+
+    /// Add `counts` to the values from the one at `offset` on, which is that of a pixel's first
+    /// channel
+    fn add_counts_at<T: Copy + Into<u64>>(&self, offset: usize, counts: &[T]) {
+        let channels = self.channels as usize;
+        let mut maxima = vec![0; channels];
+        match &self.values {
+            // The values of a row are spread over the tiles that it crosses
+            _ if self.layout == Layout::Tiled => {
+                let range = offset..offset + counts.len();
+                return self.add_tiled(range, |_, index| counts[index - offset].into());
+            }
+            Values::Compact { .. } => {
+                for (index, &count) in (offset..).zip(counts) {
+                    let count = count.into();
+                    if count > 0 {
+                        self.add_at(index, count);
+                    }
+                }
+            }
+            Values::Narrow(values) => {
+                let values = &values[offset..offset + counts.len()];
+                let pixels = values
+                    .chunks_exact(channels)
+                    .zip(counts.chunks_exact(channels));
+                for (values, counts) in pixels {
+                    for ((value, &count), maximum) in values.iter().zip(counts).zip(&mut maxima) {
+                        // Counts past 32 bits saturate, as they would have in this image
+                        let count = count.into().min(u32::MAX as u64) as u32;
+                        if count > 0 {
+                            let (new_value, saturated) = Values::add_narrow(value, count);
+                            if saturated {
+                                self.saturated.fetch_add(1, Relaxed);
+                            }
+                            *maximum = (new_value as u64).max(*maximum);
+                        }
+                    }
+                }
+            }
+            Values::Wide(values) => {
+                let values = &values[offset..offset + counts.len()];
+                let pixels = values
+                    .chunks_exact(channels)
+                    .zip(counts.chunks_exact(channels));
+                for (values, counts) in pixels {
+                    for ((value, &count), maximum) in values.iter().zip(counts).zip(&mut maxima) {
+                        let count = count.into();
+                        if count > 0 {
+                            *maximum = (value.fetch_add(count, Relaxed) + count).max(*maximum);
+                        }
+                    }
+                }
+            }
+            Values::Sparse { bands, band } => {
+                // Each band is locked once, if it has any count to take
+                let mut first = 0;
+                while first < counts.len() {
+                    let index = offset + first;
+                    let end = counts.len().min(first + band - index % band);
+                    let band_counts = &counts[first..end];
+                    if band_counts.iter().any(|&count| count.into() > 0) {
+                        let mut values = bands[index / band]
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner);
+                        for (at, &count) in (first..).zip(band_counts) {
+                            // Counts past 32 bits saturate, as they would have in this image
+                            let count = count.into().min(u32::MAX as u64) as u32;
+                            if count > 0 {
+                                let value =
+                                    values.entry(((offset + at) % band) as u32).or_insert(0);
+                                if Values::add_sparse(value, count) {
+                                    self.saturated.fetch_add(1, Relaxed);
+                                }
+                                let maximum = &mut maxima[at % channels];
+                                *maximum = (*value as u64).max(*maximum);
+                            }
+                        }
+                    }
+                    first = end;
+                }
+            }
+        }
+        for (maximum, value) in self.maxima.iter().zip(maxima) {
+            maximum.fetch_max(value, Relaxed);
         }
     }
 
-    /// Increment the value of a given `channel` at `x` - `y` coordinates
-    pub fn bump(&self, x: u32, y: u32, channel: u32) {
-        let index = ((x * self.height + y) * 3 + channel) as usize;
-        let new_value = self.data[index].fetch_add(1, SeqCst);
-        self.maximum.fetch_max(new_value, SeqCst);
+    /// Number of bands of compact counters that were promoted to 32 bits
+    pub fn promoted(&self) -> usize {
+        self.promoted.load(Relaxed)
+    }
+
+    /// Number of 32-bit counters that saturated at `u32::MAX`, which count no more points
+    pub fn saturated(&self) -> usize {
+        self.saturated.load(Relaxed)
     }
 
     /// Get a copy of the internal data
-    pub fn get_data(&self) -> Vec<u32> {
-        self.data.iter().map(|a| a.load(SeqCst)).collect()
+    pub fn get_data(&self) -> Vec<u64> {
+        let mut data = Vec::new();
+        self.snapshot_into(&mut data);
+        data
+    }
+
+    /// Copy the internal data into a `data` buffer, replacing what it held, which reuses its
+    /// memory when it already holds the data of an image of this size
+    pub fn snapshot_into(&self, data: &mut Vec<u64>) {
+        data.clear();
+        match &self.values {
+            _ if self.layout == Layout::Tiled => data.extend(self.values()),
+            Values::Compact { .. } => data.extend(self.values()),
+            Values::Narrow(values) => data.extend(values.iter().map(|a| a.load(Relaxed) as u64)),
+            Values::Wide(values) => data.extend(values.iter().map(|a| a.load(Relaxed))),
+            Values::Sparse { .. } => data.extend(self.values()),
+        }
+    }
+
+    /// Values of row `y`, with the values of every channel of a pixel side by side, without
+    /// copying them
+    pub fn row(&self, y: u32) -> impl Iterator<Item = u64> + '_ {
+        let row_length = self.width as usize * self.channels as usize;
+        self.span(y as usize * row_length..(y as usize + 1) * row_length)
+    }
+
+    /// Values of every row, from the top, like those of [`RawImage::row`]
+    pub fn rows(&self) -> impl Iterator<Item = impl Iterator<Item = u64> + '_> + '_ {
+        (0..self.height).map(|y| self.row(y))
+    }
+
+    /// Values of a `channel` of every pixel, row by row from the top left, without copying them
+    pub fn channel_iter(&self, channel: u32) -> impl Iterator<Item = u64> + '_ {
+        self.values()
+            .skip(channel as usize)
+            .step_by(self.channels as usize)
+    }
+
+    /// Get the maximum value (brightest pixel), across every channel
+    pub fn get_maximum(&self) -> u64 {
+        match &self.values {
+            Values::Compact { .. } => self.values().max().unwrap_or(0),
+            _ => self
+                .maxima
+                .iter()
+                .map(|a| a.load(Relaxed))
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Get the maximum value of each channel
+    pub fn get_channel_maxima(&self) -> Vec<u64> {
+        match &self.values {
+            Values::Compact { .. } => {
+                let mut maxima = vec![0; self.channels as usize];
+                for (index, value) in self.values().enumerate() {
+                    let maximum = &mut maxima[index % self.channels as usize];
+                    *maximum = value.max(*maximum);
+                }
+                maxima
+            }
+            _ => self.maxima.iter().map(|a| a.load(Relaxed)).collect(),
+        }
+    }
+
+    /// Whether the counters are held in a memory map
+    pub fn is_mapped(&self) -> bool {
+        matches!(
+            &self.values,
+            Values::Compact {
+                low: Store::Mapped(_),
+                ..
+            } | Values::Narrow(Store::Mapped(_))
+                | Values::Wide(Store::Mapped(_))
+        )
+    }
+
+    /// Whether only the counters that aren't 0 are held
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.values, Values::Sparse { .. })
+    }
+
+    /// Memory that the counters of a sparse image take so far, about
+    pub fn sparse_bytes(&self) -> Option<u64> {
+        let Values::Sparse { bands, .. } = &self.values else {
+            return None;
+        };
+        // Each entry takes a byte of the map's own besides its key and value
+        let entry = size_of::<(u32, u32)>() as u64 + 1;
+        let entries: u64 = bands
+            .iter()
+            .map(|values| {
+                let values = values.lock().unwrap_or_else(PoisonError::into_inner);
+                values.capacity() as u64
+            })
+            .sum();
+        Some(entries * entry + (bands.len() * size_of::<Mutex<HashMap<u32, u32>>>()) as u64)
+    }
+
+    /// A copy of the image as it is, in counters of the same width and layout, which takes no more
+    /// memory than the image does (held in memory, even when the image is in a memory map)
+    /// Nothing should add to the image while it is copied, such as between two passes, for the
+    /// copy to be consistent
+    pub fn snapshot(&self) -> RawImage {
+        fn copy<A: Sync, B: Send>(values: &[A], load: impl Fn(&A) -> B + Sync + Send) -> Vec<B> {
+            values.par_iter().map(load).collect()
+        }
+        let values = match &self.values {
+            Values::Compact { low, high, band } => Values::Compact {
+                low: Store::Memory(copy(low, |a| AtomicU16::new(a.load(Relaxed)))),
+                high: high
+                    .iter()
+                    .map(|high| match high.get() {
+                        Some(high) => OnceLock::from(
+                            high.iter()
+                                .map(|a| AtomicU16::new(a.load(Relaxed)))
+                                .collect::<Box<[_]>>(),
+                        ),
+                        None => OnceLock::new(),
+                    })
+                    .collect(),
+                band: *band,
+            },
+            Values::Narrow(values) => Values::Narrow(Store::Memory(copy(values, |a| {
+                AtomicU32::new(a.load(Relaxed))
+            }))),
+            Values::Wide(values) => Values::Wide(Store::Memory(copy(values, |a| {
+                AtomicU64::new(a.load(Relaxed))
+            }))),
+            Values::Sparse { bands, band } => Values::Sparse {
+                bands: bands
+                    .iter()
+                    .map(|values| {
+                        Mutex::new(
+                            values
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner)
+                                .clone(),
+                        )
+                    })
+                    .collect(),
+                band: *band,
+            },
+        };
+        RawImage {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            values,
+            maxima: self
+                .maxima
+                .iter()
+                .map(|a| AtomicU64::new(a.load(Relaxed)))
+                .collect(),
+            promoted: AtomicUsize::new(self.promoted()),
+            saturated: AtomicUsize::new(self.saturated()),
+            layout: self.layout,
+        }
+    }
+
+    /// Bits of the counters, 16 for compact ones
+    pub fn counter_bits(&self) -> u8 {
+        match self.values {
+            Values::Compact { .. } => 16,
+            Values::Narrow(_) | Values::Sparse { .. } => 32,
+            Values::Wide(_) => 64,
+        }
+    }
+
+    /// Every value, without copying them all at once
+    fn values(&self) -> Box<dyn Iterator<Item = u64> + '_> {
+        let len = (self.width as usize * self.height as usize) * self.channels as usize;
+        self.span(0..len)
+    }
+
+    /// Values in a `range` of indices, in the layout of [`RawImage::get_data`], without copying
+    /// them, which join the upper halves of compact counters whose bands have them
+    fn span(&self, range: Range<usize>) -> Box<dyn Iterator<Item = u64> + '_> {
+        match &self.values {
+            Values::Narrow(values) if self.layout == Layout::Tiled => Box::new(
+                self.positions(range)
+                    .map(|position| values[position].load(Relaxed) as u64),
+            ),
+            _ if self.layout == Layout::Tiled => {
+                Box::new(self.positions(range).map(|position| self.stored(position)))
+            }
+            Values::Compact { low, high, band } => {
+                let band = *band;
+                let bands = range.start / band..range.end.div_ceil(band);
+                Box::new(bands.flat_map(move |index| {
+                    let start = range.start.max(index * band);
+                    let end = range.end.min((index + 1) * band);
+                    let high = high[index].get();
+                    (start..end).map(move |at| {
+                        let upper = high.map_or(0, |high| high[at % band].load(Relaxed) as u64);
+                        upper << 16 | low[at].load(Relaxed) as u64
+                    })
+                }))
+            }
+            Values::Narrow(values) => {
+                Box::new(values[range].iter().map(|a| a.load(Relaxed) as u64))
+            }
+            Values::Wide(values) => Box::new(values[range].iter().map(|a| a.load(Relaxed))),
+            Values::Sparse { bands, band } => {
+                let band = *band;
+                let first_band = range.start / band..range.end.div_ceil(band);
+                Box::new(first_band.flat_map(move |index| {
+                    // Each band is read into values of its own, of which `start` is the first
+                    let start = range.start.max(index * band);
+                    let end = range.end.min((index + 1) * band);
+                    let mut dense = vec![0; end - start];
+                    let values = bands[index].lock().unwrap_or_else(PoisonError::into_inner);
+                    for (&at, &value) in values.iter() {
+                        let at = index * band + at as usize;
+                        if (start..end).contains(&at) {
+                            dense[at - start] = value as u64;
+                        }
+                    }
+                    dense.into_iter()
+                }))
+            }
+        }
+    }
+
+    /// Distribution of the values of a `channel`, or of every channel, over a number of `bins`,
+    /// counted a row of pixels at a time in parallel
+    /// Fails for 0 bins, which no value could be counted in
+    pub fn histogram(&self, channel: Option<u32>, bins: usize) -> Result<Histogram, NebulaeError> {
+        if bins == 0 {
+            return Err(NebulaeError::Invalid(String::from(
+                "A histogram needs at least 1 bin",
+            )));
+        }
+        let (first, step) = match channel {
+            Some(channel) => (channel as usize, self.channels as usize),
+            None => (0, 1),
+        };
+        let row = |y: u32| self.row(y).skip(first).step_by(step);
+        let maximum = (0..self.height)
+            .into_par_iter()
+            .map(|y| row(y).max().unwrap_or(0))
+            .max()
+            .unwrap_or(0);
+        // Bins are even steps of the logarithm of the values, up to that of the maximum + 1
+        let scale = bins as f64 / (maximum as f64 + 1.0).ln();
+        let bin = |value: u64| (((value as f64).ln() * scale) as usize).min(bins - 1);
+        // The bins of the most common, dim values are looked up rather than computed
+        let dim: Vec<usize> = (0..=maximum.min(HISTOGRAM_LOOKUP)).map(bin).collect();
+        let (zeros, counts) = (0..self.height)
+            .into_par_iter()
+            .fold(
+                || (0, vec![0; bins]),
+                |(mut zeros, mut counts), y| {
+                    for value in row(y) {
+                        if value == 0 {
+                            zeros += 1;
+                        } else if let Some(&bin) = dim.get(value as usize) {
+                            counts[bin] += 1;
+                        } else {
+                            counts[bin(value)] += 1;
+                        }
+                    }
+                    (zeros, counts)
+                },
+            )
+            .reduce(
+                || (0, vec![0; bins]),
+                |(zeros, mut counts), (other_zeros, other)| {
+                    for (count, other) in counts.iter_mut().zip(other) {
+                        *count += other;
+                    }
+                    (zeros + other_zeros, counts)
+                },
+            );
+        Ok(Histogram {
+            maximum,
+            zeros,
+            bins: counts,
+        })
+    }
+}
+
+/// Distribution of the values of an image, in bins of values that are even steps apart on a
+/// logarithmic scale, which keeps dim values as finely told apart as bright ones are
+///
+/// Bin `i` out of `n` holds the values from `(maximum + 1)^(i / n)` up to `(maximum + 1)^((i + 1)
+/// / n)`, and values of 0 are counted on their own.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Histogram {
+    /// Largest value
+    pub maximum: u64,
+    /// Number of values of 0
+    pub zeros: u64,
+    /// Number of values in each bin, other than those of 0
+    pub bins: Vec<u64>,
+}
+
+impl Histogram {
+    /// Number of values counted, 0 included
+    pub fn total(&self) -> u64 {
+        self.zeros + self.bins.iter().sum::<u64>()
+    }
+
+    /// Smallest value of bin `bin`, which is also the largest of the bin before it
+    pub fn edge(&self, bin: usize) -> f64 {
+        (self.maximum as f64 + 1.0).powf(bin as f64 / self.bins.len() as f64)
+    }
+
+    /// Value below which a percentage `p` (from 0 to 100) of the values lie, found within its bin
+    /// as if the values of the bin were spread evenly over the logarithmic scale
+    pub fn percentile(&self, p: f64) -> f64 {
+        let mut rank = p.clamp(0.0, 100.0) / 100.0 * self.total() as f64;
+        if rank <= self.zeros as f64 {
+            return 0.0;
+        }
+        rank -= self.zeros as f64;
+        for (bin, &count) in self.bins.iter().enumerate() {
+            if rank <= count as f64 {
+                let fraction = rank / count as f64;
+                let (low, high) = (self.edge(bin), self.edge(bin + 1));
+                return (low * (high / low).powf(fraction)).min(self.maximum as f64);
+            }
+            rank -= count as f64;
+        }
+        self.maximum as f64
+    }
+
+    /// Fraction of the values that aren't 0
+    pub fn nonzero_fraction(&self) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => (total - self.zeros) as f64 / total as f64,
+        }
+    }
+}
+
+/// A 32-bit float that can be shared between threads, held as the bits of an [`AtomicU32`]
+struct AtomicF32(AtomicU32);
+
+// The bits of zero are those of `0.0`
+unsafe impl Zeroed for AtomicF32 {
+    fn zero() -> Self {
+        AtomicF32(AtomicU32::new(0))
+    }
+}
+
+impl AtomicF32 {
+    fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Relaxed))
     }
 
-    /// Get the maximum value (brightest pixel)
-    pub fn get_maximum(&self) -> u32 {
-        self.maximum.load(SeqCst)
+    /// Add a `value`, swapping the bits of the sum in unless another thread changed them first
+    /// Returns the sum
+    fn add(&self, value: f32) -> f32 {
+        let mut old = self.0.load(Relaxed);
+        loop {
+            let new = f32::from_bits(old) + value;
+            match self
+                .0
+                .compare_exchange_weak(old, new.to_bits(), Relaxed, Relaxed)
+            {
+                Ok(_) => return new,
+                Err(bits) => old = bits,
+            }
+        }
+    }
+
+    /// Keep the larger of the value and another `value`
+    fn max(&self, value: f32) {
+        let mut old = self.0.load(Relaxed);
+        while value > f32::from_bits(old) {
+            match self
+                .0
+                .compare_exchange_weak(old, value.to_bits(), Relaxed, Relaxed)
+            {
+                Ok(_) => return,
+                Err(bits) => old = bits,
+            }
+        }
+    }
+}
+
+/// An image of weighted points, like a [`RawImage`] whose values are sums of `f32` weights rather
+/// than counts, for points that spread over several pixels or count for more than one
+///
+/// Sums can't wrap around, but past 2^24 they no longer change for weights of 1, and adding to
+/// a value contended by other threads takes a few tries, so counts are better kept in a
+/// [`RawImage`] when all of their weights are whole.
+pub struct RawImageF32 {
+    width: u32,
+    height: u32,
+    channels: u32,
+    values: Store<AtomicF32>,
+    /// Largest value of each channel
+    maxima: Box<[AtomicF32]>,
+}
+
+impl RawImageF32 {
+    /// Construct a new [`RawImageF32`] with a given width, height and number of channels,
+    /// initialized to 0
+    pub fn new(width: u32, height: u32, channels: u32) -> RawImageF32 {
+        let values = width as usize * height as usize * channels as usize;
+        RawImageF32 {
+            width,
+            height,
+            channels,
+            values: Store::Memory((0..values).map(|_| AtomicF32::zero()).collect()),
+            maxima: (0..channels).map(|_| AtomicF32::zero()).collect(),
+        }
+    }
+
+    /// Add a `weight` (at least 0) to the value of a given `channel` at `x` - `y` coordinates
+    pub fn add(&self, x: u32, y: u32, channel: u32, weight: f32) {
+        debug_assert!(
+            x < self.width && y < self.height && channel < self.channels,
+            "({x}, {y}) in channel {channel} is outside of a {}x{} image of {} channels",
+            self.width,
+            self.height,
+            self.channels
+        );
+        let index = (y as usize * self.width as usize + x as usize) * self.channels as usize
+            + channel as usize;
+        let new_value = self.values[index].add(weight);
+        self.maxima[channel as usize].max(new_value);
+    }
+
+    /// Get a copy of the internal data
+    pub fn get_data(&self) -> Vec<f32> {
+        self.values.iter().map(AtomicF32::load).collect()
+    }
+
+    /// Values of row `y`, with the values of every channel of a pixel side by side, without
+    /// copying them
+    pub fn row(&self, y: u32) -> impl Iterator<Item = f32> + '_ {
+        let row_length = self.width as usize * self.channels as usize;
+        self.values[y as usize * row_length..][..row_length]
+            .iter()
+            .map(AtomicF32::load)
+    }
+
+    /// Get the maximum value of each channel
+    pub fn get_channel_maxima(&self) -> Vec<f32> {
+        self.maxima.iter().map(AtomicF32::load).collect()
+    }
+
+    /// A copy of the image as it is, which nothing should add to while it is copied, as with
+    /// [`RawImage::snapshot`]
+    pub fn snapshot(&self) -> RawImageF32 {
+        let copy = |values: &[AtomicF32]| {
+            values
+                .par_iter()
+                .map(|value| AtomicF32(AtomicU32::new(value.0.load(Relaxed))))
+                .collect::<Vec<_>>()
+        };
+        RawImageF32 {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            values: Store::Memory(copy(&self.values)),
+            maxima: copy(&self.maxima).into_boxed_slice(),
+        }
+    }
+}
+
+/// Header of a checkpoint file, which describes the image saved in it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CheckpointHeader {
+    /// Width of the image, in pixels
+    pub width: u32,
+    /// Height of the image, in pixels
+    pub height: u32,
+    /// Number of channels of each pixel
+    pub channels: u32,
+    /// Bits of the counters of the image
+    pub counter_bits: u8,
+    /// Number of passes that the image took
+    pub passes: u64,
+}
+
+impl CheckpointHeader {
+    /// Bytes that each count takes in the file
+    fn count_bytes(&self) -> u64 {
+        if self.counter_bits == 64 {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// Size of the whole file, in bytes
+    fn file_bytes(&self) -> u64 {
+        let values = self.width as u64 * self.height as u64 * self.channels as u64;
+        CHECKPOINT_HEADER_BYTES + values * self.count_bytes() + 8
+    }
+
+    fn to_bytes(self) -> [u8; CHECKPOINT_HEADER_BYTES as usize] {
+        let mut bytes = [0; CHECKPOINT_HEADER_BYTES as usize];
+        bytes[..8].copy_from_slice(&CHECKPOINT_MAGIC);
+        bytes[8..12].copy_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.width.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.height.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.channels.to_le_bytes());
+        bytes[24] = self.counter_bits;
+        bytes[32..40].copy_from_slice(&self.passes.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; CHECKPOINT_HEADER_BYTES as usize]) -> Result<Self, String> {
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        if bytes[..8] != CHECKPOINT_MAGIC {
+            return Err(String::from("Not a checkpoint file"));
+        }
+        let version = u32_at(8);
+        if version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "Checkpoint version {version} isn't supported (only version {CHECKPOINT_VERSION} is)"
+            ));
+        }
+        let header = CheckpointHeader {
+            width: u32_at(12),
+            height: u32_at(16),
+            channels: u32_at(20),
+            counter_bits: bytes[24],
+            passes: u64::from_le_bytes(bytes[32..40].try_into().unwrap()),
+        };
+        if ![16, 32, 64].contains(&header.counter_bits) || bytes[25..32] != [0; 7] {
+            return Err(String::from("The checkpoint's header is damaged"));
+        }
+        Ok(header)
+    }
+
+    /// Check that the checkpoint holds an image that a render with some `settings` accumulates
+    pub fn check(&self, settings: &RenderSettings) -> Result<(), NebulaeError> {
+        let (width, height) = (
+            settings.width * settings.supersample,
+            settings.height * settings.supersample,
+        );
+        if (self.width, self.height) != (width, height) {
+            return Err(NebulaeError::Invalid(format!(
+                "The checkpoint holds a {}x{} image, but the render accumulates {width}x{height} pixels",
+                self.width, self.height
+            )));
+        }
+        if self.channels != settings.channels() {
+            return Err(NebulaeError::Invalid(format!(
+                "The checkpoint holds {} channels, but the render accumulates {}",
+                self.channels,
+                settings.channels()
+            )));
+        }
+        if self.counter_bits != settings.counter_bits {
+            return Err(NebulaeError::Invalid(format!(
+                "The checkpoint holds {}-bit counters, but the render uses {}-bit ones",
+                self.counter_bits, settings.counter_bits
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Checksum of the values of a checkpoint, folded from its header
+fn checksum(state: u64, value: u64) -> u64 {
+    crate::splitmix64(state ^ value)
+}
+
+impl RawImage {
+    /// Save the image to a checkpoint file at `path`, after a number of `passes`
+    /// The file is written next to it first, and only then moved over it, so that a crash can't
+    /// leave a half-written checkpoint in its place, as images are
+    pub fn save(&self, path: &Path, passes: u64) -> io::Result<()> {
+        let header = CheckpointHeader {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            counter_bits: self.counter_bits(),
+            passes,
+        };
+        let (temporary, file) = TemporaryFile::create(path)?;
+        let mut writer = BufWriter::new(file);
+        let header_bytes = header.to_bytes();
+        writer.write_all(&header_bytes)?;
+        let mut state = header_bytes.chunks_exact(8).fold(0, |state, bytes| {
+            checksum(state, u64::from_le_bytes(bytes.try_into().unwrap()))
+        });
+        for value in self.values() {
+            state = checksum(state, value);
+            if header.count_bytes() == 8 {
+                writer.write_all(&value.to_le_bytes())?;
+            } else {
+                writer.write_all(&(value as u32).to_le_bytes())?;
+            }
+        }
+        writer.write_all(&state.to_le_bytes())?;
+        temporary.persist(writer.into_inner().map_err(|error| error.into_error())?)
+    }
+
+    /// Load an image from a checkpoint file at `path`, checking that a render with some
+    /// `settings` accumulates it
+    /// Returns the image, and the header that describes it
+    pub fn load(
+        path: &Path,
+        settings: &RenderSettings,
+    ) -> Result<(RawImage, CheckpointHeader), NebulaeError> {
+        let read_error = |source| NebulaeError::Read {
+            path: path.display().to_string(),
+            source,
+        };
+        let invalid = |message| NebulaeError::Checkpoint {
+            path: path.display().to_string(),
+            message,
+        };
+        let file = File::open(path).map_err(read_error)?;
+        let length = file.metadata().map_err(read_error)?.len();
+        let mut reader = BufReader::new(file);
+        let mut header_bytes = [0; CHECKPOINT_HEADER_BYTES as usize];
+        reader
+            .read_exact(&mut header_bytes)
+            .map_err(|_| invalid(String::from("The checkpoint is too short to hold a header")))?;
+        let header = CheckpointHeader::from_bytes(&header_bytes).map_err(invalid)?;
+        header
+            .check(settings)
+            .map_err(|error| invalid(error.to_string()))?;
+        if length != header.file_bytes() {
+            return Err(invalid(format!(
+                "The checkpoint is {length} bytes long, but its image takes {}",
+                header.file_bytes()
+            )));
+        }
+        let mut state = header_bytes.chunks_exact(8).fold(0, |state, bytes| {
+            checksum(state, u64::from_le_bytes(bytes.try_into().unwrap()))
+        });
+        let image = if settings.counts_sparsely() && header.counter_bits == 32 {
+            RawImage::sparse(header.width, header.height, header.channels)
+        } else {
+            RawImage::new(
+                header.width,
+                header.height,
+                header.channels,
+                header.counter_bits,
+                settings.layout,
+            )
+        };
+        // Counts are added a row at a time, rather than all read before they are
+        let row_length = header.width as usize * header.channels as usize;
+        let mut row = Vec::with_capacity(row_length);
+        let mut bytes = [0; 8];
+        let count_bytes = header.count_bytes() as usize;
+        for y in 0..header.height as usize {
+            row.clear();
+            for _ in 0..row_length {
+                reader
+                    .read_exact(&mut bytes[..count_bytes])
+                    .map_err(read_error)?;
+                let value = if count_bytes == 8 {
+                    u64::from_le_bytes(bytes)
+                } else {
+                    u32::from_le_bytes(bytes[..4].try_into().unwrap()) as u64
+                };
+                state = checksum(state, value);
+                row.push(value);
+            }
+            image.add_counts_at(y * row_length, &row);
+        }
+        reader.read_exact(&mut bytes).map_err(read_error)?;
+        if u64::from_le_bytes(bytes) != state {
+            return Err(invalid(String::from(
+                "The checkpoint is damaged: its checksum doesn't match its counts",
+            )));
+        }
+        Ok((image, header))
+    }
+}
+
+/// Counts of a local image, as wide as those of the image they are added into
+pub(crate) enum Counts {
+    /// 32-bit counts, for an image of 16- or 32-bit counters
+    Narrow(Vec<u32>),
+    /// 64-bit counts, for an image of 64-bit counters
+    Wide(Vec<u64>),
+}
+
+impl Counts {
+    /// Add `count` to the value at `index`
+    fn add(&mut self, index: usize, count: u32) {
+        match self {
+            Counts::Narrow(counts) => counts[index] = counts[index].saturating_add(count),
+            Counts::Wide(counts) => counts[index] += count as u64,
+        }
+    }
+}
+
+/// Local copies of an image that workers count their points in, one for each thread of the pool,
+/// which spares them from contending for the values of the brightest pixels of the shared image
+///
+/// Counts are held in the same order as the counters of the shared image, so that those of a
+/// tiled one stay together in the local images too.
+pub(crate) struct LocalImages {
+    images: Vec<Mutex<Counts>>,
+}
+
+impl LocalImages {
+    /// Construct one empty local copy of a `shared` image for each of `workers` threads, with
+    /// counts 64 bits wide when its counters are
+    pub fn new(workers: usize, shared: &RawImage) -> LocalImages {
+        let values = shared.width as usize * shared.height as usize * shared.channels as usize;
+        LocalImages {
+            images: (0..workers)
+                .map(|_| {
+                    Mutex::new(if shared.counter_bits() == 64 {
+                        Counts::Wide(vec![0; values])
+                    } else {
+                        Counts::Narrow(vec![0; values])
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Memory that local copies of a `shared` image for `workers` threads would take
+    pub fn bytes(workers: usize, shared: &RawImage) -> u64 {
+        let bytes = if shared.counter_bits() == 64 { 8 } else { 4 };
+        let values = shared.width as u64 * shared.height as u64 * shared.channels as u64;
+        workers as u64 * values * bytes
+    }
+
+    /// The [`Counter`] of the current thread, which counts in its local image, or straight into
+    /// the `shared` image when it isn't a thread of the pool or its image is already in use
+    pub fn counter<'a>(&'a self, shared: &'a RawImage) -> Counter<'a> {
+        rayon::current_thread_index()
+            .and_then(|thread| self.images.get(thread))
+            .and_then(|image| image.try_lock().ok())
+            .map_or(Counter::Shared(shared), |counts| Counter::Local {
+                shared,
+                counts,
+            })
+    }
+
+    /// Add the counts of every local image into the `shared` image, and empty them
+    pub fn merge_into(&self, shared: &RawImage) {
+        for image in &self.images {
+            let mut counts = image
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            match &mut *counts {
+                Counts::Narrow(counts) => {
+                    shared.add_local_counts(counts);
+                    counts.fill(0);
+                }
+                Counts::Wide(counts) => {
+                    shared.add_local_counts(counts);
+                    counts.fill(0);
+                }
+            }
+        }
+    }
+}
+
+/// Where a worker counts the points that it plots
+pub(crate) enum Counter<'a> {
+    /// Straight into the shared image
+    Shared(&'a RawImage),
+    /// Into the worker's own local image, held for as long as the counter is, which takes the
+    /// positions of the counters of the `shared` image
+    Local {
+        shared: &'a RawImage,
+        counts: MutexGuard<'a, Counts>,
+    },
+}
+
+impl Counter<'_> {
+    /// Add `count` to the value of a given `channel` at `x` - `y` coordinates
+    pub fn add(&mut self, x: u32, y: u32, channel: u32, count: u32) {
+        match self {
+            Counter::Shared(image) => image.add(x, y, channel, count),
+            Counter::Local { shared, counts } => {
+                // Like in the shared image, a point past the end of a row would land in the next
+                debug_assert!(
+                    x < shared.width && y < shared.height && channel < shared.channels,
+                    "({x}, {y}) in channel {channel} is outside of a {}x{} image of {} channels",
+                    shared.width,
+                    shared.height,
+                    shared.channels
+                );
+                counts.add(shared.position(x, y, channel), count);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Image of a single channel and row, of the given `counts`
+    fn row_image(counts: &[u32]) -> RawImage {
+        RawImage::from_counts(counts.len() as u32, 1, 1, counts.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn histograms_count_known_distributions() {
+        // Up to 255, the 8 bins run from 2^i to 2^(i + 1), which these values are well inside of
+        let counts = [0, 0, 0, 0, 3, 3, 3, 12, 12, 12, 12, 12, 200, 200, 255];
+        let histogram = row_image(&counts).histogram(None, 8).unwrap();
+        assert_eq!(histogram.maximum, 255);
+        assert_eq!(histogram.zeros, 4);
+        assert_eq!(histogram.bins, [0, 3, 0, 5, 0, 0, 0, 3]);
+        assert_eq!(histogram.total(), 15);
+        assert!((histogram.edge(4) - 16.0).abs() < 1e-9);
+        assert_eq!(histogram.percentile(0.0), 0.0);
+        assert_eq!(histogram.percentile(100.0), 255.0);
+        // The 8th value of 15 is the first of the bin of 8 to 16
+        let median = histogram.percentile(50.0);
+        assert!((8.0..16.0).contains(&median), "{median}");
+    }
+
+    #[test]
+    fn histograms_of_one_channel_skip_the_others() {
+        let image = RawImage::from_counts(2, 1, 2, vec![5, 0, 7, 0]).unwrap();
+        assert_eq!(image.histogram(Some(1), 4).unwrap().zeros, 2);
+        assert_eq!(image.histogram(Some(0), 4).unwrap().zeros, 0);
+        assert_eq!(image.histogram(None, 4).unwrap().total(), 4);
+    }
+
+    #[test]
+    fn histograms_take_at_least_a_bin() {
+        assert!(row_image(&[1, 2, 3]).histogram(None, 0).is_err());
+        let blank = row_image(&[0, 0]).histogram(None, 1).unwrap();
+        assert_eq!((blank.zeros, &blank.bins[..]), (2, &[0][..]));
+        assert_eq!(blank.nonzero_fraction(), 0.0);
+    }
+
+    #[test]
+    fn maxima_are_the_values_after_each_add() {
+        for bits in [16, 32, 64] {
+            let image = RawImage::new(4, 4, 1, bits, Layout::Linear);
+            assert_eq!(image.get_maximum(), 0);
+            image.add(1, 2, 0, 1);
+            assert_eq!(image.get_maximum(), 1, "{bits}-bit counters");
+            for _ in 1..5 {
+                image.add(3, 0, 0, 1);
+            }
+            assert_eq!(image.get_maximum(), 4, "{bits}-bit counters");
+        }
+        // Adds racing for the same pixel still leave the maximum at its total
+        let image = RawImage::new(2, 2, 1, 32, Layout::Linear);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..1_000).for_each(|_| image.add(1, 1, 0, 1)));
+            }
+        });
+        assert_eq!(image.get_maximum(), 4_000);
+    }
+
+    #[test]
+    fn linear_pixels_are_row_by_row_with_their_channels_side_by_side() {
+        let image = RawImage::new(5, 3, 2, 32, Layout::Linear);
+        assert_eq!(image.pixel_position(0, 0), (0, 1));
+        assert_eq!(image.pixel_position(4, 0), (8, 1));
+        assert_eq!(image.pixel_position(0, 1), (10, 1));
+        assert_eq!(image.pixel_position(4, 2), (28, 1));
+        image.add(4, 1, 1, 7);
+        let mut expected = vec![0; 30];
+        expected[(5 + 4) * 2 + 1] = 7;
+        assert_eq!(image.get_data(), expected);
+        assert_eq!(image.row(1).collect::<Vec<_>>(), &expected[10..20]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "(5, 0) in channel 0 is outside of a 5x3 image of 2 channels")]
+    fn points_past_the_end_of_a_row_are_caught() {
+        RawImage::new(5, 3, 2, 32, Layout::Linear).add(5, 0, 0, 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "in channel 2 is outside")]
+    fn points_past_the_last_channel_are_caught() {
+        RawImage::new(5, 3, 2, 32, Layout::Linear).add(0, 0, 2, 1);
+    }
+
+    /// Image of `width` × `height` pixels of 2 channels in a `layout`, each of whose values is
+    /// its index in the order of [`RawImage::get_data`] + 1, and those values
+    fn numbered_image(width: u32, height: u32, bits: u8, layout: Layout) -> (RawImage, Vec<u64>) {
+        let image = RawImage::new(width, height, 2, bits, layout);
+        for y in 0..height {
+            for x in 0..width {
+                for channel in 0..2 {
+                    let index = (y * width + x) * 2 + channel;
+                    image.add(x, y, channel, index + 1);
+                }
+            }
+        }
+        let values = (1..=(width * height * 2) as u64).collect();
+        (image, values)
+    }
+
+    #[test]
+    fn tiled_pixels_are_tile_by_tile_with_their_channels_apart() {
+        // Tiles at the right and bottom edges are cut to 36 × 6 pixels
+        let image = RawImage::new(100, 70, 2, 32, Layout::Tiled);
+        assert_eq!(image.pixel_position(0, 0), (0, 64 * 64));
+        assert_eq!(image.pixel_position(1, 1), (65, 64 * 64));
+        assert_eq!(image.pixel_position(64, 0), (64 * 64 * 2, 36 * 64));
+        assert_eq!(image.pixel_position(65, 1), (64 * 64 * 2 + 37, 36 * 64));
+        let bottom = 64 * 100 * 2;
+        assert_eq!(image.pixel_position(0, 64), (bottom, 64 * 6));
+        assert_eq!(
+            image.pixel_position(99, 69),
+            (bottom + 64 * 6 * 2 + 5 * 36 + 35, 36 * 6)
+        );
+        // Every value has a counter of its own
+        let mut positions: Vec<usize> = (0..70)
+            .flat_map(|y| (0..100).flat_map(move |x| (0..2).map(move |channel| (x, y, channel))))
+            .map(|(x, y, channel)| image.position(x, y, channel))
+            .collect();
+        positions.sort_unstable();
+        assert!(positions.iter().copied().eq(0..100 * 70 * 2));
+    }
+
+    #[test]
+    fn tiled_images_read_like_linear_ones() {
+        for bits in [16, 32, 64] {
+            let (image, values) = numbered_image(100, 70, bits, Layout::Tiled);
+            assert_eq!(image.get_data(), values, "{bits}-bit counters");
+            let row: Vec<u64> = image.row(66).collect();
+            assert_eq!(row, &values[66 * 200..67 * 200]);
+            let channel: Vec<u64> = image.channel_iter(1).collect();
+            assert!(channel
+                .iter()
+                .copied()
+                .eq(values.iter().copied().skip(1).step_by(2)));
+            assert_eq!(image.get_channel_maxima(), [100 * 70 * 2 - 1, 100 * 70 * 2]);
+            // Counts added in the order of the data land where they would have been plotted
+            let copy = RawImage::new(100, 70, 2, bits, Layout::Tiled);
+            copy.add_counts(&values);
+            assert_eq!(copy.get_data(), values);
+        }
+    }
+
+    #[test]
+    fn wide_counters_count_past_32_bits() {
+        let image = RawImage::new(2, 1, 1, 64, Layout::Linear);
+        for _ in 0..3 {
+            image.add(1, 0, 0, u32::MAX);
+        }
+        let total = 3 * u32::MAX as u64;
+        assert_eq!(image.get_data(), [0, total]);
+        assert_eq!(image.get_maximum(), total);
+        assert_eq!((image.saturated(), image.counter_bits()), (0, 64));
+        image.add_counts(&[u64::from(u32::MAX) + 1, 1]);
+        assert_eq!(image.get_data(), [u32::MAX as u64 + 1, total + 1]);
+    }
+
+    #[test]
+    fn compact_counters_promote_their_band_and_keep_counts_exact() {
+        // Bands of 16 rows of 4 pixels
+        let image = RawImage::new(4, 40, 1, 16, Layout::Linear);
+        image.add(0, 0, 0, 65_535);
+        assert_eq!(image.promoted(), 0);
+        image.add(0, 0, 0, 1);
+        image.add(1, 15, 0, 3);
+        assert_eq!(image.promoted(), 1);
+        // Carries from adds racing for the same counter aren't lost
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..20_000).for_each(|_| image.add(2, 39, 0, 1)));
+            }
+        });
+        image.add(3, 39, 0, 100_000);
+        assert_eq!(image.promoted(), 2);
+        let data = image.get_data();
+        assert_eq!((data[0], data[15 * 4 + 1]), (65_536, 3));
+        assert_eq!((data[39 * 4 + 2], data[39 * 4 + 3]), (80_000, 100_000));
+        assert_eq!(data.iter().sum::<u64>(), 65_536 + 3 + 180_000);
+        assert_eq!(image.get_maximum(), 100_000);
+        assert_eq!((image.saturated(), image.counter_bits()), (0, 16));
+        // Copies keep the upper halves of the bands that have them
+        assert_eq!(image.snapshot().get_data(), data);
+    }
+
+    #[test]
+    fn counters_saturate_at_the_cap_once() {
+        let images = [
+            RawImage::new(2, 1, 1, 16, Layout::Linear),
+            RawImage::new(2, 1, 1, 32, Layout::Linear),
+            RawImage::new(2, 1, 1, 32, Layout::Tiled),
+            RawImage::sparse(2, 1, 1),
+        ];
+        for image in images {
+            image.add(0, 0, 0, u32::MAX - 1);
+            assert_eq!(image.saturated(), 0);
+            image.add(0, 0, 0, 5);
+            image.add(0, 0, 0, u32::MAX);
+            assert_eq!(image.get_data(), [u32::MAX as u64, 0]);
+            assert_eq!(
+                image.saturated(),
+                1,
+                "{}-bit counters",
+                image.counter_bits()
+            );
+            // Counts added all at once saturate like points plotted one by one
+            image.add_counts(&[1u64, u32::MAX as u64 + 7]);
+            assert_eq!(image.get_data(), [u32::MAX as u64; 2]);
+            assert_eq!(image.saturated(), 2);
+        }
+        let image = RawImage::from_counts(3, 1, 1, vec![u32::MAX, 1, u32::MAX]).unwrap();
+        assert_eq!(image.saturated(), 2);
+    }
+
+    #[test]
+    fn merges_add_the_counts_of_images_of_the_same_size() {
+        let (other, values) = numbered_image(70, 20, 32, Layout::Tiled);
+        for image in [
+            RawImage::new(70, 20, 2, 16, Layout::Linear),
+            RawImage::new(70, 20, 2, 64, Layout::Tiled),
+            RawImage::sparse(70, 20, 2),
+        ] {
+            image.add(3, 2, 1, 10);
+            image.merge(&other).unwrap();
+            image.merge(&other).unwrap();
+            let mut expected: Vec<u64> = values.iter().map(|value| value * 2).collect();
+            expected[(2 * 70 + 3) * 2 + 1] += 10;
+            assert_eq!(image.get_data(), expected);
+            assert_eq!(image.get_channel_maxima(), [70 * 20 * 4 - 2, 70 * 20 * 4]);
+        }
+        let error = RawImage::new(20, 70, 2, 32, Layout::Linear)
+            .merge(&other)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Can't merge a 70x20 image of 2 channels into a 20x70 one of 2"
+        );
+    }
+
+    #[test]
+    fn checkpoints_load_back_what_was_saved() {
+        let directory =
+            std::env::temp_dir().join(format!("nebulae-checkpoint-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("render.checkpoint");
+        for (counter_bits, layout) in [
+            (16, Layout::Tiled),
+            (32, Layout::Linear),
+            (64, Layout::Linear),
+        ] {
+            let settings = RenderSettings {
+                width: 70,
+                height: 20,
+                counter_bits,
+                layout,
+                ..RenderSettings::default()
+            };
+            let image = RawImage::new(70, 20, settings.channels(), counter_bits, layout);
+            let mut counts: Vec<u64> = (0..70 * 20 * settings.channels() as u64).collect();
+            if counter_bits == 64 {
+                counts[5] = 1 << 40;
+            }
+            image.add_counts(&counts);
+            image.save(&path, 7).unwrap();
+            let (loaded, header) = RawImage::load(&path, &settings).unwrap();
+            let expected = CheckpointHeader {
+                width: 70,
+                height: 20,
+                channels: settings.channels(),
+                counter_bits,
+                passes: 7,
+            };
+            assert_eq!(header, expected);
+            assert_eq!(loaded.get_data(), counts);
+            assert_eq!(loaded.get_channel_maxima(), image.get_channel_maxima());
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn damaged_checkpoints_are_refused() {
+        let directory =
+            std::env::temp_dir().join(format!("nebulae-damaged-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("render.checkpoint");
+        let settings = RenderSettings {
+            width: 8,
+            height: 4,
+            ..RenderSettings::default()
+        };
+        let channels = settings.channels();
+        let image = RawImage::from_counts(8, 4, channels, (0..8 * 4 * channels).collect()).unwrap();
+        image.save(&path, 3).unwrap();
+        let saved = std::fs::read(&path).unwrap();
+        let error = |bytes: &[u8], settings: &RenderSettings| {
+            std::fs::write(&path, bytes).unwrap();
+            match RawImage::load(&path, settings) {
+                Err(NebulaeError::Checkpoint { message, .. }) => message,
+                Err(error) => panic!("{error}"),
+                Ok(_) => panic!("A damaged checkpoint was loaded"),
+            }
+        };
+
+        let mut flipped = saved.clone();
+        flipped[CHECKPOINT_HEADER_BYTES as usize + 9] ^= 1;
+        assert_eq!(
+            error(&flipped, &settings),
+            "The checkpoint is damaged: its checksum doesn't match its counts"
+        );
+        assert_eq!(
+            error(&saved[..saved.len() - 1], &settings),
+            format!(
+                "The checkpoint is {} bytes long, but its image takes {}",
+                saved.len() - 1,
+                saved.len()
+            )
+        );
+        assert_eq!(
+            error(&saved[..10], &settings),
+            "The checkpoint is too short to hold a header"
+        );
+        assert_eq!(
+            error(b"a checkpoint? no, just some forty characters", &settings),
+            "Not a checkpoint file"
+        );
+        let mut version = saved.clone();
+        version[8] = 2;
+        assert_eq!(
+            error(&version, &settings),
+            "Checkpoint version 2 isn't supported (only version 1 is)"
+        );
+        let larger = RenderSettings {
+            width: 16,
+            ..settings.clone()
+        };
+        assert_eq!(
+            error(&saved, &larger),
+            "The checkpoint holds a 8x4 image, but the render accumulates 16x4 pixels"
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn images_take_any_number_of_channels() {
+        for channels in [1, 2, 5] {
+            let image = RawImage::new(3, 2, channels, 32, Layout::Linear);
+            for channel in 0..channels {
+                image.add(2, 1, channel, channel + 1);
+            }
+            let data = image.get_data();
+            assert_eq!(data.len(), 3 * 2 * channels as usize);
+            let pixel = (3 + 2) * channels as usize;
+            assert!(data[pixel..].iter().copied().eq(1..=channels as u64));
+            assert!(image
+                .get_channel_maxima()
+                .into_iter()
+                .eq(1..=channels as u64));
+        }
+    }
+
+    #[test]
+    fn channels_and_rows_read_the_values_in_place() {
+        let image = RawImage::from_counts(2, 2, 3, (1..=12).collect()).unwrap();
+        assert!(image.channel_iter(0).eq([1, 4, 7, 10]));
+        assert!(image.channel_iter(2).eq([3, 6, 9, 12]));
+        let rows: Vec<Vec<u64>> = image.rows().map(Iterator::collect).collect();
+        assert_eq!(rows, [vec![1, 2, 3, 4, 5, 6], vec![7, 8, 9, 10, 11, 12]]);
+        // Reading a copy back into the same buffer reuses its memory
+        let mut data = image.get_data();
+        let capacity = data.capacity();
+        image.add(0, 0, 0, 1);
+        image.snapshot_into(&mut data);
+        assert_eq!((data[0], data.capacity()), (2, capacity));
+    }
+
+    #[test]
+    fn each_channel_keeps_a_maximum_of_its_own() {
+        for (bits, layout) in [
+            (16, Layout::Linear),
+            (32, Layout::Tiled),
+            (64, Layout::Linear),
+        ] {
+            let image = RawImage::new(3, 3, 3, bits, layout);
+            image.add(0, 0, 0, 9);
+            image.add(1, 1, 1, 4);
+            image.add(2, 2, 1, 6);
+            // The last channel of the first and third pixels
+            let mut counts = [0u32; 27];
+            (counts[2], counts[8]) = (1, 2);
+            image.add_counts(&counts);
+            assert_eq!(image.get_channel_maxima(), [9, 6, 2], "{bits}-bit counters");
+            assert_eq!(image.get_maximum(), 9);
+        }
+        let sparse = RawImage::sparse(3, 3, 2);
+        sparse.add(1, 2, 1, 3);
+        assert_eq!(sparse.get_channel_maxima(), [0, 3]);
+    }
+
+    #[test]
+    fn sparse_images_hold_only_what_was_counted() {
+        // Bands of 16 rows, several of which take no count
+        let (dense, sparse) = (
+            RawImage::new(50, 70, 2, 32, Layout::Linear),
+            RawImage::sparse(50, 70, 2),
+        );
+        let empty = sparse.sparse_bytes().unwrap();
+        for (x, y, channel) in [(0, 0, 0), (49, 69, 1), (10, 33, 1), (10, 33, 1)] {
+            dense.add(x, y, channel, 3);
+            sparse.add(x, y, channel, 3);
+        }
+        assert!(sparse.is_sparse() && !dense.is_sparse());
+        assert_eq!(dense.sparse_bytes(), None);
+        assert!(sparse.sparse_bytes().unwrap() > empty);
+        assert_eq!(sparse.get_data(), dense.get_data());
+        assert!(sparse.row(33).eq(dense.row(33)));
+        assert_eq!(sparse.get_channel_maxima(), [3, 6]);
+        assert_eq!(sparse.snapshot().get_data(), dense.get_data());
+        // Counts added all at once skip the bands that take none
+        let counts = dense.get_data();
+        sparse.add_counts(&counts);
+        assert!(sparse
+            .get_data()
+            .iter()
+            .zip(&counts)
+            .all(|(&value, &count)| value == 2 * count));
+    }
+
+    #[test]
+    fn weights_racing_for_a_pixel_are_all_added() {
+        let image = RawImageF32::new(3, 2, 2);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..10_000).for_each(|_| image.add(2, 1, 1, 0.25)));
+            }
+        });
+        image.add(0, 1, 0, 1.5);
+        let mut expected = vec![0.0; 12];
+        (expected[6], expected[11]) = (1.5, 10_000.0);
+        assert_eq!(image.get_data(), expected);
+        assert!(image.row(1).eq(expected[6..].iter().copied()));
+        assert_eq!(image.get_channel_maxima(), [1.5, 10_000.0]);
+        assert_eq!(image.snapshot().get_data(), expected);
+    }
+
+    #[test]
+    fn local_images_are_merged_into_the_shared_one() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(3)
+            .build()
+            .unwrap();
+        for (bits, layout) in [
+            (16, Layout::Tiled),
+            (32, Layout::Linear),
+            (64, Layout::Tiled),
+        ] {
+            let shared = RawImage::new(70, 20, 2, bits, layout);
+            let local = LocalImages::new(3, &shared);
+            pool.install(|| {
+                (0..70 * 20).into_par_iter().for_each(|pixel| {
+                    let mut counter = local.counter(&shared);
+                    counter.add(pixel % 70, pixel / 70, pixel % 2, 2);
+                    counter.add(5, 7, 1, 1);
+                });
+            });
+            // Every thread of the pool counts in its own image, until they are merged
+            assert_eq!(shared.get_maximum(), 0);
+            local.merge_into(&shared);
+            let data = shared.get_data();
+            assert_eq!(
+                data[(7 * 70 + 5) * 2 + 1],
+                70 * 20 + 2 * ((7 * 70 + 5) % 2) as u64
+            );
+            assert_eq!(data.iter().sum::<u64>(), 70 * 20 * 3);
+            // The local images are emptied
+            local.merge_into(&shared);
+            assert_eq!(shared.get_data(), data, "{bits}-bit counters");
+        }
+        // Outside of the pool, points are counted in the shared image
+        let shared = RawImage::new(2, 2, 1, 32, Layout::Linear);
+        let local = LocalImages::new(1, &shared);
+        local.counter(&shared).add(1, 1, 0, 4);
+        assert_eq!(shared.get_data(), [0, 0, 0, 4]);
+        assert_eq!(LocalImages::bytes(3, &shared), 3 * 4 * 4);
     }
 }
@@ -0,0 +1,1812 @@
+//! Rendering of a Nebulabrot: samples are drawn over the sampling bounds and iterated, and the
+//! points of their orbits are counted into an image, pass after pass
+
+use crate::adaptive::{self, Noise, Sources};
+use crate::derive_seed;
+use crate::error::NebulaeError;
+use crate::guided::{self, ImportanceMap};
+use crate::halton_sampler::HaltonSampler;
+use crate::jitter_sampler::JitterSampler;
+use crate::mandelbrot::{self, Complex, ReferenceOrbit, LANES};
+use crate::mask::Mask;
+use crate::metropolis::{self, Calibration, Tally};
+use crate::noise_map::NoiseMap;
+use crate::poisson_sampler::PoissonSampler;
+use crate::raw_image::{Counter, LocalImages, RawImage, RawImageF32, MAX_LOCAL_BYTES};
+use crate::render_settings::*;
+use crate::sampler::{Sampler, Strata, UniformSampler};
+use crate::splat::Kernel;
+use crate::viewport::Viewport;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::cmp::min;
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Number of samples in each task of the workers, without a seed or in deterministic renders,
+/// which is enough that tasks cost next to nothing to hand out, and few enough that they are
+/// shared evenly between workers
+/// A multiple of [`LANES`], so that tasks split samples into the same chunks as a single task would
+const TASK_SAMPLES: u64 = 4096;
+
+/// Number of tasks that the samples of deterministic renders are split into by the samplers that
+/// take them a share of the pass at a time, such as the chains of the Metropolis sampler, which is
+/// enough to keep the threads of most machines busy
+const DETERMINISTIC_TASKS: u64 = 64;
+
+/// Number of uniform samples of the pre-pass whose escape times `auto_limits` are picked from
+const AUTO_LIMIT_SAMPLES: u64 = 1_000_000;
+
+/// Number of iterations past which a sample of that pre-pass is taken to be inside the set
+const AUTO_LIMIT_ITERATIONS: u32 = 100_000;
+
+/// Where a render reports its progress as it goes, and whoever started it is asked what to do
+/// Samples are reported from every worker at once, a batch at a time, so that a sink that does
+/// nothing with them costs next to nothing
+pub trait ProgressSink: Sync {
+    /// A pass started, which takes `samples` samples
+    fn pass_started(&self, _pass: u64, _samples: u64) {}
+
+    /// Another `samples` samples of the current pass were traced
+    fn samples_completed(&self, _samples: u64) {}
+
+    /// A pass is done, its points counted into the image
+    fn pass_finished(&self, _pass: u64) {}
+
+    /// Take the `image` at the end of a pass after which an intermediate is due, which is still
+    /// being rendered, along with the sums of its `weights` when points are weighted, and its
+    /// `noise` map when the settings ask for one
+    fn intermediate(
+        &self,
+        _image: &RawImage,
+        _weights: Option<&RawImageF32>,
+        _noise: Option<&NoiseMap>,
+    ) {
+    }
+
+    /// Show a `message` about how the render goes, such as a choice that it made for the settings
+    fn message(&self, _message: &str) {}
+}
+
+/// A [`ProgressSink`] that reports nothing
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// A token that a render can be cancelled with from any thread, shared by its clones
+/// The render checks it between chunks of samples, so it stops within moments, with the points
+/// counted so far
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// A token that isn't cancelled yet
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancel the renders that take this token
+    pub fn cancel(&self) {
+        self.0.store(true, Relaxed);
+    }
+
+    /// Whether the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Relaxed)
+    }
+}
+
+/// What a render came to: the image that its points were counted in, and statistics of the run
+pub struct RenderResult {
+    /// Image that the points were counted in
+    pub image: Arc<RawImage>,
+    /// Whether the render was cancelled before it was done, in which case the image holds the
+    /// points of the samples traced until then
+    pub partial: bool,
+    /// Greatest count of each channel
+    pub maxima: Vec<u64>,
+    /// Sums of the weights of the points in each pixel when they are weighted, by the length of
+    /// their orbit or by its origin, which the image is written from rather than from its counts
+    pub weights: Option<Arc<RawImageF32>>,
+    /// Greatest sum of weights of each channel, when points are weighted
+    pub weighted_maxima: Option<Vec<f32>>,
+    /// How noisy each pixel still is after the passes that were done, when the settings ask for a
+    /// noise map
+    pub noise: Option<NoiseMap>,
+    /// Samples drawn in the passes that were done, counted once for each group of channels that
+    /// traced them
+    pub samples: u64,
+    /// Samples of those that were skipped without iterating, as they were sure to never escape
+    pub skipped: u64,
+    /// Samples traced in each channel, including those of a pass that was cut short
+    pub traced: Vec<u64>,
+    /// Samples of those whose orbit escaped, in each channel
+    pub escaped: Vec<u64>,
+    /// Points of the orbits that landed in view and were plotted, whatever their weight
+    pub points: u64,
+    /// Points of the orbits that the orbit filter kept, but fell outside of the view
+    pub clipped: u64,
+    /// Wall-clock time of each pass that was done
+    pub pass_times: Vec<Duration>,
+    /// Settings that the image was rendered with
+    pub settings: RenderSettings,
+}
+
+impl RenderResult {
+    /// Greatest count across every channel
+    pub fn maximum(&self) -> u64 {
+        self.maxima.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Copy of the counts of the image, along with the greatest of them, as renders used to give
+    pub fn data_and_maximum(&self) -> (Vec<u64>, u64) {
+        (self.image.get_data(), self.maximum())
+    }
+}
+
+impl fmt::Display for RenderResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time: Duration = self.pass_times.iter().sum();
+        let passes = self.pass_times.len();
+        write!(f, "{passes} passes in {:.1?}", time)?;
+        if passes > 0 {
+            write!(f, " ({:.1?} each)", time / passes as u32)?;
+        }
+        write!(
+            f,
+            ", {} samples, {} points plotted",
+            self.samples, self.points
+        )?;
+        let all_points = self.points + self.clipped;
+        if all_points > 0 {
+            write!(
+                f,
+                " ({:.1}% out of view)",
+                100.0 * self.clipped as f64 / all_points as f64
+            )?;
+        }
+        let escaped: Vec<String> = self
+            .escaped
+            .iter()
+            .zip(&self.traced)
+            .map(|(&escaped, &traced)| match traced {
+                0 => String::from("-"),
+                _ => format!("{:.1}%", 100.0 * escaped as f64 / traced as f64),
+            })
+            .collect();
+        write!(f, ", escaped {}", escaped.join(" / "))
+    }
+}
+
+/// Pick the escape limits of a render whose `settings` give `auto_limits`, at those percentiles of
+/// the escape times of uniform samples over the sampling region, so that each channel takes that
+/// share of the orbits that escape; the settings' own limits are given back when they don't, or
+/// when bands replace them
+/// The samples are drawn reproducibly under a seed, whatever the number of threads
+pub fn calibrate_limits(settings: &RenderSettings) -> Result<Vec<u32>, NebulaeError> {
+    let (Some(percentiles), None) = (&settings.auto_limits, &settings.bands) else {
+        return Ok(settings.limits.clone());
+    };
+    if !(1..=MAX_CHANNELS).contains(&percentiles.len()) {
+        return Err(NebulaeError::Invalid(format!(
+            "`auto_limits` ({} values) must have from 1 to {MAX_CHANNELS} values, one for each channel",
+            percentiles.len()
+        )));
+    }
+    if let Some(percentile) = percentiles
+        .iter()
+        .find(|&&percentile| !(percentile > 0.0 && percentile <= 100.0))
+    {
+        return Err(NebulaeError::Invalid(format!(
+            "`auto_limits` ({percentile}) must be percentiles above 0 and at most 100"
+        )));
+    }
+    let mut times: Vec<u32> = (0..DETERMINISTIC_TASKS)
+        .into_par_iter()
+        .flat_map_iter(|task| {
+            // Apart from the streams of every pass, and of the other pre-passes
+            let mut rng = match settings.seed {
+                Some(seed) => StdRng::seed_from_u64(derive_seed(seed, &[u64::MAX - 2, task])),
+                None => StdRng::from_entropy(),
+            };
+            let mut orbit = Vec::new();
+            let samples = task * AUTO_LIMIT_SAMPLES / DETERMINISTIC_TASKS
+                ..(task + 1) * AUTO_LIMIT_SAMPLES / DETERMINISTIC_TASKS;
+            let mut times = Vec::new();
+            for _ in samples {
+                let point = (rng.gen(), rng.gen());
+                let (z, c) = sample_at(settings, &mut rng, point);
+                if skip_sample(settings, c) {
+                    continue;
+                }
+                let (escape, stop) = (settings.escape_radius, settings.stop_radius);
+                let escaped = match &settings.formula {
+                    Some(formula) => mandelbrot::iterate_formula_into(
+                        z,
+                        c,
+                        formula,
+                        AUTO_LIMIT_ITERATIONS,
+                        escape,
+                        stop,
+                        &mut orbit,
+                    ),
+                    // Samples inside the set are given up on as soon as their orbits cycle
+                    None => mandelbrot::iterate_into(
+                        z,
+                        c,
+                        settings.variant,
+                        settings.power,
+                        AUTO_LIMIT_ITERATIONS,
+                        escape,
+                        stop,
+                        true,
+                        &mut orbit,
+                    ),
+                };
+                if escaped {
+                    times.push(orbit.len() as u32);
+                }
+            }
+            times
+        })
+        .collect();
+    if times.is_empty() {
+        return Err(NebulaeError::Invalid(format!(
+            "No sample escaped within {AUTO_LIMIT_ITERATIONS} iterations to pick `auto_limits` from, give `limits` instead"
+        )));
+    }
+    times.sort_unstable();
+    Ok(percentiles
+        .iter()
+        .map(|percentile| {
+            let rank = (percentile / 100.0 * times.len() as f64).ceil() as usize;
+            times[rank.clamp(1, times.len()) - 1]
+        })
+        .collect())
+}
+
+/// Render a Nebulabrot from its `settings`, reporting its progress to a `sink`, until it is done
+/// or `cancel` is cancelled
+/// The render runs on its own pool of as many threads as the settings give, or on rayon's global
+/// pool when they don't
+/// Returns the image that its points were counted in, with statistics of the run
+pub fn render_nebulabrot(
+    settings: &RenderSettings,
+    sink: &dyn ProgressSink,
+    cancel: &CancelToken,
+) -> Result<RenderResult, NebulaeError> {
+    on_threads(settings, || render(settings, sink, cancel))
+}
+
+/// Render a conventional escape-time image of the view of a Nebulabrot from its `settings`, until
+/// it is done or `cancel` is cancelled
+/// The center of each pixel (of the supersampled image) is iterated up to the limit of the first
+/// channel, the way samples of the Nebulabrot are, and given a smooth escape time: the number of
+/// iterations that its orbit escaped in, corrected by how far past the escape radius it got, so
+/// that times run on continuously across the edges of the bands of whole iterations
+/// Orbits that don't escape get a time of 0. Runs on a pool of threads like
+/// [`render_nebulabrot`]; returns the times row by row
+pub fn render_escape_times(
+    settings: &RenderSettings,
+    cancel: &CancelToken,
+) -> Result<Vec<f32>, NebulaeError> {
+    on_threads(settings, || escape_times(settings, cancel))
+}
+
+/// Run some `work` on a pool of as many threads as the `settings` give, or on rayon's global pool
+/// when they don't
+fn on_threads<T: Send>(
+    settings: &RenderSettings,
+    work: impl FnOnce() -> Result<T, NebulaeError> + Send,
+) -> Result<T, NebulaeError> {
+    match settings.threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build()
+            .map_err(|error| {
+                NebulaeError::Invalid(format!("Could not start {threads} threads: {error}"))
+            })?
+            .install(work),
+        None => work(),
+    }
+}
+
+/// Render the escape times of an image on the current thread pool, as [`render_escape_times`]
+/// does
+fn escape_times(settings: &RenderSettings, cancel: &CancelToken) -> Result<Vec<f32>, NebulaeError> {
+    let width = settings.width * settings.supersample;
+    let height = settings.height * settings.supersample;
+    let viewport = Viewport::new(settings, width, height);
+    let limit = settings.limit(0);
+    let center = Complex {
+        re: settings.center_re,
+        im: settings.center_im,
+    };
+    let reference = settings
+        .deep_zoom()
+        .then(|| ReferenceOrbit::new(center, limit, settings.stop_radius));
+    // Orbits that are about to escape are raised to the function's power at every iteration,
+    // which custom functions are taken to square
+    let power = match settings.formula {
+        None if settings.power > 1.0 => settings.power,
+        _ => 2.0,
+    };
+    // Every pixel starts from the middle of the disc of starting points, rather than from a random
+    // point of it
+    let start = Complex {
+        re: settings.z0_re,
+        im: settings.z0_im,
+    };
+    let times = (0..height)
+        .into_par_iter()
+        .map_init(Vec::new, |orbit, y| {
+            if cancel.is_cancelled() {
+                return Vec::new();
+            }
+            (0..width)
+                .map(|x| {
+                    let position = (x as f64 + 0.5, y as f64 + 0.5);
+                    let delta_c = viewport.offset_at(position);
+                    let (z, c) = match settings.mode {
+                        Mode::Mandelbrot => (start, center + delta_c),
+                        Mode::Julia { c_re, c_im } => {
+                            (center + delta_c, Complex { re: c_re, im: c_im })
+                        }
+                    };
+                    if skip_sample(settings, c)
+                        || !iterate_orbit(settings, reference.as_ref(), z, c, delta_c, limit, orbit)
+                    {
+                        return 0.0;
+                    }
+                    let last = orbit.last().copied().unwrap_or(z);
+                    // Perturbed orbits are recorded as offsets from the center
+                    let last = match reference {
+                        Some(_) => center + last,
+                        None => last,
+                    };
+                    // Orbits that escape within the unit circle, past a tiny escape radius, are
+                    // left uncorrected
+                    let correction = match last.norm_sqr().sqrt().ln() {
+                        log if log > 0.0 => log.ln() / power.ln(),
+                        _ => 0.0,
+                    };
+                    (orbit.len() as f64 + 1.0 - correction).max(0.0) as f32
+                })
+                .collect()
+        })
+        .flatten_iter()
+        .collect();
+    if cancel.is_cancelled() {
+        return Err(NebulaeError::Cancelled);
+    }
+    Ok(times)
+}
+
+/// Render a Nebulabrot on the current thread pool, as [`render_nebulabrot`] does
+fn render(
+    settings: &RenderSettings,
+    sink: &dyn ProgressSink,
+    cancel: &CancelToken,
+) -> Result<RenderResult, NebulaeError> {
+    let width = settings.width * settings.supersample;
+    let height = settings.height * settings.supersample;
+    let channels = settings.channels();
+    // Read before the image is made, which may take long enough to be worth failing first
+    let mask = match &settings.mask {
+        Some(path) => Some(load_mask(path, sink, width, height)?),
+        None => None,
+    };
+    let kernel = Kernel::new(settings.splat, settings.splat_sigma);
+    let mut raw_image = Arc::new(new_image(settings, sink, width, height));
+    // Weighted points are summed apart from their counts, which still tell how many there are
+    let weights = settings
+        .weighs_points()
+        .then(|| Arc::new(RawImageF32::new(width, height, settings.image_channels())));
+    // Workers count their points in their own copies of the image, unless those take too much
+    // memory, and add them into it at the end of each pass
+    // Compact counters, sparse images and memory maps are there to save memory, which copies
+    // would spend many times over
+    let workers = rayon::current_num_threads();
+    let local_images = match LocalImages::bytes(workers, &raw_image) {
+        _ if !settings.local_images
+            || settings.counter_bits == 16
+            || raw_image.is_sparse()
+            || raw_image.is_mapped() =>
+        {
+            None
+        }
+        bytes if bytes > MAX_LOCAL_BYTES => {
+            sink.message(&format!(
+                "Counting points straight into the image, as a copy for each of {workers} threads would take {} MiB",
+                bytes >> 20
+            ));
+            None
+        }
+        _ => Some(LocalImages::new(workers, &raw_image)),
+    };
+    let viewport = Viewport::new(settings, width, height);
+    // Deep zooms follow a precise orbit of the center, that every channel can share
+    let reference = settings.deep_zoom().then(|| {
+        ReferenceOrbit::new(
+            Complex {
+                re: settings.center_re,
+                im: settings.center_im,
+            },
+            settings.highest_limit(),
+            settings.stop_radius,
+        )
+    });
+
+    // Channels that share the orbits of their samples, each iterated once up to the highest limit
+    let groups: Vec<Vec<u32>> = if settings.shares_orbits() {
+        vec![(0..channels).collect()]
+    } else {
+        (0..channels).map(|channel| vec![channel]).collect()
+    };
+
+    let tallies = Tallies::new(channels);
+    let mut pass_times = Vec::new();
+    let mut last_render = Instant::now();
+    // Samples that were skipped without iterating, out of all those drawn
+    let (mut skipped, mut drawn) = (0, 0);
+    let (mut promotion_logged, mut saturation_logged) = (false, false);
+
+    let mut sampler = new_sampler(settings);
+    // The weights of Metropolis chains and guided samples are measured once, so that every pass
+    // plots on one scale
+    let (calibration, importance) = {
+        let canvas = Canvas {
+            settings,
+            viewport: &viewport,
+            raw_image: &raw_image,
+            local_images: None,
+            reference: reference.as_ref(),
+            sampler: sampler.as_ref(),
+            tallies: None,
+            mask: mask.as_ref(),
+            kernel: kernel.as_ref(),
+            weights: None,
+            cancel,
+        };
+        (
+            settings.metropolis().then(|| calibrate(&canvas, &groups)),
+            settings.guided().then(|| map_importance(&canvas, &groups)),
+        )
+    };
+    // Passes stratified together are laid out from one random origin, reproducibly so under a
+    // seed
+    let strata = settings.stratifies_passes().then(|| {
+        let mut rng = match settings.seed {
+            Some(seed) => StdRng::seed_from_u64(derive_seed(seed, &[u64::MAX - 1])),
+            None => StdRng::from_entropy(),
+        };
+        Strata::new(&mut rng)
+    });
+    // Adaptive passes gather where the samples that feed the noisy parts of the image come from
+    let mut sources = settings.adaptive.then(Sources::default);
+    let mut noise = settings.noise_map.is_some().then(|| {
+        NoiseMap::new(
+            settings.width,
+            settings.height,
+            settings.supersample,
+            channels,
+        )
+    });
+    let passes = match settings.passes {
+        0 => u64::MAX,
+        passes => passes as u64,
+    };
+    let mut partial = false;
+    for pass in 0..passes {
+        if cancel.is_cancelled() {
+            partial = true;
+            break;
+        }
+        // Each pass lays out its samples differently, reproducibly so under a seed
+        let mut rng = match settings.seed {
+            Some(seed) => StdRng::seed_from_u64(derive_seed(seed, &[pass])),
+            None => StdRng::from_entropy(),
+        };
+        let pass_start = Instant::now();
+        sampler.reset(&mut rng, strata.map(|strata| strata.pass(pass)));
+        let canvas = Canvas {
+            settings,
+            viewport: &viewport,
+            raw_image: &raw_image,
+            local_images: local_images.as_ref(),
+            reference: reference.as_ref(),
+            sampler: sampler.as_ref(),
+            tallies: Some(&tallies),
+            mask: mask.as_ref(),
+            kernel: kernel.as_ref(),
+            weights: weights.as_deref(),
+            cancel,
+        };
+        // Weighted samples are traced for every group of channels, as many as the largest group
+        // takes
+        let weighted_samples = settings.weighs_samples().then(|| {
+            let samples = groups.iter().map(|group| canvas.group_samples(group));
+            samples.max().unwrap_or(0)
+        });
+        let total_samples = match weighted_samples {
+            Some(samples) => samples * groups.len() as u64,
+            None => groups.iter().map(|group| canvas.group_samples(group)).sum(),
+        };
+        sink.pass_started(pass, total_samples);
+        let pass_skipped = if let (Some(gathered), Some(samples)) = (&mut sources, weighted_samples)
+        {
+            // The first passes are uniform, until there are enough samples to go by
+            let strength = if pass < adaptive::WARMUP_PASSES {
+                0.0
+            } else {
+                settings.adaptive_strength
+            };
+            let importance = gathered.map(strength);
+            let noise = Noise::new(&raw_image, width, height, channels, settings.splat.scale());
+            let (pass_skipped, pass_sources) = trace_guided(
+                &canvas,
+                sink,
+                &groups,
+                (&importance, adaptive::SCALE),
+                Some(&noise),
+                pass,
+                samples,
+            );
+            *gathered = std::mem::take(gathered).merge(pass_sources);
+            pass_skipped
+        } else if let (Some(calibration), Some(samples)) = (&calibration, weighted_samples) {
+            trace_chains(&canvas, sink, &groups, calibration, pass, samples)
+        } else if let (Some(importance), Some(samples)) = (&importance, weighted_samples) {
+            let weights = (importance, importance.scale());
+            trace_guided(&canvas, sink, &groups, weights, None, pass, samples).0
+        } else {
+            groups
+                .par_iter()
+                .map(|channels| {
+                    // Each channel only takes its own number of the group's samples
+                    let samples = canvas.group_samples(channels);
+                    match settings.seed {
+                        // Samples are split into tasks of many chunks, and each worker reuses one
+                        // random generator and one set of buffers for the orbits it traces
+                        None => (0..samples.div_ceil(TASK_SAMPLES))
+                            .into_par_iter()
+                            .map_init(
+                                || (rand::thread_rng(), new_orbits()),
+                                |(rng, orbits), task| {
+                                    let first = task * TASK_SAMPLES;
+                                    let end = min(samples, first + TASK_SAMPLES);
+                                    trace_samples(&canvas, sink, rng, channels, first..end, orbits)
+                                },
+                            )
+                            .sum::<u64>(),
+                        // Deterministic seeded renders split the samples into tasks of a fixed
+                        // size, each seeded from the pass, the group and its own index, so that
+                        // the samples don't depend on the number of threads or on which worker
+                        // traces each task
+                        Some(seed) if settings.deterministic => (0..samples.div_ceil(TASK_SAMPLES))
+                            .into_par_iter()
+                            .map_init(new_orbits, |orbits, task| {
+                                let mut rng = StdRng::seed_from_u64(derive_seed(
+                                    seed,
+                                    &[pass, channels[0] as u64, task],
+                                ));
+                                let first = task * TASK_SAMPLES;
+                                let end = min(samples, first + TASK_SAMPLES);
+                                trace_samples(&canvas, sink, &mut rng, channels, first..end, orbits)
+                            })
+                            .sum::<u64>(),
+                        Some(seed) => {
+                            // A fixed split of the samples, so that each task's random stream is reproducible
+                            let tasks = rayon::current_num_threads() as u64;
+                            (0..tasks)
+                                .into_par_iter()
+                                .map(|task| {
+                                    let mut rng = StdRng::seed_from_u64(derive_seed(
+                                        seed,
+                                        &[pass, channels[0] as u64, task],
+                                    ));
+                                    let (first, end) =
+                                        (task * samples / tasks, (task + 1) * samples / tasks);
+                                    trace_samples(
+                                        &canvas,
+                                        sink,
+                                        &mut rng,
+                                        channels,
+                                        first..end,
+                                        &mut new_orbits(),
+                                    )
+                                })
+                                .sum::<u64>()
+                        }
+                    }
+                })
+                .sum::<u64>()
+        };
+        if let Some(local_images) = &local_images {
+            local_images.merge_into(&raw_image);
+        }
+        if raw_image.promoted() > 0 && !promotion_logged {
+            sink.message(
+                "Compact counters went past 65535, promoting them to 32 bits where they did",
+            );
+            promotion_logged = true;
+        }
+        if raw_image.saturated() > 0 && !saturation_logged {
+            sink.message(&format!(
+                "Counters reached {}, and count no more points past it; 64-bit counters don't saturate",
+                u32::MAX
+            ));
+            saturation_logged = true;
+        }
+        // The samples of a pass that was cut short aren't counted, only their points are
+        if cancel.is_cancelled() {
+            partial = true;
+            break;
+        }
+        // A sparse image that takes more memory than dense counters would is turned into them
+        let dense_bytes = RawImage::bytes(width, height, channels, settings.counter_bits);
+        if raw_image
+            .sparse_bytes()
+            .is_some_and(|bytes| bytes > dense_bytes)
+        {
+            sink.message(
+                "The sparse image came to take more memory than dense counters, turning it into them",
+            );
+            let dense = dense_image(settings, sink, width, height);
+            dense.merge(&raw_image)?;
+            raw_image = Arc::new(dense);
+        }
+        skipped += pass_skipped;
+        drawn += total_samples;
+        pass_times.push(pass_start.elapsed());
+        // Only whole passes are taken into the noise map, as one cut short would add fewer points
+        // than the others
+        if let Some(noise) = &mut noise {
+            noise.add_pass(&raw_image);
+        }
+
+        sink.pass_finished(pass);
+        let intermediate_due = match settings.intermediate_every {
+            // Intervals of 0 passes aren't parsed, but can still be given by the library
+            IntermediateInterval::Passes(passes) => {
+                (pass + 1).checked_rem(passes as u64) == Some(0)
+            }
+            IntermediateInterval::Seconds(seconds) => {
+                last_render.elapsed() >= Duration::from_secs(seconds)
+            }
+            IntermediateInterval::Never => false,
+        };
+        if intermediate_due {
+            sink.intermediate(&raw_image, weights.as_deref(), noise.as_ref());
+            last_render = Instant::now();
+        }
+    }
+    if skipped > 0 {
+        sink.message(&format!(
+            "Skipped {skipped} of {drawn} samples ({:.1}%) inside the main cardioid or period-2 bulb",
+            100.0 * skipped as f64 / drawn as f64
+        ));
+    }
+    if raw_image.saturated() > 0 {
+        sink.message(&format!(
+            "{} counters saturated at {}, so the brightest pixels lost points",
+            raw_image.saturated(),
+            u32::MAX
+        ));
+    }
+    if settings.samples_disk() && drawn > 0 {
+        // How many of the samples that the sampling bounds would have taken are iterated
+        let (re_size, im_size) = (
+            settings.sample_re_max - settings.sample_re_min,
+            settings.sample_im_max - settings.sample_im_min,
+        );
+        let share = std::f64::consts::PI * settings.escape_radius.powi(2) / (re_size * im_size);
+        sink.message(&format!(
+            "Sampled the disk of radius {}, {:.1}% of the sampling bounds, for an acceptance of {:.1}%",
+            settings.escape_radius,
+            100.0 * share,
+            100.0 * share * (drawn - skipped) as f64 / drawn as f64
+        ));
+    }
+    let (traced, escaped, points, clipped) = tallies.totals();
+    Ok(RenderResult {
+        maxima: raw_image.get_channel_maxima(),
+        weighted_maxima: weights.as_ref().map(|weights| weights.get_channel_maxima()),
+        weights,
+        noise,
+        image: raw_image,
+        partial,
+        samples: drawn,
+        skipped,
+        traced,
+        escaped,
+        points,
+        clipped,
+        pass_times,
+        settings: settings.clone(),
+    })
+}
+
+/// Everything that samples are traced against, shared by all the samples of a render
+struct Canvas<'a> {
+    settings: &'a RenderSettings,
+    viewport: &'a Viewport,
+    raw_image: &'a RawImage,
+    /// Local copies of the image that workers count their points in, if any
+    local_images: Option<&'a LocalImages>,
+    reference: Option<&'a ReferenceOrbit>,
+    sampler: &'a dyn Sampler,
+    /// Statistics of the samples traced, unless they are only measured and not plotted
+    tallies: Option<&'a Tallies>,
+    /// Mask of the pixels where points are counted, if any
+    mask: Option<&'a Mask>,
+    /// Kernel that points are shared between the pixels around them with, unless each is counted
+    /// in the pixel it lands in
+    kernel: Option<&'a Kernel>,
+    /// Sums of the weights of the points, when orbits are weighted by their length or colored by
+    /// their origin
+    weights: Option<&'a RawImageF32>,
+    /// Token that stops the tracing of further samples once cancelled
+    cancel: &'a CancelToken,
+}
+
+impl<'a> Canvas<'a> {
+    /// Whether the mask, if any, counts the point at `index` in the orbit of `c`, which lands on
+    /// the pixel at `(x, y)`
+    fn unmasked(&self, x: u32, y: u32, c: Complex, index: usize) -> bool {
+        self.mask.is_none_or(|mask| {
+            let hash = derive_seed(c.re.to_bits(), &[c.im.to_bits(), index as u64]);
+            mask.keeps(x, y, hash)
+        })
+    }
+
+    /// Count the point at `index` in the orbit of `c` in a `channel`, at its `position` on the
+    /// grid of pixels, passing each pixel that it is counted in to `plot` with its share of it
+    /// Returns whether the point is in view, or `None` if it is and the mask left it out
+    fn splat(
+        &self,
+        position: (f64, f64),
+        channel: u32,
+        c: Complex,
+        index: usize,
+        plot: &mut impl FnMut(u32, u32, u32, u32),
+    ) -> Option<bool> {
+        let pixel = self.viewport.pixel_at(position);
+        let Some(kernel) = self.kernel else {
+            return match pixel {
+                Some((x, y)) if self.unmasked(x, y, c, index) => {
+                    plot(x, y, channel, 1);
+                    Some(true)
+                }
+                Some(_) => None,
+                None => Some(false),
+            };
+        };
+        // Points just out of view still share some of themselves with the pixels at the edges
+        if !self.viewport.covers(position, kernel.margin()) {
+            return Some(false);
+        }
+        let ((left, top), shares) = kernel.at(position);
+        let mut counted = false;
+        if self.mask.is_none() && self.viewport.covers(position, -kernel.margin()) {
+            // Every share is in view
+            let (left, top) = (left as i64, top as i64);
+            for &(dx, dy, share) in shares {
+                let (x, y) = (left + dx as i64, top + dy as i64);
+                plot(x as u32, y as u32, channel, share);
+            }
+            return Some(true);
+        }
+        for &(dx, dy, share) in shares {
+            let center = (left + dx as f64 + 0.5, top + dy as f64 + 0.5);
+            match self.viewport.pixel_at(center) {
+                Some((x, y)) if self.unmasked(x, y, c, index) => {
+                    plot(x, y, channel, share);
+                    counted = true;
+                }
+                _ => {}
+            }
+        }
+        match pixel {
+            Some(_) if !counted => None,
+            pixel => Some(pixel.is_some()),
+        }
+    }
+
+    /// Where the current worker counts the points that it plots
+    fn counter(&self) -> Counter<'a> {
+        match self.local_images {
+            Some(local_images) => local_images.counter(self.raw_image),
+            None => Counter::Shared(self.raw_image),
+        }
+    }
+
+    /// Channels out of a group of `channels` that take the sample at `index` within a pass, as each
+    /// only takes its own number of samples
+    fn channels_of<'c>(
+        &'c self,
+        channels: &'c [u32],
+        index: u64,
+    ) -> impl Iterator<Item = u32> + 'c {
+        channels
+            .iter()
+            .copied()
+            .filter(move |&channel| index < self.samples(channel))
+    }
+
+    /// Number of samples that a channel takes in a pass, which the sampler may not match exactly
+    fn samples(&self, channel: u32) -> u64 {
+        self.sampler
+            .samples(self.settings.samples.get(channel as usize) as u64)
+    }
+
+    /// Number of samples drawn for a group of `channels` in a pass, enough for every channel
+    fn group_samples(&self, channels: &[u32]) -> u64 {
+        channels
+            .iter()
+            .map(|&channel| self.samples(channel))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Limit up to which the sample at `index` must be iterated, to serve every channel that takes
+    /// it
+    fn limit(&self, channels: &[u32], index: u64) -> u32 {
+        self.channels_of(channels, index)
+            .map(|channel| self.settings.limit(channel))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Statistics of the samples that a render traces, counted apart by each thread of the pool so
+/// that they don't contend for them, once for each orbit rather than for each point
+struct Tallies {
+    /// One for each thread of the pool, and a last one for any thread outside of it
+    slots: Vec<TallySlot>,
+    channels: usize,
+}
+
+/// Statistics counted by a single thread, on cache lines of their own
+#[derive(Default)]
+#[repr(align(128))]
+struct TallySlot {
+    traced: [AtomicU64; MAX_CHANNELS],
+    escaped: [AtomicU64; MAX_CHANNELS],
+    points: AtomicU64,
+    clipped: AtomicU64,
+}
+
+impl Tallies {
+    fn new(channels: u32) -> Tallies {
+        Tallies {
+            slots: (0..=rayon::current_num_threads())
+                .map(|_| TallySlot::default())
+                .collect(),
+            channels: channels as usize,
+        }
+    }
+
+    /// Count an orbit traced in a `channel`, whether it `escaped`, and how many of its points were
+    /// `plotted` in view or `clipped` out of it
+    fn add(&self, channel: u32, escaped: bool, plotted: u64, clipped: u64) {
+        let (slot, own) =
+            match rayon::current_thread_index().and_then(|thread| self.slots.get(thread)) {
+                Some(slot) => (slot, true),
+                None => (&self.slots[self.slots.len() - 1], false),
+            };
+        // A thread of the pool is the only one to add to its slot, so it spares the cost of an
+        // atomic addition, which takes about as long as plotting a short orbit
+        let add = |count: &AtomicU64, value: u64| {
+            if own {
+                count.store(count.load(Relaxed) + value, Relaxed);
+            } else {
+                count.fetch_add(value, Relaxed);
+            }
+        };
+        add(&slot.traced[channel as usize], 1);
+        add(&slot.escaped[channel as usize], escaped as u64);
+        add(&slot.points, plotted);
+        add(&slot.clipped, clipped);
+    }
+
+    /// Samples traced and escaped in each channel, and points plotted and clipped, of every thread
+    fn totals(&self) -> (Vec<u64>, Vec<u64>, u64, u64) {
+        let per_channel = |counts: fn(&TallySlot) -> &[AtomicU64; MAX_CHANNELS]| {
+            (0..self.channels)
+                .map(|channel| {
+                    let counts = self
+                        .slots
+                        .iter()
+                        .map(|slot| counts(slot)[channel].load(Relaxed));
+                    counts.sum()
+                })
+                .collect()
+        };
+        (
+            per_channel(|slot| &slot.traced),
+            per_channel(|slot| &slot.escaped),
+            self.slots
+                .iter()
+                .map(|slot| slot.points.load(Relaxed))
+                .sum(),
+            self.slots
+                .iter()
+                .map(|slot| slot.clipped.load(Relaxed))
+                .sum(),
+        )
+    }
+}
+
+/// Trace the random samples of a `range` of indices within a pass, a chunk of [`LANES`] at a
+/// time, into reused `orbits` buffers, until the render is cancelled
+/// Returns how many of the samples were skipped without iterating
+fn trace_samples<R: Rng>(
+    canvas: &Canvas,
+    sink: &dyn ProgressSink,
+    rng: &mut R,
+    channels: &[u32],
+    range: Range<u64>,
+    orbits: &mut [Vec<Complex>; LANES],
+) -> u64 {
+    let mut skipped = 0;
+    for chunk in range.clone().step_by(LANES) {
+        if canvas.cancel.is_cancelled() {
+            break;
+        }
+        let count = min(LANES, (range.end - chunk) as usize);
+        sink.samples_completed(count as u64);
+        skipped += trace_chunk(canvas, rng, channels, chunk, &mut orbits[..count]);
+    }
+    skipped
+}
+
+/// Trace as many random samples as there are `orbits` buffers (at most [`LANES`]), starting from
+/// the sample at index `first` within a pass, in a single batch when the settings allow it
+/// Returns how many of the samples were skipped without iterating
+fn trace_chunk<R: Rng>(
+    canvas: &Canvas,
+    rng: &mut R,
+    channels: &[u32],
+    first: u64,
+    orbits: &mut [Vec<Complex>],
+) -> u64 {
+    let settings = canvas.settings;
+    let mut counter = canvas.counter();
+    // The first half of an antithetic pair, until its mirror image is drawn
+    let mut pending = None;
+    if !settings.batches() {
+        let mut skipped = 0;
+        for (index, orbit) in (first..).zip(orbits.iter_mut()) {
+            if trace_sample(
+                canvas,
+                rng,
+                channels,
+                index,
+                orbit,
+                &mut pending,
+                &mut counter,
+            ) {
+                skipped += 1;
+            }
+        }
+        return skipped;
+    }
+    // Skipped samples don't take up a lane
+    let mut z = [Complex { re: 0.0, im: 0.0 }; LANES];
+    let mut c = z;
+    let mut indices = [0; LANES];
+    let mut lanes = 0;
+    for index in first..first + orbits.len() as u64 {
+        let (_, sample_z, sample_c) = draw_sample(canvas, rng, index, &mut pending);
+        if !skip_sample(settings, sample_c) {
+            z[lanes] = sample_z;
+            c[lanes] = sample_c;
+            indices[lanes] = index;
+            lanes += 1;
+        }
+    }
+    // The first sample is taken by the most channels, so its limit serves the whole chunk
+    let escaped = mandelbrot::iterate_batch(
+        z,
+        c,
+        canvas.limit(channels, first),
+        settings.escape_radius,
+        settings.stop_radius,
+        settings.checks_periodicity(),
+        &mut orbits[..lanes],
+    );
+    for lane in 0..lanes {
+        plot_channels(
+            canvas,
+            channels,
+            indices[lane],
+            z[lane],
+            c[lane],
+            &orbits[lane],
+            escaped[lane],
+            &mut |x, y, channel, share| counter.add(x, y, channel, share),
+        );
+    }
+    (orbits.len() - lanes) as u64
+}
+
+/// Iterate the random sample at `index` within a pass (a value of `c`, or a starting point in
+/// Julia mode), and plot its orbit in each of the `channels` that take it, if the orbit filter
+/// keeps it
+/// The orbit is traced into a buffer that is reused from one sample to the next, the first half
+/// of an antithetic pair is kept in `pending` for the next sample, and points are counted with
+/// `counter`
+/// Returns whether the sample was skipped without iterating, as it was sure to never escape
+fn trace_sample<R: Rng>(
+    canvas: &Canvas,
+    rng: &mut R,
+    channels: &[u32],
+    index: u64,
+    orbit: &mut Vec<Complex>,
+    pending: &mut Option<(f64, f64)>,
+    counter: &mut Counter,
+) -> bool {
+    let point = draw_point(canvas, rng, index, pending);
+    trace_point(
+        canvas,
+        rng,
+        channels,
+        index,
+        point,
+        orbit,
+        &mut |x, y, channel, share| counter.add(x, y, channel, share),
+    )
+}
+
+/// Iterate the sample at `(u, v)` within the sampling bounds, as the sample at `index` within a
+/// pass, and pass the points that its orbit plots to `plot`
+/// Returns whether the sample was skipped without iterating
+fn trace_point<R: Rng>(
+    canvas: &Canvas,
+    rng: &mut R,
+    channels: &[u32],
+    index: u64,
+    (u, v): (f64, f64),
+    orbit: &mut Vec<Complex>,
+    plot: &mut impl FnMut(u32, u32, u32, u32),
+) -> bool {
+    let settings = canvas.settings;
+    let limit = canvas.limit(channels, index);
+    let (z, c) = sample_at(settings, rng, (u, v));
+    if skip_sample(settings, c) {
+        return true;
+    }
+    // Perturbed orbits take the sample relative to the center, as it may be finer than `f64` can
+    // resolve, and are plotted relative to it too
+    let delta_c = Complex {
+        re: (settings.sample_re_min - settings.center_re)
+            + u * (settings.sample_re_max - settings.sample_re_min),
+        im: (settings.sample_im_min - settings.center_im)
+            + v * (settings.sample_im_max - settings.sample_im_min),
+    };
+    let bailed = iterate_orbit(settings, canvas.reference, z, c, delta_c, limit, orbit);
+    let c = match canvas.reference {
+        Some(_) => delta_c,
+        None => c,
+    };
+    plot_channels(canvas, channels, index, z, c, orbit, bailed, plot);
+    false
+}
+
+/// Iterate the orbit of `c` from `z` up to `limit` iterations into `orbit`, with the
+/// implementation that the `settings` call for, or of the offset `delta_c` from the `reference`
+/// of a deep zoom, recorded as offsets from it
+/// Returns whether the orbit escaped
+fn iterate_orbit(
+    settings: &RenderSettings,
+    reference: Option<&ReferenceOrbit>,
+    z: Complex,
+    c: Complex,
+    delta_c: Complex,
+    limit: u32,
+    orbit: &mut Vec<Complex>,
+) -> bool {
+    match (&settings.formula, reference) {
+        (_, Some(reference)) => mandelbrot::iterate_perturbed_into(
+            reference,
+            z,
+            delta_c,
+            limit,
+            settings.escape_radius,
+            settings.stop_radius,
+            orbit,
+        ),
+        (Some(formula), None) => mandelbrot::iterate_formula_into(
+            z,
+            c,
+            formula,
+            limit,
+            settings.escape_radius,
+            settings.stop_radius,
+            orbit,
+        ),
+        // Only the standard function has a single precision implementation
+        (None, None) if settings.precision == Precision::F32 && settings.standard_function() => {
+            mandelbrot::iterate_f32_into(
+                z,
+                c,
+                limit,
+                settings.escape_radius,
+                settings.stop_radius,
+                orbit,
+            )
+        }
+        (None, None) => mandelbrot::iterate_into(
+            z,
+            c,
+            settings.variant,
+            settings.power,
+            limit,
+            settings.escape_radius,
+            settings.stop_radius,
+            settings.checks_periodicity(),
+            orbit,
+        ),
+    }
+}
+
+/// Draw the random sample at `index` within a pass, given as its position `(u, v)` within the
+/// sampling bounds, along with the starting point and `c` of its orbit
+fn draw_sample<R: Rng>(
+    canvas: &Canvas,
+    rng: &mut R,
+    index: u64,
+    pending: &mut Option<(f64, f64)>,
+) -> ((f64, f64), Complex, Complex) {
+    let (u, v) = draw_point(canvas, rng, index, pending);
+    let (z, c) = sample_at(canvas.settings, rng, (u, v));
+    ((u, v), z, c)
+}
+
+/// Draw the position `(u, v)` of the sample at `index` within a pass
+/// Antithetic samples come in pairs, whose second half is the first one mirrored across the
+/// middle of the imaginary range of the sampling bounds (its conjugate, for bounds that are
+/// symmetric about the real axis): the first half is drawn from the point of the sampler at half
+/// the index, and kept in `pending` until the second one reuses it, or drawn again when the pair
+/// is split between two chunks
+fn draw_point<R: Rng>(
+    canvas: &Canvas,
+    rng: &mut R,
+    index: u64,
+    pending: &mut Option<(f64, f64)>,
+) -> (f64, f64) {
+    // Samples are only drawn within the pass, but any past the samples of the sampler would be
+    // independent ones
+    let mut point = |index| {
+        canvas
+            .sampler
+            .point(index, rng)
+            .unwrap_or_else(|| (rng.gen(), rng.gen()))
+    };
+    if !canvas.settings.antithetic {
+        return point(index);
+    }
+    let first = match pending.take() {
+        Some(point) if !index.is_multiple_of(2) => point,
+        _ => point(index / 2),
+    };
+    if index.is_multiple_of(2) {
+        *pending = Some(first);
+        first
+    } else {
+        (first.0, 1.0 - first.1)
+    }
+}
+
+/// Starting point and `c` of the orbit of the sample at `(u, v)` within the sampling bounds
+fn sample_at<R: Rng>(
+    settings: &RenderSettings,
+    rng: &mut R,
+    (u, v): (f64, f64),
+) -> (Complex, Complex) {
+    let sample = if settings.samples_disk() {
+        disk_point(settings.escape_radius, (u, v))
+    } else {
+        Complex {
+            re: settings.sample_re_min + u * (settings.sample_re_max - settings.sample_re_min),
+            im: settings.sample_im_min + v * (settings.sample_im_max - settings.sample_im_min),
+        }
+    };
+    match settings.mode {
+        Mode::Mandelbrot => (start(rng, settings), sample),
+        Mode::Julia { c_re, c_im } => (sample, Complex { re: c_re, im: c_im }),
+    }
+}
+
+/// Point of the disk of a given `radius` around the origin for the sample at `(u, v)`, by mapping
+/// concentric squares of the unit square onto concentric circles, which keeps areas and nearby
+/// samples close together
+fn disk_point(radius: f64, (u, v): (f64, f64)) -> Complex {
+    use std::f64::consts::FRAC_PI_4;
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    let (r, angle) = if a == 0.0 && b == 0.0 {
+        (0.0, 0.0)
+    } else if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, 2.0 * FRAC_PI_4 - FRAC_PI_4 * (a / b))
+    };
+    Complex {
+        re: radius * r * angle.cos(),
+        im: radius * r * angle.sin(),
+    }
+}
+
+/// Whether a sample can be skipped without iterating, as it is sure to never escape
+fn skip_sample(settings: &RenderSettings, c: Complex) -> bool {
+    settings.skips_interior() && (mandelbrot::in_main_cardioid(c) || mandelbrot::in_period2_bulb(c))
+}
+
+/// Plot the orbit of `c` for the sample at `index` within a pass, from its starting point `z`, in
+/// each of the `channels` that take it
+/// The orbit may have been iterated further than some of those channels' limits, in which case
+/// they plot it as they would have iterated it themselves: cut short at their own limit, and only
+/// escaped if its last point before that is outside of the escape radius
+/// Each point plotted is passed to `plot`, with its pixel, channel, and share of the point
+#[allow(clippy::too_many_arguments)]
+fn plot_channels(
+    canvas: &Canvas,
+    channels: &[u32],
+    index: u64,
+    z: Complex,
+    c: Complex,
+    orbit: &[Complex],
+    bailed: bool,
+    plot: &mut impl FnMut(u32, u32, u32, u32),
+) {
+    let sample = match canvas.settings.mode {
+        Mode::Mandelbrot => c,
+        Mode::Julia { .. } => z,
+    };
+    for channel in canvas.channels_of(channels, index) {
+        let limit = canvas.settings.limit(channel) as usize;
+        if orbit.len() <= limit {
+            plot_orbit(canvas, channel, c, sample, orbit, bailed, plot);
+        } else {
+            let last = if limit == 0 { z } else { orbit[limit - 1] };
+            let escape = canvas.settings.escape_radius;
+            plot_orbit(
+                canvas,
+                channel,
+                c,
+                sample,
+                &orbit[..limit],
+                last.norm_sqr() > escape * escape,
+                plot,
+            );
+        }
+    }
+}
+
+/// Plot the orbit of `c` from a `sample` (`c` itself, or the starting point in Julia mode) in a
+/// given `channel`, if the orbit filter keeps it, projecting each of its points onto the plane of
+/// the view, and passing the pixels of each one that is in view to `plot` with their shares of it
+fn plot_orbit(
+    canvas: &Canvas,
+    channel: u32,
+    c: Complex,
+    sample: Complex,
+    orbit: &[Complex],
+    bailed: bool,
+    plot: &mut impl FnMut(u32, u32, u32, u32),
+) {
+    let settings = canvas.settings;
+    let kept = match settings.orbit_filter {
+        OrbitFilter::Escaped => bailed,
+        OrbitFilter::Trapped => !bailed,
+        OrbitFilter::All => true,
+    };
+    // Orbits that don't escape within the last band are as long as it, and fall in it
+    let kept = kept
+        && settings
+            .band(orbit.len())
+            .is_none_or(|band| band == channel);
+    let (mut plotted, mut clipped) = (0, 0);
+    if kept && orbit.len() >= settings.min_iterations.get(channel as usize) as usize {
+        // Orbits are recorded whole, as channels that share them may skip different heads
+        let skip = settings.skip_iterations.get(channel as usize) as usize;
+        let orbit = &orbit[min(skip, orbit.len())..];
+        // Shares of weighted orbits add up to one point for the whole orbit, in view or not
+        let weight = match settings.weighs_orbits() {
+            true => 1.0 / (orbit.len() as f32 * settings.splat.scale() as f32),
+            false => 1.0 / settings.splat.scale() as f32,
+        };
+        // The conjugate sample's orbit is the mirror image of this one, so it comes for free
+        let halves: &[bool] = if settings.mirrors() {
+            &[false, true]
+        } else {
+            &[false]
+        };
+        for &mirrored in halves {
+            let (in_view, out_of_view) = match canvas.weights {
+                None => splat_orbit(canvas, channel, c, orbit, mirrored, plot),
+                Some(weights) => {
+                    // Orbits colored by their origin share each point between the channels of
+                    // their hue
+                    let hue = settings.colors_by_origin().then(|| {
+                        let sample = if mirrored { sample.conj() } else { sample };
+                        origin_hue(settings, sample, canvas.reference.is_some())
+                    });
+                    let plot = &mut |x, y, channel, share| {
+                        plot(x, y, channel, share);
+                        let weight = share as f32 * weight;
+                        match hue {
+                            Some(hue) => {
+                                for (channel, part) in (0..).zip(hue) {
+                                    if part > 0.0 {
+                                        weights.add(x, y, channel, weight * part);
+                                    }
+                                }
+                            }
+                            None => weights.add(x, y, channel, weight),
+                        }
+                    };
+                    splat_orbit(canvas, channel, c, orbit, mirrored, plot)
+                }
+            };
+            plotted += in_view;
+            clipped += out_of_view;
+        }
+    }
+    if let Some(tallies) = canvas.tallies {
+        tallies.add(channel, bailed, plotted, clipped);
+    }
+}
+
+/// Splat the points of the `orbit` of `c` in a `channel`, or those of its mirror image across the
+/// real axis if `mirrored`, passing the pixels of each one that is in view to `plot` with their
+/// shares of it
+/// Returns how many of the points were plotted, and how many fell outside of the view
+fn splat_orbit(
+    canvas: &Canvas,
+    channel: u32,
+    c: Complex,
+    orbit: &[Complex],
+    mirrored: bool,
+    plot: &mut impl FnMut(u32, u32, u32, u32),
+) -> (u64, u64) {
+    let settings = canvas.settings;
+    let projected = settings.projection != Projection::Z;
+    let (mut plotted, mut clipped) = (0, 0);
+    for (index, &z) in orbit.iter().enumerate() {
+        let position = if mirrored {
+            let z = settings.projection.project(z.conj(), c.conj());
+            canvas.viewport.position(z)
+        } else {
+            let z = if projected {
+                settings.projection.project(z, c)
+            } else {
+                z
+            };
+            // Perturbed orbits are given as offsets from the center, to keep their precision
+            match canvas.reference {
+                Some(_) => canvas.viewport.position_from_center(z),
+                None => canvas.viewport.position(z),
+            }
+        };
+        let c = if mirrored { c.conj() } else { c };
+        match canvas.splat(position, channel, c, index, plot) {
+            Some(true) => plotted += 1,
+            Some(false) => clipped += 1,
+            None => {}
+        }
+    }
+    (plotted, clipped)
+}
+
+/// Shares of the red, green, and blue channels in the points of the orbit of a `sample`, by the
+/// hue of its angle around the middle of the sampling region, which add up to one: red towards
+/// the positive real axis, green a third of a turn around, and blue two thirds
+/// Perturbed samples are given `relative` to the center of the view
+fn origin_hue(settings: &RenderSettings, sample: Complex, relative: bool) -> [f32; 3] {
+    let ((middle_re, middle_im), (re_radius, im_radius)) = if settings.samples_disk() {
+        ((0.0, 0.0), (settings.escape_radius, settings.escape_radius))
+    } else {
+        (
+            (
+                (settings.sample_re_min + settings.sample_re_max) / 2.0,
+                (settings.sample_im_min + settings.sample_im_max) / 2.0,
+            ),
+            (
+                (settings.sample_re_max - settings.sample_re_min) / 2.0,
+                (settings.sample_im_max - settings.sample_im_min) / 2.0,
+            ),
+        )
+    };
+    let (middle_re, middle_im) = match relative {
+        true => (
+            middle_re - settings.center_re,
+            middle_im - settings.center_im,
+        ),
+        false => (middle_re, middle_im),
+    };
+    // Angles are taken as if the region were square, so that narrow regions still take every hue
+    let angle = ((sample.im - middle_im) / im_radius).atan2((sample.re - middle_re) / re_radius);
+    // From 0 to 3, through each channel in turn
+    let hue = angle.rem_euclid(std::f64::consts::TAU) * 3.0 / std::f64::consts::TAU;
+    let part = |channel: f64| {
+        let distance = (hue - channel).rem_euclid(3.0);
+        (1.0 - distance.min(3.0 - distance)).max(0.0) as f32
+    };
+    [part(0.0), part(1.0), part(2.0)]
+}
+
+/// Read the mask at `path`, stretched to the image of `width` × `height` pixels that points are
+/// counted in, telling the `sink` if that changes its aspect ratio
+fn load_mask(
+    path: &str,
+    sink: &dyn ProgressSink,
+    width: u32,
+    height: u32,
+) -> Result<Mask, NebulaeError> {
+    let mask = Mask::load(path, width, height)?;
+    let (mask_width, mask_height) = mask.source_size();
+    let (mask_ratio, ratio) = (
+        mask_width as f64 / mask_height as f64,
+        width as f64 / height as f64,
+    );
+    if (mask_ratio / ratio - 1.0).abs() > 0.01 {
+        sink.message(&format!(
+            "The mask of {mask_width}x{mask_height} pixels is stretched over the image of {width}x{height}"
+        ));
+    }
+    Ok(mask)
+}
+
+/// The image of `width` × `height` pixels that points are counted in, sparse when the settings
+/// count points sparsely
+fn new_image(
+    settings: &RenderSettings,
+    sink: &dyn ProgressSink,
+    width: u32,
+    height: u32,
+) -> RawImage {
+    if settings.counts_sparsely() {
+        sink.message(
+            "Counting points in a sparse image, which only holds the counters that aren't 0",
+        );
+        return RawImage::sparse(width, height, settings.channels());
+    }
+    dense_image(settings, sink, width, height)
+}
+
+/// The image of `width` × `height` pixels of dense counters that points are counted in, in a
+/// memory map when the settings hold its counters in one, or in memory if the map can't be made
+fn dense_image(
+    settings: &RenderSettings,
+    sink: &dyn ProgressSink,
+    width: u32,
+    height: u32,
+) -> RawImage {
+    let (channels, bits) = (settings.channels(), settings.counter_bits);
+    if !settings.maps_memory() {
+        return RawImage::new(width, height, channels, bits, settings.layout);
+    }
+    let directory = settings.map_directory();
+    let megabytes = RawImage::bytes(width, height, channels, bits).div_ceil(1 << 20);
+    match RawImage::mapped(width, height, channels, bits, settings.layout, &directory) {
+        Ok(image) => {
+            sink.message(&format!(
+                "Counting points in a memory map of {megabytes} MiB in {}",
+                directory.display()
+            ));
+            image
+        }
+        Err(error) => {
+            sink.message(&format!(
+                "Counting points in memory, as a memory map of {megabytes} MiB couldn't be made in {}: {error}",
+                directory.display()
+            ));
+            RawImage::new(width, height, channels, bits, settings.layout)
+        }
+    }
+}
+
+/// The [`Sampler`] chosen by the settings, which serves every pass of a render once reset for it
+fn new_sampler(settings: &RenderSettings) -> Box<dyn Sampler> {
+    let samples = (0..settings.channels() as usize)
+        .map(|channel| settings.samples.get(channel) as u64)
+        .max()
+        .unwrap_or(0);
+    let jittered = || {
+        let (re_size, im_size) = settings.sample_size();
+        // The sampler draws the positions of the samples of each pass itself
+        Box::new(match settings.seed {
+            Some(seed) => JitterSampler::with_rng(
+                samples,
+                re_size / im_size,
+                StdRng::seed_from_u64(derive_seed(seed, &[u64::MAX - 3])),
+            ),
+            None => JitterSampler::new(samples, re_size / im_size),
+        })
+    };
+    match settings.sampler {
+        // Independent samples have nothing to lay out together, so uniform passes that are
+        // stratified together take one sample in each cell of a grid instead
+        SamplerKind::Uniform | SamplerKind::Metropolis if settings.stratifies_passes() => {
+            jittered()
+        }
+        // Chains of the Metropolis sampler and guided samples aren't drawn by index, but the
+        // Metropolis sampler falls back to uniform samples
+        SamplerKind::Uniform | SamplerKind::Metropolis | SamplerKind::Guided => {
+            Box::new(UniformSampler)
+        }
+        SamplerKind::Jittered => jittered(),
+        SamplerKind::Halton => Box::new(HaltonSampler::new(samples)),
+        SamplerKind::Poisson => Box::new(PoissonSampler::new(samples)),
+    }
+}
+
+/// Number of tasks that the samplers that take a share of the pass in each task split it into:
+/// one for each thread, or a fixed number in deterministic renders, whose random streams then
+/// don't depend on the number of threads
+fn share_tasks(settings: &RenderSettings) -> u64 {
+    match settings.seed {
+        Some(_) if settings.deterministic => DETERMINISTIC_TASKS,
+        _ => rayon::current_num_threads() as u64,
+    }
+}
+
+/// Trace `samples` samples for every group of channels along chains of the Metropolis sampler,
+/// one for each thread, and plot them in every channel
+/// Returns how many of the samples were skipped without iterating, counted once for each group
+fn trace_chains(
+    canvas: &Canvas,
+    sink: &dyn ProgressSink,
+    groups: &[Vec<u32>],
+    calibration: &Calibration,
+    pass: u64,
+    samples: u64,
+) -> u64 {
+    let settings = canvas.settings;
+    // Steps are sized after the view, within the sampling bounds
+    let (re_size, im_size) = canvas.viewport.size();
+    let (re_extent, im_extent) = settings.sample_size();
+    let step = (
+        settings.metropolis_scale * re_size / re_extent,
+        settings.metropolis_scale * im_size / im_extent,
+    );
+    let tasks = share_tasks(settings);
+    let skipped: u64 = (0..tasks)
+        .into_par_iter()
+        .map(|task| {
+            let mut rng = match settings.seed {
+                Some(seed) => StdRng::seed_from_u64(derive_seed(seed, &[pass, task])),
+                None => StdRng::from_entropy(),
+            };
+            let mut orbit = Vec::new();
+            let mut counter = canvas.counter();
+            // Chains weigh samples by the points they plot across every channel, which keeps the
+            // channels balanced, so every channel takes every sample
+            metropolis::run_chain(
+                &mut rng,
+                (task + 1) * samples / tasks - task * samples / tasks,
+                settings.metropolis_warmup as u64,
+                step,
+                calibration,
+                canvas.cancel,
+                |rng, point, plots| {
+                    sink.samples_completed(groups.len() as u64);
+                    trace_groups(canvas, rng, groups, point, &mut orbit, plots)
+                },
+                |(x, y, channel, share), count| counter.add(x, y, channel, count * share),
+            )
+        })
+        .sum();
+    skipped * groups.len() as u64
+}
+
+/// Measure the weights of the Metropolis chains over uniform samples, which aren't plotted
+fn calibrate(canvas: &Canvas, groups: &[Vec<u32>]) -> Calibration {
+    let settings = canvas.settings;
+    let tasks = share_tasks(settings);
+    let samples = metropolis::CALIBRATION_SAMPLES;
+    let tally = (0..tasks)
+        .into_par_iter()
+        .map(|task| {
+            // Apart from the streams of every pass
+            let mut rng = match settings.seed {
+                Some(seed) => StdRng::seed_from_u64(derive_seed(seed, &[u64::MAX, task])),
+                None => StdRng::from_entropy(),
+            };
+            let (mut orbit, mut plots) = (Vec::new(), Vec::new());
+            let mut tally = Tally::default();
+            for _ in task * samples / tasks..(task + 1) * samples / tasks {
+                let point = (rng.gen(), rng.gen());
+                plots.clear();
+                trace_groups(canvas, &mut rng, groups, point, &mut orbit, &mut plots);
+                tally.add(plots.len() as u64);
+            }
+            tally
+        })
+        // Merged in the order of the tasks, as sums of floats depend on it
+        .collect::<Vec<_>>()
+        .into_iter()
+        .fold(Tally::default(), Tally::merge);
+    Calibration::new(tally)
+}
+
+/// Trace `samples` samples for every group of channels, drawn from an importance map, and plot
+/// them in every channel with their weights, given on a scale
+/// With the `noise` of the image, samples are also scored by the noise of the points they plot
+/// Returns how many of the samples were skipped without iterating, counted once for each group,
+/// and the scores of the samples
+fn trace_guided(
+    canvas: &Canvas,
+    sink: &dyn ProgressSink,
+    groups: &[Vec<u32>],
+    (importance, scale): (&ImportanceMap, f64),
+    noise: Option<&Noise>,
+    pass: u64,
+    samples: u64,
+) -> (u64, Sources) {
+    let tasks = share_tasks(canvas.settings);
+    let (skipped, sources) = (0..tasks)
+        .into_par_iter()
+        .map(|task| {
+            let mut rng = match canvas.settings.seed {
+                Some(seed) => StdRng::seed_from_u64(derive_seed(seed, &[pass, task])),
+                None => StdRng::from_entropy(),
+            };
+            let (mut orbit, mut plots) = (Vec::new(), Vec::new());
+            let mut skipped = 0;
+            let mut sources = Sources::default();
+            let mut counter = canvas.counter();
+            let (first, end) = (task * samples / tasks, (task + 1) * samples / tasks);
+            // Counted a batch at a time, like other samples are
+            for chunk in (first..end).step_by(LANES) {
+                if canvas.cancel.is_cancelled() {
+                    break;
+                }
+                let count = min(LANES as u64, end - chunk);
+                sink.samples_completed(count * groups.len() as u64);
+                for _ in 0..count {
+                    let (point, density) = importance.sample(&mut rng);
+                    plots.clear();
+                    if trace_groups(canvas, &mut rng, groups, point, &mut orbit, &mut plots) {
+                        skipped += 1;
+                    }
+                    // Rounded once for the whole orbit, which keeps its expected value just as
+                    // well
+                    let copies = metropolis::copies(&mut rng, scale / density);
+                    if copies > 0 {
+                        for &(x, y, channel, share) in &plots {
+                            counter.add(x, y, channel, copies * share);
+                        }
+                    }
+                    if let Some(noise) = noise {
+                        let scores = plots
+                            .iter()
+                            .map(|&(x, y, _, share)| noise.at(x, y) * share as f64);
+                        sources.add(point, scores.sum());
+                    }
+                }
+            }
+            (skipped, sources)
+        })
+        // Merged in the order of the tasks, as sums of floats depend on it
+        .collect::<Vec<_>>()
+        .into_iter()
+        .fold((0, Sources::default()), |(a, sources_a), (b, sources_b)| {
+            (a + b, sources_a.merge(sources_b))
+        });
+    (skipped * groups.len() as u64, sources)
+}
+
+/// Count the points that uniform samples of every cell of the guided sampler's grid plot in
+/// view, which are measured in a pre-pass and aren't plotted
+fn map_importance(canvas: &Canvas, groups: &[Vec<u32>]) -> ImportanceMap {
+    let settings = canvas.settings;
+    let grid = settings.guided_grid;
+    let tasks = share_tasks(settings);
+    let samples = settings.guided_samples as u64;
+    let counts = (0..tasks)
+        .into_par_iter()
+        .map(|task| {
+            // Apart from the streams of every pass
+            let mut rng = match settings.seed {
+                Some(seed) => StdRng::seed_from_u64(derive_seed(seed, &[u64::MAX, task])),
+                None => StdRng::from_entropy(),
+            };
+            let (mut orbit, mut plots) = (Vec::new(), Vec::new());
+            let mut counts = vec![0.0; (grid * grid) as usize];
+            for _ in task * samples / tasks..(task + 1) * samples / tasks {
+                let point = (rng.gen(), rng.gen());
+                plots.clear();
+                trace_groups(canvas, &mut rng, groups, point, &mut orbit, &mut plots);
+                counts[guided::cell(grid, point)] += plots.len() as f64;
+            }
+            counts
+        })
+        .reduce(
+            || vec![0.0; (grid * grid) as usize],
+            |mut a, b| {
+                a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                a
+            },
+        );
+    ImportanceMap::new(grid, &counts, 1.0)
+}
+
+/// Trace the sample at `(u, v)` for every group of channels, adding the points it plots to
+/// `plots`, and return whether it was skipped without iterating
+fn trace_groups(
+    canvas: &Canvas,
+    rng: &mut StdRng,
+    groups: &[Vec<u32>],
+    (u, v): (f64, f64),
+    orbit: &mut Vec<Complex>,
+    plots: &mut Vec<metropolis::Plot>,
+) -> bool {
+    let mut skipped = false;
+    for channels in groups {
+        skipped |= trace_point(
+            canvas,
+            rng,
+            channels,
+            0,
+            (u, v),
+            orbit,
+            &mut |x, y, channel, share| plots.push((x, y, channel, share)),
+        );
+    }
+    skipped
+}
+
+/// Buffers for the orbits of a chunk of samples
+fn new_orbits() -> [Vec<Complex>; LANES] {
+    std::array::from_fn(|_| Vec::new())
+}
+
+/// Starting point of an orbit in Mandelbrot mode, randomized within `z0_radius` of `z0`
+fn start<R: Rng>(rng: &mut R, settings: &RenderSettings) -> Complex {
+    let mut z = Complex {
+        re: settings.z0_re,
+        im: settings.z0_im,
+    };
+    // No random numbers are drawn without a radius, so that renders stay reproducible
+    if settings.z0_radius > 0.0 {
+        // Uniformly distributed over the disc
+        let distance = settings.z0_radius * rng.gen::<f64>().sqrt();
+        let angle = rng.gen::<f64>() * std::f64::consts::TAU;
+        z.re += distance * angle.cos();
+        z.im += distance * angle.sin();
+    }
+    z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_points_spread_evenly_over_the_disk() {
+        assert_eq!(disk_point(2.0, (0.5, 0.5)).norm_sqr(), 0.0);
+        for corner in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.5, 1.0)] {
+            let r = disk_point(2.0, corner).norm_sqr().sqrt();
+            assert!((r - 2.0).abs() < 1e-12, "{corner:?}");
+        }
+        // Areas are kept, so that a quarter of the points of a grid fall within half the radius
+        let steps = 200;
+        let inner = (0..steps * steps)
+            .filter(|index| {
+                let u = (index % steps) as f64 / steps as f64 + 0.5 / steps as f64;
+                let v = (index / steps) as f64 / steps as f64 + 0.5 / steps as f64;
+                disk_point(2.0, (u, v)).norm_sqr() < 1.0
+            })
+            .count();
+        let share = inner as f64 / (steps * steps) as f64;
+        assert!((share - 0.25).abs() < 0.01, "{share}");
+    }
+}
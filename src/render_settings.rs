@@ -1,128 +1,2291 @@
 //! Utility for rendering settings
 
-use crate::{Term, CHANNELS};
+use crate::adaptive;
+use crate::config_file::{ConfigFormat, CONFIG_VERSION};
+use crate::error::NebulaeError;
+use crate::formula::Formula;
+use crate::mandelbrot::{Complex, Variant};
+use crate::mapping;
+use crate::noise_map::NoiseMap;
+use crate::raw_image::RawImage;
+use crate::splat;
+use crate::viewport::Viewport;
+use dialoguer::console::Term;
 use dialoguer::theme::ColorfulTheme;
-use dialoguer::{Confirm, Select};
+use dialoguer::{Confirm, Input, Select};
 use serde::{Deserialize, Serialize};
-use std::io::Error;
-use std::{fmt, fs};
+use std::path::PathBuf;
+use std::{env, fmt, fs};
 
 /// Configuration Settings for the main function
-#[derive(Serialize, Deserialize, Clone, Copy)]
+/// Fields missing from a configuration file take their value from [`RenderSettings::default`]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct RenderSettings {
-    /// Iteration multiplier for each of the red, green, and blue channels
-    /// Must be an array of CHANNELS integers
-    pub limits: [u32; CHANNELS as usize],
+    /// Version of the configuration format
+    pub version: u32,
+    /// Iteration multiplier for each channel that points are accumulated in, which sets how many
+    /// channels there are (from 1 to [`MAX_CHANNELS`], 3 for the red, green, and blue channels of
+    /// the image)
+    pub limits: Vec<u32>,
+    /// Percentiles of the escape times of a quick pre-pass to pick the escape limits at instead,
+    /// one for each channel, such as `[99, 99.9, 99.99]`, as most samples escape within a few
+    /// iterations (ignored when `limits` are given)
+    /// Picked by [`calibrate_limits`](crate::calibrate_limits), which the renderer leaves to its
+    /// caller
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_limits: Option<Vec<f64>>,
+    /// Highest escape times of bands of orbits, such as `[100, 1000, 10000]`, which replace the
+    /// channels of `limits` with a channel for each band: every orbit is traced up to the last
+    /// band, and counted in the channel of the band that its escape time falls in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bands: Option<Vec<u32>>,
+    /// Write each band into a grayscale image of its own, named after the output image and the
+    /// highest escape time of the band, such as `image-band-100.png`, rather than mixing them into
+    /// a single image
+    pub separate_bands: bool,
+    /// Shortest orbit that gets plotted, for each channel
+    /// Either a single value, or an array of one value for each channel, each less than the
+    /// channel's limit
+    pub min_iterations: PerChannel<u32>,
+    /// Number of points at the start of each orbit that aren't plotted, for each channel
+    /// Either a single value, or an array of one value for each channel, each less than the
+    /// channel's limit
+    pub skip_iterations: PerChannel<u32>,
     /// Number of random samples to take, per channel, per pass
-    pub samples: u32,
-    /// Number of passes to run
-    pub passes: u16,
-    /// Resolution of the rendered image (size × size pixels)
-    pub size: u32,
+    /// Either a single value, or an array of one value for each channel
+    pub samples: PerChannel<u32>,
+    /// Number of passes to run, or 0 (or `"infinite"`) to run until interrupted
+    #[serde(deserialize_with = "deserialize_passes")]
+    pub passes: u32,
+    /// Width of the rendered image, in pixels
+    pub width: u32,
+    /// Height of the rendered image, in pixels
+    pub height: u32,
+    /// Number of accumulated pixels along each axis that are averaged into one output pixel (1, 2, or 4)
+    pub supersample: u32,
+    /// How each point of an orbit is counted: `"nearest"` in the pixel it lands in, `"bilinear"`
+    /// shared between the four pixels around it, which smooths filaments that cross pixels at a
+    /// slant, or `"gaussian"` spread over the pixels around it, for a soft look (much slower)
+    pub splat: Splat,
+    /// Standard deviation of the Gaussian that points are shared along with `splat = "gaussian"`,
+    /// in pixels of the accumulated image (from 0.25 to 2)
+    pub splat_sigma: f64,
+    /// How much each orbit counts for: `"uniform"`, where every point counts for one, so that long
+    /// orbits count for far more than short ones, or `"per-orbit"`, where each point counts for
+    /// one over the length of its orbit, so that every orbit counts for one in all (summed in 32-bit
+    /// floats held in memory on top of the counters, and not for the weighted samplers or
+    /// `adaptive`)
+    pub weighting: Weighting,
+    /// How the channels of the image are colored: `"limits"`, each by the orbits traced up to its
+    /// own limit, or `"origin"`, red, green, and blue by the hue of the angle of each orbit's
+    /// sample around the middle of the sampling region, which takes a single limit (summed in
+    /// 32-bit floats held in memory on top of the counters, and not for the weighted samplers or
+    /// `adaptive`)
+    pub coloring: Coloring,
+    /// Weights of the accumulated channels in each channel of the output image: an array of 1
+    /// (grayscale) or 3 (red, green, and blue) arrays, each of one weight for each accumulated
+    /// channel, or an empty array to output 1 or 3 accumulated channels as they are
+    pub channel_mixing: Vec<Vec<f64>>,
+    /// Filter that smooths the noise of sparse parts of the image before it is tonemapped:
+    /// `"none"`, `"gaussian"` for a blur, or `"median"` for the median of the 3×3 pixels around
+    /// each pixel
+    pub denoise: Denoise,
+    /// Number of points in an output pixel, across every channel, at which it takes half of its
+    /// filtered value (sparser pixels take more of it, and brighter ones less)
+    pub denoise_strength: f64,
+    /// How the values of the image are scaled before they are tonemapped: `"global"` (or
+    /// `"joint"`) by the brightest value of any channel, which keeps the balance of the channels, or
+    /// `"per-channel"` by the brightest value of each channel, which brings every channel to its
+    /// full range
+    pub normalize: Normalize,
     /// Colour correction curve to apply (value between 0 and 1, raised to this power)
-    pub curve: f64,
+    /// Either a single value, or an array of one value for each channel of the output image
+    pub curve: PerChannel<f64>,
+    /// Bits per channel of the output PNG (8 or 16)
+    pub bit_depth: u8,
+    /// PNG image that the tonemapped image is composited over instead of black, cropped around
+    /// its center to the aspect ratio of the output image, and scaled down to its size (a path
+    /// relative to the configuration file)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    /// How the image is composited over its background: `"normal"` over it, with the brightness
+    /// of each pixel as its opacity, or `"additive"` added to it
+    pub blend: Blend,
+    /// Grayscale PNG written alongside the image and its intermediates, of how noisy each pixel
+    /// still is: the relative standard error of its count, measured from how much the points that
+    /// each pass added to it vary, from black for a pixel that has converged to white for one that
+    /// is as uncertain as its count or took no points yet (a path relative to the configuration
+    /// file)
+    /// Takes another 16 bytes of memory for each pixel of the output image, and two passes or
+    /// more
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub noise_map: Option<String>,
+    /// Bits of the counters that points are accumulated in (32, 64 for extreme renders whose
+    /// brightest pixels would otherwise saturate, or 16 for drafts, which takes half the memory
+    /// and promotes counters to 32 bits where they overflow)
+    pub counter_bits: u8,
+    /// Real part of the point at the center of the rendered image
+    pub center_re: f64,
+    /// Imaginary part of the point at the center of the rendered image
+    pub center_im: f64,
+    /// Magnification of the rendered image (1 shows the complex plane from -2 to 2 on both axes)
+    pub zoom: f64,
+    /// Rotation of the view around its center, in degrees
+    pub rotation: f64,
+    /// Lower bound of the real part of the randomly sampled points
+    pub sample_re_min: f64,
+    /// Upper bound of the real part of the randomly sampled points
+    pub sample_re_max: f64,
+    /// Lower bound of the imaginary part of the randomly sampled points
+    pub sample_im_min: f64,
+    /// Upper bound of the imaginary part of the randomly sampled points
+    pub sample_im_max: f64,
+    /// Region that samples are taken from: `"bounds"` for the sampling bounds, `"disk"` for the
+    /// disk of radius `escape_radius` around the origin, or `"auto"` for the disk when it leaves
+    /// the image unchanged
+    pub sample_domain: SampleDomain,
+    /// How samples are spread over the sampling region: `"uniform"`, `"jittered"` to take one in
+    /// each cell of a grid, `"halton"` to follow a quasi-random sequence, `"poisson"` to keep
+    /// samples a minimum distance apart, `"metropolis"` to wander towards samples whose orbits
+    /// are in view, or `"guided"` to take more samples where a pre-pass found orbits in view
+    pub sampler: SamplerKind,
+    /// Whether samples are drawn in mirrored pairs, the second of each being the conjugate of the
+    /// first (for sampling bounds that are symmetric about the real axis)
+    pub antithetic: bool,
+    /// Whether the samples of successive passes are laid out together, so that those of the first
+    /// few passes fill the sampling region evenly between them: `"always"`, `"never"`, or `"auto"`
+    /// for only when a seed is set
+    pub stratify_passes: Stratify,
+    /// Largest step from one sample to the next of the Metropolis sampler, as a fraction of the
+    /// size of the view
+    pub metropolis_scale: f64,
+    /// Number of uniform samples at the start of each chain of the Metropolis sampler, which pick
+    /// where the chain starts (how bright the view is is measured once, over more samples that
+    /// aren't plotted, at the start of the render)
+    pub metropolis_warmup: u32,
+    /// Number of cells along each axis of the importance map of the guided sampler
+    pub guided_grid: u32,
+    /// Number of uniform samples of the pre-pass that measures the importance map of the guided
+    /// sampler
+    pub guided_samples: u32,
+    /// Whether passes after the first few take more samples from where orbits that feed the
+    /// noisiest parts of the image come from, in place of the `sampler`
+    pub adaptive: bool,
+    /// How strongly adaptive passes follow the noise of the image, from 0 (uniform samples) to 1
+    pub adaptive_strength: f64,
+    /// Distance from the origin past which an orbit is considered to have escaped
+    pub escape_radius: f64,
+    /// Distance from the origin at which iteration stops (at least `escape_radius`)
+    pub stop_radius: f64,
+    /// Seed for the random number generators, for reproducible renders (random if unset)
+    /// Renders are only reproduced exactly when using the same number of threads, unless they are
+    /// `deterministic`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Split the samples of seeded renders into the same tasks whatever the number of threads, so
+    /// that they are reproduced exactly on every machine (only with a `seed`)
+    pub deterministic: bool,
+    /// Number of threads to render with (as many as the machine runs at once if unset)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+    /// Stretch the view to fit the image, instead of extending the imaginary range to keep its aspect ratio
+    pub stretch: bool,
+    /// PNG image stretched over the image, whose gray levels are the share of the points landing
+    /// on each pixel that are counted: none on black, all on white (a path relative to the
+    /// configuration file)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask: Option<String>,
+    /// Real part of the starting point of orbits, in Mandelbrot mode
+    pub z0_re: f64,
+    /// Imaginary part of the starting point of orbits, in Mandelbrot mode
+    pub z0_im: f64,
+    /// Radius around the starting point within which each orbit's start is randomized (0 for none)
+    pub z0_radius: f64,
+    /// Skip samples inside the main cardioid and period-2 bulb without iterating them, as they never
+    /// escape (only when plotting escaped orbits of the standard function from 0)
+    pub skip_interior: bool,
+    /// Give up on orbits once they are found to cycle, as they never escape (only when plotting
+    /// escaped orbits)
+    pub check_periodicity: bool,
+    /// Iterate samples in batches that are advanced in lockstep, so that the arithmetic can be
+    /// vectorized (only for the standard function in double precision, outside of deep zooms)
+    pub batched: bool,
+    /// Whether each thread counts its points in its own copy of the image, added into the image at
+    /// the end of each pass, rather than all of them counting straight into it (only while the
+    /// copies take at most 1 GiB together, and not with 16-bit counters, a sparse image, or a
+    /// memory map)
+    pub local_images: bool,
+    /// Whether only the counters of the image that aren't 0 are held, which takes far less memory
+    /// when few points land in the image, as in deep zooms: `"always"`, `"never"`, or `"auto"`
+    /// when the viewport covers less than 1/1024 of the sampling bounds (only with 32-bit
+    /// counters; a sparse image that comes to take more memory than dense counters is turned into
+    /// them at the end of a pass)
+    pub sparse: Sparse,
+    /// Order that the counters of the image are held in: `"linear"`, row by row like the output
+    /// image, or `"tiled"`, in tiles of 64×64 pixels that each hold the values of one channel
+    /// after those of another, which keeps points that land near each other close in memory (not
+    /// for sparse images; passes of full views are slower tiled, as their orbits hop across the
+    /// image)
+    pub layout: Layout,
+    /// Whether the counters of the image are held in a memory map of a file, which lets the
+    /// system write the parts of the image that aren't in use back to the file rather than run
+    /// out of memory: `"always"`, `"never"`, or `"auto"` for images larger than
+    /// `memory_map_threshold` (only on 64-bit Unix)
+    pub memory_map: MemoryMap,
+    /// Size of the counters of the image, in MiB, above which they are held in a memory map when
+    /// `memory_map` is `"auto"`
+    pub memory_map_threshold: u64,
+    /// Directory that the file of the memory map is created in (removed as soon as it is mapped),
+    /// the system's temporary directory if unset, which may be held in memory itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_map_directory: Option<String>,
+    /// Iterate each sample once for all channels, up to the highest limit, and plot it in each
+    /// channel as it would have been iterated up to that channel's own limit
+    pub share_orbits: bool,
+    /// Also plot the mirror image of every orbit across the real axis, which is the orbit of the
+    /// conjugate sample, doubling the density of samples (only for functions that are symmetric)
+    pub mirror_symmetry: bool,
+    /// Recurrence to iterate: `"mandelbrot"`, `"burning-ship"`, `"tricorn"`, or `"celtic"`
+    pub variant: Variant,
+    /// Orbits to plot: those that `"escaped"`, those that stayed `"trapped"`, or `"all"` of them
+    pub orbit_filter: OrbitFilter,
+    /// Plane of the 4D space of orbits that their points are projected onto: `"z"`, `"c"`, or a
+    /// pair of coordinates out of `z_re`, `z_im`, `c_re`, and `c_im`, such as `"c_re,z_im"`
+    pub projection: Projection,
+    /// Exponent `d` of the iterated function `z^d + c` (greater than 1)
+    pub power: f64,
+    /// Floating-point precision of the iteration: `"f64"`, or the faster `"f32"` for shallow views of
+    /// the standard Mandelbrot function
+    pub precision: Precision,
+    /// Custom iterated function of `z` and `c`, replacing `variant` and `power`, such as `"z^2 + c/z"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formula: Option<Formula>,
+    /// Fractal to render: `"mandelbrot"`, or `{ julia = { c_re = X, c_im = Y } }`
+    pub mode: Mode,
+    /// How often to write intermediate images: `{ passes = N }`, `{ seconds = N }`, or `"none"`
+    pub intermediate_every: IntermediateInterval,
+}
+
+/// Number of bytes of memory for the accumulated image above which a warning is given
+const MEMORY_WARNING: u128 = 8 << 30;
+
+/// Zoom above which a warning about single precision is given
+const F32_ZOOM_WARNING: f64 = 1_000.0;
+
+/// Zoom past which double precision becomes inaccurate, and deep zoom is used
+const DEEP_ZOOM: f64 = 1e12;
+
+/// Image width or height above which a warning is given
+const SIZE_WARNING: u32 = 32_768;
+
+/// Largest number of channels that points can be accumulated in
+pub const MAX_CHANNELS: usize = 16;
+
+/// Largest number of cells along each axis of the importance map of the guided sampler
+const MAX_GUIDED_GRID: u32 = 4_096;
+
+/// Share of the area of the sampling bounds that the viewport covers, below which `sparse =
+/// "auto"` counts points in a sparse image
+const SPARSE_SHARE: f64 = 1.0 / 1_024.0;
+
+/// A problem found by [`RenderSettings::validate`]
+pub enum Problem {
+    /// The render can't be made
+    Error(String),
+    /// The render can be made, but probably isn't what was intended
+    Warning(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Error(message) | Problem::Warning(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// A configuration as written to a file, which can also say where to write the image
+#[derive(Serialize)]
+struct SavedConfig<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<&'a str>,
+    #[serde(flatten)]
+    settings: &'a RenderSettings,
+}
+
+/// A setting that is either shared by every channel, or given separately for each of them
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum PerChannel<T> {
+    /// The same value for every channel
+    Uniform(T),
+    /// One value for each channel
+    Channels(Vec<T>),
+}
+
+impl<T: Copy> PerChannel<T> {
+    /// Get the value for a given `channel`
+    pub fn get(&self, channel: usize) -> T {
+        match self {
+            PerChannel::Uniform(value) => *value,
+            PerChannel::Channels(values) => values[channel],
+        }
+    }
+
+    /// Whether there is a value for each of a number of `channels`, and no more
+    pub fn fits(&self, channels: usize) -> bool {
+        match self {
+            PerChannel::Uniform(_) => true,
+            PerChannel::Channels(values) => values.len() == channels,
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for PerChannel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PerChannel::Uniform(value) => write!(f, "{value}"),
+            PerChannel::Channels(values) => {
+                let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+                write!(f, "{}", values.join(","))
+            }
+        }
+    }
+}
+
+/// How samples are spread over the sampling region
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerKind {
+    /// Independent samples, see [`crate::sampler::UniformSampler`]
+    Uniform,
+    /// One sample in each cell of a grid, see [`crate::jitter_sampler::JitterSampler`]
+    Jittered,
+    /// Quasi-random samples, see [`crate::halton_sampler::HaltonSampler`]
+    Halton,
+    /// Samples kept apart by a minimum distance, see [`crate::poisson_sampler::PoissonSampler`]
+    Poisson,
+    /// Chains of samples drawn towards orbits in view, see [`crate::metropolis`]
+    Metropolis,
+    /// Samples drawn from a map of where orbits in view come from, see [`crate::guided`]
+    Guided,
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SamplerKind::Uniform => write!(f, "uniform"),
+            SamplerKind::Jittered => write!(f, "jittered"),
+            SamplerKind::Halton => write!(f, "halton"),
+            SamplerKind::Poisson => write!(f, "poisson"),
+            SamplerKind::Metropolis => write!(f, "metropolis"),
+            SamplerKind::Guided => write!(f, "guided"),
+        }
+    }
+}
+
+/// Region of the plane that samples are taken from
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleDomain {
+    /// The disk when it fits within the sampling bounds, and the view within the disk
+    Auto,
+    /// The rectangle of the sampling bounds
+    Bounds,
+    /// The disk of radius `escape_radius` around the origin, outside of which orbits of the
+    /// standard function from 0 escape at once, and never come back inside
+    Disk,
+}
+
+impl fmt::Display for SampleDomain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleDomain::Auto => write!(f, "auto"),
+            SampleDomain::Bounds => write!(f, "bounds"),
+            SampleDomain::Disk => write!(f, "disk"),
+        }
+    }
+}
+
+/// How each point of an orbit is counted in the pixels around it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Splat {
+    /// Once in the pixel it lands in
+    Nearest,
+    /// Shared between the four pixels whose centers are around it, in proportion to how close it
+    /// is to each
+    Bilinear,
+    /// Shared between the pixels around it along a Gaussian of `splat_sigma` pixels
+    Gaussian,
+}
+
+impl Splat {
+    /// Whole weight that a point is counted with, across the pixels it is shared between, which
+    /// keeps every share a whole count
+    pub fn scale(self) -> u32 {
+        match self {
+            Splat::Nearest => 1,
+            Splat::Bilinear => splat::STEPS * splat::STEPS,
+            Splat::Gaussian => splat::GAUSSIAN_SCALE,
+        }
+    }
+}
+
+impl fmt::Display for Splat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Splat::Nearest => write!(f, "nearest"),
+            Splat::Bilinear => write!(f, "bilinear"),
+            Splat::Gaussian => write!(f, "gaussian"),
+        }
+    }
+}
+
+/// How much the points of each orbit count for
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Weighting {
+    /// One each
+    Uniform,
+    /// One over the length of their orbit, so that every orbit counts for one in all
+    PerOrbit,
+}
+
+impl fmt::Display for Weighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Weighting::Uniform => write!(f, "uniform"),
+            Weighting::PerOrbit => write!(f, "per-orbit"),
+        }
+    }
+}
+
+/// What the channels of the image are colored by
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Coloring {
+    /// The limit of each channel, up to which its orbits are traced
+    Limits,
+    /// The sample of each orbit, whose angle around the middle of the sampling region picks the
+    /// hue that its points are shared between the red, green, and blue channels with
+    Origin,
+}
+
+impl fmt::Display for Coloring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Coloring::Limits => write!(f, "limits"),
+            Coloring::Origin => write!(f, "origin"),
+        }
+    }
+}
+
+/// Filter that smooths the noise of the image before it is tonemapped
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Denoise {
+    /// The counts as they are
+    None,
+    /// A Gaussian blur of each channel, with a standard deviation of 1 pixel
+    Gaussian,
+    /// The median of each channel over the 3×3 pixels around each pixel
+    Median,
+}
+
+impl fmt::Display for Denoise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denoise::None => write!(f, "none"),
+            Denoise::Gaussian => write!(f, "gaussian"),
+            Denoise::Median => write!(f, "median"),
+        }
+    }
+}
+
+/// What the values of each channel of the image are divided by before they are tonemapped
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalize {
+    /// The largest value of any channel, which can also be written `"joint"`
+    #[serde(alias = "joint")]
+    Global,
+    /// The largest value of the channel
+    PerChannel,
+}
+
+impl fmt::Display for Normalize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Normalize::Global => write!(f, "global"),
+            Normalize::PerChannel => write!(f, "per-channel"),
+        }
+    }
+}
+
+/// How the tonemapped image is composited over its background
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Blend {
+    /// Over the background, with the brightness of each pixel as its opacity, so that bright parts
+    /// cover it and dark parts let it show through
+    Normal,
+    /// Added to the background, clipped to white
+    Additive,
+}
+
+impl fmt::Display for Blend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Blend::Normal => write!(f, "normal"),
+            Blend::Additive => write!(f, "additive"),
+        }
+    }
+}
+
+/// Whether the counters of the image are held in a memory map of a file
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryMap {
+    /// Only for images larger than a threshold
+    Auto,
+    /// For every image
+    Always,
+    /// For no image
+    Never,
+}
+
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryMap::Auto => write!(f, "auto"),
+            MemoryMap::Always => write!(f, "always"),
+            MemoryMap::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// Whether only the counters of the image that aren't 0 are held
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sparse {
+    /// Only when the viewport covers a small share of the sampling bounds
+    Auto,
+    /// For every image
+    Always,
+    /// For no image
+    Never,
+}
+
+impl fmt::Display for Sparse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sparse::Auto => write!(f, "auto"),
+            Sparse::Always => write!(f, "always"),
+            Sparse::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// Order that the counters of the image are held in
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Row by row, with the values of every channel of a pixel side by side
+    Linear,
+    /// In tiles of pixels, with the values of each channel side by side within each tile
+    Tiled,
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layout::Linear => write!(f, "linear"),
+            Layout::Tiled => write!(f, "tiled"),
+        }
+    }
+}
+
+/// Whether the samples of successive passes are laid out together
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stratify {
+    /// Only for seeded renders
+    Auto,
+    /// For every render
+    Always,
+    /// For no render
+    Never,
+}
+
+impl fmt::Display for Stratify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stratify::Auto => write!(f, "auto"),
+            Stratify::Always => write!(f, "always"),
+            Stratify::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// Which orbits are plotted, depending on whether they escaped
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrbitFilter {
+    /// Orbits that escaped, giving the Buddhabrot
+    Escaped,
+    /// Orbits that reached the iteration limit without escaping, giving the anti-Buddhabrot
+    Trapped,
+    /// Every orbit
+    All,
+}
+
+impl fmt::Display for OrbitFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrbitFilter::Escaped => write!(f, "escaped"),
+            OrbitFilter::Trapped => write!(f, "trapped"),
+            OrbitFilter::All => write!(f, "all"),
+        }
+    }
+}
+
+/// Coordinate of the 4D space of orbits, where each point of an orbit also carries its `c`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Coordinate {
+    /// Real part of the point
+    ZRe,
+    /// Imaginary part of the point
+    ZIm,
+    /// Real part of the orbit's `c`
+    CRe,
+    /// Imaginary part of the orbit's `c`
+    CIm,
+}
+
+impl Coordinate {
+    const NAMES: [(&'static str, Coordinate); 4] = [
+        ("z_re", Coordinate::ZRe),
+        ("z_im", Coordinate::ZIm),
+        ("c_re", Coordinate::CRe),
+        ("c_im", Coordinate::CIm),
+    ];
+
+    /// Value of this coordinate for a point `z` of the orbit of `c`
+    fn of(self, z: Complex, c: Complex) -> f64 {
+        match self {
+            Coordinate::ZRe => z.re,
+            Coordinate::ZIm => z.im,
+            Coordinate::CRe => c.re,
+            Coordinate::CIm => c.im,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        Coordinate::NAMES
+            .iter()
+            .find(|(_, coordinate)| *coordinate == self)
+            .map(|(name, _)| *name)
+            .unwrap_or_default()
+    }
+}
+
+/// Plane of the 4D space of orbits that their points are projected onto, given by the coordinates
+/// plotted along the real and imaginary axes of the view
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Projection {
+    /// Coordinate plotted along the real axis
+    pub re: Coordinate,
+    /// Coordinate plotted along the imaginary axis
+    pub im: Coordinate,
+}
+
+impl Projection {
+    /// The points of the orbit themselves
+    pub const Z: Projection = Projection {
+        re: Coordinate::ZRe,
+        im: Coordinate::ZIm,
+    };
+    /// The `c` of the orbit, once for each of its points
+    pub const C: Projection = Projection {
+        re: Coordinate::CRe,
+        im: Coordinate::CIm,
+    };
+
+    /// Projects a point `z` of the orbit of `c`
+    pub fn project(self, z: Complex, c: Complex) -> Complex {
+        Complex {
+            re: self.re.of(z, c),
+            im: self.im.of(z, c),
+        }
+    }
+
+    /// Whether either axis shows a coordinate of `c`
+    pub fn uses_c(self) -> bool {
+        [self.re, self.im]
+            .iter()
+            .any(|axis| matches!(axis, Coordinate::CRe | Coordinate::CIm))
+    }
+}
+
+impl TryFrom<String> for Projection {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let coordinate = |name: &str| {
+            Coordinate::NAMES
+                .iter()
+                .find(|(known, _)| *known == name.trim())
+                .map(|(_, coordinate)| *coordinate)
+        };
+        match text.as_str() {
+            "z" => Ok(Projection::Z),
+            "c" => Ok(Projection::C),
+            _ => text
+                .split_once(',')
+                .and_then(|(re, im)| {
+                    Some(Projection {
+                        re: coordinate(re)?,
+                        im: coordinate(im)?,
+                    })
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "invalid projection `{text}`, expected `z`, `c`, or two of `z_re`, `z_im`, `c_re`, and `c_im` separated by a comma"
+                    )
+                }),
+        }
+    }
+}
+
+impl From<Projection> for String {
+    fn from(projection: Projection) -> Self {
+        projection.to_string()
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Projection::Z => write!(f, "z"),
+            Projection::C => write!(f, "c"),
+            Projection { re, im } => write!(f, "{},{}", re.name(), im.name()),
+        }
+    }
+}
+
+/// Floating-point precision of the iteration
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    /// Single precision, enough for views of the whole set
+    F32,
+    /// Double precision, switching to `Deep` past zooms where it becomes inaccurate
+    F64,
+    /// Orbits followed as differences from a double-double precision orbit of the center
+    Deep,
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Precision::F32 => write!(f, "f32"),
+            Precision::F64 => write!(f, "f64"),
+            Precision::Deep => write!(f, "deep"),
+        }
+    }
+}
+
+/// Fractal whose orbits are plotted
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(try_from = "ModeRepr", into = "ModeRepr")]
+pub enum Mode {
+    /// Orbits of 0 for random values of `c`, taken from the sampling region
+    Mandelbrot,
+    /// Orbits of random starting points, taken from the sampling region, for a fixed `c`
+    Julia {
+        /// Real part of `c`
+        c_re: f64,
+        /// Imaginary part of `c`
+        c_im: f64,
+    },
+}
+
+/// Representation of a [`Mode`] in configuration files
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum ModeRepr {
+    Julia { julia: JuliaRepr },
+    Name(String),
+}
+
+/// Representation of the parameters of [`Mode::Julia`] in configuration files
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct JuliaRepr {
+    c_re: f64,
+    c_im: f64,
+}
+
+impl TryFrom<ModeRepr> for Mode {
+    type Error = String;
+
+    fn try_from(repr: ModeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ModeRepr::Julia {
+                julia: JuliaRepr { c_re, c_im },
+            } => Ok(Mode::Julia { c_re, c_im }),
+            ModeRepr::Name(name) if name == "mandelbrot" => Ok(Mode::Mandelbrot),
+            ModeRepr::Name(name) => Err(format!(
+                "invalid mode `{name}`, expected `mandelbrot` or `{{ julia = {{ c_re = X, c_im = Y }} }}`"
+            )),
+        }
+    }
+}
+
+impl From<Mode> for ModeRepr {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Mandelbrot => ModeRepr::Name(String::from("mandelbrot")),
+            Mode::Julia { c_re, c_im } => ModeRepr::Julia {
+                julia: JuliaRepr { c_re, c_im },
+            },
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Mandelbrot => write!(f, "Mandelbrot"),
+            Mode::Julia { c_re, c_im } => write!(f, "Julia, c = {c_re}{c_im:+}i"),
+        }
+    }
+}
+
+/// Representation of the number of passes in configuration files
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PassesRepr {
+    Number(u32),
+    Text(String),
+}
+
+/// Reads a number of passes, where `"infinite"` stands for 0
+fn deserialize_passes<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    match PassesRepr::deserialize(deserializer)? {
+        PassesRepr::Number(passes) => Ok(passes),
+        PassesRepr::Text(text) if text == "infinite" => Ok(0),
+        PassesRepr::Text(text) => Err(serde::de::Error::custom(format!(
+            "invalid number of passes `{text}`, expected a number or `infinite`"
+        ))),
+    }
+}
+
+/// How often intermediate images are written, checked at the end of each pass
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "IntervalRepr", into = "IntervalRepr")]
+pub enum IntermediateInterval {
+    /// After every given number of passes
+    Passes(u32),
+    /// After the first pass that ends at least this many seconds after the previous intermediate
+    Seconds(u64),
+    /// Never write intermediates
+    Never,
+}
+
+/// Representation of an [`IntermediateInterval`] in configuration files
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum IntervalRepr {
+    Passes { passes: u32 },
+    Seconds { seconds: u64 },
+    Number(u64),
+    Text(String),
+}
+
+impl TryFrom<IntervalRepr> for IntermediateInterval {
+    type Error = String;
+
+    fn try_from(repr: IntervalRepr) -> Result<Self, Self::Error> {
+        match repr {
+            IntervalRepr::Passes { passes: 0 } | IntervalRepr::Number(0) => {
+                Ok(IntermediateInterval::Never)
+            }
+            IntervalRepr::Passes { passes } => Ok(IntermediateInterval::Passes(passes)),
+            IntervalRepr::Seconds { seconds } => Ok(IntermediateInterval::Seconds(seconds)),
+            IntervalRepr::Number(_) => Err(String::from(
+                "a bare number is ambiguous, use `{ passes = N }` or `{ seconds = N }`",
+            )),
+            IntervalRepr::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl From<IntermediateInterval> for IntervalRepr {
+    fn from(interval: IntermediateInterval) -> Self {
+        match interval {
+            IntermediateInterval::Passes(passes) => IntervalRepr::Passes { passes },
+            IntermediateInterval::Seconds(seconds) => IntervalRepr::Seconds { seconds },
+            IntermediateInterval::Never => IntervalRepr::Text(String::from("none")),
+        }
+    }
+}
+
+impl std::str::FromStr for IntermediateInterval {
+    type Err = String;
+
+    /// Parses `N` as a number of passes, `Ns` as a number of seconds, and 0 passes (however it is
+    /// written, such as `00`) or `none` as never
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!("invalid interval `{text}`, expected a number of passes (`5`), of seconds (`120s`), or `none`")
+        };
+        match text.trim() {
+            "none" => Ok(IntermediateInterval::Never),
+            text => match text.strip_suffix('s') {
+                Some(seconds) => Ok(IntermediateInterval::Seconds(
+                    seconds.parse().map_err(|_| invalid())?,
+                )),
+                None => match text.parse().map_err(|_| invalid())? {
+                    0 => Ok(IntermediateInterval::Never),
+                    passes => Ok(IntermediateInterval::Passes(passes)),
+                },
+            },
+        }
+    }
+}
+
+impl fmt::Display for IntermediateInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntermediateInterval::Passes(1) => write!(f, "every pass"),
+            IntermediateInterval::Passes(passes) => write!(f, "every {passes} passes"),
+            IntermediateInterval::Seconds(seconds) => write!(f, "every {seconds}s"),
+            IntermediateInterval::Never => write!(f, "never"),
+        }
+    }
 }
 
 /// Default settings (Equivalent to selecting the default values in the configuration wizard)
-pub const DEFAULT_RENDER_SETTINGS: RenderSettings = RenderSettings {
-    limits: [7_740, 2_580, 860],
-    size: 1 << 11,
-    samples: 1_000_000,
-    passes: 100,
-    curve: 0.5,
-};
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            version: CONFIG_VERSION,
+            limits: vec![7_740, 2_580, 860],
+            auto_limits: None,
+            bands: None,
+            separate_bands: false,
+            min_iterations: PerChannel::Uniform(0),
+            skip_iterations: PerChannel::Uniform(0),
+            width: 1 << 11,
+            height: 1 << 11,
+            samples: PerChannel::Uniform(1_000_000),
+            passes: 100,
+            supersample: 1,
+            splat: Splat::Nearest,
+            splat_sigma: 0.5,
+            weighting: Weighting::Uniform,
+            coloring: Coloring::Limits,
+            channel_mixing: Vec::new(),
+            denoise: Denoise::None,
+            denoise_strength: 64.0,
+            normalize: Normalize::Global,
+            curve: PerChannel::Uniform(0.5),
+            bit_depth: 8,
+            background: None,
+            blend: Blend::Normal,
+            noise_map: None,
+            counter_bits: 32,
+            center_re: 0.0,
+            center_im: 0.0,
+            zoom: 1.0,
+            rotation: 0.0,
+            sample_re_min: -2.5,
+            sample_re_max: 2.5,
+            sample_im_min: -2.5,
+            sample_im_max: 2.5,
+            sample_domain: SampleDomain::Auto,
+            sampler: SamplerKind::Uniform,
+            antithetic: false,
+            stratify_passes: Stratify::Auto,
+            metropolis_scale: 0.1,
+            metropolis_warmup: 10_000,
+            guided_grid: 64,
+            guided_samples: 1_000_000,
+            adaptive: false,
+            adaptive_strength: 0.5,
+            escape_radius: 2.0,
+            stop_radius: 3.0,
+            seed: None,
+            deterministic: false,
+            threads: None,
+            stretch: false,
+            mask: None,
+            z0_re: 0.0,
+            z0_im: 0.0,
+            z0_radius: 0.0,
+            skip_interior: true,
+            check_periodicity: true,
+            batched: false,
+            local_images: true,
+            sparse: Sparse::Auto,
+            layout: Layout::Linear,
+            memory_map: MemoryMap::Auto,
+            memory_map_threshold: 4_096,
+            memory_map_directory: None,
+            share_orbits: true,
+            mirror_symmetry: false,
+            variant: Variant::Mandelbrot,
+            orbit_filter: OrbitFilter::Escaped,
+            projection: Projection::Z,
+            power: 2.0,
+            precision: Precision::F64,
+            formula: None,
+            mode: Mode::Mandelbrot,
+            intermediate_every: IntermediateInterval::Seconds(60),
+        }
+    }
+}
+
+/// A named point of interest on the complex plane, which can be referred to with the `location` key
+pub struct Location {
+    /// Name to use for the `location` key
+    pub name: &'static str,
+    /// Short description of what can be seen there
+    pub description: &'static str,
+    /// Real part of the center of the view
+    pub center_re: f64,
+    /// Imaginary part of the center of the view
+    pub center_im: f64,
+    /// Magnification of the view
+    pub zoom: f64,
+}
+
+/// Built-in catalog of [`Location`]s
+pub const LOCATIONS: [Location; 7] = [
+    Location {
+        name: "whole-set",
+        description: "The entire Mandelbrot set",
+        center_re: 0.0,
+        center_im: 0.0,
+        zoom: 1.0,
+    },
+    Location {
+        name: "seahorse-valley",
+        description: "Between the main cardioid and the period-2 bulb",
+        center_re: -0.75,
+        center_im: 0.1,
+        zoom: 10.0,
+    },
+    Location {
+        name: "elephant-valley",
+        description: "At the cusp of the main cardioid",
+        center_re: 0.275,
+        center_im: 0.0,
+        zoom: 10.0,
+    },
+    Location {
+        name: "triple-spiral-valley",
+        description: "Between the main cardioid and the period-3 bulb",
+        center_re: -0.088,
+        center_im: 0.654,
+        zoom: 20.0,
+    },
+    Location {
+        name: "scepter-valley",
+        description: "Between the period-2 and period-4 bulbs",
+        center_re: -1.36,
+        center_im: 0.005,
+        zoom: 20.0,
+    },
+    Location {
+        name: "spike-minibrot",
+        description: "The largest mini-brot on the spike",
+        center_re: -1.7548,
+        center_im: 0.0,
+        zoom: 50.0,
+    },
+    Location {
+        name: "north-bulb",
+        description: "The period-3 bulb on top of the main cardioid",
+        center_re: -0.122,
+        center_im: 0.745,
+        zoom: 8.0,
+    },
+];
+
+impl Location {
+    /// Finds a [`Location`] by name in the built-in catalog
+    pub fn find(name: &str) -> Result<&'static Location, NebulaeError> {
+        LOCATIONS
+            .iter()
+            .find(|location| location.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = LOCATIONS.iter().map(|location| location.name).collect();
+                NebulaeError::Invalid(format!(
+                    "Unknown location `{name}`, expected one of: {}",
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let view = format!("{}{:+}i ×{}", self.center_re, self.center_im, self.zoom);
+        write!(f, "{:<24}{:<24}{}", self.name, view, self.description)
+    }
+}
+
+/// A way of setting the escape limits of each channel, which can be referred to by name with the
+/// `palette` key
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Palette {
+    /// Index of the intensity multiplier used by each of the red, green, and blue channels
+    pub channels: [usize; 3],
+    /// Intensity multipliers, before being assigned to channels
+    pub intensity: [u32; 3],
+    /// Multiplier applied to every channel
+    pub definition: u32,
+}
+
+/// Built-in [`Palette`]s, with their names
+pub const PALETTES: [(&str, Palette); 4] = [
+    (
+        "nebulous",
+        Palette {
+            channels: [2, 1, 0],
+            intensity: [215, 645, 1_935],
+            definition: 4,
+        },
+    ),
+    (
+        "blue-ish",
+        Palette {
+            channels: [0, 1, 2],
+            intensity: [215, 645, 1_935],
+            definition: 4,
+        },
+    ),
+    (
+        "cyber-pink",
+        Palette {
+            channels: [2, 0, 1],
+            intensity: [215, 645, 1_935],
+            definition: 4,
+        },
+    ),
+    (
+        "cyber-purple",
+        Palette {
+            channels: [1, 0, 2],
+            intensity: [215, 645, 1_935],
+            definition: 4,
+        },
+    ),
+];
+
+impl Palette {
+    /// Gives the escape limits of each channel
+    pub fn limits(&self) -> Vec<u32> {
+        self.channels
+            .iter()
+            .map(|&channel| self.intensity[channel].saturating_mul(self.definition))
+            .collect()
+    }
+}
+
+/// Named [`Palette`]s that can be used, starting with the built-in ones
+pub struct PaletteRegistry {
+    palettes: Vec<(String, Palette)>,
+}
+
+impl PaletteRegistry {
+    /// Adds a [`Palette`], replacing any other with the same name
+    pub fn add(&mut self, name: &str, palette: Palette) -> Result<(), NebulaeError> {
+        if palette
+            .channels
+            .iter()
+            .any(|&channel| channel >= palette.intensity.len())
+        {
+            return Err(NebulaeError::Invalid(format!(
+                "`channels` of palette `{name}` must be indices less than {}",
+                palette.intensity.len()
+            )));
+        }
+        match self.palettes.iter_mut().find(|(known, _)| known == name) {
+            Some((_, known)) => *known = palette,
+            None => self.palettes.push((name.to_string(), palette)),
+        }
+        Ok(())
+    }
+
+    /// Finds a [`Palette`] by name
+    pub fn find(&self, name: &str) -> Result<&Palette, NebulaeError> {
+        self.iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, palette)| palette)
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.iter().map(|(name, _)| name).collect();
+                NebulaeError::Invalid(format!(
+                    "Unknown palette `{name}`, expected one of: {}",
+                    names.join(", ")
+                ))
+            })
+    }
+
+    /// Iterates over the names and [`Palette`]s, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Palette)> {
+        self.palettes
+            .iter()
+            .map(|(name, palette)| (name.as_str(), palette))
+    }
+}
+
+impl Default for PaletteRegistry {
+    fn default() -> Self {
+        PaletteRegistry {
+            palettes: PALETTES
+                .iter()
+                .map(|(name, palette)| (name.to_string(), *palette))
+                .collect(),
+        }
+    }
+}
 
 impl fmt::Display for RenderSettings {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
+        // Orbits
+        match &self.bands {
+            Some(bands) => writeln!(
+                f,
+                "Escape limits:\t{} (bands)",
+                PerChannel::Channels(bands.clone())
+            )?,
+            None => writeln!(
+                f,
+                "Escape limits:\t{}",
+                PerChannel::Channels(self.limits.clone())
+            )?,
+        }
+        writeln!(f, "Min. orbits:\t{}", self.min_iterations)?;
+        writeln!(f, "Skipped:\t{}", self.skip_iterations)?;
+        writeln!(f, "Runs per pass:\t{}", self.samples)?;
+        match self.passes {
+            0 => writeln!(f, "Passes:\t\tuntil interrupted")?,
+            passes => writeln!(f, "Passes:\t\t{passes}")?,
+        }
+
+        // Image
+        let splat = match self.splat {
+            Splat::Gaussian => format!("gaussian (σ {})", self.splat_sigma),
+            splat => splat.to_string(),
+        };
+        writeln!(
+            f,
+            "Resolution:\t{}x{} (x{} supersampling, {splat} splats)",
+            self.width, self.height, self.supersample
+        )?;
+        writeln!(f, "Weighting:\t{}", self.weighting)?;
+        let output = match (self.output_channels(), self.channel_mixing.is_empty()) {
+            (1, true) => "grayscale",
+            (1, false) => "mixed to grayscale",
+            (_, true) => "RGB",
+            (_, false) => "mixed to RGB",
+        };
+        writeln!(
+            f,
+            "Channels:\t{} ({output}, colored by {})",
+            self.image_channels(),
+            self.coloring
+        )?;
+        writeln!(
+            f,
+            "Denoise:\t{} (strength {})",
+            self.denoise, self.denoise_strength
+        )?;
+        writeln!(f, "Normalize:\t{}", self.normalize)?;
+        writeln!(f, "Correction:\t{}", self.curve)?;
+        writeln!(
+            f,
+            "Bit depth:\t{} ({}-bit counters)",
+            self.bit_depth, self.counter_bits
+        )?;
+        writeln!(
+            f,
+            "Background:\t{} ({})",
+            self.background.as_deref().unwrap_or("none"),
+            self.blend
+        )?;
+        writeln!(
+            f,
+            "Noise map:\t{}",
+            self.noise_map.as_deref().unwrap_or("none")
+        )?;
+
+        // View and sampling
+        writeln!(f, "Center:\t\t{}{:+}i", self.center_re, self.center_im)?;
+        writeln!(f, "Zoom:\t\t{}", self.zoom)?;
+        writeln!(f, "Rotation:\t{}°", self.rotation)?;
+        writeln!(
             f,
-            "Escape limits:\t{},{},{}\nRuns per pass:\t{}\nPasses:\t\t{}\nResolution:\t{}x{}\nCorrection\t{}",
-            self.limits[0],
-            self.limits[1],
-            self.limits[2],
-            self.samples,
-            self.passes,
-            self.size,
-            self.size,
-            self.curve,
-        )
+            "Sampling:\t[{}, {}]x[{}, {}]i ({}, {})",
+            self.sample_re_min,
+            self.sample_re_max,
+            self.sample_im_min,
+            self.sample_im_max,
+            self.sample_domain,
+            self.sampler
+        )?;
+        writeln!(f, "Antithetic:\t{}", self.antithetic)?;
+        writeln!(
+            f,
+            "Stratified:\t{} ({})",
+            self.stratifies_passes(),
+            self.stratify_passes
+        )?;
+        writeln!(
+            f,
+            "Metropolis:\t{} (scale), {} (warm-up)",
+            self.metropolis_scale, self.metropolis_warmup
+        )?;
+        writeln!(
+            f,
+            "Guided:\t\t{} (grid), {} (pre-pass)",
+            self.guided_grid, self.guided_samples
+        )?;
+        writeln!(
+            f,
+            "Adaptive:\t{} (strength {})",
+            self.adaptive, self.adaptive_strength
+        )?;
+        writeln!(
+            f,
+            "Radii:\t\t{} (escape), {} (stop)",
+            self.escape_radius, self.stop_radius
+        )?;
+        match self.seed {
+            Some(seed) => writeln!(f, "Seed:\t\t{seed}")?,
+            None => writeln!(f, "Seed:\t\trandom")?,
+        }
+        writeln!(f, "Deterministic:\t{}", self.deterministic)?;
+        match self.threads {
+            Some(threads) => writeln!(f, "Threads:\t{threads}")?,
+            None => writeln!(f, "Threads:\tall")?,
+        }
+
+        // Iteration and memory
+        writeln!(
+            f,
+            "Start:\t\t{}{:+}i (±{})",
+            self.z0_re, self.z0_im, self.z0_radius
+        )?;
+        writeln!(f, "Skip interior:\t{}", self.skip_interior)?;
+        writeln!(f, "Periodicity:\t{}", self.check_periodicity)?;
+        writeln!(f, "Batched:\t{}", self.batched)?;
+        writeln!(f, "Local images:\t{}", self.local_images)?;
+        writeln!(
+            f,
+            "Sparse image:\t{} ({})",
+            self.sparse,
+            if self.counts_sparsely() {
+                "sparse"
+            } else {
+                "dense"
+            }
+        )?;
+        writeln!(f, "Layout:\t\t{}", self.layout)?;
+        writeln!(
+            f,
+            "Memory map:\t{} (above {} MiB, in {})",
+            self.memory_map,
+            self.memory_map_threshold,
+            self.map_directory().display()
+        )?;
+        writeln!(f, "Share orbits:\t{}", self.share_orbits)?;
+        writeln!(f, "Mirror:\t\t{}", self.mirror_symmetry)?;
+
+        // Fractal
+        writeln!(f, "Variant:\t{}", self.variant)?;
+        writeln!(f, "Orbits:\t\t{}", self.orbit_filter)?;
+        writeln!(f, "Projection:\t{}", self.projection)?;
+        writeln!(f, "Power:\t\t{}", self.power)?;
+        writeln!(f, "Precision:\t{}", self.precision)?;
+        match &self.formula {
+            Some(formula) => writeln!(f, "Formula:\t{formula}")?,
+            None => writeln!(f, "Formula:\tnone")?,
+        }
+        writeln!(f, "Mode:\t\t{}", self.mode)?;
+        // The last line has no line break, so that the settings can be printed like any value
+        write!(f, "Intermediates:\t{}", self.intermediate_every)
     }
 }
 
 impl RenderSettings {
-    /// Serializes and writes the configuration in TOML format to a file
-    pub fn to_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        fs::write(path, self.serialize()?)?;
+    /// Checks that the settings describe a render that can actually be made, listing every
+    /// problem found
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        // Every other check reads the values of each channel, which must all be there first
+        let channels = self.channels() as usize;
+        let name = match self.bands {
+            Some(_) => "bands",
+            None => "limits",
+        };
+        if !(1..=MAX_CHANNELS).contains(&channels) {
+            problems.push(Problem::Error(format!(
+                "`{name}` ({channels} values) must have from 1 to {MAX_CHANNELS} values, one for each channel"
+            )));
+        }
+        for (name, fits) in [
+            ("min_iterations", self.min_iterations.fits(channels)),
+            ("skip_iterations", self.skip_iterations.fits(channels)),
+            ("samples", self.samples.fits(channels)),
+        ] {
+            if !fits {
+                problems.push(Problem::Error(format!(
+                    "`{name}` must be a single value, or an array of {channels} values, one for each channel"
+                )));
+            }
+        }
+        if self.coloring == Coloring::Origin && self.bands.is_some() {
+            problems.push(Problem::Error(String::from(
+                "`coloring = \"origin\"` colors orbits by their sample rather than by their escape time, so it can't take `bands`",
+            )));
+        } else if self.coloring == Coloring::Origin && channels != 1 {
+            problems.push(Problem::Error(format!(
+                "`coloring = \"origin\"` colors orbits by their sample rather than by their limit, so `limits` ({channels} values) must have a single value"
+            )));
+        }
+        // Channels colored by their origin are mixed like any others
+        let mixed = self.image_channels() as usize;
+        if self.separates_bands() {
+            // Each band is written on its own
+        } else if self.channel_mixing.is_empty() {
+            if (1..=MAX_CHANNELS).contains(&mixed) && ![1, 3].contains(&mixed) {
+                problems.push(Problem::Error(format!(
+                    "`channel_mixing` must map the {mixed} channels to 1 (grayscale) or 3 (red, green, and blue) channels of the output image"
+                )));
+            }
+        } else {
+            if ![1, 3].contains(&self.channel_mixing.len()) {
+                problems.push(Problem::Error(format!(
+                    "`channel_mixing` ({} arrays) must have 1 array for a grayscale image, or 3 for the red, green, and blue channels",
+                    self.channel_mixing.len()
+                )));
+            }
+            if self
+                .channel_mixing
+                .iter()
+                .any(|weights| weights.len() != mixed)
+            {
+                problems.push(Problem::Error(format!(
+                    "Each array of `channel_mixing` must have {mixed} weights, one for each channel"
+                )));
+            }
+        }
+        let outputs = self.output_channels() as usize;
+        if !self.curve.fits(outputs) {
+            problems.push(Problem::Error(format!(
+                "`curve` must be a single value, or an array of {outputs} values, one for each channel of the output image"
+            )));
+        }
+        // The image and its view are measured by checks below, such as those of its memory, which
+        // would only give meaningless numbers for an empty image or a view that isn't there
+        if self.width == 0 || self.height == 0 {
+            problems.push(Problem::Error(format!(
+                "`width` and `height` ({}x{}) must be greater than 0",
+                self.width, self.height
+            )));
+        }
+        if !(self.zoom.is_finite() && self.zoom > 0.0) {
+            problems.push(Problem::Error(format!(
+                "`zoom` ({}) must be greater than 0",
+                self.zoom
+            )));
+        }
+        for (name, value) in [
+            ("center_re", self.center_re),
+            ("center_im", self.center_im),
+            ("rotation", self.rotation),
+        ] {
+            if !value.is_finite() {
+                problems.push(Problem::Error(format!(
+                    "`{name}` ({value}) must be a finite number"
+                )));
+            }
+        }
+        if !problems.is_empty() {
+            return problems;
+        }
+        let mut error = |message: String| problems.push(Problem::Error(message));
+        if (0..channels).all(|channel| self.samples.get(channel) == 0) {
+            error(String::from("`samples` must be greater than 0"));
+        }
+        if self.sample_re_min >= self.sample_re_max {
+            error(format!(
+                "`sample_re_min` ({}) must be less than `sample_re_max` ({})",
+                self.sample_re_min, self.sample_re_max
+            ));
+        }
+        if self.sample_im_min >= self.sample_im_max {
+            error(format!(
+                "`sample_im_min` ({}) must be less than `sample_im_max` ({})",
+                self.sample_im_min, self.sample_im_max
+            ));
+        }
+        if ![1, 2, 4].contains(&self.supersample) {
+            error(format!(
+                "`supersample` ({}) must be 1, 2, or 4",
+                self.supersample
+            ));
+        }
+        if self.denoise != Denoise::None
+            && !(self.denoise_strength.is_finite() && self.denoise_strength > 0.0)
+        {
+            error(format!(
+                "`denoise_strength` ({}) must be greater than 0",
+                self.denoise_strength
+            ));
+        }
+        if ![8, 16].contains(&self.bit_depth) {
+            error(format!("`bit_depth` ({}) must be 8 or 16", self.bit_depth));
+        }
+        if ![16, 32, 64].contains(&self.counter_bits) {
+            error(format!(
+                "`counter_bits` ({}) must be 16, 32, or 64",
+                self.counter_bits
+            ));
+        }
+        let curves = match &self.curve {
+            PerChannel::Uniform(curve) => vec![(String::new(), *curve)],
+            PerChannel::Channels(curves) => curves
+                .iter()
+                .enumerate()
+                .map(|(channel, &curve)| (format!(" for channel {channel}"), curve))
+                .collect(),
+        };
+        for (channel, curve) in curves {
+            if curve.is_nan() || curve <= 0.0 {
+                error(format!("`curve`{channel} ({curve}) must be greater than 0"));
+            }
+        }
+        for (output, weights) in self.channel_mixing.iter().enumerate() {
+            if weights
+                .iter()
+                .any(|weight| !(weight.is_finite() && *weight >= 0.0))
+            {
+                error(format!(
+                    "`channel_mixing` weights for output channel {output} must be at least 0"
+                ));
+            } else if weights.iter().all(|&weight| weight == 0.0) {
+                error(format!(
+                    "`channel_mixing` for output channel {output} must have a weight greater than 0"
+                ));
+            }
+        }
+        if let Some(bands) = &self.bands {
+            if bands[0] == 0 {
+                error(String::from("`bands` must be greater than 0"));
+            }
+            if let Some(pair) = bands.windows(2).find(|pair| pair[0] >= pair[1]) {
+                error(format!(
+                    "`bands` must be in increasing order, but {} comes before {}",
+                    pair[0], pair[1]
+                ));
+            }
+        }
+        for channel in 0..channels {
+            let limit = self.limit(channel as u32);
+            // Bands are checked above
+            if limit == 0 && self.bands.is_none() {
+                error(format!(
+                    "`limits` for channel {channel} must be greater than 0"
+                ));
+            }
+            if limit != 0 && self.min_iterations.get(channel) >= limit {
+                error(format!(
+                    "`min_iterations` for channel {channel} ({}) must be less than its limit ({limit})",
+                    self.min_iterations.get(channel),
+                ));
+            }
+            if limit != 0 && self.skip_iterations.get(channel) >= limit {
+                error(format!(
+                    "`skip_iterations` for channel {channel} ({}) must be less than its limit ({limit})",
+                    self.skip_iterations.get(channel),
+                ));
+            }
+        }
+        if self.escape_radius.is_nan() || self.escape_radius <= 0.0 {
+            error(format!(
+                "`escape_radius` ({}) must be greater than 0",
+                self.escape_radius
+            ));
+        }
+        if self.stop_radius < self.escape_radius {
+            error(format!(
+                "`stop_radius` ({}) must be at least `escape_radius` ({})",
+                self.stop_radius, self.escape_radius
+            ));
+        }
+
+        // The accumulated image is held in memory as a 32-bit counter per channel, and the noise
+        // map alongside it
+        let image_memory = u128::from(self.width)
+            * u128::from(self.height)
+            * u128::from(self.supersample).pow(2)
+            * channels as u128
+            * 4;
+        let (memory, with_noise) = match self.noise_map {
+            Some(_) => (
+                image_memory + NoiseMap::bytes(self.width, self.height),
+                " and its noise map",
+            ),
+            None => (image_memory, ""),
+        };
+        if memory > isize::MAX as u128 {
+            problems.push(Problem::Error(format!(
+                "A {}x{} image with {}x supersampling{with_noise} needs more memory than can be addressed",
+                self.width, self.height, self.supersample
+            )));
+        } else if memory > MEMORY_WARNING {
+            problems.push(Problem::Warning(format!(
+                "A {}x{} image with {}x supersampling{with_noise} needs {} GiB of memory",
+                self.width,
+                self.height,
+                self.supersample,
+                memory >> 30
+            )));
+        }
+        if self.power.is_nan() || self.power <= 1.0 {
+            problems.push(Problem::Error(format!(
+                "`power` ({}) must be greater than 1",
+                self.power
+            )));
+        } else {
+            // Past this distance from the origin, orbits of `z^d + c` are sure to escape (for |c| no larger)
+            let escape_bound = 2f64.powf(1.0 / (self.power - 1.0));
+            if self.escape_radius < escape_bound {
+                problems.push(Problem::Warning(format!(
+                    "`escape_radius` ({}) is less than {escape_bound:.3}, so some orbits of power {} may be plotted without escaping",
+                    self.escape_radius, self.power
+                )));
+            }
+        }
+        if self.z0_radius.is_nan() || self.z0_radius < 0.0 {
+            problems.push(Problem::Error(format!(
+                "`z0_radius` ({}) must not be negative",
+                self.z0_radius
+            )));
+        }
+        if self.threads == Some(0) {
+            problems.push(Problem::Error(String::from("`threads` must be at least 1")));
+        }
+        if self.deterministic && self.seed.is_none() {
+            problems.push(Problem::Warning(String::from(
+                "`deterministic` only applies to seeded renders, the render isn't reproducible without a `seed`",
+            )));
+        }
+        if self.precision != Precision::F64 && !self.standard_function() {
+            problems.push(Problem::Warning(format!(
+                "`precision = \"{}\"` only applies to the standard Mandelbrot function, the render uses f64",
+                self.precision
+            )));
+        } else if self.precision == Precision::F32 && self.zoom > F32_ZOOM_WARNING {
+            problems.push(Problem::Warning(format!(
+                "`precision = \"f32\"` is inaccurate at zooms past {F32_ZOOM_WARNING}, the render may look blocky"
+            )));
+        } else if self.zoom > DEEP_ZOOM && !self.deep_zoom() {
+            problems.push(Problem::Warning(format!(
+                "f64 is inaccurate at zooms past {DEEP_ZOOM:e}, which only the standard Mandelbrot function can go beyond, the render may look blocky"
+            )));
+        }
+        if self.precision == Precision::Deep && self.mode != Mode::Mandelbrot {
+            problems.push(Problem::Warning(String::from(
+                "`precision = \"deep\"` only applies in Mandelbrot mode, the render uses f64",
+            )));
+        }
+        if self.formula.is_some() && (self.variant != Variant::Mandelbrot || self.power != 2.0) {
+            problems.push(Problem::Warning(String::from(
+                "`variant` and `power` are ignored when a `formula` is set",
+            )));
+        }
+        if !(0.25..=2.0).contains(&self.splat_sigma) {
+            problems.push(Problem::Error(format!(
+                "`splat_sigma` ({}) must be from 0.25 to 2 pixels",
+                self.splat_sigma
+            )));
+        } else if self.splat_sigma != 0.5 && self.splat != Splat::Gaussian {
+            problems.push(Problem::Warning(String::from(
+                "`splat_sigma` only applies with `splat = \"gaussian\"`",
+            )));
+        }
+        if self.blend != Blend::Normal && self.background.is_none() {
+            problems.push(Problem::Warning(String::from(
+                "`blend` only applies with a `background`, the image is written over black",
+            )));
+        }
+        if self.noise_map.is_some() && self.passes == 1 {
+            problems.push(Problem::Warning(String::from(
+                "The noise map measures how passes vary, so a render of 1 pass writes none",
+            )));
+        }
+        if self.orbit_filter != OrbitFilter::Escaped {
+            problems.push(Problem::Warning(format!(
+                "Trapped orbits are as long as their channel's limit (up to {}), so each one is much slower to plot than an escaped one",
+                self.highest_limit()
+            )));
+        }
+        if self.projection.re == self.projection.im {
+            problems.push(Problem::Warning(format!(
+                "The projection shows `{}` along both axes, which only plots a diagonal line",
+                self.projection.re.name()
+            )));
+        }
+        if self.projection.uses_c() && self.mode != Mode::Mandelbrot {
+            problems.push(Problem::Warning(String::from(
+                "The projection shows `c`, which is the same for every orbit in Julia mode",
+            )));
+        }
+        if self.mirror_symmetry {
+            let asymmetry = if self.formula.is_some() {
+                Some(String::from("a `formula`"))
+            } else if self.variant == Variant::BurningShip {
+                Some(format!("the {} variant", self.variant))
+            } else {
+                match self.mode {
+                    Mode::Julia { c_im, .. } if c_im != 0.0 => {
+                        Some(String::from("a Julia set for a `c` that isn't real"))
+                    }
+                    Mode::Mandelbrot if self.z0_im != 0.0 => {
+                        Some(String::from("orbits starting off the real axis"))
+                    }
+                    _ => None,
+                }
+            };
+            if let Some(asymmetry) = asymmetry {
+                problems.push(Problem::Error(format!(
+                    "`mirror_symmetry` can't be used with {asymmetry}, which isn't symmetric about the real axis"
+                )));
+            } else if self.deep_zoom() {
+                problems.push(Problem::Warning(String::from(
+                    "`mirror_symmetry` doesn't apply to deep zooms, whose mirror image is out of view",
+                )));
+            } else if self.sample_im_min != -self.sample_im_max && !self.samples_disk() {
+                problems.push(Problem::Warning(format!(
+                    "The sampling region [{}, {}]i isn't symmetric about the real axis, so mirrored orbits come from samples outside of it",
+                    self.sample_im_min, self.sample_im_max
+                )));
+            }
+        }
+        if self.sample_domain == SampleDomain::Disk {
+            if self.deep_zoom() {
+                problems.push(Problem::Error(String::from(
+                    "Deep zooms can't take samples from the disk, as they are taken relative to the sampling bounds",
+                )));
+            } else if !self.leaves_disk() {
+                problems.push(Problem::Warning(format!(
+                    "Orbits from outside of the disk of radius {} may still come back into view, but are never sampled",
+                    self.escape_radius
+                )));
+            } else if Viewport::new(self, self.width, self.height).reach() > self.escape_radius {
+                problems.push(Problem::Warning(format!(
+                    "The view reaches past the disk of radius {}, where the first points of samples outside of it are no longer plotted",
+                    self.escape_radius
+                )));
+            }
+        }
+        if self.counter_bits != 64 && self.passes > 0 {
+            // The brightest pixel of renders measured so far takes at most a few percent of its
+            // share of every point that orbits could plot, which this leaves a wide margin for
+            let samples = (0..channels)
+                .map(|channel| self.samples.get(channel) as f64)
+                .fold(0.0, f64::max);
+            let limit = self.highest_limit() as f64;
+            let pixels = self.width as f64 * self.height as f64 * (self.supersample as f64).powi(2);
+            let points = samples * self.passes as f64 * limit / pixels;
+            if points >= u32::MAX as f64 {
+                problems.push(Problem::Warning(format!(
+                    "The brightest pixels may take more points than 32-bit counters hold ({points:.2e} for each pixel, out of {}), consider `counter_bits = 64`",
+                    u32::MAX
+                )));
+            }
+        }
+        if self.antithetic {
+            if self.weighs_samples() {
+                problems.push(Problem::Warning(String::from(
+                    "Weighted samples aren't drawn in antithetic pairs",
+                )));
+            } else if self.mirrors() {
+                problems.push(Problem::Warning(String::from(
+                    "`mirror_symmetry` already plots the mirror image of every orbit, so antithetic pairs plot each of them twice",
+                )));
+            } else if self.sample_im_min != -self.sample_im_max && !self.samples_disk() {
+                problems.push(Problem::Warning(format!(
+                    "The sampling region [{}, {}]i isn't symmetric about the real axis, so antithetic pairs are mirrored across its middle instead",
+                    self.sample_im_min, self.sample_im_max
+                )));
+            }
+        }
+        if self.sparse == Sparse::Always && self.counter_bits != 32 {
+            problems.push(Problem::Warning(format!(
+                "Sparse images have 32-bit counters, so the image is dense with {}-bit ones",
+                self.counter_bits
+            )));
+        }
+        if self.sparse == Sparse::Always && self.memory_map == MemoryMap::Always {
+            problems.push(Problem::Warning(String::from(
+                "A sparse image is held in memory, and only held in a memory map once it takes more memory than dense counters",
+            )));
+        }
+        if self.sparse == Sparse::Always && self.layout == Layout::Tiled {
+            problems.push(Problem::Warning(String::from(
+                "Sparse images are held row by row, and only tiled once they are turned into dense counters",
+            )));
+        }
+        if self.memory_map == MemoryMap::Always && !mapping::SUPPORTED {
+            problems.push(Problem::Warning(String::from(
+                "Memory maps are only made on 64-bit Unix, so the image is held in memory",
+            )));
+        }
+        if self.bands.is_some() && self.auto_limits.is_some() {
+            problems.push(Problem::Warning(String::from(
+                "`bands` replace the channels of `limits`, so `auto_limits` aren't picked",
+            )));
+        }
+        if self.separate_bands {
+            if self.bands.is_none() {
+                problems.push(Problem::Warning(String::from(
+                    "`separate_bands` only applies with `bands`, the channels are written into a single image",
+                )));
+            } else if !self.channel_mixing.is_empty() || self.background.is_some() {
+                problems.push(Problem::Warning(String::from(
+                    "Separate bands are written as they are, so `channel_mixing` and `background` are ignored",
+                )));
+            }
+        }
+        if self.coloring == Coloring::Origin && self.weighs_samples() {
+            problems.push(Problem::Warning(String::from(
+                "Weighted samples aren't colored by their origin, so the image is grayscale",
+            )));
+        }
+        if self.weighting == Weighting::PerOrbit && self.weighs_samples() {
+            problems.push(Problem::Warning(String::from(
+                "Weighted samples aren't weighted by the length of their orbits, so every point counts for one",
+            )));
+        }
+        if self.stratify_passes == Stratify::Always && self.weighs_samples() {
+            problems.push(Problem::Warning(String::from(
+                "Weighted samples aren't stratified across passes",
+            )));
+        }
+        if self.sampler == SamplerKind::Metropolis {
+            if !(self.metropolis_scale.is_finite() && self.metropolis_scale > 0.0) {
+                problems.push(Problem::Error(format!(
+                    "`metropolis_scale` ({}) must be greater than 0",
+                    self.metropolis_scale
+                )));
+            }
+            if self.zoom <= 1.0 {
+                problems.push(Problem::Warning(String::from(
+                    "The Metropolis sampler has nothing to gain when the whole set is in view, the render samples uniformly",
+                )));
+            }
+        }
+        if self.sampler == SamplerKind::Guided {
+            if !(1..=MAX_GUIDED_GRID).contains(&self.guided_grid) {
+                problems.push(Problem::Error(format!(
+                    "`guided_grid` ({}) must be between 1 and {MAX_GUIDED_GRID}",
+                    self.guided_grid
+                )));
+            }
+            if self.guided_samples == 0 {
+                problems.push(Problem::Error(String::from(
+                    "`guided_samples` must be greater than 0",
+                )));
+            }
+        }
+        if self.adaptive {
+            if !(0.0..=1.0).contains(&self.adaptive_strength) {
+                problems.push(Problem::Error(format!(
+                    "`adaptive_strength` ({}) must be between 0 and 1",
+                    self.adaptive_strength
+                )));
+            }
+            if self.sampler != SamplerKind::Uniform {
+                problems.push(Problem::Warning(format!(
+                    "Adaptive passes take samples from their own map, so the {} sampler isn't used",
+                    self.sampler
+                )));
+            }
+            if (1..=adaptive::WARMUP_PASSES).contains(&(self.passes as u64)) {
+                problems.push(Problem::Warning(format!(
+                    "Adaptive sampling only starts after {} uniform passes, but the render has {}",
+                    adaptive::WARMUP_PASSES,
+                    self.passes
+                )));
+            }
+        }
+        if self.weighs_samples() {
+            let samples: Vec<u32> = (0..channels)
+                .map(|channel| self.samples.get(channel))
+                .collect();
+            if samples.iter().any(|&count| count != samples[0]) {
+                let sampling = if self.adaptive {
+                    String::from("Adaptive sampling")
+                } else {
+                    format!("The {} sampler", self.sampler)
+                };
+                problems.push(Problem::Warning(format!(
+                    "{sampling} plots every sample in every channel, so each channel takes {} samples",
+                    samples.iter().max().unwrap_or(&0)
+                )));
+            }
+        }
+        if self.passes == 0 && self.intermediate_every == IntermediateInterval::Never {
+            problems.push(Problem::Warning(String::from(
+                "The render runs until interrupted, but no intermediate images will be written",
+            )));
+        }
+        if self.width > SIZE_WARNING || self.height > SIZE_WARNING {
+            problems.push(Problem::Warning(format!(
+                "A {}x{} image is larger than {SIZE_WARNING} pixels along an axis, which many programs can't open",
+                self.width, self.height
+            )));
+        }
+        problems
+    }
+
+    /// Whether the iterated function is the standard `z^2 + c`
+    pub fn standard_function(&self) -> bool {
+        self.formula.is_none() && self.variant == Variant::Mandelbrot && self.power == 2.0
+    }
+
+    /// Whether samples inside the main cardioid and period-2 bulb are skipped, which only leaves the
+    /// image unchanged for escaped orbits of the standard function, starting from 0
+    pub fn skips_interior(&self) -> bool {
+        self.skip_interior
+            && self.orbit_filter == OrbitFilter::Escaped
+            && self.mode == Mode::Mandelbrot
+            && self.standard_function()
+            && self.z0_re == 0.0
+            && self.z0_im == 0.0
+            && self.z0_radius == 0.0
+            && !self.deep_zoom()
+    }
+
+    /// Whether samples are taken from the disk of radius `escape_radius` around the origin rather
+    /// than from the sampling bounds
+    /// Left to choose, the disk is only picked when it is all within the bounds, and when the
+    /// samples outside of it can't plot anything in view: orbits of the standard function from 0
+    /// never come back inside of the disk once they leave it, so their points, and their samples,
+    /// are all further from the origin than the view reaches
+    pub fn samples_disk(&self) -> bool {
+        let radius = self.escape_radius;
+        match self.sample_domain {
+            SampleDomain::Bounds => false,
+            SampleDomain::Disk => true,
+            SampleDomain::Auto => {
+                self.leaves_disk()
+                    && !self.deep_zoom()
+                    && matches!(self.projection, Projection::Z | Projection::C)
+                    && Viewport::new(self, self.width, self.height).reach() <= radius
+                    && self.sample_re_min <= -radius
+                    && self.sample_re_max >= radius
+                    && self.sample_im_min <= -radius
+                    && self.sample_im_max >= radius
+            }
+        }
+    }
+
+    /// Whether orbits that leave the disk of radius `escape_radius` around the origin never come
+    /// back inside, as for the standard function from 0 with a radius of at least 2
+    fn leaves_disk(&self) -> bool {
+        self.mode == Mode::Mandelbrot
+            && self.standard_function()
+            && self.z0_re == 0.0
+            && self.z0_im == 0.0
+            && self.z0_radius == 0.0
+            && self.escape_radius >= 2.0
+    }
+
+    /// Size of the region that samples are taken from, along the real and imaginary axes
+    pub fn sample_size(&self) -> (f64, f64) {
+        if self.samples_disk() {
+            (2.0 * self.escape_radius, 2.0 * self.escape_radius)
+        } else {
+            (
+                self.sample_re_max - self.sample_re_min,
+                self.sample_im_max - self.sample_im_min,
+            )
+        }
+    }
+
+    /// Whether orbits that fall into a cycle are given up on, which only leaves the image unchanged
+    /// when plotting escaped orbits, as trapped ones would be cut short
+    pub fn checks_periodicity(&self) -> bool {
+        self.check_periodicity && self.orbit_filter == OrbitFilter::Escaped
+    }
+
+    /// Whether samples are iterated in batches, which is only implemented for the standard function
+    /// in double precision
+    pub fn batches(&self) -> bool {
+        self.batched
+            && self.standard_function()
+            && self.precision == Precision::F64
+            && !self.deep_zoom()
+    }
+
+    /// Whether samples are shared by all channels, which isn't possible for deep zooms, where the
+    /// points of an orbit can't tell whether it had escaped at a given iteration
+    pub fn shares_orbits(&self) -> bool {
+        self.share_orbits && !self.deep_zoom()
+    }
+
+    /// Whether orbits are also plotted mirrored across the real axis, which deep zooms can't do as
+    /// their orbits are given relative to the center
+    pub fn mirrors(&self) -> bool {
+        self.mirror_symmetry && !self.deep_zoom()
+    }
+
+    /// Whether the counters of the image are held in a memory map, where this platform has them
+    pub fn maps_memory(&self) -> bool {
+        let bytes = RawImage::bytes(
+            self.width * self.supersample,
+            self.height * self.supersample,
+            self.channels(),
+            self.counter_bits,
+        );
+        mapping::SUPPORTED
+            && match self.memory_map {
+                MemoryMap::Auto => bytes > self.memory_map_threshold << 20,
+                MemoryMap::Always => true,
+                MemoryMap::Never => false,
+            }
+    }
+
+    /// Whether points are counted in a sparse image, which `"auto"` chooses when the viewport
+    /// covers a small share of the sampling bounds, as the orbits of few samples then pass
+    /// through it
+    pub fn counts_sparsely(&self) -> bool {
+        let (width, height) = (
+            self.width * self.supersample,
+            self.height * self.supersample,
+        );
+        let (re_size, im_size) = Viewport::new(self, width, height).size();
+        let (sample_re_size, sample_im_size) = self.sample_size();
+        self.counter_bits == 32
+            && match self.sparse {
+                Sparse::Auto => re_size * im_size < SPARSE_SHARE * sample_re_size * sample_im_size,
+                Sparse::Always => true,
+                Sparse::Never => false,
+            }
+    }
+
+    /// Directory that the file of the memory map is created in
+    pub fn map_directory(&self) -> PathBuf {
+        self.memory_map_directory
+            .as_ref()
+            .map_or_else(env::temp_dir, PathBuf::from)
+    }
+
+    /// Number of channels that orbits are traced and points are counted in, one for each limit,
+    /// or for each band
+    pub fn channels(&self) -> u32 {
+        match &self.bands {
+            Some(bands) => bands.len() as u32,
+            None => self.limits.len() as u32,
+        }
+    }
+
+    /// Escape limit of a `channel`, up to which its orbits are traced: the highest escape time of
+    /// the last band for every band
+    pub fn limit(&self, channel: u32) -> u32 {
+        match &self.bands {
+            Some(bands) => bands.last().copied().unwrap_or(0),
+            None => self.limits[channel as usize],
+        }
+    }
+
+    /// Highest escape limit of any channel
+    pub fn highest_limit(&self) -> u32 {
+        (0..self.channels())
+            .map(|channel| self.limit(channel))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Channel of the band that an orbit of a given `length` escapes in, or `None` if there are
+    /// no bands, in which case every channel plots it
+    pub fn band(&self, length: usize) -> Option<u32> {
+        let bands = self.bands.as_ref()?;
+        Some(bands.partition_point(|&band| (band as usize) < length) as u32)
+    }
+
+    /// Whether each band is written into an image of its own
+    pub fn separates_bands(&self) -> bool {
+        self.separate_bands && self.bands.is_some()
+    }
+
+    /// Number of channels of the image that the output image is made from: those that points are
+    /// counted in, or the red, green, and blue channels that orbits colored by their origin are
+    /// shared between
+    pub fn image_channels(&self) -> u32 {
+        match self.colors_by_origin() {
+            true => 3,
+            false => self.channels(),
+        }
+    }
+
+    /// Number of channels of the output image, 1 for grayscale or 3 for red, green, and blue, or
+    /// one for each band when they are written separately
+    pub fn output_channels(&self) -> u32 {
+        match self.channel_mixing.len() {
+            0 => self.image_channels(),
+            _ if self.separates_bands() => self.image_channels(),
+            outputs => outputs as u32,
+        }
+    }
+
+    /// Whether the samples of successive passes are laid out together, which is left to seeded
+    /// renders by default, and doesn't apply to weighted samples
+    pub fn stratifies_passes(&self) -> bool {
+        let stratify = match self.stratify_passes {
+            Stratify::Auto => self.seed.is_some(),
+            Stratify::Always => true,
+            Stratify::Never => false,
+        };
+        stratify && !self.weighs_samples()
+    }
+
+    /// Whether samples are drawn by chains of the Metropolis sampler, which falls back to uniform
+    /// samples when the view isn't zoomed in, as most orbits are then in view anyway
+    pub fn metropolis(&self) -> bool {
+        self.sampler == SamplerKind::Metropolis && self.zoom > 1.0 && !self.adaptive
+    }
+
+    /// Whether samples are drawn from the importance map of the guided sampler
+    pub fn guided(&self) -> bool {
+        self.sampler == SamplerKind::Guided && !self.adaptive
+    }
+
+    /// Whether samples are plotted with weights, which are measured across every channel, so
+    /// that every group of channels is traced along the same samples
+    pub fn weighs_samples(&self) -> bool {
+        self.metropolis() || self.guided() || self.adaptive
+    }
+
+    /// Whether the points of each orbit are weighted by its length, and summed apart from the
+    /// counts of the image
+    pub fn weighs_orbits(&self) -> bool {
+        self.weighting == Weighting::PerOrbit && !self.weighs_samples()
+    }
+
+    /// Whether orbits are colored by the angle of their sample, rather than by their limit
+    pub fn colors_by_origin(&self) -> bool {
+        self.coloring == Coloring::Origin && !self.weighs_samples()
+    }
+
+    /// Whether points are summed with weights apart from the counts of the image, when orbits are
+    /// weighted by their length or colored by their origin
+    pub fn weighs_points(&self) -> bool {
+        self.weighs_orbits() || self.colors_by_origin()
+    }
+
+    /// Whether orbits are followed as differences from a precise orbit of the center
+    pub fn deep_zoom(&self) -> bool {
+        self.standard_function()
+            && self.mode == Mode::Mandelbrot
+            && match self.precision {
+                Precision::Deep => true,
+                Precision::F64 => self.zoom > DEEP_ZOOM,
+                Precision::F32 => false,
+            }
+    }
+
+    /// Multiplies the escape limit of every channel by `scale`, keeping each at least 1
+    pub fn scale_limits(&mut self, scale: f64) -> Result<(), NebulaeError> {
+        if !(scale.is_finite() && scale > 0.0) {
+            return Err(NebulaeError::Invalid(format!(
+                "The limit scale ({scale}) must be greater than 0"
+            )));
+        }
+        for limit in &mut self.limits {
+            *limit = (*limit as f64 * scale).round().max(1.0) as u32;
+        }
         Ok(())
     }
 
-    /// Serializes the configuration to TOML
-    pub fn serialize(&self) -> Result<String, Box<dyn std::error::Error>> {
-        Ok(toml::to_string_pretty(self)?)
+    /// Serializes and writes the configuration to a file, along with the path of the image to
+    /// render, if any
+    /// The format is picked from the file's extension, defaulting to TOML
+    pub fn to_file(&self, path: &str, output: Option<&str>) -> Result<(), NebulaeError> {
+        let format = ConfigFormat::from_path(path).unwrap_or(ConfigFormat::Toml);
+        let config = SavedConfig {
+            output,
+            settings: self,
+        };
+        fs::write(path, serialize(&config, format)?).map_err(|source| NebulaeError::Write {
+            path: path.to_string(),
+            source,
+        })
     }
 
-    /// Opens a TOML file to a [`RenderSettings`]
-    pub fn from_file(path: &str) -> Result<RenderSettings, Box<dyn std::error::Error>> {
-        let data: String = fs::read_to_string(path)?;
-        Ok(toml::from_str(data.as_str())?)
+    /// Serializes the configuration to a given format
+    pub fn serialize(&self, format: ConfigFormat) -> Result<String, NebulaeError> {
+        serialize(self, format)
     }
 
     /// Generates a [`RenderSettings`] from a TUI in the terminal
-    pub fn from_wizard() -> Result<Option<RenderSettings>, Box<dyn std::error::Error>> {
-        let color_palette = match select(
-            "Palette",
-            vec![
-                ("Nebulous", &[2, 1, 0]),
-                ("Blue-ish", &[0, 1, 2]),
-                ("Cyber-pink", &[2, 0, 1]),
-                ("Cyber-purple", &[1, 0, 2]),
-            ],
-            0,
-        )? {
+    pub fn from_wizard(palettes: &PaletteRegistry) -> Result<Option<RenderSettings>, NebulaeError> {
+        let palette = match select("Palette", palettes.iter().collect(), 0)? {
             Some(val) => val,
             None => return Ok(None),
         };
 
-        let intensity = match select(
+        let intensity = match select_or_own(
             "Saturation",
             vec![
                 ("Cloudy (x2)", &[400, 800, 1_600]),
                 ("Warm (x3)", &[215, 645, 1_935]),
                 ("Intense (x10)", &[25, 250, 2_500]),
             ],
-            1,
+            &palette.intensity,
         )? {
-            Some(val) => val,
+            Some(val) => *val,
             None => return Ok(None),
         };
 
-        let definition = match select(
+        let definition = match select_or_own(
             "Definition",
             vec![("Faded", &2), ("Bright", &4), ("Harsh", &8)],
-            1,
+            &palette.definition,
         )? {
-            Some(val) => val,
+            Some(val) => *val,
             None => return Ok(None),
         };
 
-        let limits: [u32; CHANNELS as usize] = [
-            (intensity[color_palette[0]] * definition),
-            (intensity[color_palette[1]] * definition),
-            (intensity[color_palette[2]] * definition),
-        ];
-
-        if limits.len() as u32 != CHANNELS {
-            return Err(format!("This program expects {CHANNELS} channels, but the limits array was set up with {} values", limits.len()).into());
+        let limits = Palette {
+            intensity,
+            definition,
+            ..*palette
         }
+        .limits();
 
-        let resolution = match select(
+        let (width, height) = match select(
             "Resolution",
             vec![
-                ("Small (1024)", &(1 << 10)),
-                ("Medium (2048)", &(1 << 11)),
-                ("Large (4096)", &(1 << 12)),
-                ("Massive (8096)", &(1 << 13)),
-                ("Love knows no bounds (16 384)", &(1 << 14)),
+                ("Small (1024)", &(1 << 10, 1 << 10)),
+                ("Medium (2048)", &(1 << 11, 1 << 11)),
+                ("Large (4096)", &(1 << 12, 1 << 12)),
+                ("Massive (8096)", &(1 << 13, 1 << 13)),
+                ("Love knows no bounds (16 384)", &(1 << 14, 1 << 14)),
+                ("HD wallpaper (1920x1080)", &(1920, 1080)),
+                ("4K wallpaper (3840x2160)", &(3840, 2160)),
             ],
             1,
         )? {
@@ -144,22 +2307,61 @@ impl RenderSettings {
             None => return Ok(None),
         };
 
-        let settings = RenderSettings {
+        let mode = match select(
+            "Fractal",
+            vec![("Mandelbrot", &false), ("Julia (enter c)", &true)],
+            0,
+        )? {
+            Some(false) => Mode::Mandelbrot,
+            Some(true) => Mode::Julia {
+                c_re: input("Real part of c", -0.8)?,
+                c_im: input("Imaginary part of c", 0.156)?,
+            },
+            None => return Ok(None),
+        };
+
+        let mut settings = RenderSettings {
+            mode,
             limits,
-            samples: iterations,
-            passes: DEFAULT_RENDER_SETTINGS.passes,
-            size: resolution,
-            curve: DEFAULT_RENDER_SETTINGS.curve,
+            samples: PerChannel::Uniform(iterations),
+            width,
+            height,
+            ..RenderSettings::default()
         };
 
         if Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(format!("Render like this?"))
+            .with_prompt("Configure advanced settings?")
+            .default(false)
+            .interact()
+            .map_err(NebulaeError::Terminal)?
+        {
+            settings.escape_radius = input(
+                "Escape radius (orbits reaching it are plotted)",
+                settings.escape_radius,
+            )?;
+            settings.stop_radius = input(
+                "Stop radius (orbits reaching it stop iterating)",
+                settings.stop_radius,
+            )?;
+            if Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Pick the limits from a quick pre-pass instead?")
+                .default(false)
+                .interact()
+                .map_err(NebulaeError::Terminal)?
+            {
+                settings.auto_limits = Some(vec![99.0, 99.9, 99.99]);
+            }
+        }
+
+        if Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Render like this?")
             .default(true)
-            .interact()?
+            .interact()
+            .map_err(NebulaeError::Terminal)?
         {
             Ok(Some(settings))
         } else {
-            Err("Canceled".into())
+            Err(NebulaeError::Cancelled)
         }
     }
 }
@@ -168,15 +2370,190 @@ fn select<'a, T>(
     prompt: &str,
     items: Vec<(&str, &'a T)>,
     default: usize,
-) -> Result<Option<&'a T>, Error> {
+) -> Result<Option<&'a T>, NebulaeError> {
     let (selections, values): (Vec<&str>, Vec<&T>) = items.into_iter().unzip();
     match Select::with_theme(&ColorfulTheme::default())
         .with_prompt(prompt)
         .items(&selections)
         .default(default)
-        .interact_on_opt(&Term::stderr())?
+        .interact_on_opt(&Term::stderr())
+        .map_err(NebulaeError::Terminal)?
     {
         Some(index) => Ok(Some(values[index])),
         None => Ok(None),
     }
 }
+
+/// Like [`select`], defaulting to the item whose value is `own`, or to an extra one for it
+fn select_or_own<'a, T: PartialEq>(
+    prompt: &str,
+    mut items: Vec<(&str, &'a T)>,
+    own: &'a T,
+) -> Result<Option<&'a T>, NebulaeError> {
+    let default = match items.iter().position(|(_, value)| *value == own) {
+        Some(index) => index,
+        None => {
+            items.push(("Palette's own", own));
+            items.len() - 1
+        }
+    };
+    select(prompt, items, default)
+}
+
+fn input<T>(prompt: &str, default: T) -> Result<T, NebulaeError>
+where
+    T: Clone + fmt::Display + std::str::FromStr,
+    T::Err: fmt::Display + fmt::Debug,
+{
+    Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(default)
+        .interact_text_on(&Term::stderr())
+        .map_err(NebulaeError::Terminal)
+}
+
+/// Serializes some settings to a given format
+fn serialize(settings: &impl Serialize, format: ConfigFormat) -> Result<String, NebulaeError> {
+    let serialized = match format {
+        ConfigFormat::Toml => toml::to_string_pretty(settings).map_err(|error| error.to_string()),
+        ConfigFormat::Json => {
+            serde_json::to_string_pretty(settings).map_err(|error| error.to_string())
+        }
+    };
+    serialized.map_err(|error| {
+        NebulaeError::Invalid(format!(
+            "Could not serialize the settings to {format}: {error}"
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_of_zero_passes_are_never() {
+        for text in ["0", "00", " 000 ", "none"] {
+            assert!(text.parse::<IntermediateInterval>() == Ok(IntermediateInterval::Never));
+        }
+        for json in ["\"00\"", "{\"passes\": 0}", "0"] {
+            let interval: IntermediateInterval = serde_json::from_str(json).unwrap();
+            assert!(interval == IntermediateInterval::Never);
+        }
+        assert!("3".parse() == Ok(IntermediateInterval::Passes(3)));
+        assert!("0s".parse() == Ok(IntermediateInterval::Seconds(0)));
+        assert!("-1".parse::<IntermediateInterval>().is_err());
+    }
+
+    #[test]
+    fn settings_are_shown_a_line_each() {
+        let settings = RenderSettings {
+            seed: Some(7),
+            passes: 0,
+            ..RenderSettings::default()
+        };
+        let shown = settings.to_string();
+        let lines: Vec<&str> = shown.lines().collect();
+        // Every line is a label and a value, lined up by tabs
+        for line in &lines {
+            let (label, value) = line.split_once(":\t").unwrap_or_else(|| panic!("{line:?}"));
+            assert!(!label.is_empty() && !label.contains('\t'), "{line:?}");
+            let value = value.trim_start_matches('\t');
+            assert!(!value.is_empty() && !value.starts_with(' '), "{line:?}");
+        }
+        assert_eq!(lines[0], "Escape limits:\t7740,2580,860");
+        assert!(lines.contains(&"Passes:\t\tuntil interrupted"));
+        assert!(lines.contains(&"Seed:\t\t7"));
+        assert!(lines.contains(&"Correction:\t0.5"), "{shown}");
+        assert_eq!(lines.last(), Some(&"Intermediates:\tevery 60s"));
+        assert!(!shown.ends_with('\n'));
+    }
+
+    /// Messages of the errors that some settings are found to have, and their number of warnings
+    fn problems(settings: &RenderSettings) -> (Vec<String>, usize) {
+        let mut errors = Vec::new();
+        let mut warnings = 0;
+        for problem in settings.validate() {
+            match problem {
+                Problem::Error(message) => errors.push(message),
+                Problem::Warning(_) => warnings += 1,
+            }
+        }
+        (errors, warnings)
+    }
+
+    #[test]
+    fn views_that_are_not_there_are_errors() {
+        assert!(problems(&RenderSettings::default()).0.is_empty());
+        type Change = fn(&mut RenderSettings);
+        let views: [(&str, Change); 6] = [
+            ("`zoom`", |settings| settings.zoom = 0.0),
+            ("`zoom`", |settings| settings.zoom = -2.0),
+            ("`zoom`", |settings| settings.zoom = f64::INFINITY),
+            ("`center_re`", |settings| settings.center_re = f64::NAN),
+            ("`center_im`", |settings| {
+                settings.center_im = f64::NEG_INFINITY
+            }),
+            ("`rotation`", |settings| settings.rotation = f64::NAN),
+        ];
+        for (key, change) in views {
+            let mut settings = RenderSettings::default();
+            change(&mut settings);
+            let (errors, _) = problems(&settings);
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            assert!(errors[0].starts_with(key), "{errors:?}");
+        }
+    }
+
+    #[test]
+    fn empty_images_are_errors_without_estimates() {
+        for (width, height) in [(0, 512), (512, 0), (0, 0)] {
+            let settings = RenderSettings {
+                width,
+                height,
+                ..RenderSettings::default()
+            };
+            let (errors, warnings) = problems(&settings);
+            assert_eq!(errors.len(), 1, "{errors:?}");
+            assert!(errors[0].starts_with("`width` and `height`"));
+            assert_eq!(warnings, 0);
+        }
+    }
+
+    #[test]
+    fn limits_scale_to_at_least_1() {
+        let mut settings = RenderSettings {
+            limits: vec![1_000, 3, 1],
+            ..RenderSettings::default()
+        };
+        settings.scale_limits(0.25).unwrap();
+        assert_eq!(settings.limits, [250, 1, 1]);
+        settings.scale_limits(4.0).unwrap();
+        assert_eq!(settings.limits, [1_000, 4, 4]);
+        for scale in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(settings.scale_limits(scale).is_err(), "{scale}");
+        }
+        assert_eq!(settings.limits, [1_000, 4, 4]);
+    }
+
+    #[test]
+    fn radii_that_stop_orbits_early_are_reported() {
+        let radii = |escape_radius, stop_radius| {
+            problems(&RenderSettings {
+                escape_radius,
+                stop_radius,
+                ..RenderSettings::default()
+            })
+        };
+        let (errors, _) = radii(0.0, 3.0);
+        assert!(errors[0].starts_with("`escape_radius`"), "{errors:?}");
+        let (errors, _) = radii(f64::NAN, 3.0);
+        assert!(errors[0].starts_with("`escape_radius`"), "{errors:?}");
+        let (errors, _) = radii(4.0, 3.0);
+        assert!(errors[0].starts_with("`stop_radius`"), "{errors:?}");
+        // Orbits of the standard function may come back from inside a radius of 2
+        let (errors, warnings) = radii(1.5, 3.0);
+        assert!(errors.is_empty() && warnings > problems(&RenderSettings::default()).1);
+        assert!(radii(8.0, 8.0).0.is_empty());
+    }
+}
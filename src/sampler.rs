@@ -0,0 +1,148 @@
+//! Sources of the positions of random samples, which are drawn in the unit square before being
+//! mapped onto the sampling region
+
+use rand::{Rng, RngCore};
+
+/// Gives the position of each sample of a pass within the unit square
+/// Samples are identified by their index within the pass, so that any number of threads can draw
+/// them in any order, and every pass starts over from the first one
+pub trait Sampler: Sync {
+    /// Position of the sample at `index` within a pass, or `None` past the samples that the
+    /// sampler lays out in one
+    /// Any randomness that the sampler doesn't draw from a generator of its own comes from `rng`,
+    /// which belongs to the task drawing the sample and is seeded from the render's seed when it
+    /// has one, so that seeded renders are reproduced
+    fn point(&self, index: u64, rng: &mut dyn RngCore) -> Option<(f64, f64)>;
+
+    /// Prepare for a new pass, drawing whatever changes from one pass to the next from `rng`,
+    /// which is seeded from the render's seed and the pass when it has one, or laying the pass
+    /// out from `strata` when passes are stratified together
+    fn reset(&mut self, _rng: &mut dyn RngCore, _strata: Option<Strata>) {}
+
+    /// Number of samples actually taken in a pass, for a `requested` number of them
+    fn samples(&self, requested: u64) -> u64 {
+        requested
+    }
+}
+
+/// Steps of the offsets of successive passes along each axis, the inverses of the plastic number
+/// and of its square, for which every run of passes has offsets spread evenly over the square
+const STEPS: (f64, f64) = (0.754_877_666_246_692_7, 0.569_840_290_998_053_3);
+
+/// What the passes of a render share when they are stratified together, so that the samples of
+/// the first few passes fill the unit square evenly together, not just each on their own
+///
+/// Each pass shifts a layout of samples shared by every pass by its own step, wrapping around (a
+/// Cranley-Patterson rotation), within whatever the layout spreads its samples over, such as the
+/// cells of a grid. The steps follow the R2 sequence, which spreads those of any number of
+/// consecutive passes evenly, where random steps would clump as much as random samples.
+#[derive(Clone, Copy)]
+pub struct Strata {
+    /// Key that the random parts of the shared layout are hashed from
+    key: u64,
+    /// Random offset of the shared layout
+    origin: (f64, f64),
+    /// Number of the pass, and its step
+    pass: u64,
+    step: (f64, f64),
+}
+
+impl Strata {
+    /// Draw the [`Strata`] of a render, for its first pass
+    pub fn new(rng: &mut dyn RngCore) -> Strata {
+        Strata {
+            key: rng.gen(),
+            origin: (rng.gen(), rng.gen()),
+            pass: 0,
+            step: (0.0, 0.0),
+        }
+    }
+
+    /// The same [`Strata`], for another `pass`
+    pub fn pass(self, pass: u64) -> Strata {
+        Strata {
+            pass,
+            step: rotate((0.0, 0.0), (pass as f64 * STEPS.0, pass as f64 * STEPS.1)),
+            ..self
+        }
+    }
+
+    /// Number of the pass
+    pub fn number(&self) -> u64 {
+        self.pass
+    }
+
+    /// Random offset of the shared layout
+    pub fn origin(&self) -> (f64, f64) {
+        self.origin
+    }
+
+    /// Step of the pass, within the unit square
+    pub fn step(&self) -> (f64, f64) {
+        self.step
+    }
+
+    /// Random point of the unit square for an `index`, the same in every pass
+    pub fn hash(&self, index: u64) -> (f64, f64) {
+        hash(self.key, index)
+    }
+}
+
+/// Random point of the unit square for an `index`, hashed from a `key`
+pub fn hash(key: u64, index: u64) -> (f64, f64) {
+    let u = crate::splitmix64(key ^ crate::splitmix64(index));
+    let v = crate::splitmix64(u);
+    // The upper 53 bits of each give a coordinate, as many as a float holds
+    let unit = |bits: u64| (bits >> 11) as f64 / (1u64 << 53) as f64;
+    (unit(u), unit(v))
+}
+
+/// Shift a point of the unit square by an `offset`, wrapping around its edges
+pub fn rotate((u, v): (f64, f64), offset: (f64, f64)) -> (f64, f64) {
+    ((u + offset.0).fract(), (v + offset.1).fract())
+}
+
+/// Independent, uniformly distributed samples, which tend to clump together
+pub struct UniformSampler;
+
+impl Sampler for UniformSampler {
+    fn point(&self, _index: u64, rng: &mut dyn RngCore) -> Option<(f64, f64)> {
+        Some((rng.gen::<f64>(), rng.gen::<f64>()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn passes_are_stepped_apart() {
+        let strata = Strata::new(&mut StdRng::seed_from_u64(3));
+        assert_eq!(strata.pass(0).step(), (0.0, 0.0));
+        // The steps of the first passes are all far apart, wrapping around the square
+        let steps: Vec<(f64, f64)> = (0..16).map(|pass| strata.pass(pass).step()).collect();
+        for (a, first) in steps.iter().enumerate() {
+            for second in &steps[a + 1..] {
+                let gap = |a: f64, b: f64| (a - b).abs().min(1.0 - (a - b).abs());
+                let distance = gap(first.0, second.0).hypot(gap(first.1, second.1));
+                assert!(distance > 0.1, "{first:?} {second:?}");
+            }
+        }
+        // What passes share stays the same from one to the next
+        assert_eq!(strata.pass(5).origin(), strata.origin());
+        assert_eq!(strata.pass(5).hash(9), strata.hash(9));
+        assert_eq!(strata.pass(5).number(), 5);
+    }
+
+    #[test]
+    fn points_wrap_around_the_square() {
+        assert_eq!(rotate((0.75, 0.5), (0.5, 0.25)), (0.25, 0.75));
+        for index in 0..1000 {
+            let (u, v) = hash(7, index);
+            assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+        }
+        assert_ne!(hash(7, 1), hash(8, 1));
+    }
+}
@@ -0,0 +1,201 @@
+//! Kernels that share each point of an orbit between the pixels around it, rather than counting
+//! it once in the pixel it lands in
+//!
+//! The position of a point between the centers of the pixels around it is rounded to [`STEPS`]
+//! steps along each axis, and the kernel of each of those positions is laid out once for the whole
+//! render, as whole shares of the point that add up to the same scale for every position. Points
+//! are then counted in the integer counters of the image like any others, only on a larger scale,
+//! and each adds the same total to the image wherever it lands, apart from the shares that fall
+//! off its edges.
+
+use crate::render_settings::Splat;
+
+/// Number of steps that the position of a point between two pixel centers is rounded to
+pub const STEPS: u32 = 4;
+
+/// Whole weight of a point shared by a Gaussian kernel, which leaves its smallest shares a few
+/// steps of their own
+pub const GAUSSIAN_SCALE: u32 = 1_024;
+
+/// Number of standard deviations that the pixels of a Gaussian kernel reach out to, past which
+/// their shares would round to almost nothing
+const GAUSSIAN_REACH: f64 = 3.0;
+
+/// Number of points along each axis of a pixel that the Gaussian is averaged over, to find the
+/// share of the pixel
+const GAUSSIAN_SUBSAMPLES: u32 = 8;
+
+/// A share of a point: the offset of its pixel from the one whose center is at the top left of
+/// the point, and the part of the point counted there
+pub type Share = (i32, i32, u32);
+
+/// Shares of a point for each position between the centers of the pixels around it
+pub struct Kernel {
+    /// Shares of each position, row by row of the steps
+    shares: Vec<Vec<Share>>,
+    /// Number of pixels past those around the point that its shares reach, along each axis
+    radius: u32,
+}
+
+impl Kernel {
+    /// Lay out the kernel of a `splat`, whose Gaussian has a standard deviation of `sigma` pixels,
+    /// or none when each point is counted in the pixel it lands in
+    pub fn new(splat: Splat, sigma: f64) -> Option<Kernel> {
+        let radius = match splat {
+            Splat::Nearest => return None,
+            Splat::Bilinear => 0,
+            Splat::Gaussian => (GAUSSIAN_REACH * sigma).ceil() as u32,
+        };
+        // Weight along an axis of a pixel whose center is `distance` pixels away from the point
+        let weight = |distance: f64| match splat {
+            // The share is the distance to the center on the other side
+            Splat::Bilinear => 1.0 - distance,
+            // The Gaussian over the whole pixel rather than at its center, which would pull
+            // narrow kernels towards the nearest pixel, cut off as far on either side of the point
+            _ => {
+                let samples = GAUSSIAN_SUBSAMPLES as f64;
+                (0..GAUSSIAN_SUBSAMPLES)
+                    .map(|sample| distance - 0.5 + (sample as f64 + 0.5) / samples)
+                    .filter(|x| x.abs() <= GAUSSIAN_REACH * sigma)
+                    .map(|x| (-x * x / (2.0 * sigma * sigma)).exp())
+                    .sum::<f64>()
+                    / samples
+            }
+        };
+        let (steps, reach) = (STEPS as i32, radius as i32);
+        let shares = (0..steps * steps)
+            .map(|step| {
+                let (x, y) = (
+                    (step % steps) as f64 / steps as f64,
+                    (step / steps) as f64 / steps as f64,
+                );
+                let pixels: Vec<(i32, i32)> = (-reach..=reach + 1)
+                    .flat_map(|dy| (-reach..=reach + 1).map(move |dx| (dx, dy)))
+                    .collect();
+                let weights: Vec<f64> = pixels
+                    .iter()
+                    .map(|&(dx, dy)| weight((dx as f64 - x).abs()) * weight((dy as f64 - y).abs()))
+                    .collect();
+                let shares = whole_shares(&weights, splat.scale());
+                pixels
+                    .into_iter()
+                    .zip(shares)
+                    .filter(|&(_, share)| share > 0)
+                    .map(|((dx, dy), share)| (dx, dy, share))
+                    .collect()
+            })
+            .collect();
+        Some(Kernel { shares, radius })
+    }
+
+    /// Pixel whose center is at the top left of a point at a `position` on the grid of pixels,
+    /// along with the shares of the point around it
+    pub fn at(&self, position: (f64, f64)) -> ((f64, f64), &[Share]) {
+        let (x, y) = (position.0 - 0.5, position.1 - 0.5);
+        let (x, right) = round_to_step(x);
+        let (y, bottom) = round_to_step(y);
+        let shares = &self.shares[(bottom * STEPS + right) as usize];
+        ((x, y), shares)
+    }
+
+    /// Number of pixels from its edges that points are counted in the image from, which points
+    /// further out of view can't reach
+    pub fn margin(&self) -> f64 {
+        self.radius as f64 + 0.5
+    }
+}
+
+/// Round a coordinate to a step between the pixel centers on either side of it, giving the
+/// center before it, and the step from it
+fn round_to_step(coordinate: f64) -> (f64, u32) {
+    let before = coordinate.floor();
+    let step = ((coordinate - before) * STEPS as f64).round() as u32;
+    if step == STEPS {
+        (before + 1.0, 0)
+    } else {
+        (before, step)
+    }
+}
+
+/// Share a whole `scale` out between some `weights`, in proportion to them: each takes the whole
+/// part of its share, and the steps left go to those with the largest fractions
+fn whole_shares(weights: &[f64], scale: u32) -> Vec<u32> {
+    let total: f64 = weights.iter().sum();
+    let exact: Vec<f64> = weights
+        .iter()
+        .map(|weight| weight / total * scale as f64)
+        .collect();
+    let mut shares: Vec<u32> = exact.iter().map(|&share| share as u32).collect();
+    let left = scale - shares.iter().sum::<u32>();
+    let mut order: Vec<usize> = (0..exact.len()).collect();
+    order.sort_by(|&a, &b| exact[b].fract().total_cmp(&exact[a].fract()));
+    for &index in order.iter().take(left as usize) {
+        shares[index] += 1;
+    }
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shares of the kernel of a `splat` of a point at a `position` on the grid of pixels, by the
+    /// pixel they are counted in
+    fn shares(kernel: &Kernel, position: (f64, f64)) -> Vec<(i32, i32, u32)> {
+        let ((x, y), shares) = kernel.at(position);
+        let mut shares: Vec<_> = shares
+            .iter()
+            .map(|&(dx, dy, share)| (x as i32 + dx, y as i32 + dy, share))
+            .collect();
+        shares.sort_unstable();
+        shares
+    }
+
+    #[test]
+    fn bilinear_points_are_shared_by_their_distance_to_each_center() {
+        let kernel = Kernel::new(Splat::Bilinear, 0.0).unwrap();
+        assert_eq!(shares(&kernel, (3.5, 7.5)), [(3, 7, 16)]);
+        // A quarter of the way from the center of (3, 7) to that of (4, 8)
+        assert_eq!(
+            shares(&kernel, (3.75, 7.75)),
+            [(3, 7, 9), (3, 8, 3), (4, 7, 3), (4, 8, 1)]
+        );
+        assert_eq!(shares(&kernel, (4.0, 7.5)), [(3, 7, 8), (4, 7, 8)]);
+        assert_eq!(kernel.margin(), 0.5);
+        assert!(Kernel::new(Splat::Nearest, 1.0).is_none());
+    }
+
+    #[test]
+    fn gaussian_shares_add_up_to_a_point_and_spread_evenly() {
+        for sigma in [0.3, 1.0, 2.5] {
+            let kernel = Kernel::new(Splat::Gaussian, sigma).unwrap();
+            for step in &kernel.shares {
+                assert_eq!(
+                    step.iter().map(|share| share.2).sum::<u32>(),
+                    GAUSSIAN_SCALE
+                );
+            }
+            // A point at the center of a pixel takes the largest share, and spreads alike on
+            // every side of it
+            let centered = shares(&kernel, (10.5, 10.5));
+            let share = |x, y| {
+                centered
+                    .iter()
+                    .find(|s| (s.0, s.1) == (x, y))
+                    .map_or(0, |s| s.2)
+            };
+            let center = share(10, 10);
+            assert!(centered.iter().all(|&(.., other)| other <= center));
+            assert_eq!(share(9, 10), share(11, 10));
+            assert_eq!(share(10, 9), share(10, 11));
+            assert_eq!(kernel.margin(), (3.0 * sigma).ceil() + 0.5);
+        }
+    }
+
+    #[test]
+    fn whole_shares_take_the_steps_left_by_their_fractions() {
+        assert_eq!(whole_shares(&[1.0, 1.0, 1.0], 10), [4, 3, 3]);
+        assert_eq!(whole_shares(&[0.1, 0.6, 0.3], 10), [1, 6, 3]);
+        assert_eq!(whole_shares(&[2.0, 0.0], 16), [16, 0]);
+    }
+}
@@ -0,0 +1,326 @@
+//! Mapping of points on the complex plane to pixels of the rendered image
+
+use crate::mandelbrot::Complex;
+use crate::render_settings::RenderSettings;
+
+/// Half the extent of the real axis of the viewport at a zoom of 1, which shows the whole set
+const BASE_HALF_EXTENT: f64 = 2.0;
+
+/// A rectangular window onto the complex plane, covering an image of `width` × `height` pixels
+///
+/// The real axis runs down the rows of the image, from the top, and the imaginary axis along its
+/// columns, from the left, which stands the set upright with its period-2 bulb at the top, the way
+/// the Buddhabrot is usually shown. This is intended, as are the image's extents: `zoom` sets
+/// the extent of the real axis, over the height of the image.
+#[derive(Clone, Copy)]
+pub struct Viewport {
+    re_min: f64,
+    re_max: f64,
+    im_min: f64,
+    im_max: f64,
+    /// Half the extents of each axis, which stay precise even when they are far smaller than the
+    /// coordinates of the center
+    re_half_extent: f64,
+    im_half_extent: f64,
+    width: u32,
+    height: u32,
+    center: Complex,
+    /// Cosine and sine of the rotation, or `None` when the view isn't rotated
+    rotation: Option<(f64, f64)>,
+}
+
+impl Viewport {
+    /// Construct the [`Viewport`] described by the center and zoom of some [`RenderSettings`],
+    /// covering a grid of `width` × `height` pixels
+    ///
+    /// Unless `stretch` is set, the imaginary range is scaled to the aspect ratio of the image so
+    /// that pixels stay square.
+    pub fn new(settings: &RenderSettings, width: u32, height: u32) -> Viewport {
+        let re_half_extent = BASE_HALF_EXTENT / settings.zoom;
+        let im_half_extent = if settings.stretch {
+            re_half_extent
+        } else {
+            re_half_extent * width as f64 / height as f64
+        };
+        Viewport {
+            re_min: settings.center_re - re_half_extent,
+            re_max: settings.center_re + re_half_extent,
+            im_min: settings.center_im - im_half_extent,
+            im_max: settings.center_im + im_half_extent,
+            re_half_extent,
+            im_half_extent,
+            width,
+            height,
+            center: Complex {
+                re: settings.center_re,
+                im: settings.center_im,
+            },
+            rotation: if settings.rotation == 0.0 {
+                None
+            } else {
+                let angle = settings.rotation.to_radians();
+                Some((angle.cos(), angle.sin()))
+            },
+        }
+    }
+
+    /// Extents of the real and imaginary axes that the viewport covers
+    pub fn size(&self) -> (f64, f64) {
+        (2.0 * self.re_half_extent, 2.0 * self.im_half_extent)
+    }
+
+    /// Distance from the origin past which no point is in view, which may overestimate it for a
+    /// rotated view
+    pub fn reach(&self) -> f64 {
+        match self.rotation {
+            None => (self.center.re.abs() + self.re_half_extent)
+                .hypot(self.center.im.abs() + self.im_half_extent),
+            Some(_) => {
+                self.center.re.hypot(self.center.im)
+                    + self.re_half_extent.hypot(self.im_half_extent)
+            }
+        }
+    }
+
+    /// Get the pixel coordinates of a `position` given by [`Viewport::position`], or `None` if it
+    /// falls outside of the viewport
+    pub fn pixel_at(&self, (x, y): (f64, f64)) -> Option<(u32, u32)> {
+        let x = coordinate_to_index(x, self.width);
+        let y = coordinate_to_index(y, self.height);
+        x.zip(y).map(|(x, y)| (x as u32, y as u32))
+    }
+
+    /// Whether a `position` given by [`Viewport::position`] is within a `margin` of pixels around
+    /// the viewport
+    pub fn covers(&self, (x, y): (f64, f64), margin: f64) -> bool {
+        x > -margin
+            && x < self.width as f64 + margin
+            && y > -margin
+            && y < self.height as f64 + margin
+    }
+
+    /// Get the continuous position of a point on the grid of pixels, as the column and row it
+    /// falls at in pixels from the top left corner of the image, whose pixel centers are halfway
+    /// between whole values
+    /// Positions are given for points beyond the viewport too, or are NaN for an empty viewport
+    pub fn position(&self, z: Complex) -> (f64, f64) {
+        let z = match self.rotation {
+            None => z,
+            Some((cos, sin)) => {
+                // Rotating the point clockwise around the center turns the view counter-clockwise
+                let re = z.re - self.center.re;
+                let im = z.im - self.center.im;
+                Complex {
+                    re: self.center.re + re * cos + im * sin,
+                    im: self.center.im - re * sin + im * cos,
+                }
+            }
+        };
+        (
+            f64_to_coordinate(z.im, self.im_min, self.im_max, self.width),
+            f64_to_coordinate(z.re, self.re_min, self.re_max, self.height),
+        )
+    }
+
+    /// Get the offset from the center of the viewport of the point at a continuous `position` on
+    /// the grid of pixels, the other way around from [`Viewport::position_from_center`]
+    pub fn offset_at(&self, (x, y): (f64, f64)) -> Complex {
+        let offset = Complex {
+            re: (2.0 * y / self.height as f64 - 1.0) * self.re_half_extent,
+            im: (2.0 * x / self.width as f64 - 1.0) * self.im_half_extent,
+        };
+        match self.rotation {
+            None => offset,
+            // Rotating the offset counter-clockwise undoes the clockwise rotation of points
+            Some((cos, sin)) => Complex {
+                re: offset.re * cos - offset.im * sin,
+                im: offset.re * sin + offset.im * cos,
+            },
+        }
+    }
+
+    /// Get the continuous position of a point given by its offset from the center of the
+    /// viewport, like [`Viewport::position`] does
+    pub fn position_from_center(&self, offset: Complex) -> (f64, f64) {
+        let offset = match self.rotation {
+            None => offset,
+            Some((cos, sin)) => Complex {
+                re: offset.re * cos + offset.im * sin,
+                im: -offset.re * sin + offset.im * cos,
+            },
+        };
+        (
+            f64_to_coordinate(
+                offset.im,
+                -self.im_half_extent,
+                self.im_half_extent,
+                self.width,
+            ),
+            f64_to_coordinate(
+                offset.re,
+                -self.re_half_extent,
+                self.re_half_extent,
+                self.height,
+            ),
+        )
+    }
+}
+
+/// Position of a `point` along an axis of `size` pixels that runs from `min` to `max`
+fn f64_to_coordinate(point: f64, min: f64, max: f64, size: u32) -> f64 {
+    if min == max {
+        return f64::NAN;
+    };
+    size as f64 * ((point - min) / (max - min))
+}
+
+/// Pixel that a `coordinate` falls in along an axis of `size` pixels, if any
+fn coordinate_to_index(coordinate: f64, size: u32) -> Option<usize> {
+    let pixel = coordinate as usize;
+    if coordinate >= 0.0 && pixel < (size as usize) {
+        Some(pixel)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Asserts that a position is within a millionth of a pixel of the `expected` one
+    fn assert_near((x, y): (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (x - expected.0).abs() < 1e-6 && (y - expected.1).abs() < 1e-6,
+            "({x}, {y}) isn't {expected:?}"
+        );
+    }
+
+    #[test]
+    fn views_are_centered_and_zoomed() {
+        let settings = RenderSettings {
+            center_re: -0.5,
+            center_im: 0.25,
+            zoom: 4.0,
+            ..RenderSettings::default()
+        };
+        let viewport = Viewport::new(&settings, 100, 100);
+        assert_near(
+            viewport.position(Complex { re: -0.5, im: 0.25 }),
+            (50.0, 50.0),
+        );
+        // The real axis runs down the rows, over half the range at a zoom of 1
+        assert_near(
+            viewport.position(Complex {
+                re: -1.0,
+                im: -0.25,
+            }),
+            (0.0, 0.0),
+        );
+        assert_near(
+            viewport.position(Complex { re: 0.0, im: 0.75 }),
+            (100.0, 100.0),
+        );
+        assert_eq!(viewport.size(), (1.0, 1.0));
+        assert_eq!(viewport.pixel_at((99.9, 0.0)), Some((99, 0)));
+        assert_eq!(viewport.pixel_at((100.0, 0.0)), None);
+        assert_eq!(viewport.pixel_at((-0.1, 0.0)), None);
+    }
+
+    #[test]
+    fn wide_images_keep_square_pixels_unless_stretched() {
+        let settings = RenderSettings {
+            zoom: 1.0,
+            ..RenderSettings::default()
+        };
+        let viewport = Viewport::new(&settings, 200, 100);
+        assert_eq!(viewport.size(), (4.0, 8.0));
+        assert_near(
+            viewport.position(Complex { re: 2.0, im: -4.0 }),
+            (0.0, 100.0),
+        );
+
+        let stretched = RenderSettings {
+            stretch: true,
+            ..settings
+        };
+        let viewport = Viewport::new(&stretched, 200, 100);
+        assert_eq!(viewport.size(), (4.0, 4.0));
+        assert_near(
+            viewport.position(Complex { re: 2.0, im: -2.0 }),
+            (0.0, 100.0),
+        );
+    }
+
+    #[test]
+    fn rotated_views_turn_around_their_center() {
+        let settings = RenderSettings {
+            center_re: -0.5,
+            zoom: 2.0,
+            rotation: 90.0,
+            ..RenderSettings::default()
+        };
+        let viewport = Viewport::new(&settings, 100, 100);
+        assert_near(
+            viewport.position(Complex { re: -0.5, im: 0.0 }),
+            (50.0, 50.0),
+        );
+        // The view turns counter-clockwise, so the point that was at the bottom is now on the left
+        assert_near(viewport.position(Complex { re: 0.5, im: 0.0 }), (0.0, 50.0));
+        for position in [(0.0, 0.0), (12.5, 80.0), (100.0, 33.0)] {
+            let offset = viewport.offset_at(position);
+            assert_near(viewport.position_from_center(offset), position);
+            let point = Complex {
+                re: -0.5 + offset.re,
+                im: offset.im,
+            };
+            assert_near(viewport.position(point), position);
+        }
+    }
+
+    #[test]
+    fn pixels_are_always_within_the_image() {
+        // Random coordinates, some of them around the image, and those at its edges or not finite
+        let coordinates = |rng: &mut StdRng, size: u32| -> Vec<f64> {
+            let size = size as f64;
+            (0..8)
+                .map(|_| rng.gen_range(-1.0..2.0) * size)
+                .chain([
+                    0.0,
+                    -0.0,
+                    f64::MIN_POSITIVE,
+                    -f64::MIN_POSITIVE,
+                    size - size * f64::EPSILON,
+                    size,
+                    size + 0.5,
+                    f64::MAX,
+                    f64::MIN,
+                    f64::NAN,
+                    f64::INFINITY,
+                    f64::NEG_INFINITY,
+                ])
+                .collect()
+        };
+        let mut rng = StdRng::seed_from_u64(69);
+        for _ in 0..1_000 {
+            let (width, height) = (rng.gen_range(1..2_000), rng.gen_range(1..2_000));
+            let viewport = Viewport::new(&RenderSettings::default(), width, height);
+            let (xs, ys) = (coordinates(&mut rng, width), coordinates(&mut rng, height));
+            for &x in &xs {
+                if let Some(column) = coordinate_to_index(x, width) {
+                    assert!(column < width as usize, "{x} in {width}");
+                }
+                for &y in &ys {
+                    if let Some((column, row)) = viewport.pixel_at((x, y)) {
+                        assert!(
+                            column < width && row < height,
+                            "({x}, {y}) in {width}x{height}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,602 @@
+//! Renders through the library crate, as programs of their own would make them
+
+use nebulae::output::{self, Source};
+use nebulae::render_settings::{
+    Coloring, Mode, OrbitFilter, PerChannel, SamplerKind, Stratify, Weighting,
+};
+use nebulae::{
+    calibrate_limits, render_escape_times, render_nebulabrot, CancelToken, NoProgress,
+    ProgressSink, RenderResult, RenderSettings,
+};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Mutex;
+
+/// Settings of a small, seeded render that takes a moment
+fn small() -> RenderSettings {
+    RenderSettings {
+        width: 48,
+        height: 32,
+        samples: PerChannel::Uniform(20_000),
+        passes: 2,
+        seed: Some(7),
+        ..RenderSettings::default()
+    }
+}
+
+/// Samples of the PNG that some `settings` write the image of a render into, in a directory of a
+/// given `name`
+fn written(name: &str, settings: &RenderSettings) -> Vec<u8> {
+    let result = render_nebulabrot(settings, &NoProgress, &CancelToken::new()).unwrap();
+    let directory: PathBuf =
+        std::env::temp_dir().join(format!("nebulae-library-{}-{name}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("image.png");
+    output::write_image(
+        settings.clone(),
+        &path.to_string_lossy(),
+        Source::Image(result.image),
+        None,
+    )
+    .join()
+    .unwrap()
+    .unwrap();
+    let mut reader = png::Decoder::new(File::open(&path).unwrap())
+        .read_info()
+        .unwrap();
+    let mut samples = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut samples).unwrap();
+    assert_eq!((info.width, info.height), (settings.width, settings.height));
+    fs::remove_dir_all(&directory).unwrap();
+    samples
+}
+
+#[test]
+fn renders_are_written_through_the_library() {
+    let samples = written("view", &small());
+    assert_eq!(samples.len(), 48 * 32 * 3);
+    assert!(samples.contains(&255));
+    // A view where no orbit lands has nothing to show, and is written black
+    let nowhere = RenderSettings {
+        center_re: 40.0,
+        zoom: 1_000.0,
+        ..small()
+    };
+    assert!(written("nowhere", &nowhere)
+        .iter()
+        .all(|&sample| sample == 0));
+}
+
+#[test]
+fn renders_where_no_orbit_escapes_are_written_black() {
+    // Samples within the main cardioid, none of which escape in a single iteration
+    let interior = RenderSettings {
+        limits: vec![1, 1, 1],
+        sample_re_min: -0.3,
+        sample_re_max: -0.1,
+        sample_im_min: -0.1,
+        sample_im_max: 0.1,
+        ..small()
+    };
+    let result = render_nebulabrot(&interior, &NoProgress, &CancelToken::new()).unwrap();
+    assert_eq!(result.maximum(), 0);
+    assert_eq!(result.escaped, [0, 0, 0]);
+    let samples = written("interior", &interior);
+    assert_eq!(samples.len(), 48 * 32 * 3);
+    assert!(samples.iter().all(|&sample| sample == 0));
+}
+
+#[test]
+fn deterministic_renders_are_the_same_on_any_number_of_threads() {
+    let samplers = [
+        (SamplerKind::Uniform, Stratify::Never),
+        (SamplerKind::Uniform, Stratify::Always),
+        (SamplerKind::Jittered, Stratify::Never),
+        (SamplerKind::Halton, Stratify::Auto),
+        (SamplerKind::Poisson, Stratify::Never),
+    ];
+    for (sampler, stratify_passes) in samplers {
+        let render = |threads| {
+            let settings = RenderSettings {
+                sampler,
+                stratify_passes,
+                threads: Some(threads),
+                deterministic: true,
+                ..small()
+            };
+            render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap()
+        };
+        let (one, four) = (render(1), render(4));
+        assert!(one.points > 0, "{sampler} samples");
+        assert_eq!(
+            one.image.get_data(),
+            four.image.get_data(),
+            "{sampler} samples"
+        );
+        assert_eq!((one.samples, one.points), (four.samples, four.points));
+        // Another seed draws other samples
+        let other = RenderSettings {
+            sampler,
+            stratify_passes,
+            seed: Some(8),
+            ..small()
+        };
+        let other = render_nebulabrot(&other, &NoProgress, &CancelToken::new()).unwrap();
+        assert_ne!(
+            one.image.get_data(),
+            other.image.get_data(),
+            "{sampler} samples"
+        );
+    }
+}
+
+/// A [`ProgressSink`] that keeps what it is told, and cancels its render after some passes
+#[derive(Default)]
+struct Recorder {
+    passes: Mutex<Vec<(u64, u64)>>,
+    finished: AtomicU64,
+    samples: AtomicU64,
+    cancel_after: Option<(u64, CancelToken)>,
+}
+
+impl ProgressSink for Recorder {
+    fn pass_started(&self, pass: u64, samples: u64) {
+        self.passes.lock().unwrap().push((pass, samples));
+    }
+
+    fn samples_completed(&self, samples: u64) {
+        self.samples.fetch_add(samples, Relaxed);
+    }
+
+    fn pass_finished(&self, _pass: u64) {
+        let finished = self.finished.fetch_add(1, Relaxed) + 1;
+        if let Some((passes, cancel)) = &self.cancel_after {
+            if finished == *passes {
+                cancel.cancel();
+            }
+        }
+    }
+}
+
+#[test]
+fn renders_report_their_progress() {
+    let settings = RenderSettings {
+        passes: 3,
+        ..small()
+    };
+    let recorder = Recorder::default();
+    render_nebulabrot(&settings, &recorder, &CancelToken::new()).unwrap();
+    assert_eq!(
+        *recorder.passes.lock().unwrap(),
+        [(0, 20_000), (1, 20_000), (2, 20_000)]
+    );
+    assert_eq!(recorder.finished.load(Relaxed), 3);
+    assert_eq!(recorder.samples.load(Relaxed), 60_000);
+}
+
+#[test]
+fn results_tell_how_the_render_went() {
+    let settings = RenderSettings {
+        passes: 3,
+        ..small()
+    };
+    let result = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+    assert!(!result.partial);
+    assert_eq!(result.samples, 60_000);
+    assert_eq!(result.pass_times.len(), 3);
+    assert_eq!(result.maxima, result.image.get_channel_maxima());
+    // Samples inside the main cardioid and bulb are skipped rather than traced
+    assert!(result.skipped > 0);
+    assert_eq!(result.traced, [60_000 - result.skipped; 3]);
+    assert!(result
+        .escaped
+        .iter()
+        .zip(&result.traced)
+        .all(|(escaped, traced)| escaped <= traced));
+    // Each point plotted is counted once
+    let counted: u64 = result.image.get_data().iter().sum();
+    assert_eq!(counted, result.points);
+}
+
+#[test]
+fn cancelled_renders_keep_what_they_counted() {
+    let cancel = CancelToken::new();
+    let settings = RenderSettings {
+        passes: 0,
+        ..small()
+    };
+    let recorder = Recorder {
+        cancel_after: Some((2, cancel.clone())),
+        ..Recorder::default()
+    };
+    // Renders of endless passes only stop once cancelled
+    let result = render_nebulabrot(&settings, &recorder, &cancel).unwrap();
+    assert!(result.partial && cancel.is_cancelled());
+    assert_eq!(result.pass_times.len(), 2);
+    assert!(result.points > 0);
+
+    // A render cancelled before it starts counts nothing
+    let result = render_nebulabrot(&small(), &NoProgress, &cancel).unwrap();
+    assert!(result.partial);
+    assert_eq!((result.samples, result.image.get_maximum()), (0, 0));
+}
+
+#[test]
+fn channels_take_their_own_number_of_samples() {
+    let settings = RenderSettings {
+        samples: PerChannel::Channels(vec![10_000, 20_000, 5_000]),
+        skip_interior: false,
+        ..small()
+    };
+    let result = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+    assert_eq!(result.traced, [20_000, 40_000, 10_000]);
+    // Orbits are shared between channels, each of which traces as many of the first samples as it
+    // takes
+    assert_eq!(result.samples, 40_000);
+}
+
+/// Asserts that a render plotted points, symmetrically about the real axis
+fn assert_symmetric(result: &RenderResult) {
+    assert!(result.points > 0);
+    // The imaginary axis runs along the rows, which the real axis cuts in halves
+    for row in result.image.rows() {
+        let pixels: Vec<Vec<u64>> = row
+            .collect::<Vec<_>>()
+            .chunks(3)
+            .map(<[u64]>::to_vec)
+            .collect();
+        let mirrored: Vec<Vec<u64>> = pixels.iter().rev().cloned().collect();
+        assert_eq!(pixels, mirrored);
+    }
+}
+
+#[test]
+fn mirrored_orbits_plot_symmetric_images() {
+    let settings = RenderSettings {
+        mirror_symmetry: true,
+        ..small()
+    };
+    let result = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+    assert_symmetric(&result);
+}
+
+/// Points that a render of some `settings` plots
+fn points(settings: &RenderSettings) -> u64 {
+    render_nebulabrot(settings, &NoProgress, &CancelToken::new())
+        .unwrap()
+        .points
+}
+
+#[test]
+fn short_orbits_are_left_out() {
+    let all = points(&small());
+    let long = points(&RenderSettings {
+        min_iterations: PerChannel::Uniform(20),
+        ..small()
+    });
+    assert!(0 < long && long < all, "{long} of {all}");
+    // Orbits are left out of each channel by its own minimum
+    let first = points(&RenderSettings {
+        min_iterations: PerChannel::Channels(vec![20, 0, 0]),
+        ..small()
+    });
+    assert!(long < first && first < all, "{first} of {all}");
+}
+
+#[test]
+fn the_heads_of_orbits_are_skipped() {
+    let all = points(&small());
+    let skipped = points(&RenderSettings {
+        skip_iterations: PerChannel::Uniform(3),
+        ..small()
+    });
+    assert!(0 < skipped && skipped < all, "{skipped} of {all}");
+    let fewer = points(&RenderSettings {
+        skip_iterations: PerChannel::Uniform(10),
+        ..small()
+    });
+    assert!(fewer < skipped, "{fewer} of {skipped}");
+}
+
+#[test]
+fn every_orbit_is_plotted_by_one_filter_or_the_other() {
+    let filtered = |orbit_filter| {
+        // Trapped orbits take every iteration, which is kept short
+        points(&RenderSettings {
+            orbit_filter,
+            limits: vec![200, 100, 50],
+            ..small()
+        })
+    };
+    let (escaped, trapped) = (
+        filtered(OrbitFilter::Escaped),
+        filtered(OrbitFilter::Trapped),
+    );
+    assert!(escaped > 0 && trapped > 0);
+    assert_eq!(filtered(OrbitFilter::All), escaped + trapped);
+}
+
+#[test]
+fn julia_orbits_escape_from_outside_their_set() {
+    // The Julia set of 0 is the unit disk, which escaping orbits never enter
+    let settings = RenderSettings {
+        mode: Mode::Julia {
+            c_re: 0.0,
+            c_im: 0.0,
+        },
+        center_re: 0.0,
+        zoom: 1.0,
+        ..small()
+    };
+    let result = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+    assert!(result.points > 0);
+    assert_eq!(result.skipped, 0);
+    for (y, row) in result.image.rows().enumerate() {
+        let re = (2.0 * (y as f64 + 0.5) / 32.0 - 1.0) * 2.0;
+        for (x, pixel) in row.collect::<Vec<_>>().chunks(3).enumerate() {
+            let im = (2.0 * (x as f64 + 0.5) / 48.0 - 1.0) * 3.0;
+            if re * re + im * im < 0.8 {
+                assert_eq!(pixel, [0; 3], "{re} + {im}i");
+            }
+        }
+    }
+}
+
+#[test]
+fn orbits_start_from_z0() {
+    let from_zero = render_nebulabrot(&small(), &NoProgress, &CancelToken::new()).unwrap();
+    let settings = RenderSettings {
+        z0_re: 0.1,
+        z0_im: -0.2,
+        ..small()
+    };
+    let shifted = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+    // The interior of the set from 0 isn't that of other starting points, so none are skipped
+    assert_eq!(shifted.skipped, 0);
+    assert!(shifted.points > 0);
+    assert_ne!(from_zero.image.get_data(), shifted.image.get_data());
+}
+
+#[test]
+fn sharing_orbits_leaves_the_image_as_it_is() {
+    let render = |share_orbits| {
+        let settings = RenderSettings {
+            share_orbits,
+            ..small()
+        };
+        render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap()
+    };
+    let (shared, traced_apart) = (render(true), render(false));
+    assert!(shared.points > 0);
+    assert_eq!(shared.image.get_data(), traced_apart.image.get_data());
+    // Apart, each sample is traced once for every channel rather than once for all of them
+    assert_eq!(traced_apart.samples, 3 * shared.samples);
+}
+
+#[test]
+fn batched_renders_plot_the_same_points() {
+    let render = |batched| {
+        let settings = RenderSettings { batched, ..small() };
+        render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap()
+    };
+    let (batched, alone) = (render(true), render(false));
+    assert!(batched.points > 0);
+    assert_eq!(batched.image.get_data(), alone.image.get_data());
+}
+
+#[test]
+fn antithetic_pairs_plot_symmetric_images() {
+    let settings = RenderSettings {
+        antithetic: true,
+        ..small()
+    };
+    let result = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+    assert_symmetric(&result);
+}
+
+/// Sum of the weights of each channel of a render of weighted points
+fn weight_sums(result: &RenderResult) -> Vec<f64> {
+    let weights = result.weights.as_ref().unwrap().get_data();
+    (0..3)
+        .map(|channel| {
+            weights
+                .iter()
+                .skip(channel)
+                .step_by(3)
+                .map(|&weight| f64::from(weight))
+                .sum()
+        })
+        .collect()
+}
+
+#[test]
+fn orbits_weighted_by_their_length_count_for_one() {
+    let settings = RenderSettings {
+        weighting: Weighting::PerOrbit,
+        ..small()
+    };
+    let result = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+    assert!(result.weighted_maxima.is_some());
+    // Orbits count for their share of points that land in view
+    for (sum, &escaped) in weight_sums(&result).into_iter().zip(&result.escaped) {
+        assert!(
+            0.0 < sum && sum <= escaped as f64 + 1e-3,
+            "{sum} of {escaped}"
+        );
+    }
+}
+
+#[test]
+fn points_colored_by_their_origin_are_shared_between_channels() {
+    let settings = RenderSettings {
+        coloring: Coloring::Origin,
+        limits: vec![1_000],
+        ..small()
+    };
+    let result = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+    let total: f64 = weight_sums(&result).iter().sum();
+    assert!(
+        (total - result.points as f64).abs() < 1e-3 * total,
+        "{total} of {}",
+        result.points
+    );
+}
+
+#[test]
+fn orbits_are_counted_in_the_band_of_their_escape_time() {
+    let banded = RenderSettings {
+        bands: Some(vec![50, 500, 5_000]),
+        ..small()
+    };
+    let banded = render_nebulabrot(&banded, &NoProgress, &CancelToken::new()).unwrap();
+    let whole = RenderSettings {
+        limits: vec![5_000],
+        ..small()
+    };
+    let whole = render_nebulabrot(&whole, &NoProgress, &CancelToken::new()).unwrap();
+    assert_eq!(banded.image.get_channel_maxima().len(), 3);
+    assert!(banded.maxima.iter().all(|&maximum| maximum > 0));
+    // Bands split the orbits of a single limit between them
+    assert_eq!(banded.points, whole.points);
+    let summed: Vec<u64> = banded
+        .image
+        .get_data()
+        .chunks(3)
+        .map(|pixel| pixel.iter().sum())
+        .collect();
+    assert_eq!(summed, whole.image.get_data());
+}
+
+#[test]
+fn escape_times_are_0_inside_the_set() {
+    let settings = RenderSettings {
+        center_re: 0.0,
+        zoom: 1.0,
+        ..small()
+    };
+    let times = render_escape_times(&settings, &CancelToken::new()).unwrap();
+    assert_eq!(times.len(), 48 * 32);
+    // The pixel at the origin is in the main cardioid, and the corners are far from the set
+    assert_eq!(times[16 * 48 + 24], 0.0);
+    for corner in [0, 47, 31 * 48, 32 * 48 - 1] {
+        assert!(
+            times[corner] > 0.0 && times[corner] < 3.0,
+            "{}",
+            times[corner]
+        );
+    }
+}
+
+#[test]
+fn limits_are_picked_at_their_percentiles() {
+    let settings = RenderSettings {
+        auto_limits: Some(vec![50.0, 99.0, 99.9]),
+        ..small()
+    };
+    let limits = calibrate_limits(&settings).unwrap();
+    assert_eq!(limits.len(), 3);
+    assert!(
+        limits[0] < limits[1] && limits[1] <= limits[2],
+        "{limits:?}"
+    );
+    // Seeded pre-passes are drawn the same on any number of threads
+    let threaded = RenderSettings {
+        threads: Some(3),
+        ..settings.clone()
+    };
+    assert_eq!(calibrate_limits(&threaded).unwrap(), limits);
+    // Given limits are kept
+    assert_eq!(calibrate_limits(&small()).unwrap(), small().limits);
+}
+
+/// Asserts that a deterministic render of some `settings` plots points, the same ones on one
+/// thread as on four
+fn assert_reproduced(settings: &RenderSettings) {
+    let render = |threads| {
+        let settings = RenderSettings {
+            threads: Some(threads),
+            deterministic: true,
+            ..settings.clone()
+        };
+        render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap()
+    };
+    let (one, four) = (render(1), render(4));
+    assert!(one.points > 0);
+    assert_eq!(one.image.get_data(), four.image.get_data());
+}
+
+#[test]
+fn metropolis_chains_are_reproduced() {
+    assert_reproduced(&RenderSettings {
+        sampler: SamplerKind::Metropolis,
+        limits: vec![500, 200, 100],
+        ..small()
+    });
+}
+
+#[test]
+fn guided_samples_are_reproduced() {
+    assert_reproduced(&RenderSettings {
+        sampler: SamplerKind::Guided,
+        limits: vec![500, 200, 100],
+        guided_grid: 16,
+        guided_samples: 50_000,
+        ..small()
+    });
+}
+
+#[test]
+fn adaptive_passes_are_reproduced() {
+    assert_reproduced(&RenderSettings {
+        adaptive: true,
+        passes: 4,
+        limits: vec![500, 200, 100],
+        ..small()
+    });
+}
+
+#[test]
+fn quarter_turns_transpose_the_image() {
+    let size = 32;
+    // Off the real axis, so that the view isn't its own mirror image
+    let settings = RenderSettings {
+        width: size,
+        height: size,
+        center_im: 0.3,
+        deterministic: true,
+        ..small()
+    };
+    let counts = |rotation| {
+        let settings = RenderSettings {
+            rotation,
+            ..settings.clone()
+        };
+        let result = render_nebulabrot(&settings, &NoProgress, &CancelToken::new()).unwrap();
+        result.image.get_data()
+    };
+    let (upright, turned) = (counts(0.0), counts(90.0));
+    let at = |data: &[u64], (x, y): (u32, u32), channel: u32| {
+        data[((y * size + x) * 3 + channel) as usize]
+    };
+    // Sum of the differences between the counts of the turned image and of the upright one, with
+    // each pixel of the turned image taken from that of the upright one at `from` it
+    let difference = |from: &dyn Fn(u32, u32) -> (u32, u32)| {
+        let mut difference = 0;
+        for y in 0..size {
+            for x in 0..size {
+                for channel in 0..3 {
+                    let upright = at(&upright, from(x, y), channel);
+                    difference += at(&turned, (x, y), channel).abs_diff(upright);
+                }
+            }
+        }
+        difference
+    };
+    // A quarter turn transposes the image and flips it, while points on the edges of pixels may
+    // round either way once turned
+    let total: u64 = upright.iter().sum();
+    assert!(difference(&|x, y| (y, size - 1 - x)) <= total / 1_000);
+    assert!(difference(&|x, y| (x, y)) > total / 10);
+}